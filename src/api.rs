@@ -1,22 +1,34 @@
 use regex_lite::Regex;
-use reqwest::Client;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE},
+    Client,
+};
 use reqwest_cookie_store::CookieStoreMutex;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use std::{
-    collections::HashMap,
-    sync::{Arc, LazyLock},
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, LazyLock,
+    },
     time::{Duration, SystemTime},
 };
 
 use crate::{
+    cache,
     error::{
         ContextCreationError, DigitalDownloadError, InformationRetrievalError,
         ReleaseRetrievalError,
     },
-    middlewares::{RateLimitMiddleware, RetryMiddleware},
+    middlewares::{MetricsMiddleware, RateLimitMiddleware, RequestMetrics, RetryMiddleware},
+    pinning::{self, TlsPin},
+    progress::{NoProgress, ProgressReporter},
 };
 
 pub mod data;
+pub mod endpoints;
+mod html;
 
 static STAT_RESPONSE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
@@ -25,13 +37,6 @@ static STAT_RESPONSE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     .expect("Regex pattern for \"stat_response_regex\" should compile successfully")
 });
 
-static DATA_BLOB_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-        r#"(?s)<div\s+(?:[^>]*?\s+)?id="pagedata"(?:\s+[^>]*?)?\s+data-blob="((?:[^"\\]|\\.)*)""#,
-    )
-    .expect("Regex pattern for \"data_blob_regex\" should compile successfully")
-});
-
 fn generate_token(item_id: i64, item_type: &str) -> String {
     let timestamp = SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -41,25 +46,277 @@ fn generate_token(item_id: i64, item_type: &str) -> String {
     format!("{timestamp}:{item_id}:{item_type}::")
 }
 
+// The wishlist is listed from its newest item, with no need to name one
+fn wishlist_start_token() -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    format!("{timestamp}::a::")
+}
+
 pub struct BandcampAPIContext {
     pub client: ClientWithMiddleware,
+    // for the files themselves, which need neither the session nor the middlewares
+    download_client: Client,
+    // the fancollection API version found to work, 0 until the first request settles it
+    fancollection_version: AtomicU32,
+    progress: Arc<dyn ProgressReporter>,
+}
+
+// Bandcamp localizes parts of its pages, which shifts the markup being scraped
+pub const DEFAULT_ACCEPT_LANGUAGE: &str = "en";
+
+// Requests allowed through every RATE_LIMIT_PERIOD, bursts included
+pub const RATE_LIMIT_REQUESTS: u64 = 10;
+pub const RATE_LIMIT_PERIOD: Duration = Duration::from_secs(10);
+
+// Before asking again for a download Bandcamp is still preparing
+pub const STAT_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// What a stat download answers: the link to download from, or the one to ask again once Bandcamp
+// has prepared the download
+pub enum StatDownload {
+    Ready(String),
+    Preparing(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    // When set, connections to servers whose certificate matches none of the pins are refused
+    pub tls_pins: Option<Vec<TlsPin>>,
+    pub accept_language: String,
+    // When set, every request is counted into it
+    pub metrics: Option<Arc<RequestMetrics>>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            tls_pins: None,
+            accept_language: DEFAULT_ACCEPT_LANGUAGE.to_owned(),
+            metrics: None,
+        }
+    }
 }
 
 pub type SaleIdUrlMap = HashMap<String, String>;
 
+// The owned items of a collection, along with the download pages of those that have one
+#[derive(Default)]
+pub struct Collection {
+    pub download_urls: SaleIdUrlMap,
+    pub items: Vec<data::CollectionItem>,
+    // the releases hidden from the collection page, when hidden items were listed at all
+    pub hidden: Option<BTreeSet<String>>,
+}
+
+impl Collection {
+    // Sale ids are normalized, so a release keeps the same one whichever scope lists it, and
+    // hiding or unhiding it doesn't make it look new
+    fn add_page(&mut self, page: data::ParsedCollectionItems, scope: CollectionScope) {
+        for (sale_id, download_url) in page.redownload_urls.unwrap_or_default() {
+            let sale_id = cache::normalize_release_id(&sale_id);
+            if scope == CollectionScope::Hidden {
+                self.hidden
+                    .get_or_insert_with(BTreeSet::new)
+                    .insert(sale_id.clone());
+            }
+            self.download_urls.insert(sale_id, download_url);
+        }
+        self.items.extend(page.items);
+    }
+
+    // Maps an owned item, such as one found with `lookup_owned_item`, to its sale id
+    pub fn sale_key_of(&self, owned_item: &data::TrAlbumLookupItem) -> Option<String> {
+        self.items
+            .iter()
+            .find(|item| item.item_id == Some(owned_item.item_id))
+            .and_then(data::CollectionItem::sale_key)
+    }
+
+    // Collection pages may leave out when an item was bought, which the fan summary still knows
+    fn fill_purchase_dates(&mut self, summary: &data::ParsedFanCollectionSummary) {
+        let Some(tralbum_lookup) = &summary.collection_summary.tralbum_lookup else {
+            return;
+        };
+        for item in self
+            .items
+            .iter_mut()
+            .filter(|item| item.purchased.is_none())
+        {
+            item.purchased = item
+                .lookup_key()
+                .and_then(|key| tralbum_lookup.get(&key))
+                .map(|owned_item| owned_item.purchased.clone());
+        }
+    }
+}
+
+// The lists of a fan page. Promo grants sent to label and artist accounts aren't in any list the
+// fancollection API is known to have, so they aren't a scope, and aren't downloaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CollectionScope {
+    Collection,
+    Hidden,
+    // Not owned, so nothing on it has a download link. Listed on its own with `get_wishlist`.
+    Wishlist,
+}
+
+impl CollectionScope {
+    pub const fn endpoint_name(self) -> &'static str {
+        match self {
+            Self::Collection => "collection_items",
+            Self::Hidden => "hidden_items",
+            Self::Wishlist => "wishlist_items",
+        }
+    }
+}
+
+fn collection_start_token(summary: &data::ParsedFanCollectionSummary) -> String {
+    let first_item = summary
+        .collection_summary
+        .tralbum_lookup
+        .as_ref()
+        .unwrap()
+        .iter()
+        .next()
+        .unwrap();
+
+    generate_token(first_item.1.item_id, &first_item.1.item_type)
+}
+
+// Items bought in the same second as the one looked for may come before it
+const PURCHASE_PAGE_SIZE: u32 = 20;
+
+// Lists the collection from just after the item's purchase
+fn purchase_token(owned_item: &data::TrAlbumLookupItem) -> Option<String> {
+    let purchased = data::parse_bandcamp_timestamp(&owned_item.purchased)?;
+    Some(format!(
+        "{}:{}:{}::",
+        purchased.unix_timestamp() + 1,
+        owned_item.item_id,
+        owned_item.item_type
+    ))
+}
+
+fn find_owned_item<'a>(
+    summary: &'a data::ParsedFanCollectionSummary,
+    tralbum: &data::TralbumData,
+) -> Option<&'a data::TrAlbumLookupItem> {
+    summary
+        .collection_summary
+        .tralbum_lookup
+        .as_ref()?
+        .get(&tralbum.lookup_key()?)
+}
+
+fn extract_tralbum_data(page: &str) -> Result<String, InformationRetrievalError> {
+    html::start_tags(page)
+        .find_map(|tag| tag.attribute("data-tralbum").map(Cow::into_owned))
+        .ok_or(InformationRetrievalError::TralbumDataNotFound)
+}
+
+fn extract_data_blob(page: &str) -> Result<String, InformationRetrievalError> {
+    html::start_tags(page)
+        .find(|tag| tag.attribute("id").as_deref() == Some("pagedata"))
+        .and_then(|tag| tag.attribute("data-blob").map(Cow::into_owned))
+        .ok_or(InformationRetrievalError::DataBlobNotFound)
+}
+
+// `None` once the last page has been reached
+pub fn next_page_token(
+    page: &data::ParsedCollectionItems,
+) -> Result<Option<String>, ReleaseRetrievalError> {
+    if !page.more_available {
+        return Ok(None);
+    }
+
+    page.last_token
+        .clone()
+        .map(Some)
+        .ok_or(ReleaseRetrievalError::MissingPaginationToken)
+}
+
+// With the TLS pins checked during the handshake, when there are some
+fn client_builder(options: &ClientOptions) -> Result<reqwest::ClientBuilder, ContextCreationError> {
+    let builder = Client::builder();
+    Ok(match &options.tls_pins {
+        Some(tls_pins) => {
+            builder.use_preconfigured_tls(pinning::pinned_tls_config(tls_pins.clone())?)
+        }
+        None => builder,
+    })
+}
+
 impl BandcampAPIContext {
     pub fn new(cookie_data: &str) -> Result<Self, ContextCreationError> {
+        Self::with_options(cookie_data, &ClientOptions::default())
+    }
+
+    pub fn with_options(
+        cookie_data: &str,
+        options: &ClientOptions,
+    ) -> Result<Self, ContextCreationError> {
         let cookie_store = crate::cookies::read_json_file(cookie_data, "https://bandcamp.com")?;
-        let client = Client::builder()
+        Self::with_cookie_store(cookie_store, options)
+    }
+
+    // Only suitable for public information, such as fan pages
+    pub fn new_without_cookies(options: &ClientOptions) -> Result<Self, ContextCreationError> {
+        Self::with_cookie_store(cookie_store::CookieStore::default(), options)
+    }
+
+    fn with_cookie_store(
+        cookie_store: cookie_store::CookieStore,
+        options: &ClientOptions,
+    ) -> Result<Self, ContextCreationError> {
+        let accept_language = HeaderValue::from_str(&options.accept_language).map_err(|_| {
+            ContextCreationError::InvalidAcceptLanguage(options.accept_language.clone())
+        })?;
+        let default_headers = HeaderMap::from_iter([(ACCEPT_LANGUAGE, accept_language)]);
+
+        let client = client_builder(options)?
             .cookie_provider(Arc::new(CookieStoreMutex::new(cookie_store)))
+            .default_headers(default_headers)
             .build()?;
 
-        let client = ClientBuilder::new(client)
+        let mut client = ClientBuilder::new(client)
             .with(RetryMiddleware::new(5))
-            .with(RateLimitMiddleware::new(10, Duration::from_secs(10)))
-            .build();
+            .with(RateLimitMiddleware::new(
+                RATE_LIMIT_REQUESTS,
+                RATE_LIMIT_PERIOD,
+            ));
+        if let Some(metrics) = &options.metrics {
+            client = client.with(MetricsMiddleware::new(Arc::clone(metrics)));
+        }
+
+        Ok(Self {
+            client: client.build(),
+            download_client: client_builder(options)?.build()?,
+            fancollection_version: AtomicU32::new(0),
+            progress: Arc::new(NoProgress),
+        })
+    }
 
-        Ok(Self { client })
+    #[must_use]
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressReporter>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn progress(&self) -> &dyn ProgressReporter {
+        self.progress.as_ref()
+    }
+
+    // For reporting from where the context isn't at hand, such as the thread committing downloads
+    pub fn progress_reporter(&self) -> Arc<dyn ProgressReporter> {
+        Arc::clone(&self.progress)
+    }
+
+    pub const fn download_client(&self) -> &Client {
+        &self.download_client
     }
 
     pub async fn get_summary(
@@ -67,7 +324,7 @@ impl BandcampAPIContext {
     ) -> Result<data::ParsedFanCollectionSummary, InformationRetrievalError> {
         let response = self
             .client
-            .get("https://bandcamp.com/api/fan/2/collection_summary")
+            .get(endpoints::collection_summary_url())
             .send()
             .await?;
         let response_text = response.text().await?;
@@ -77,35 +334,66 @@ impl BandcampAPIContext {
         Ok(parsed_summary)
     }
 
+    // Checked before anything else, so expired cookies are reported as such rather than as an
+    // unreadable response further into the run
+    pub async fn verify_session(
+        &self,
+    ) -> Result<data::ParsedFanCollectionSummary, InformationRetrievalError> {
+        let response = self
+            .client
+            .get(endpoints::collection_summary_url())
+            .send()
+            .await?;
+        let status = response.status();
+        let response_text = response.text().await?;
+
+        parse_session_summary(status, &response_text)
+    }
+
     pub async fn get_all_releases(
         &self,
         summary: &data::ParsedFanCollectionSummary,
-        include_hidden: bool,
+        scopes: &[CollectionScope],
     ) -> Result<SaleIdUrlMap, ReleaseRetrievalError> {
-        let mut collection = SaleIdUrlMap::new();
-
-        let first_item = summary
-            .collection_summary
-            .tralbum_lookup
-            .as_ref()
-            .unwrap()
-            .iter()
-            .next()
-            .unwrap();
-
-        let token = generate_token(first_item.1.item_id, &first_item.1.item_type);
+        Ok(self.get_collection(summary, scopes).await?.download_urls)
+    }
 
-        collection.extend(
-            self.get_webui_download_urls(summary.fan_id, &token, "collection_items")
-                .await?,
-        );
+    pub async fn get_collection(
+        &self,
+        summary: &data::ParsedFanCollectionSummary,
+        scopes: &[CollectionScope],
+    ) -> Result<Collection, ReleaseRetrievalError> {
+        let mut collection = Collection {
+            hidden: scopes
+                .contains(&CollectionScope::Hidden)
+                .then(BTreeSet::new),
+            ..Collection::default()
+        };
 
-        if include_hidden {
-            collection.extend(
-                self.get_webui_download_urls(summary.fan_id, &token, "hidden_items")
-                    .await?,
+        let token = collection_start_token(summary);
+        // each page of a scope needs the token of the one before, so it's the scopes that are
+        // paged through side by side, two at a time
+        for scope_pair in scopes.chunks(2) {
+            let (first_pages, second_pages) = tokio::join!(
+                self.get_collection_pages(summary.fan_id, &token, scope_pair[0].endpoint_name()),
+                async {
+                    match scope_pair.get(1) {
+                        Some(scope) => self
+                            .get_collection_pages(summary.fan_id, &token, scope.endpoint_name())
+                            .await
+                            .map(Some),
+                        None => Ok(None),
+                    }
+                }
             );
+            for page in first_pages? {
+                collection.add_page(page, scope_pair[0]);
+            }
+            for page in second_pages?.into_iter().flatten() {
+                collection.add_page(page, scope_pair[1]);
+            }
         }
+        collection.fill_purchase_dates(summary);
         Ok(collection)
     }
 
@@ -116,40 +404,318 @@ impl BandcampAPIContext {
         collection_name: &str,
     ) -> Result<SaleIdUrlMap, ReleaseRetrievalError> {
         let mut download_urls = SaleIdUrlMap::new();
+        for page in self
+            .get_collection_pages(fan_id, last_token, collection_name)
+            .await?
+        {
+            download_urls.extend(page.redownload_urls.unwrap_or_default());
+        }
+
+        Ok(download_urls)
+    }
+
+    // Owned items without any download, which can only be streamed
+    pub async fn get_stream_only_items(
+        &self,
+        summary: &data::ParsedFanCollectionSummary,
+        scopes: &[CollectionScope],
+    ) -> Result<Vec<data::CollectionItem>, ReleaseRetrievalError> {
+        let collection = self.get_collection(summary, scopes).await?;
+
+        Ok(collection
+            .items
+            .into_iter()
+            .filter(|item| {
+                item.sale_key()
+                    .is_some_and(|key| !collection.download_urls.contains_key(&key))
+            })
+            .collect())
+    }
+
+    // The collection is listed from the newest purchase, so a page starting just after the item's
+    // own purchase has it near its top, without going through the rest of the collection. Only
+    // when its purchase date can't be read is the whole collection looked through.
+    pub async fn find_download_url(
+        &self,
+        summary: &data::ParsedFanCollectionSummary,
+        owned_item: &data::TrAlbumLookupItem,
+        scopes: &[CollectionScope],
+    ) -> Result<Option<(String, String)>, ReleaseRetrievalError> {
+        let purchase_token = purchase_token(owned_item);
+        let (start_token, page_size) = purchase_token.clone().map_or_else(
+            || (collection_start_token(summary), 100),
+            |token| (token, PURCHASE_PAGE_SIZE),
+        );
+        for scope in scopes {
+            let mut token = start_token.clone();
+            loop {
+                let page = self
+                    .get_collection_page(summary.fan_id, &token, scope.endpoint_name(), page_size)
+                    .await?;
+                self.progress.collection_page_fetched(page.items.len());
+
+                let download_url = page
+                    .items
+                    .iter()
+                    .find(|item| item.item_id == Some(owned_item.item_id))
+                    .and_then(data::CollectionItem::sale_key)
+                    .and_then(|sale_id| {
+                        let download_url = page.redownload_urls.as_ref()?.get(&sale_id)?.clone();
+                        Some((sale_id, download_url))
+                    });
+                if download_url.is_some() {
+                    return Ok(download_url);
+                }
+
+                match next_page_token(&page)? {
+                    Some(next_token) if purchase_token.is_none() && !page.items.is_empty() => {
+                        token = next_token;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn get_wishlist(
+        &self,
+        fan_id: i64,
+    ) -> Result<Vec<data::CollectionItem>, ReleaseRetrievalError> {
+        let mut items = Vec::new();
+        let mut token = wishlist_start_token();
+        loop {
+            let page = self
+                .get_collection_page(
+                    fan_id,
+                    &token,
+                    CollectionScope::Wishlist.endpoint_name(),
+                    100,
+                )
+                .await?;
+            self.progress.collection_page_fetched(page.items.len());
+            if page.items.is_empty() {
+                break;
+            }
+            let next_token = next_page_token(&page)?;
+            items.extend(page.items);
+
+            let Some(next_token) = next_token else {
+                break;
+            };
+            token = next_token;
+        }
+
+        Ok(items)
+    }
+
+    // Every page up to and including the first one without download links. Pages are asked for
+    // as large as Bandcamp allows, so that most scopes take a single round trip.
+    async fn get_collection_pages(
+        &self,
+        fan_id: i64,
+        last_token: &str,
+        collection_name: &str,
+    ) -> Result<Vec<data::ParsedCollectionItems>, ReleaseRetrievalError> {
+        let mut pages = Vec::new();
         let mut current_token = last_token.to_string();
 
+        let mut retried_page = false;
         loop {
-            let body = format!(
-                "{{\"fan_id\": {fan_id}, \"older_than_token\": \"{current_token}\", \"count\":100000}}"
-            );
+            let parsed_collection_data = self
+                .get_collection_page(fan_id, &current_token, collection_name, 100_000)
+                .await?;
+
+            let next_token = match next_page_token(&parsed_collection_data) {
+                Err(ReleaseRetrievalError::MissingPaginationToken) if !retried_page => {
+                    eprintln!(
+                        "Bandcamp reported more {collection_name} but no token to fetch them with, retrying the page once"
+                    );
+                    retried_page = true;
+                    continue;
+                }
+                next_token => next_token?,
+            };
+            retried_page = false;
+
+            self.progress
+                .collection_page_fetched(parsed_collection_data.items.len());
+            let last_page = parsed_collection_data.redownload_urls.is_none();
+            pages.push(parsed_collection_data);
+            if last_page {
+                break;
+            }
+
+            let Some(next_token) = next_token else {
+                break;
+            };
+            current_token = next_token;
+        }
+
+        Ok(pages)
+    }
+
+    async fn get_collection_page(
+        &self,
+        fan_id: i64,
+        older_than_token: &str,
+        collection_name: &str,
+        count: u32,
+    ) -> Result<data::ParsedCollectionItems, ReleaseRetrievalError> {
+        let body = format!(
+            "{{\"fan_id\": {fan_id}, \"older_than_token\": \"{older_than_token}\", \"count\":{count}}}"
+        );
 
+        let known_version = self.fancollection_version.load(Ordering::Relaxed);
+        if known_version != 0 {
             let response = self
                 .client
-                .post(format!(
-                    "https://bandcamp.com/api/fancollection/1/{collection_name}"
-                ))
+                .post(endpoints::fancollection_url(known_version, collection_name))
                 .body(body)
                 .send()
                 .await?;
+            return Ok(serde_json::from_str(&response.text().await?)?);
+        }
+
+        // negotiating: the first version that answers with a readable page is kept for later pages
+        let mut last_error = ReleaseRetrievalError::NoAvailableApiVersion;
+        for &version in endpoints::FANCOLLECTION_VERSIONS {
+            let response = self
+                .client
+                .post(endpoints::fancollection_url(version, collection_name))
+                .body(body.clone())
+                .send()
+                .await?;
+            if endpoints::is_unavailable(response.status()) {
+                last_error = ReleaseRetrievalError::NoAvailableApiVersion;
+                continue;
+            }
+
+            match serde_json::from_str(&response.text().await?) {
+                Ok(page) => {
+                    self.fancollection_version.store(version, Ordering::Relaxed);
+                    eprintln!("Using version {version} of the fancollection API");
+                    return Ok(page);
+                }
+                Err(e) => last_error = e.into(),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    async fn fan_page(
+        &self,
+        fan_url: &str,
+    ) -> Result<data::ParsedFanpageData, InformationRetrievalError> {
+        let response = self.client.get(fan_url).send().await?;
+        if endpoints::is_unavailable(response.status()) {
+            return Err(InformationRetrievalError::Unavailable);
+        }
+        let fan_page = response.text().await?;
+        Ok(serde_json::from_str(&extract_data_blob(&fan_page)?)?)
+    }
 
-            let parsed_collection_data: data::ParsedCollectionItems =
-                serde_json::from_str(&response.text().await?)?;
+    // Read from the fan's public page, so no session is needed
+    pub async fn public_fan_id(&self, fan_url: &str) -> Result<i64, InformationRetrievalError> {
+        Ok(self.fan_page(fan_url).await?.fan_data.fan_id)
+    }
 
-            let Some(redownload_urls) = parsed_collection_data.redownload_urls else {
+    // The items the fan shows publicly, without the hidden ones
+    pub async fn public_collection(
+        &self,
+        fan_url: &str,
+    ) -> Result<Vec<data::CollectionItem>, InformationRetrievalError> {
+        let fanpage_data = self.fan_page(fan_url).await?;
+
+        let mut items: Vec<_> = fanpage_data.item_cache.collection.into_values().collect();
+        let mut last_token = fanpage_data.collection_data.last_token;
+        let item_count = fanpage_data
+            .collection_data
+            .item_count
+            .and_then(|count| usize::try_from(count).ok())
+            .unwrap_or_default();
+
+        while items.len() < item_count {
+            let Some(token) = last_token else {
                 break;
             };
 
-            download_urls.extend(redownload_urls);
+            let page = self
+                .get_collection_page(
+                    fanpage_data.fan_data.fan_id,
+                    &token,
+                    CollectionScope::Collection.endpoint_name(),
+                    100,
+                )
+                .await?;
+            if page.items.is_empty() {
+                break;
+            }
+            items.extend(page.items);
 
-            if !parsed_collection_data.more_available {
+            if !page.more_available {
                 break;
             }
-            current_token = parsed_collection_data
-                .last_token
-                .expect("Server returned more_available=true but no last_token");
+            last_token = page.last_token;
         }
 
-        Ok(download_urls)
+        Ok(items)
+    }
+
+    // Artists, albums and tracks matching `query`, as Bandcamp's search box suggests them
+    pub async fn search(
+        &self,
+        query: &str,
+    ) -> Result<Vec<data::SearchResult>, InformationRetrievalError> {
+        let body = serde_json::json!({
+            "search_text": query,
+            "search_filter": "",
+            "full_page": false,
+            "fan_id": null,
+        });
+        let response = self
+            .client
+            .post(endpoints::search_url())
+            .body(body.to_string())
+            .send()
+            .await?;
+        let page: data::SearchPage = serde_json::from_str(&response.text().await?)?;
+        Ok(page.auto.results)
+    }
+
+    pub async fn get_tralbum_data(
+        &self,
+        item_url: &str,
+    ) -> Result<data::TralbumData, InformationRetrievalError> {
+        let response = self.client.get(item_url).send().await?;
+        let page = response.text().await?;
+
+        Ok(serde_json::from_str(&extract_tralbum_data(&page)?)?)
+    }
+
+    // Whether an arbitrary album or track page is one of the fan's own items
+    pub async fn lookup_owned_item(
+        &self,
+        summary: &data::ParsedFanCollectionSummary,
+        item_url: &str,
+    ) -> Result<Option<data::TrAlbumLookupItem>, InformationRetrievalError> {
+        let tralbum = self.get_tralbum_data(item_url).await?;
+        Ok(find_owned_item(summary, &tralbum).cloned())
+    }
+
+    // The cover art of a release, as its image file
+    pub async fn get_art(
+        &self,
+        art_id: i64,
+        size: data::ArtSize,
+    ) -> Result<Vec<u8>, InformationRetrievalError> {
+        let art_url = endpoints::art_url(art_id, size.image_format());
+        let response = self.client.get(art_url).send().await?;
+        if endpoints::is_unavailable(response.status()) {
+            return Err(InformationRetrievalError::Unavailable);
+        }
+        Ok(response.error_for_status()?.bytes().await?.to_vec())
     }
 
     pub async fn get_digital_download_item(
@@ -157,15 +723,12 @@ impl BandcampAPIContext {
         item_url: &str,
     ) -> Result<Option<data::DigitalItem>, InformationRetrievalError> {
         let response = self.client.get(item_url).send().await?;
+        if endpoints::is_unavailable(response.status()) {
+            return Err(InformationRetrievalError::Unavailable);
+        }
         let response_data = response.text().await?;
 
-        let data_blob = DATA_BLOB_REGEX
-            .captures(&response_data)
-            .ok_or(InformationRetrievalError::DataBlobNotFound)?
-            .get(1)
-            .ok_or(InformationRetrievalError::DataBlobNotFound)?
-            .as_str();
-        let data_blob = htmlize::unescape(data_blob);
+        let data_blob = extract_data_blob(&response_data)?;
 
         let bandcamp_data = serde_json::from_str::<data::ParsedBandcampData>(&data_blob)?;
         if bandcamp_data.digital_items.is_empty() {
@@ -193,22 +756,34 @@ impl BandcampAPIContext {
     ) -> Result<String, DigitalDownloadError> {
         let mut actual_dl_link = download_link.to_string();
         loop {
-            let inner = self
-                .retrieve_digital_download_stat_data(&actual_dl_link)
-                .await?;
-
-            match get_qualified_digital_download_url(&inner) {
-                Ok(url) => return Ok(url),
-                Err(DigitalDownloadError::JsonResponseErrorCode(url)) => {
+            match self.stat_digital_download_link(&actual_dl_link).await? {
+                StatDownload::Ready(url) => return Ok(url),
+                StatDownload::Preparing(url) => {
                     actual_dl_link = url;
-                    tokio::time::sleep(Duration::from_secs(5)).await;
-                    continue;
+                    tokio::time::sleep(STAT_RETRY_DELAY).await;
                 }
-                Err(e) => return Err(e),
             }
         }
     }
 
+    // A single stat request, leaving the wait for a download being prepared to the caller
+    pub async fn stat_digital_download_link(
+        &self,
+        download_link: &str,
+    ) -> Result<StatDownload, DigitalDownloadError> {
+        let inner = self
+            .retrieve_digital_download_stat_data(download_link)
+            .await?;
+
+        match get_qualified_digital_download_url(&inner) {
+            Ok(url) => Ok(StatDownload::Ready(url)),
+            Err(DigitalDownloadError::JsonResponseErrorCode(url)) => {
+                Ok(StatDownload::Preparing(url))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     pub async fn retrieve_digital_download_stat_data(
         &self,
         download_link: &str,
@@ -227,6 +802,36 @@ impl BandcampAPIContext {
     }
 }
 
+// Logged out requests are refused, or get an error object without a fan instead of the summary.
+// Server errors and pages that aren't JSON at all are reported as they are, as they don't say
+// anything about the session.
+pub fn parse_session_summary(
+    status: reqwest::StatusCode,
+    response_text: &str,
+) -> Result<data::ParsedFanCollectionSummary, InformationRetrievalError> {
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(InformationRetrievalError::NotLoggedIn);
+    }
+    if !status.is_success() {
+        return Err(InformationRetrievalError::SessionCheckFailed(status));
+    }
+
+    let summary: serde_json::Value = serde_json::from_str(response_text)?;
+    let has_fan_id = summary
+        .get("fan_id")
+        .and_then(serde_json::Value::as_i64)
+        .is_some_and(|fan_id| fan_id > 0);
+    if !has_fan_id {
+        return Err(InformationRetrievalError::NotLoggedIn);
+    }
+
+    let summary: data::ParsedFanCollectionSummary = serde_json::from_value(summary)?;
+    if summary.collection_summary.username.is_empty() {
+        return Err(InformationRetrievalError::NotLoggedIn);
+    }
+    Ok(summary)
+}
+
 pub fn get_unqualified_digital_download_link(
     digital_item: &data::DigitalItem,
     download_format: data::DownloadFormat,
@@ -249,12 +854,19 @@ pub fn get_unqualified_digital_download_link(
 pub fn get_qualified_digital_download_url(
     stat_response_body: &str,
 ) -> Result<String, DigitalDownloadError> {
-    let inner_json = STAT_RESPONSE_REGEX
-        .captures(stat_response_body)
-        .ok_or(DigitalDownloadError::JsonBodyNotFound)?
-        .get(1)
-        .ok_or(DigitalDownloadError::JsonBodyNotFound)?
-        .as_str();
+    // depending on the flags of the request, the JSON comes either bare or as a script calling
+    // `Downloads.statResult`
+    let trimmed_body = stat_response_body.trim();
+    let inner_json = if trimmed_body.starts_with('{') {
+        trimmed_body
+    } else {
+        STAT_RESPONSE_REGEX
+            .captures(stat_response_body)
+            .ok_or(DigitalDownloadError::JsonBodyNotFound)?
+            .get(1)
+            .ok_or(DigitalDownloadError::JsonBodyNotFound)?
+            .as_str()
+    };
 
     let inner_data: data::ParsedStatDownload = serde_json::from_str(inner_json)?;
     if Some("err".into()) == inner_data.result {
@@ -268,3 +880,282 @@ pub fn get_qualified_digital_download_url(
         .download_url
         .ok_or(DigitalDownloadError::NoLinkFound)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    #[test]
+    pub fn test_invalid_accept_language() {
+        let options = ClientOptions {
+            accept_language: "en\n".to_owned(),
+            ..ClientOptions::default()
+        };
+        assert_matches!(
+            BandcampAPIContext::with_cookie_store(cookie_store::CookieStore::default(), &options)
+                .err(),
+            Some(ContextCreationError::InvalidAcceptLanguage(_))
+        );
+    }
+
+    #[test]
+    pub fn test_parse_session_summary() {
+        let summary = parse_session_summary(
+            reqwest::StatusCode::OK,
+            r#"{"fan_id": 1234, "collection_summary": {"fan_id": 1234, "username": "someone", "url": "https://bandcamp.com/someone"}}"#,
+        )
+        .unwrap();
+        assert_eq!(summary.fan_id, 1234);
+        assert_eq!(summary.collection_summary.username, "someone");
+    }
+
+    #[test_case(reqwest::StatusCode::OK, r#"{"error": true, "error_message": "not logged in"}"#; "error object")]
+    #[test_case(reqwest::StatusCode::OK, r#"{"fan_id": null}"#; "no fan")]
+    #[test_case(reqwest::StatusCode::FORBIDDEN, "{}"; "forbidden")]
+    pub fn test_parse_session_summary_logged_out(status: reqwest::StatusCode, response_text: &str) {
+        assert_matches!(
+            parse_session_summary(status, response_text).err(),
+            Some(InformationRetrievalError::NotLoggedIn)
+        );
+    }
+
+    #[test]
+    pub fn test_parse_session_summary_failed() {
+        assert_matches!(
+            parse_session_summary(
+                reqwest::StatusCode::SERVICE_UNAVAILABLE,
+                "<!DOCTYPE html><html></html>"
+            )
+            .err(),
+            Some(InformationRetrievalError::SessionCheckFailed(
+                reqwest::StatusCode::SERVICE_UNAVAILABLE
+            ))
+        );
+        assert_matches!(
+            parse_session_summary(reqwest::StatusCode::OK, "<!DOCTYPE html><html></html>").err(),
+            Some(InformationRetrievalError::JsonParseError(_))
+        );
+    }
+
+    fn parse_page(page: &str) -> data::ParsedCollectionItems {
+        serde_json::from_str(page).unwrap()
+    }
+
+    #[test]
+    pub fn test_next_page_token_last_page() {
+        let page =
+            parse_page(r#"{"more_available": false, "last_token": null, "redownload_urls": {}}"#);
+        assert_matches!(next_page_token(&page), Ok(None));
+
+        let page = parse_page(
+            r#"{"more_available": false, "last_token": "1234:5678:a::", "redownload_urls": {}}"#,
+        );
+        assert_matches!(next_page_token(&page), Ok(None));
+    }
+
+    #[test]
+    pub fn test_next_page_token_more_available() {
+        let page = parse_page(
+            r#"{"more_available": true, "last_token": "1234:5678:a::", "redownload_urls": {}}"#,
+        );
+        assert_eq!(
+            next_page_token(&page).unwrap().as_deref(),
+            Some("1234:5678:a::")
+        );
+    }
+
+    #[test]
+    pub fn test_next_page_token_malformed_page() {
+        let page = parse_page(
+            r#"{"more_available": true, "redownload_urls": {"p1": "https://bandcamp.com/download?id=1"}}"#,
+        );
+        assert_matches!(
+            next_page_token(&page),
+            Err(ReleaseRetrievalError::MissingPaginationToken)
+        );
+    }
+
+    #[test_case(r#"if ( window.Downloads ) { Downloads.statResult ( {"result":"ok","download_url":"https://p4.bcbits.com/download/album/1","url":"bandcamp.com/download"} ) };"#; "script")]
+    #[test_case(r#"{"result":"ok","download_url":"https://p4.bcbits.com/download/album/1","url":"bandcamp.com/download"}"#; "bare json")]
+    #[test_case("\n  {\"download_url\":\"https://p4.bcbits.com/download/album/1\",\"url\":\"bandcamp.com/download\"}\n"; "bare json with whitespace")]
+    pub fn test_get_qualified_digital_download_url(body: &str) {
+        assert_eq!(
+            get_qualified_digital_download_url(body).unwrap(),
+            "https://p4.bcbits.com/download/album/1"
+        );
+    }
+
+    #[test]
+    pub fn test_get_qualified_digital_download_url_errors() {
+        assert_matches!(
+            get_qualified_digital_download_url(
+                r#"{"result":"err","url":"bandcamp.com/download?id=1"}"#
+            ),
+            Err(DigitalDownloadError::JsonResponseErrorCode(url)) if url == "https://bandcamp.com/download?id=1"
+        );
+        assert_matches!(
+            get_qualified_digital_download_url("<html></html>"),
+            Err(DigitalDownloadError::JsonBodyNotFound)
+        );
+    }
+
+    #[test]
+    pub fn test_extract_tralbum_data() {
+        let page = r#"<script type="text/javascript" src="tralbum.js" data-band="{}" data-tralbum="{&quot;artist&quot;:&quot;Anomalie&quot;,&quot;current&quot;:{&quot;title&quot;:&quot;Galerie&quot;},&quot;trackinfo&quot;:[{&quot;title&quot;:&quot;Odyss\u00e9e&quot;,&quot;track_num&quot;:1,&quot;file&quot;:{&quot;mp3-128&quot;:&quot;https://t4.bcbits.com/stream/1&quot;}}]}"></script>"#;
+
+        let tralbum: data::TralbumData =
+            serde_json::from_str(&extract_tralbum_data(page).unwrap()).unwrap();
+        assert_eq!(tralbum.artist, "Anomalie");
+        assert_eq!(tralbum.trackinfo[0].title, "Odyssée");
+        assert_eq!(
+            tralbum.trackinfo[0].mp3_128_url().as_deref(),
+            Some("https://t4.bcbits.com/stream/1")
+        );
+
+        assert_matches!(
+            extract_tralbum_data("<html></html>"),
+            Err(InformationRetrievalError::TralbumDataNotFound)
+        );
+    }
+
+    // Blobs whose strings hold escaped quotes and backslashes, from artist and fan pages that the
+    // former regex cut short
+    #[test_case(r#"<div id="pagedata" data-blob="{&quot;title&quot;:&quot;The \&quot;Best\&quot; Of&quot;}"></div>"#, r#"The "Best" Of"#; "escaped quotes")]
+    #[test_case(r#"<div id="pagedata" data-blob="{&quot;title&quot;:&quot;C:\\&quot;}"></div>"#, r"C:\"; "trailing backslash")]
+    #[test_case(r#"<div data-blob="{&quot;title&quot;:&quot;a > b&quot;}" id="pagedata"></div>"#, "a > b"; "id after the blob")]
+    #[test_case(r#"<div class="x>y" id='pagedata' data-blob='{"title":"Galerie"}'></div>"#, "Galerie"; "single quoted")]
+    #[test_case(r#"<div id="other" data-blob="{}"></div><div id="pagedata" data-blob="{&quot;title&quot;:&quot;Galerie&quot;}"></div>"#, "Galerie"; "another blob first")]
+    pub fn test_extract_data_blob(page: &str, title: &str) {
+        let blob: serde_json::Value =
+            serde_json::from_str(&extract_data_blob(page).unwrap()).unwrap();
+        assert_eq!(blob["title"], title);
+    }
+
+    #[test]
+    pub fn test_extract_data_blob_missing() {
+        assert_matches!(
+            extract_data_blob(
+                r#"<!-- <div id="pagedata" data-blob="{}"> --><div id="pagedata"></div>"#
+            ),
+            Err(InformationRetrievalError::DataBlobNotFound)
+        );
+    }
+
+    #[test]
+    pub fn test_find_owned_item() {
+        let summary: data::ParsedFanCollectionSummary = serde_json::from_str(
+            r#"{"fan_id": 1, "collection_summary": {"fan_id": 1, "username": "fan", "url": "https://bandcamp.com/fan",
+                "tralbum_lookup": {"a1234567": {"item_type": "a", "item_id": 1234567, "band_id": 42, "purchased": "01 Jan 2021 00:00:00 GMT"}}}}"#,
+        )
+        .unwrap();
+        let tralbum = |id: i64, item_type: &str| -> data::TralbumData {
+            serde_json::from_value(serde_json::json!({
+                "id": id, "item_type": item_type, "artist": "Anomalie", "current": {"title": "Galerie"}
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(
+            find_owned_item(&summary, &tralbum(1_234_567, "album")).map(|item| item.band_id),
+            Some(42)
+        );
+        assert!(find_owned_item(&summary, &tralbum(1_234_567, "track")).is_none());
+        assert!(find_owned_item(&summary, &tralbum(7_654_321, "album")).is_none());
+
+        let collection = Collection {
+            download_urls: SaleIdUrlMap::new(),
+            items: vec![serde_json::from_value(serde_json::json!({
+                "sale_item_id": 199_396_767, "sale_item_type": "p", "band_name": "Anomalie",
+                "item_title": "Galerie", "item_id": 1_234_567
+            }))
+            .unwrap()],
+            hidden: None,
+        };
+        let owned_item = find_owned_item(&summary, &tralbum(1_234_567, "album")).unwrap();
+        assert_eq!(
+            collection.sale_key_of(owned_item).as_deref(),
+            Some("p199396767")
+        );
+    }
+
+    #[test]
+    pub fn test_purchase_token() {
+        let owned_item = |purchased: &str| data::TrAlbumLookupItem {
+            item_type: "a".to_owned(),
+            item_id: 1_234_567,
+            band_id: 42,
+            purchased: purchased.to_owned(),
+        };
+
+        assert_eq!(
+            purchase_token(&owned_item("01 Jan 2021 00:00:00 GMT")).as_deref(),
+            Some("1609459201:1234567:a::")
+        );
+        assert_eq!(purchase_token(&owned_item("")), None);
+    }
+
+    #[test]
+    pub fn test_fill_purchase_dates() {
+        let summary: data::ParsedFanCollectionSummary = serde_json::from_str(
+            r#"{"fan_id": 1, "collection_summary": {"fan_id": 1, "username": "fan", "url": "https://bandcamp.com/fan",
+                "tralbum_lookup": {"a1234567": {"item_type": "a", "item_id": 1234567, "band_id": 42, "purchased": "01 Jan 2021 00:00:00 GMT"}}}}"#,
+        )
+        .unwrap();
+        let item = |item_id: i64| -> data::CollectionItem {
+            serde_json::from_value(serde_json::json!({
+                "sale_item_id": 1, "sale_item_type": "p", "band_name": "Anomalie",
+                "item_title": "Galerie", "item_id": item_id, "tralbum_type": "a"
+            }))
+            .unwrap()
+        };
+        let mut collection = Collection {
+            download_urls: SaleIdUrlMap::new(),
+            items: vec![item(1_234_567), item(7_654_321)],
+            hidden: None,
+        };
+
+        collection.fill_purchase_dates(&summary);
+        assert_eq!(
+            collection.items[0]
+                .purchase_date()
+                .map(|date| date.to_string()),
+            Some("2021-01-01".to_owned())
+        );
+        assert_eq!(collection.items[1].purchased, None);
+    }
+
+    #[test]
+    pub fn test_add_page_normalizes_scopes() {
+        let page = |redownload_urls: serde_json::Value| -> data::ParsedCollectionItems {
+            serde_json::from_value(serde_json::json!({
+                "more_available": false,
+                "last_token": null,
+                "redownload_urls": redownload_urls,
+            }))
+            .unwrap()
+        };
+        let mut collection = Collection {
+            hidden: Some(BTreeSet::new()),
+            ..Collection::default()
+        };
+
+        collection.add_page(
+            page(serde_json::json!({"p1": "https://bandcamp.com/download?id=1"})),
+            CollectionScope::Collection,
+        );
+        collection.add_page(
+            page(serde_json::json!({"1": "https://bandcamp.com/download?id=1", "2": "https://bandcamp.com/download?id=2"})),
+            CollectionScope::Hidden,
+        );
+
+        let mut sale_ids: Vec<_> = collection.download_urls.keys().cloned().collect();
+        sale_ids.sort();
+        assert_eq!(sale_ids, ["p1", "p2"]);
+        assert_eq!(
+            collection.hidden,
+            Some(BTreeSet::from(["p1".to_owned(), "p2".to_owned()]))
+        );
+    }
+}
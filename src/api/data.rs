@@ -1,6 +1,7 @@
 use clap::ValueEnum;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, time::Duration};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
 
 use std::str::FromStr;
 
@@ -32,9 +33,10 @@ pub enum DownloadFormat {
     AiffLossless,
 }
 
-impl std::fmt::Display for DownloadFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = match self {
+impl DownloadFormat {
+    // As Bandcamp names it, e.g. "mp3-v0"
+    pub const fn name(self) -> &'static str {
+        match self {
             Self::Mp3_V0 => "mp3-v0",
             Self::Mp3_320 => "mp3-320",
             Self::Flac => "flac",
@@ -43,8 +45,13 @@ impl std::fmt::Display for DownloadFormat {
             Self::Alac => "alac",
             Self::Wav => "wav",
             Self::AiffLossless => "aiff-lossless",
-        };
-        write!(f, "({str})")
+        }
+    }
+}
+
+impl std::fmt::Display for DownloadFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({})", self.name())
     }
 }
 
@@ -69,10 +76,83 @@ impl FromStr for DownloadFormat {
     }
 }
 
+// The sizes Bandcamp's image server scales cover art to, by the width of the square
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum ArtSize {
+    // as the artist uploaded it, which may be a PNG
+    #[default]
+    Original,
+    #[value(name = "1200")]
+    Large,
+    #[value(name = "700")]
+    Medium,
+    #[value(name = "350")]
+    Small,
+}
+
+impl ArtSize {
+    // The number in the image's url that picks the size
+    pub const fn image_format(self) -> u32 {
+        match self {
+            Self::Original => 0,
+            Self::Large => 10,
+            Self::Medium => 16,
+            Self::Small => 2,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchPage {
+    pub auto: SearchSuggestions,
+}
+
+#[derive(Deserialize)]
+pub struct SearchSuggestions {
+    #[serde(default)]
+    pub results: Vec<SearchResult>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SearchResult {
+    // "b" for an artist or label, "a" for an album, "t" for a track
+    #[serde(rename = "type")]
+    pub result_type: String,
+    pub name: String,
+    // unset for artists, which are the band
+    #[serde(default)]
+    pub band_name: Option<String>,
+    // of albums and tracks, while artists only have their page's root
+    #[serde(default)]
+    pub item_url_path: Option<String>,
+    #[serde(default)]
+    pub item_url_root: Option<String>,
+    #[serde(default)]
+    pub art_id: Option<i64>,
+}
+
+impl SearchResult {
+    pub fn kind(&self) -> &str {
+        match self.result_type.as_str() {
+            "b" => "artist",
+            "a" => "album",
+            "t" => "track",
+            other => other,
+        }
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.item_url_path
+            .as_deref()
+            .or(self.item_url_root.as_deref())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct ParsedFanpageData {
     pub fan_data: FanData,
     pub collection_data: CollectionData,
+    #[serde(default)]
     pub hidden_data: CollectionData,
     pub item_cache: ItemCache,
 }
@@ -92,7 +172,7 @@ pub struct FanCollectionSummary {
     pub followers: Option<Vec<()>>, // TODO
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TrAlbumLookupItem {
     pub item_type: String,
     pub item_id: i64,
@@ -107,18 +187,68 @@ pub struct FanData {
 
 #[derive(Serialize, Deserialize)]
 pub struct ItemCache {
-    pub collection: HashMap<String, CachedItem>,
-    pub hidden: HashMap<String, CachedItem>,
+    pub collection: HashMap<String, CollectionItem>,
+    #[serde(default)]
+    pub hidden: HashMap<String, CollectionItem>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct CachedItem {
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CollectionItem {
     pub sale_item_id: i64,
+    #[serde(default)]
+    pub sale_item_type: Option<String>,
     pub band_name: String,
     pub item_title: String,
+    #[serde(default)]
+    pub item_id: Option<i64>,
+    #[serde(default)]
+    pub item_type: Option<String>,
+    #[serde(default)]
+    pub band_id: Option<i64>,
+    #[serde(default)]
+    pub item_url: Option<String>,
+    #[serde(default)]
+    pub purchased: Option<String>,
+    // "a" or "t", as in the keys of the fan summary's `tralbum_lookup`
+    #[serde(default)]
+    pub tralbum_type: Option<String>,
+    // received as part of a subscription to the artist, rather than bought
+    #[serde(default)]
+    pub is_subscription_item: bool,
 }
 
-#[derive(Serialize, Deserialize)]
+impl CollectionItem {
+    // 'a' for albums, 't' for tracks
+    fn type_initial(&self) -> Option<char> {
+        match &self.tralbum_type {
+            Some(tralbum_type) => tralbum_type.chars().next(),
+            None => self.item_type.as_ref()?.chars().next(),
+        }
+    }
+
+    // The key of the item in the fan summary's `tralbum_lookup`, e.g. "a1234567"
+    pub fn lookup_key(&self) -> Option<String> {
+        Some(format!("{}{}", self.type_initial()?, self.item_id?))
+    }
+
+    // Packages, such as a vinyl with its digital album, count as albums
+    pub fn is_track(&self) -> Option<bool> {
+        self.type_initial().map(|initial| initial == 't')
+    }
+
+    pub fn purchase_date(&self) -> Option<Date> {
+        self.purchased.as_deref().and_then(parse_bandcamp_date)
+    }
+
+    // The key used for the item in `redownload_urls` and the download cache, e.g. "p199396767"
+    pub fn sale_key(&self) -> Option<String> {
+        self.sale_item_type
+            .as_ref()
+            .map(|sale_item_type| format!("{sale_item_type}{}", self.sale_item_id))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct CollectionData {
     pub batch_size: i64,
     pub item_count: Option<i64>,
@@ -131,6 +261,8 @@ pub struct ParsedCollectionItems {
     pub more_available: bool,
     pub last_token: Option<String>,
     pub redownload_urls: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub items: Vec<CollectionItem>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -138,6 +270,129 @@ pub struct ParsedBandcampData {
     pub digital_items: Vec<DigitalItem>,
 }
 
+// The `data-tralbum` attribute of album and track pages
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TralbumData {
+    #[serde(default)]
+    pub id: Option<i64>,
+    // "album" or "track"
+    #[serde(default)]
+    pub item_type: Option<String>,
+    pub artist: String,
+    pub current: TralbumCurrent,
+    #[serde(default)]
+    pub trackinfo: Vec<TrackInfo>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub art_id: Option<i64>,
+    #[serde(default)]
+    pub album_release_date: Option<String>,
+    #[serde(default)]
+    pub album_is_preorder: Option<bool>,
+    // The download page of free releases, and of name your price ones that can be had for nothing
+    #[serde(default, rename = "freeDownloadPage")]
+    pub free_download_page: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TralbumCurrent {
+    pub title: String,
+    #[serde(default)]
+    pub release_date: Option<String>,
+    #[serde(default)]
+    pub about: Option<String>,
+    #[serde(default)]
+    pub credits: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub title: String,
+    #[serde(default)]
+    pub track_id: Option<i64>,
+    #[serde(default)]
+    pub track_num: Option<i64>,
+    // only set when it differs from the album artist, as on compilations
+    #[serde(default)]
+    pub artist: Option<String>,
+    // given in seconds
+    #[serde(
+        default,
+        deserialize_with = "deserialize_seconds",
+        serialize_with = "serialize_seconds"
+    )]
+    pub duration: Option<Duration>,
+    // relative to the artist's site, e.g. "/track/odyss-e"
+    #[serde(default)]
+    pub title_link: Option<String>,
+    #[serde(default)]
+    pub file: Option<StreamUrls>,
+}
+
+// The streams of a track, which aren't there for tracks that can't be played on the page
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StreamUrls {
+    #[serde(default, rename = "mp3-128")]
+    pub mp3_128: Option<String>,
+}
+
+impl TralbumData {
+    // The key of the item in the fan summary's `tralbum_lookup`, e.g. "a1234567"
+    pub fn lookup_key(&self) -> Option<String> {
+        let type_initial = self.item_type.as_ref()?.chars().next()?;
+        Some(format!("{type_initial}{}", self.id?))
+    }
+
+    // The release date of the album, falling back to the one of the item itself for tracks
+    pub fn release_date(&self) -> Option<Date> {
+        self.album_release_date
+            .as_deref()
+            .or(self.current.release_date.as_deref())
+            .and_then(parse_bandcamp_date)
+    }
+
+    // Only as long as the tracks whose durations are known
+    pub fn total_duration(&self) -> Duration {
+        self.trackinfo
+            .iter()
+            .filter_map(|track| track.duration)
+            .sum()
+    }
+}
+
+impl TrackInfo {
+    pub fn mp3_128_url(&self) -> Option<String> {
+        let url = self.file.as_ref()?.mp3_128.as_ref()?;
+        // older pages use protocol relative links
+        Some(if url.starts_with("//") {
+            format!("https:{url}")
+        } else {
+            url.clone()
+        })
+    }
+}
+
+// A negative, infinite or far too long duration fails parsing the page, rather than being dropped
+fn deserialize_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(|seconds| Duration::try_from_secs_f64(seconds).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+// serde hands over a reference to the field itself
+#[allow(clippy::ref_option)]
+fn serialize_seconds<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs_f64())
+        .serialize(serializer)
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct DownloadData {
     pub size_mb: Option<String>,
@@ -146,6 +401,27 @@ pub struct DownloadData {
     pub url: String,
 }
 
+impl DownloadData {
+    // `size_mb` is a human readable hint such as "98.2MB" or "1.1GB"
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn size_bytes(&self) -> Option<u64> {
+        let size = self.size_mb.as_deref()?.trim().to_uppercase();
+        let (number, multiplier) = [("GB", 1e9), ("MB", 1e6), ("KB", 1e3)]
+            .into_iter()
+            .find_map(|(suffix, multiplier)| {
+                size.strip_suffix(suffix).map(|number| (number, multiplier))
+            })
+            .unwrap_or((size.as_str(), 1e6));
+
+        let number: f64 = number.trim().parse().ok()?;
+        (number.is_finite() && number >= 0.0).then_some((number * multiplier) as u64)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct DigitalItem {
     pub downloads: Option<HashMap<DownloadFormat, DownloadData>>,
@@ -156,6 +432,62 @@ pub struct DigitalItem {
     pub download_type_str: String,
     pub item_type: String,
     pub art_id: i64,
+    #[serde(default)]
+    pub page_url: Option<String>,
+}
+
+impl DigitalItem {
+    pub fn release_date(&self) -> Option<Date> {
+        self.package_release_date
+            .as_deref()
+            .and_then(parse_bandcamp_date)
+    }
+
+    pub fn release_year(&self) -> Option<i32> {
+        self.release_date().map(Date::year)
+    }
+}
+
+// Bandcamp dates look like "01 Apr 2024 00:00:00 GMT", only the date part is of interest
+pub fn parse_bandcamp_date(date: &str) -> Option<Date> {
+    let mut parts = date.split_whitespace();
+    let day = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => Month::January,
+        "Feb" => Month::February,
+        "Mar" => Month::March,
+        "Apr" => Month::April,
+        "May" => Month::May,
+        "Jun" => Month::June,
+        "Jul" => Month::July,
+        "Aug" => Month::August,
+        "Sep" => Month::September,
+        "Oct" => Month::October,
+        "Nov" => Month::November,
+        "Dec" => Month::December,
+        _ => return None,
+    };
+    let year = parts.next()?.parse().ok()?;
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+// The whole of a Bandcamp date, which is always in GMT. A date without a time is taken at midnight.
+pub fn parse_bandcamp_timestamp(date: &str) -> Option<OffsetDateTime> {
+    let day = parse_bandcamp_date(date)?;
+    let time = match date.split_whitespace().nth(3) {
+        Some(time) => {
+            let mut parts = time.splitn(3, ':').map(str::parse);
+            let (Some(Ok(hour)), Some(Ok(minute)), second) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return None;
+            };
+            Time::from_hms(hour, minute, second.and_then(Result::ok).unwrap_or(0)).ok()?
+        }
+        None => Time::MIDNIGHT,
+    };
+    Some(PrimitiveDateTime::new(day, time).assume_utc())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -164,3 +496,138 @@ pub struct ParsedStatDownload {
     pub download_url: Option<String>,
     pub url: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("01 Apr 2024 00:00:00 GMT", 2024, Month::April, 1)]
+    #[test_case("15 Jul 2022 13:37:00 GMT", 2022, Month::July, 15)]
+    #[test_case("31 Dec 1999", 1999, Month::December, 31)]
+    pub fn test_parse_bandcamp_date(date: &str, year: i32, month: Month, day: u8) {
+        assert_eq!(
+            parse_bandcamp_date(date),
+            Some(Date::from_calendar_date(year, month, day).unwrap())
+        );
+    }
+
+    #[test_case("15 Jul 2022 13:37:05 GMT", "2022-07-15T13:37:05Z")]
+    #[test_case("31 Dec 1999", "1999-12-31T00:00:00Z")]
+    pub fn test_parse_bandcamp_timestamp(date: &str, expected: &str) {
+        assert_eq!(
+            parse_bandcamp_timestamp(date).map(crate::timestamp::to_rfc3339),
+            Some(expected.to_owned())
+        );
+        assert_eq!(parse_bandcamp_timestamp("15 Jul 2022 25:00:00 GMT"), None);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("Apr 01 2024"; "wrong order")]
+    #[test_case("31 Feb 2024 00:00:00 GMT"; "impossible date")]
+    #[test_case("01 April 2024 00:00:00 GMT"; "long month name")]
+    pub fn test_parse_bandcamp_date_invalid(date: &str) {
+        assert_eq!(parse_bandcamp_date(date), None);
+    }
+
+    #[test]
+    pub fn test_search_page() {
+        let page: SearchPage = serde_json::from_value(serde_json::json!({
+            "auto": {
+                "results": [
+                    {
+                        "type": "b",
+                        "name": "Anomalie",
+                        "item_url_root": "https://anomalie.bandcamp.com",
+                        "img_id": 1,
+                    },
+                    {
+                        "type": "a",
+                        "name": "Galerie",
+                        "band_name": "Anomalie",
+                        "item_url_path": "https://anomalie.bandcamp.com/album/galerie",
+                        "art_id": 42,
+                    },
+                ],
+            },
+        }))
+        .unwrap();
+
+        let [artist, album] = page.auto.results.as_slice() else {
+            panic!("expected two results");
+        };
+        assert_eq!(artist.kind(), "artist");
+        assert_eq!(artist.url(), Some("https://anomalie.bandcamp.com"));
+        assert_eq!(album.kind(), "album");
+        assert_eq!(
+            album.url(),
+            Some("https://anomalie.bandcamp.com/album/galerie")
+        );
+        assert_eq!(album.art_id, Some(42));
+    }
+
+    #[test_case(Some("98.2MB"), Some(98_200_000))]
+    #[test_case(Some("1.5GB"), Some(1_500_000_000))]
+    #[test_case(Some("512kb"), Some(512_000))]
+    #[test_case(Some("12"), Some(12_000_000))]
+    #[test_case(Some("big"), None)]
+    #[test_case(None, None)]
+    pub fn test_download_size_bytes(size_mb: Option<&str>, expected: Option<u64>) {
+        let download = DownloadData {
+            size_mb: size_mb.map(str::to_owned),
+            description: String::new(),
+            encoding_name: String::new(),
+            url: String::new(),
+        };
+        assert_eq!(download.size_bytes(), expected);
+    }
+
+    #[test]
+    pub fn test_parse_tralbum_data() {
+        let tralbum: TralbumData = serde_json::from_value(serde_json::json!({
+            "id": 1_234_567,
+            "item_type": "album",
+            "artist": "Anomalie",
+            "url": "https://anomalie.bandcamp.com/album/galerie",
+            "art_id": 7_654_321,
+            "album_release_date": "01 Apr 2024 00:00:00 GMT",
+            "current": {"title": "Galerie", "release_date": "29 Mar 2024 00:00:00 GMT", "about": null},
+            "trackinfo": [
+                {"title": "Odyssée", "track_id": 11, "track_num": 1, "duration": 201.5, "title_link": "/track/odyss-e", "file": {"mp3-128": "https://t4.bcbits.com/stream/1"}},
+                {"title": "Velours", "track_id": 12, "track_num": 2, "duration": 180.25, "file": null},
+                {"title": "Unknown length", "duration": null}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(tralbum.lookup_key().as_deref(), Some("a1234567"));
+        assert_eq!(
+            tralbum.release_date(),
+            Some(Date::from_calendar_date(2024, Month::April, 1).unwrap())
+        );
+        assert_eq!(tralbum.total_duration(), Duration::from_millis(381_750));
+        assert_eq!(
+            tralbum.trackinfo[0].mp3_128_url().as_deref(),
+            Some("https://t4.bcbits.com/stream/1")
+        );
+        assert_eq!(tralbum.trackinfo[1].mp3_128_url(), None);
+    }
+
+    #[test]
+    pub fn test_parse_invalid_track_duration() {
+        for duration in [-1.0, 1e30] {
+            let track = serde_json::from_value::<TrackInfo>(
+                serde_json::json!({"title": "Odyssée", "duration": duration}),
+            );
+            assert!(track.is_err(), "{duration} was taken as a duration");
+        }
+    }
+
+    #[test]
+    pub fn test_release_year_from_download_page() {
+        let data = include_str!("../data/fake/parse-example.json");
+        let parsed: ParsedBandcampData = serde_json::from_str(data).unwrap();
+
+        assert_eq!(parsed.digital_items[0].release_year(), Some(2024));
+    }
+}
@@ -0,0 +1,128 @@
+use reqwest::{StatusCode, Url};
+
+const API_BASE_URL: &str = "https://bandcamp.com/api";
+
+pub const COLLECTION_SUMMARY_VERSION: u32 = 2;
+
+// Newest first. Versions that turn out to be missing or unreadable are skipped, and the oldest one
+// is kept as the known working fallback.
+pub const FANCOLLECTION_VERSIONS: &[u32] = &[2, 1];
+
+pub fn collection_summary_url() -> String {
+    format!("{API_BASE_URL}/fan/{COLLECTION_SUMMARY_VERSION}/collection_summary")
+}
+
+pub fn fancollection_url(version: u32, collection_name: &str) -> String {
+    format!("{API_BASE_URL}/fancollection/{version}/{collection_name}")
+}
+
+// A fan's public page, from its url or the username in it
+pub fn fan_page_url(fan: &str) -> String {
+    if fan.starts_with("https://") || fan.starts_with("http://") {
+        fan.to_owned()
+    } else {
+        format!("https://bandcamp.com/{}", fan.trim_matches('/'))
+    }
+}
+
+// The search box of Bandcamp's pages, which answers without a session
+pub fn search_url() -> String {
+    format!("{API_BASE_URL}/bcsearch_public_api/1/autocomplete_elastic")
+}
+
+// Cover art on Bandcamp's image server, e.g. format 0 for the original upload
+pub fn art_url(art_id: i64, image_format: u32) -> String {
+    format!("https://f4.bcbits.com/img/a{art_id:010}_{image_format}.jpg")
+}
+
+// Statuses of an API version that doesn't exist (anymore), rather than of a failing request
+pub fn is_unavailable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::METHOD_NOT_ALLOWED
+    )
+}
+
+// Groups requests for metrics by what they're for, rather than by their exact url
+pub fn endpoint_class(url: &Url) -> &'static str {
+    let host = url.host_str().unwrap_or_default();
+    let path = url.path();
+    if host.ends_with("bcbits.com") {
+        "stream"
+    } else if path.starts_with("/api/fan/") {
+        "collection_summary"
+    } else if path.starts_with("/api/fancollection/") {
+        "fancollection"
+    } else if path.starts_with("/statdownload/") {
+        "stat_download"
+    } else if path.starts_with("/download") {
+        "download_page"
+    } else if path.starts_with("/album/") || path.starts_with("/track/") {
+        "item_page"
+    } else {
+        "other"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_urls() {
+        assert_eq!(
+            collection_summary_url(),
+            "https://bandcamp.com/api/fan/2/collection_summary"
+        );
+        assert_eq!(
+            fancollection_url(1, "hidden_items"),
+            "https://bandcamp.com/api/fancollection/1/hidden_items"
+        );
+        assert_eq!(
+            search_url(),
+            "https://bandcamp.com/api/bcsearch_public_api/1/autocomplete_elastic"
+        );
+        assert_eq!(fan_page_url("someone"), "https://bandcamp.com/someone");
+        assert_eq!(
+            fan_page_url("https://bandcamp.com/someone"),
+            "https://bandcamp.com/someone"
+        );
+        assert_eq!(
+            art_url(1_234_567, 10),
+            "https://f4.bcbits.com/img/a0001234567_10.jpg"
+        );
+    }
+
+    #[test]
+    pub fn test_endpoint_class() {
+        let class = |url: &str| endpoint_class(&Url::parse(url).unwrap());
+
+        assert_eq!(class(&collection_summary_url()), "collection_summary");
+        assert_eq!(
+            class(&fancollection_url(1, "collection_items")),
+            "fancollection"
+        );
+        assert_eq!(
+            class("https://bandcamp.com/download?id=1&sig=abc"),
+            "download_page"
+        );
+        assert_eq!(
+            class("https://popplers5.bandcamp.com/statdownload/album?id=1&.vrs=1"),
+            "stat_download"
+        );
+        assert_eq!(
+            class("https://anomalie.bandcamp.com/album/galerie"),
+            "item_page"
+        );
+        assert_eq!(class("https://t4.bcbits.com/stream/1"), "stream");
+        assert_eq!(class("https://bandcamp.com/fan"), "other");
+    }
+
+    #[test]
+    pub fn test_is_unavailable() {
+        assert!(is_unavailable(StatusCode::NOT_FOUND));
+        assert!(is_unavailable(StatusCode::GONE));
+        assert!(!is_unavailable(StatusCode::OK));
+        assert!(!is_unavailable(StatusCode::TOO_MANY_REQUESTS));
+    }
+}
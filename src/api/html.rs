@@ -0,0 +1,154 @@
+use std::borrow::Cow;
+
+// Elements whose contents are text rather than markup, so a "<div" in a script isn't a tag
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+// A start tag, with its attribute values as they're written in the page
+#[derive(Debug, PartialEq, Eq)]
+pub struct StartTag<'a> {
+    pub name: &'a str,
+    attributes: Vec<(&'a str, &'a str)>,
+}
+
+impl StartTag<'_> {
+    // The value of an attribute, with its character references unescaped. Names are compared
+    // case insensitively, as in HTML.
+    pub fn attribute(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, value)| htmlize::unescape(*value))
+    }
+}
+
+// The start tags of a page, in order. Only as much of HTML is understood as finding attributes
+// needs: values may be double, single or not quoted, and hold any character but their own quote,
+// including backslashes and ">". Comments and the contents of scripts are skipped.
+pub fn start_tags(page: &str) -> impl Iterator<Item = StartTag<'_>> {
+    let mut rest = page;
+    std::iter::from_fn(move || loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+
+        let (tag, after) = parse_start_tag(rest);
+        rest = after;
+        if RAW_TEXT_ELEMENTS
+            .iter()
+            .any(|element| element.eq_ignore_ascii_case(tag.name))
+        {
+            rest = skip_raw_text(rest, tag.name);
+        }
+        return Some(tag);
+    })
+}
+
+// `tag` starts right after the "<". Returns the tag and what follows its ">".
+fn parse_start_tag(tag: &str) -> (StartTag<'_>, &str) {
+    let name_end = tag
+        .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+        .unwrap_or(tag.len());
+    let name = &tag[..name_end];
+    let mut rest = &tag[name_end..];
+    let mut attributes = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+
+        let attribute_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len());
+        let attribute = &rest[..attribute_end];
+        rest = rest[attribute_end..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+        let mut value = "";
+        if let Some(after) = rest.strip_prefix('=') {
+            (value, rest) = parse_value(after.trim_start());
+        }
+        attributes.push((attribute, value));
+    }
+
+    (StartTag { name, attributes }, rest)
+}
+
+// Returns the raw value and what follows it
+fn parse_value(value: &str) -> (&str, &str) {
+    if let Some(quote @ ('"' | '\'')) = value.chars().next() {
+        let value = &value[1..];
+        return value
+            .find(quote)
+            .map_or((value, ""), |end| (&value[..end], &value[end + 1..]));
+    }
+
+    let end = value
+        .find(|c: char| c.is_ascii_whitespace() || c == '>')
+        .unwrap_or(value.len());
+    value.split_at(end)
+}
+
+fn skip_raw_text<'a>(text: &'a str, element: &str) -> &'a str {
+    let closing = format!("</{}", element.to_ascii_lowercase());
+    text.to_ascii_lowercase()
+        .find(&closing)
+        .map_or("", |end| &text[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn attribute(page: &str, tag: &str, name: &str) -> Option<String> {
+        start_tags(page)
+            .find(|start_tag| start_tag.name == tag)
+            .and_then(|start_tag| start_tag.attribute(name).map(Cow::into_owned))
+    }
+
+    #[test_case(r#"<div data-blob="{&quot;a&quot;:1}">"#, r#"{"a":1}"#; "double quoted")]
+    #[test_case(r"<div data-blob='{&quot;a&quot;:&#39;b&#39;}'>", r#"{"a":'b'}"#; "single quoted")]
+    #[test_case("<div data-blob=plain>", "plain"; "unquoted")]
+    #[test_case(r#"<div DATA-BLOB="x">"#, "x"; "uppercase name")]
+    #[test_case(r#"<div
+        data-blob = "x" >"#, "x"; "spaced")]
+    #[test_case(r#"<div hidden data-blob="x"/>"#, "x"; "after a bare attribute")]
+    #[test_case(r#"<div data-blob="a > b">"#, "a > b"; "angle bracket in value")]
+    pub fn test_attribute(page: &str, expected: &str) {
+        assert_eq!(
+            attribute(page, "div", "data-blob").as_deref(),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    pub fn test_skips_comments_and_scripts() {
+        let page = r#"<!-- <div id="old"> --><script>if (a<b) document.write('<div id="script">')</script><div id="real">"#;
+        let ids: Vec<_> = start_tags(page)
+            .filter_map(|tag| tag.attribute("id").map(Cow::into_owned))
+            .collect();
+        assert_eq!(ids, ["real"]);
+    }
+
+    #[test]
+    pub fn test_unterminated() {
+        assert_eq!(
+            attribute(r#"<div data-blob="x"#, "div", "data-blob").as_deref(),
+            Some("x")
+        );
+        assert_eq!(start_tags("<div").count(), 1);
+        assert_eq!(start_tags("a < b").count(), 0);
+    }
+}
@@ -0,0 +1,751 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{error::ExtractionError, layout};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    SevenZip,
+}
+
+impl fmt::Display for ArchiveKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::SevenZip => "7z",
+        })
+    }
+}
+
+// Enough of the file to find the magic bytes of every known kind, the tar one being the furthest in
+const SNIFF_LENGTH: usize = 262;
+
+impl ArchiveKind {
+    // Going by the contents rather than the extension, as downloads are always saved as ".zip"
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if header.starts_with(b"7z\xbc\xaf\x27\x1c") {
+            Some(Self::SevenZip)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+pub trait Extractor: Send + Sync {
+    fn kind(&self) -> ArchiveKind;
+
+    // Returns the extracted files. Entries that would land outside of `destination` are skipped,
+    // and the names of the others are made writable on every platform.
+    fn extract(&self, archive: &Path, destination: &Path) -> Result<Vec<PathBuf>, ExtractionError>;
+
+    // The files `extract` would write, relative to its destination, without extracting anything
+    fn entries(&self, archive: &Path) -> Result<Vec<PathBuf>, ExtractionError>;
+}
+
+// The formats Bandcamp offers, by extension
+const AUDIO_EXTENSIONS: &[&str] = &["flac", "mp3", "m4a", "aac", "ogg", "wav", "aif", "aiff"];
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            AUDIO_EXTENSIONS
+                .iter()
+                .any(|audio| audio.eq_ignore_ascii_case(extension))
+        })
+}
+
+// The audio files anywhere under `folder`
+pub fn audio_files(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = walk_files(folder, false)?;
+    files.retain(|path| is_audio_file(path));
+    Ok(files)
+}
+
+// The files anywhere under a download folder, leaving out its hidden folders, such as the state
+// and staging folders
+pub fn visible_files(folder: &Path) -> io::Result<Vec<PathBuf>> {
+    walk_files(folder, true)
+}
+
+fn walk_files(folder: &Path, skip_hidden: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut folders = vec![folder.to_owned()];
+    while let Some(folder) = folders.pop() {
+        for entry in std::fs::read_dir(&folder)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if !skip_hidden || !entry.file_name().to_string_lossy().starts_with('.') {
+                    folders.push(path);
+                }
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[derive(Default)]
+pub struct ZipExtractor {
+    // transliterate the entries' names, as for --ascii-filenames
+    pub ascii_filenames: bool,
+}
+
+impl Extractor for ZipExtractor {
+    fn kind(&self) -> ArchiveKind {
+        ArchiveKind::Zip
+    }
+
+    fn extract(&self, archive: &Path, destination: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+        let mut archive = zip::ZipArchive::new(File::open(archive)?)?;
+
+        let mut extracted = Vec::new();
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            let Some(entry_path) = entry
+                .enclosed_name()
+                .and_then(|path| layout::sanitize_relative_path(&path, self.ascii_filenames))
+            else {
+                continue;
+            };
+            let output_path = destination.join(entry_path);
+
+            if entry.is_dir() {
+                std::fs::create_dir_all(&output_path)?;
+                continue;
+            }
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut entry, &mut File::create(&output_path)?)?;
+            extracted.push(output_path);
+        }
+
+        Ok(extracted)
+    }
+
+    fn entries(&self, archive: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+        let mut archive = zip::ZipArchive::new(File::open(archive)?)?;
+
+        let mut entries = Vec::new();
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+            if let Some(entry_path) = entry
+                .enclosed_name()
+                .and_then(|path| layout::sanitize_relative_path(&path, self.ascii_filenames))
+            {
+                entries.push(entry_path);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(feature = "tar")]
+#[derive(Default)]
+pub struct TarExtractor {
+    pub ascii_filenames: bool,
+}
+
+#[cfg(feature = "tar")]
+impl Extractor for TarExtractor {
+    fn kind(&self) -> ArchiveKind {
+        ArchiveKind::Tar
+    }
+
+    fn extract(&self, archive: &Path, destination: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+        let mut archive = tar::Archive::new(File::open(archive)?);
+
+        let mut extracted = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_type = entry.header().entry_type();
+            // links could point anywhere, and releases have no use for them
+            if !entry_type.is_file() && !entry_type.is_dir() {
+                continue;
+            }
+            let Some(entry_path) =
+                layout::sanitize_relative_path(&entry.path()?, self.ascii_filenames)
+            else {
+                continue;
+            };
+            let output_path = destination.join(entry_path);
+
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&output_path)?;
+            if entry_type.is_file() {
+                extracted.push(output_path);
+            }
+        }
+
+        Ok(extracted)
+    }
+
+    fn entries(&self, archive: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+        let mut archive = tar::Archive::new(File::open(archive)?);
+
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            if let Some(entry_path) =
+                layout::sanitize_relative_path(&entry.path()?, self.ascii_filenames)
+            {
+                entries.push(entry_path);
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+// What to do with a file an updated archive has in a different version than the one on disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpdatePolicy {
+    // Replace it with the archive's version
+    #[default]
+    ReplaceChanged,
+    // Keep the one on disk, e.g. for libraries whose tags were edited afterwards
+    KeepExisting,
+}
+
+// The files an updated archive brought in, relative to the destination. Files on disk that the
+// archive doesn't have, such as lyrics added since, are always left alone.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UpdateReport {
+    pub added: Vec<PathBuf>,
+    pub replaced: Vec<PathBuf>,
+    pub unchanged: Vec<PathBuf>,
+    // Changed in the archive, but kept as they were on disk by the policy
+    pub kept: Vec<PathBuf>,
+}
+
+impl UpdateReport {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} replaced, {} unchanged, {} kept",
+            self.added.len(),
+            self.replaced.len(),
+            self.unchanged.len(),
+            self.kept.len()
+        )
+    }
+
+    // What the archive left in the folder as it has it, by path within the folder
+    pub fn extracted(&self) -> impl Iterator<Item = &PathBuf> {
+        self.added
+            .iter()
+            .chain(&self.replaced)
+            .chain(&self.unchanged)
+    }
+}
+
+// Extracted next to the files they're compared against, so moving them in is a rename
+pub const UPDATE_SCRATCH_DIR_NAME: &str = ".bandcamp-dl-update";
+
+fn file_crc32(path: &Path) -> io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+// Dispatches archives to the extractor of their kind. Formats beyond the built-in ones can be
+// registered by library users, or added here behind a cargo feature.
+pub struct ExtractorRegistry {
+    extractors: Vec<Box<dyn Extractor>>,
+}
+
+impl Default for ExtractorRegistry {
+    fn default() -> Self {
+        Self::with_ascii_filenames(false)
+    }
+}
+
+impl ExtractorRegistry {
+    // The built-in extractors, transliterating the names of what they extract when asked to
+    pub fn with_ascii_filenames(ascii_filenames: bool) -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(ZipExtractor { ascii_filenames }));
+        #[cfg(feature = "tar")]
+        registry.register(Box::new(TarExtractor { ascii_filenames }));
+        registry
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            extractors: Vec::new(),
+        }
+    }
+
+    // Replaces any extractor previously registered for the same kind
+    pub fn register(&mut self, extractor: Box<dyn Extractor>) {
+        self.extractors.retain(|e| e.kind() != extractor.kind());
+        self.extractors.push(extractor);
+    }
+
+    pub fn supports(&self, kind: ArchiveKind) -> bool {
+        self.extractors.iter().any(|e| e.kind() == kind)
+    }
+
+    pub fn detect(archive: &Path) -> Result<Option<ArchiveKind>, ExtractionError> {
+        let mut header = Vec::with_capacity(SNIFF_LENGTH);
+        File::open(archive)?
+            .take(SNIFF_LENGTH as u64)
+            .read_to_end(&mut header)?;
+        Ok(ArchiveKind::detect(&header))
+    }
+
+    fn extractor_for(&self, kind: ArchiveKind) -> Result<&dyn Extractor, ExtractionError> {
+        self.extractors
+            .iter()
+            .find(|e| e.kind() == kind)
+            .map(AsRef::as_ref)
+            .ok_or(ExtractionError::UnsupportedArchive(kind))
+    }
+
+    pub fn extract(
+        &self,
+        archive: &Path,
+        destination: &Path,
+    ) -> Result<Vec<PathBuf>, ExtractionError> {
+        let kind = Self::detect(archive)?.ok_or(ExtractionError::UnknownArchive)?;
+        self.extractor_for(kind)?.extract(archive, destination)
+    }
+
+    // For a release that was downloaded again after changing on Bandcamp: only the files whose
+    // CRC differs from the one on disk are written, so an unchanged library isn't rewritten
+    pub fn extract_update(
+        &self,
+        archive: &Path,
+        destination: &Path,
+        policy: UpdatePolicy,
+    ) -> Result<UpdateReport, ExtractionError> {
+        let scratch_dir = destination.join(UPDATE_SCRATCH_DIR_NAME);
+        if scratch_dir.exists() {
+            std::fs::remove_dir_all(&scratch_dir)?;
+        }
+
+        let result = self
+            .extract(archive, &scratch_dir)
+            .and_then(|extracted| Self::merge_update(&scratch_dir, destination, extracted, policy));
+        let _ = std::fs::remove_dir_all(&scratch_dir);
+        result
+    }
+
+    fn merge_update(
+        scratch_dir: &Path,
+        destination: &Path,
+        extracted: Vec<PathBuf>,
+        policy: UpdatePolicy,
+    ) -> Result<UpdateReport, ExtractionError> {
+        let mut report = UpdateReport::default();
+        for extracted_path in extracted {
+            let Ok(relative_path) = extracted_path.strip_prefix(scratch_dir) else {
+                continue;
+            };
+            let relative_path = relative_path.to_owned();
+            let target_path = destination.join(&relative_path);
+
+            if !target_path.exists() {
+                if let Some(parent) = target_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::rename(&extracted_path, &target_path)?;
+                report.added.push(relative_path);
+            } else if file_crc32(&extracted_path)? == file_crc32(&target_path)? {
+                report.unchanged.push(relative_path);
+            } else if policy == UpdatePolicy::ReplaceChanged {
+                std::fs::rename(&extracted_path, &target_path)?;
+                report.replaced.push(relative_path);
+            } else {
+                report.kept.push(relative_path);
+            }
+        }
+        Ok(report)
+    }
+
+    // Unpacks a downloaded release into its own folder, and checks every file of the archive made
+    // it there. A folder left by an earlier extraction is updated in place, while a new one only
+    // has files added. Single tracks aren't archives, and give None.
+    pub fn extract_release(
+        &self,
+        download: &Path,
+        folder: &Path,
+    ) -> Result<Option<UpdateReport>, ExtractionError> {
+        let Some(kind) = Self::detect(download)? else {
+            return Ok(None);
+        };
+        let expected = self.extractor_for(kind)?.entries(download)?.len();
+        let report = if folder.exists() {
+            self.extract_update(download, folder, UpdatePolicy::ReplaceChanged)?
+        } else {
+            let added = self
+                .extract(download, folder)?
+                .iter()
+                .filter_map(|path| path.strip_prefix(folder).ok())
+                .map(Path::to_owned)
+                .collect();
+            UpdateReport {
+                added,
+                ..UpdateReport::default()
+            }
+        };
+
+        let extracted = report.extracted().count() + report.kept.len();
+        if extracted == expected {
+            Ok(Some(report))
+        } else {
+            Err(ExtractionError::Incomplete {
+                extracted,
+                expected,
+            })
+        }
+    }
+
+    // Single track releases are downloaded as the audio file itself rather than an archive, under
+    // the same ".zip" name
+    pub fn count_tracks(&self, download: &Path) -> Result<usize, ExtractionError> {
+        Ok(self.tracks(download)?.len())
+    }
+
+    // The audio files of a download, by their path in the archive. A single track is listed by the
+    // name it was downloaded under.
+    pub fn tracks(&self, download: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+        match Self::detect(download)? {
+            Some(kind) => Ok(self
+                .extractor_for(kind)?
+                .entries(download)?
+                .into_iter()
+                .filter(|entry| is_audio_file(entry))
+                .collect()),
+            None => Ok(download
+                .file_name()
+                .map(PathBuf::from)
+                .into_iter()
+                .collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::io::Write;
+    use test_case::test_case;
+
+    fn tar_header() -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    #[test_case(b"PK\x03\x04\x14\x00", Some(ArchiveKind::Zip); "zip")]
+    #[test_case(b"PK\x05\x06\x00\x00", Some(ArchiveKind::Zip); "empty zip")]
+    #[test_case(b"7z\xbc\xaf\x27\x1c\x00\x04", Some(ArchiveKind::SevenZip); "7z")]
+    #[test_case(b"fLaC\x00\x00\x00\x22", None; "flac")]
+    #[test_case(b"", None; "empty")]
+    pub fn test_detect(header: &[u8], expected: Option<ArchiveKind>) {
+        assert_eq!(ArchiveKind::detect(header), expected);
+    }
+
+    #[test]
+    pub fn test_detect_tar() {
+        assert_eq!(ArchiveKind::detect(&tar_header()), Some(ArchiveKind::Tar));
+    }
+
+    #[test]
+    pub fn test_extract_zip_regardless_of_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p199396767-flac.tar");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        writer
+            .start_file("Anomalie - Galerie - 01 Odyssée.flac", options)
+            .unwrap();
+        writer.write_all(b"fLaC").unwrap();
+        writer.start_file("../escaped.flac", options).unwrap();
+        writer.write_all(b"fLaC").unwrap();
+        writer.finish().unwrap();
+
+        let destination = dir.path().join("Galerie");
+        let extracted = ExtractorRegistry::default()
+            .extract(&archive_path, &destination)
+            .unwrap();
+
+        assert_eq!(
+            extracted,
+            [destination.join("Anomalie - Galerie - 01 Odyssée.flac")]
+        );
+        assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"fLaC");
+        assert!(!dir.path().join("escaped.flac").exists());
+    }
+
+    #[test]
+    pub fn test_count_tracks() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p199396767-(flac).zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for name in ["01 Odyssée.flac", "02 Velours.FLAC", "cover.jpg"] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(b"fLaC").unwrap();
+        }
+        writer.add_directory("scans", options).unwrap();
+        writer.finish().unwrap();
+
+        let registry = ExtractorRegistry::default();
+        assert_eq!(registry.count_tracks(&archive_path).unwrap(), 2);
+
+        let track_path = dir.path().join("t123-(mp3-320).zip");
+        std::fs::write(&track_path, b"ID3").unwrap();
+        assert_eq!(registry.count_tracks(&track_path).unwrap(), 1);
+    }
+
+    #[test]
+    pub fn test_extract_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p199396767-(flac).zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, contents) in [
+            ("01 Odyssée.flac", b"fLaC 1"),
+            ("02 Velours.flac", b"fLaC 2"),
+            ("03 Galerie.flac", b"fLaC 3"),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let destination = dir.path().join("Galerie");
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("01 Odyssée.flac"), b"fLaC 1").unwrap();
+        std::fs::write(destination.join("02 Velours.flac"), b"fLaC 0").unwrap();
+        std::fs::write(destination.join("lyrics.txt"), b"la la").unwrap();
+
+        let registry = ExtractorRegistry::default();
+        let report = registry
+            .extract_update(&archive_path, &destination, UpdatePolicy::KeepExisting)
+            .unwrap();
+        assert_eq!(report.added, [PathBuf::from("03 Galerie.flac")]);
+        assert_eq!(report.kept, [PathBuf::from("02 Velours.flac")]);
+        assert_eq!(report.summary(), "1 added, 0 replaced, 1 unchanged, 1 kept");
+        assert_eq!(
+            std::fs::read(destination.join("02 Velours.flac")).unwrap(),
+            b"fLaC 0"
+        );
+
+        let report = registry
+            .extract_update(&archive_path, &destination, UpdatePolicy::ReplaceChanged)
+            .unwrap();
+        assert_eq!(report.replaced, [PathBuf::from("02 Velours.flac")]);
+        assert_eq!(report.unchanged.len(), 2);
+        assert_eq!(
+            std::fs::read(destination.join("02 Velours.flac")).unwrap(),
+            b"fLaC 2"
+        );
+        assert!(destination.join("lyrics.txt").exists());
+        assert!(!destination.join(UPDATE_SCRATCH_DIR_NAME).exists());
+    }
+
+    #[test]
+    pub fn test_extract_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p199396767-(flac).zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for name in ["01 Odyssée.flac", "02 Velours.flac", "cover.jpg"] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(b"fLaC").unwrap();
+        }
+        writer.add_directory("scans", options).unwrap();
+        writer.finish().unwrap();
+
+        let registry = ExtractorRegistry::default();
+        let folder = dir.path().join("Anomalie").join("Galerie");
+        let report = registry
+            .extract_release(&archive_path, &folder)
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.added.len(), 3);
+        assert!(report.added.contains(&PathBuf::from("cover.jpg")));
+        assert!(folder.join("cover.jpg").is_file());
+
+        // downloaded again, into the folder extracted before
+        std::fs::write(folder.join("lyrics.txt"), b"la la").unwrap();
+        let report = registry
+            .extract_release(&archive_path, &folder)
+            .unwrap()
+            .unwrap();
+        assert_eq!((report.added.len(), report.unchanged.len()), (0, 3));
+
+        let track_path = dir.path().join("t123-(mp3-320).zip");
+        std::fs::write(&track_path, b"ID3").unwrap();
+        assert!(registry
+            .extract_release(&track_path, &folder)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_visible_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in [
+            "Anomalie/Galerie/01 Velours.flac",
+            "Anomalie/Galerie/cover.jpg",
+            ".bandcamp-dl/staging/p1-(flac).zip",
+        ] {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+
+        let mut visible = visible_files(dir.path()).unwrap();
+        visible.sort();
+        assert_eq!(
+            visible,
+            [
+                dir.path().join("Anomalie/Galerie/01 Velours.flac"),
+                dir.path().join("Anomalie/Galerie/cover.jpg"),
+            ]
+        );
+        assert_eq!(audio_files(dir.path()).unwrap().len(), 1);
+        assert_eq!(
+            audio_files(&dir.path().join(".bandcamp-dl")).unwrap(),
+            Vec::<PathBuf>::new()
+        );
+    }
+
+    #[test]
+    pub fn test_entries_match_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p199396767-(flac).zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        // the last two have no name left once sanitized, or would escape the folder
+        for name in ["01 Odyssée: Part I?.flac", " ", "../escape.flac"] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(b"fLaC").unwrap();
+        }
+        writer.finish().unwrap();
+
+        let extractor = ZipExtractor::default();
+        assert_eq!(
+            extractor.entries(&archive_path).unwrap(),
+            [PathBuf::from("01 Odyssée_ Part I_.flac")]
+        );
+        let report = ExtractorRegistry::default()
+            .extract_release(&archive_path, &dir.path().join("Galerie"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.added, [PathBuf::from("01 Odyssée_ Part I_.flac")]);
+    }
+
+    #[test]
+    pub fn test_extract_sanitizes_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p199396767-(flac).zip");
+
+        let mut writer = zip::ZipWriter::new(File::create(&archive_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for name in ["01 Odyssée: Part I?.flac", "AUX.flac", "../escape.flac"] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(b"fLaC").unwrap();
+        }
+        writer.finish().unwrap();
+
+        let destination = dir.path().join("Galerie");
+        let mut extracted = ExtractorRegistry::with_ascii_filenames(true)
+            .extract(&archive_path, &destination)
+            .unwrap();
+        extracted.sort();
+        assert_eq!(
+            extracted,
+            [
+                destination.join("01 Odyssee_ Part I_.flac"),
+                destination.join("_AUX.flac")
+            ]
+        );
+        assert!(!dir.path().join("escape.flac").exists());
+    }
+
+    #[test]
+    pub fn test_extract_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("p1-flac.zip");
+
+        std::fs::write(&archive_path, b"7z\xbc\xaf\x27\x1c\x00\x04").unwrap();
+        assert_matches!(
+            ExtractorRegistry::default().extract(&archive_path, dir.path()),
+            Err(ExtractionError::UnsupportedArchive(ArchiveKind::SevenZip))
+        );
+
+        std::fs::write(&archive_path, b"<html>").unwrap();
+        assert_matches!(
+            ExtractorRegistry::default().extract(&archive_path, dir.path()),
+            Err(ExtractionError::UnknownArchive)
+        );
+    }
+
+    #[test]
+    pub fn test_register_replaces_kind() {
+        struct NoopZipExtractor;
+        impl Extractor for NoopZipExtractor {
+            fn kind(&self) -> ArchiveKind {
+                ArchiveKind::Zip
+            }
+
+            fn extract(&self, _: &Path, _: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+                Ok(Vec::new())
+            }
+
+            fn entries(&self, _: &Path) -> Result<Vec<PathBuf>, ExtractionError> {
+                Ok(Vec::new())
+            }
+        }
+
+        let mut registry = ExtractorRegistry::empty();
+        assert!(!registry.supports(ArchiveKind::Zip));
+
+        registry.register(Box::new(ZipExtractor::default()));
+        registry.register(Box::new(NoopZipExtractor));
+        assert!(registry.supports(ArchiveKind::Zip));
+        assert_eq!(registry.extractors.len(), 1);
+    }
+}
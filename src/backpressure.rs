@@ -0,0 +1,281 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::staging;
+
+// Written to the download folder to see how fast it takes data, named like a partial download so
+// `clean` removes it should a run stop halfway through
+pub const PROBE_FILE_NAME: &str = ".bandcamp-dl-write-probe.part";
+
+const PROBE_BYTES: usize = 4 * 1024 * 1024;
+
+// Filesystem types of /proc/self/mounts whose data goes over the network
+const NETWORK_FILESYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "9p",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+    "fuse.glusterfs",
+];
+
+// The type of the filesystem `path` is on when it's a network one, such as "cifs" for an SMB share
+#[cfg(target_os = "linux")]
+pub fn network_filesystem(path: &Path) -> Option<String> {
+    let path = std::fs::canonicalize(path).ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+    mount_filesystem(&mounts, &path)
+        .filter(|filesystem| NETWORK_FILESYSTEMS.contains(filesystem))
+        .map(str::to_owned)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn network_filesystem(_: &Path) -> Option<String> {
+    None
+}
+
+// The type of the innermost mount holding `path`, from a mount table like /proc/self/mounts, where
+// spaces in mount points are written as "\040"
+fn mount_filesystem<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let filesystem = fields.next()?;
+            path.starts_with(&mount_point)
+                .then(|| (Path::new(&mount_point).components().count(), filesystem))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, filesystem)| filesystem)
+}
+
+// Bytes per second `folder` takes a small file at, flushed to the disk
+pub fn measure_write_rate(folder: &Path) -> io::Result<f64> {
+    std::fs::create_dir_all(folder)?;
+    let probe_path = folder.join(PROBE_FILE_NAME);
+    let started = Instant::now();
+    let result = std::fs::File::create(&probe_path).and_then(|mut probe| {
+        probe.write_all(&vec![0; PROBE_BYTES])?;
+        probe.sync_all()
+    });
+    let elapsed = started.elapsed();
+    let _ = std::fs::remove_file(&probe_path);
+    result?;
+
+    let mut rate = Rate::default();
+    rate.record(PROBE_BYTES as u64, elapsed);
+    Ok(rate.bytes_per_second().unwrap_or(f64::INFINITY))
+}
+
+// Bytes moved over some time, in total
+#[derive(Clone, Copy, Debug, Default)]
+struct Rate {
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Rate {
+    fn record(&mut self, bytes: u64, elapsed: Duration) {
+        self.bytes += bytes;
+        self.elapsed += elapsed;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn bytes_per_second(&self) -> Option<f64> {
+        let seconds = self.elapsed.as_secs_f64();
+        (self.bytes > 0 && seconds > 0.0).then(|| self.bytes as f64 / seconds)
+    }
+}
+
+// Completed downloads waiting in staging to be written to a download folder on another
+// filesystem. Downloading more waits while they'd add up to more than `max_queued_bytes`, rather
+// than piling up in staging when the download folder is slower than the network. For a
+// network-mounted download folder, fewer releases are also downloaded at once while it writes
+// slower than they come in.
+#[derive(Debug)]
+pub struct WriterQueue {
+    // no limit when committing is a rename, which keeps up with anything
+    max_queued_bytes: Option<u64>,
+    queued_bytes: u64,
+    network_filesystem: Option<String>,
+    downloaded: Rate,
+    written: Rate,
+    // from a probe, until some releases were written
+    probed_write_rate: Option<f64>,
+}
+
+impl WriterQueue {
+    pub fn unbounded() -> Self {
+        Self {
+            max_queued_bytes: None,
+            queued_bytes: 0,
+            network_filesystem: None,
+            downloaded: Rate::default(),
+            written: Rate::default(),
+            probed_write_rate: None,
+        }
+    }
+
+    // Only a network mount is probed, as only its write rate changes how many releases are
+    // downloaded at once
+    pub fn for_destination(destination: &Path, staging_dir: &Path, max_queued_bytes: u64) -> Self {
+        if staging::same_filesystem(staging_dir, destination) != Some(false) {
+            return Self::unbounded();
+        }
+
+        let network_filesystem = network_filesystem(destination);
+        Self {
+            max_queued_bytes: Some(max_queued_bytes),
+            probed_write_rate: network_filesystem
+                .as_ref()
+                .and_then(|_| measure_write_rate(destination).ok()),
+            network_filesystem,
+            ..Self::unbounded()
+        }
+    }
+
+    pub fn network_filesystem(&self) -> Option<&str> {
+        self.network_filesystem.as_deref()
+    }
+
+    pub fn write_rate(&self) -> Option<f64> {
+        self.written.bytes_per_second().or(self.probed_write_rate)
+    }
+
+    pub fn record_downloaded(&mut self, bytes: u64, elapsed: Duration) {
+        self.downloaded.record(bytes, elapsed);
+        self.queued_bytes += bytes;
+    }
+
+    pub fn record_written(&mut self, bytes: u64, elapsed: Duration) {
+        self.written.record(bytes, elapsed);
+        self.queued_bytes = self.queued_bytes.saturating_sub(bytes);
+    }
+
+    pub const fn queued_bytes(&self) -> u64 {
+        self.queued_bytes
+    }
+
+    // Whether `bytes` more can be downloaded without going over the limit. An empty queue always
+    // has room, so a release larger than the limit is downloaded on its own.
+    pub fn has_room(&self, bytes: u64) -> bool {
+        self.max_queued_bytes.is_none_or(|max_queued_bytes| {
+            self.queued_bytes == 0 || self.queued_bytes.saturating_add(bytes) <= max_queued_bytes
+        })
+    }
+
+    // Of at most `most` releases downloaded at once, as many as the download folder keeps up with
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn batch_size(&self, most: usize) -> usize {
+        if self.network_filesystem.is_none() {
+            return most;
+        }
+        match (self.write_rate(), self.downloaded.bytes_per_second()) {
+            (Some(write_rate), Some(download_rate)) if write_rate < download_rate => {
+                ((most as f64 * write_rate / download_rate).ceil() as usize).clamp(1, most)
+            }
+            _ => most,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTS: &str = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+tmpfs /tmp tmpfs rw,nosuid,nodev 0 0
+//nas/music /mnt/nas\\040music cifs rw,relatime,vers=3.1.1 0 0
+nas:/export/backup /mnt/nas\\040music/backup nfs4 rw,relatime 0 0
+";
+
+    fn network_queue(max_queued_bytes: u64) -> WriterQueue {
+        WriterQueue {
+            max_queued_bytes: Some(max_queued_bytes),
+            network_filesystem: Some("cifs".to_owned()),
+            ..WriterQueue::unbounded()
+        }
+    }
+
+    #[test]
+    pub fn test_mount_filesystem() {
+        assert_eq!(
+            mount_filesystem(MOUNTS, Path::new("/mnt/nas music/Anomalie")),
+            Some("cifs")
+        );
+        assert_eq!(
+            mount_filesystem(MOUNTS, Path::new("/mnt/nas music/backup/2021")),
+            Some("nfs4")
+        );
+        assert_eq!(
+            mount_filesystem(MOUNTS, Path::new("/mnt/nas")),
+            Some("ext4")
+        );
+        assert_eq!(mount_filesystem("", Path::new("/tmp")), None);
+    }
+
+    #[test]
+    pub fn test_has_room() {
+        let mut queue = network_queue(100);
+        assert!(queue.has_room(500));
+        queue.record_downloaded(60, Duration::from_secs(1));
+        assert!(queue.has_room(40));
+        assert!(!queue.has_room(41));
+        queue.record_written(30, Duration::from_secs(1));
+        assert!(queue.has_room(70));
+        queue.record_written(30, Duration::from_secs(1));
+        assert_eq!(queue.queued_bytes(), 0);
+        assert!(queue.has_room(u64::MAX));
+
+        let mut unbounded = WriterQueue::unbounded();
+        unbounded.record_downloaded(u64::MAX, Duration::from_secs(1));
+        assert!(unbounded.has_room(u64::MAX));
+    }
+
+    #[test]
+    pub fn test_batch_size() {
+        let mut queue = network_queue(100);
+        assert_eq!(queue.batch_size(8), 8);
+
+        // writing at a quarter of the download rate
+        queue.record_downloaded(400, Duration::from_secs(1));
+        queue.record_written(100, Duration::from_secs(1));
+        assert_eq!(queue.batch_size(8), 2);
+
+        // far slower still, and never less than one at a time
+        queue.record_written(0, Duration::from_secs(100));
+        assert_eq!(queue.batch_size(8), 1);
+
+        let mut local = WriterQueue {
+            network_filesystem: None,
+            ..network_queue(100)
+        };
+        local.record_downloaded(400, Duration::from_secs(1));
+        local.record_written(100, Duration::from_secs(1));
+        assert_eq!(local.batch_size(8), 8);
+    }
+
+    #[test]
+    pub fn test_measure_write_rate() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(measure_write_rate(dir.path()).unwrap() > 0.0);
+        assert!(!dir.path().join(PROBE_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap};
+
+use time::Date;
+
+use crate::{api::data::CollectionItem, cache};
+
+// A band whose releases were bought under more than one name, as artists renaming themselves keep
+// their band id
+#[derive(Debug, PartialEq, Eq)]
+pub struct BandRename {
+    pub band_id: i64,
+    // each name with its number of releases, the one most recently bought from last
+    pub names: Vec<(String, usize)>,
+}
+
+impl BandRename {
+    pub fn current_name(&self) -> &str {
+        self.names.last().map_or("", |(name, _)| name)
+    }
+
+    pub fn describe(&self) -> String {
+        let names: Vec<_> = self
+            .names
+            .iter()
+            .map(|(name, releases)| format!("\"{name}\" ({releases} release(s))"))
+            .collect();
+        format!(
+            "Band {} was bought from as {}. Consider merging their folders into \"{}\"",
+            self.band_id,
+            names.join(", "),
+            self.current_name()
+        )
+    }
+}
+
+// By band id, with the names sorted by when they were last bought from. Items without a purchase
+// date count as the oldest.
+pub fn find_renames(items: &[CollectionItem]) -> Vec<BandRename> {
+    let mut bands: BTreeMap<i64, HashMap<&str, (Option<Date>, usize)>> = BTreeMap::new();
+    for item in items {
+        let Some(band_id) = item.band_id else {
+            continue;
+        };
+        let (last_bought, releases) = bands
+            .entry(band_id)
+            .or_default()
+            .entry(&item.band_name)
+            .or_default();
+        *last_bought = (*last_bought).max(item.purchase_date());
+        *releases += 1;
+    }
+
+    bands
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|(band_id, names)| {
+            let mut names: Vec<_> = names.into_iter().collect();
+            names.sort_by_key(|&(name, (last_bought, _))| (last_bought, name));
+            BandRename {
+                band_id,
+                names: names
+                    .into_iter()
+                    .map(|(name, (_, releases))| (name.to_owned(), releases))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+// The band id of each release, keyed like the download cache
+pub fn band_ids(items: &[CollectionItem]) -> HashMap<String, i64> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let sale_id = item.sale_key()?;
+            Some((cache::normalize_release_id(&sale_id), item.band_id?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection_item(
+        sale_item_id: i64,
+        band_id: i64,
+        band_name: &str,
+        purchased: &str,
+    ) -> CollectionItem {
+        serde_json::from_value(serde_json::json!({
+            "sale_item_id": sale_item_id,
+            "sale_item_type": "p",
+            "band_name": band_name,
+            "item_title": "Galerie",
+            "band_id": band_id,
+            "purchased": purchased,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn test_find_renames() {
+        let items = [
+            collection_item(1, 42, "Anomalie", "01 Jun 2023 00:00:00 GMT"),
+            collection_item(2, 42, "Nicolas Dupuis", "01 Jan 2019 00:00:00 GMT"),
+            collection_item(3, 42, "Anomalie", "01 Jan 2021 00:00:00 GMT"),
+            collection_item(4, 7, "Camellia", "01 Jan 2022 00:00:00 GMT"),
+        ];
+
+        let renames = find_renames(&items);
+        assert_eq!(
+            renames,
+            [BandRename {
+                band_id: 42,
+                names: vec![("Nicolas Dupuis".to_owned(), 1), ("Anomalie".to_owned(), 2)],
+            }]
+        );
+        assert_eq!(
+            renames[0].describe(),
+            "Band 42 was bought from as \"Nicolas Dupuis\" (1 release(s)), \"Anomalie\" (2 release(s)). Consider merging their folders into \"Anomalie\""
+        );
+        assert_eq!(band_ids(&items).get("p4"), Some(&7));
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{cookies::RawCookie, error::BrowserCookieError};
+
+pub mod chromium;
+
+use chromium::ChromiumBrowser;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chromium(ChromiumBrowser),
+}
+
+impl fmt::Display for Browser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Firefox => "firefox",
+            Self::Chromium(ChromiumBrowser::Chrome) => "chrome",
+            Self::Chromium(ChromiumBrowser::Chromium) => "chromium",
+            Self::Chromium(ChromiumBrowser::Edge) => "edge",
+        })
+    }
+}
+
+// A browser to take the cookies from, and optionally which of its profiles, e.g. "firefox:work"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BrowserCookieSource {
+    pub browser: Browser,
+    pub profile: Option<String>,
+}
+
+impl FromStr for BrowserCookieSource {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let (browser, profile) = source
+            .split_once(':')
+            .map_or((source, None), |(browser, profile)| {
+                (browser, Some(profile))
+            });
+        let browser = match browser.to_lowercase().as_str() {
+            "firefox" => Browser::Firefox,
+            "chrome" => Browser::Chromium(ChromiumBrowser::Chrome),
+            "chromium" => Browser::Chromium(ChromiumBrowser::Chromium),
+            "edge" => Browser::Chromium(ChromiumBrowser::Edge),
+            _ => {
+                return Err(format!(
+                    "unsupported browser \"{browser}\", expected firefox, chrome, chromium or edge"
+                ))
+            }
+        };
+
+        Ok(Self {
+            browser,
+            profile: profile
+                .filter(|profile| !profile.is_empty())
+                .map(str::to_owned),
+        })
+    }
+}
+
+impl BrowserCookieSource {
+    // Bandcamp's cookies, in the same shape as those of a cookie export
+    pub fn read_cookies(&self) -> Result<Vec<RawCookie>, BrowserCookieError> {
+        match self.browser {
+            Browser::Firefox => {
+                let profile_dir = firefox_profile_dir(self.profile.as_deref())?;
+                read_firefox_cookies(&profile_dir.join("cookies.sqlite"))
+            }
+            Browser::Chromium(browser) => {
+                chromium::read_cookies(browser, self.profile.as_deref().unwrap_or("Default"))
+            }
+        }
+    }
+}
+
+fn firefox_root_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(|appdata| PathBuf::from(appdata).join("Mozilla/Firefox"))
+    } else {
+        let home = PathBuf::from(std::env::var_os("HOME")?);
+        Some(if cfg!(target_os = "macos") {
+            home.join("Library/Application Support/Firefox")
+        } else {
+            home.join(".mozilla/firefox")
+        })
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FirefoxProfile {
+    name: String,
+    path: PathBuf,
+    default: bool,
+}
+
+// The profiles listed in profiles.ini, with the one the last used installation defaults to
+// first, since that's the one opened by just starting Firefox
+fn parse_profiles_ini(profiles_ini: &str, root_dir: &Path) -> Vec<FirefoxProfile> {
+    let mut profiles = Vec::new();
+    let mut install_defaults = Vec::new();
+    let mut section = String::new();
+    let mut profile: Option<FirefoxProfile> = None;
+    let mut is_relative = true;
+
+    let mut finish_profile = |profile: Option<FirefoxProfile>, is_relative: bool| {
+        if let Some(mut profile) = profile {
+            if is_relative {
+                profile.path = root_dir.join(profile.path);
+            }
+            profiles.push(profile);
+        }
+    };
+
+    for line in profiles_ini.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            finish_profile(profile.take(), is_relative);
+            name.clone_into(&mut section);
+            is_relative = true;
+            if section.starts_with("Profile") {
+                profile = Some(FirefoxProfile::default());
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        if section.starts_with("Install") && key == "Default" {
+            install_defaults.push(root_dir.join(value));
+        } else if let Some(profile) = profile.as_mut() {
+            match key {
+                "Name" => value.clone_into(&mut profile.name),
+                "Path" => profile.path = PathBuf::from(value),
+                "IsRelative" => is_relative = value == "1",
+                "Default" => profile.default = value == "1",
+                _ => {}
+            }
+        }
+    }
+    finish_profile(profile, is_relative);
+
+    for profile in &mut profiles {
+        if install_defaults.contains(&profile.path) {
+            profile.default = true;
+        }
+    }
+    profiles.sort_by_key(|profile| !install_defaults.contains(&profile.path));
+    profiles
+}
+
+// A profile is picked by its name, its folder name, or a path to its folder
+fn firefox_profile_dir(profile: Option<&str>) -> Result<PathBuf, BrowserCookieError> {
+    if let Some(profile) = profile.map(Path::new).filter(|profile| profile.is_dir()) {
+        return Ok(profile.to_owned());
+    }
+
+    let root_dir = firefox_root_dir().ok_or(BrowserCookieError::NoProfileDirectory)?;
+    let profiles_ini = std::fs::read_to_string(root_dir.join("profiles.ini"))
+        .map_err(|_| BrowserCookieError::NoProfileDirectory)?;
+    let mut profiles = parse_profiles_ini(&profiles_ini, &root_dir);
+
+    let found = if let Some(name) = profile {
+        profiles
+            .into_iter()
+            .find(|profile| profile.name == name || profile.path.file_name() == Some(name.as_ref()))
+    } else {
+        // without any marked as the default, the only or first one listed
+        let index = profiles
+            .iter()
+            .position(|profile| profile.default)
+            .unwrap_or(0);
+        (index < profiles.len()).then(|| profiles.swap_remove(index))
+    };
+    found
+        .map(|profile| profile.path)
+        .ok_or_else(|| BrowserCookieError::ProfileNotFound(profile.unwrap_or("default").to_owned()))
+}
+
+// Both moz_cookies and Chromium's cookies table store sameSite as 0 (none), 1 (lax) and 2 (strict)
+#[cfg_attr(not(feature = "browser-cookies"), allow(dead_code))]
+const fn same_site_name(same_site: i64) -> Option<&'static str> {
+    match same_site {
+        0 => Some("no_restriction"),
+        1 => Some("lax"),
+        2 => Some("strict"),
+        _ => None,
+    }
+}
+
+// Browsers keep their databases locked while they run, so a copy is read instead. Recent changes
+// may still be in the write ahead log next to it, which is copied along. The copy holds session
+// cookies, so it's made in a folder only the user can open, which is removed however reading ends.
+#[cfg(feature = "browser-cookies")]
+fn with_database_copy<T>(
+    database_path: &Path,
+    read: impl FnOnce(&rusqlite::Connection) -> Result<T, BrowserCookieError>,
+) -> Result<T, BrowserCookieError> {
+    if !database_path.is_file() {
+        return Err(BrowserCookieError::NoCookieDatabase(
+            database_path.to_owned(),
+        ));
+    }
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("bandcamp-dl-browser-cookies-");
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+    let copy_dir = builder.tempdir_in(crate::staging::temp_dir())?;
+
+    let copy_path = copy_dir.path().join("cookies.sqlite");
+    std::fs::copy(database_path, &copy_path)?;
+    let mut wal_name = database_path.file_name().unwrap_or_default().to_owned();
+    wal_name.push("-wal");
+    if database_path.with_file_name(&wal_name).is_file() {
+        std::fs::copy(
+            database_path.with_file_name(&wal_name),
+            copy_dir.path().join("cookies.sqlite-wal"),
+        )?;
+    }
+
+    read(&rusqlite::Connection::open(&copy_path)?)
+}
+
+#[cfg(feature = "browser-cookies")]
+fn read_firefox_cookies(database_path: &Path) -> Result<Vec<RawCookie>, BrowserCookieError> {
+    with_database_copy(database_path, |connection| {
+        let mut statement = connection.prepare(
+            "SELECT host, name, value, path, expiry, isSecure, isHttpOnly, sameSite FROM moz_cookies \
+             WHERE host = 'bandcamp.com' OR host LIKE '%.bandcamp.com'",
+        )?;
+
+        let cookies = statement.query_map([], |row| {
+            let mut cookie = RawCookie::new(row.get(1)?, row.get(2)?);
+            cookie.host = Some(row.get(0)?);
+            cookie.path = Some(row.get(3)?);
+            cookie.expires = Some(row.get::<_, i64>(4)?.to_string());
+            cookie.send_for = Some((row.get::<_, i64>(5)? != 0).to_string());
+            cookie.http_only = Some((row.get::<_, i64>(6)? != 0).to_string());
+            cookie.same_site = same_site_name(row.get(7)?).map(str::to_owned);
+            Ok(cookie)
+        })?;
+
+        Ok(cookies.collect::<Result<_, _>>()?)
+    })
+}
+
+#[cfg(not(feature = "browser-cookies"))]
+#[allow(clippy::missing_const_for_fn)]
+fn read_firefox_cookies(_: &Path) -> Result<Vec<RawCookie>, BrowserCookieError> {
+    Err(BrowserCookieError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("firefox", None)]
+    #[test_case("Firefox:work", Some("work"))]
+    #[test_case("firefox:", None; "empty profile")]
+    pub fn test_parse_source(source: &str, profile: Option<&str>) {
+        assert_eq!(
+            source.parse::<BrowserCookieSource>(),
+            Ok(BrowserCookieSource {
+                browser: Browser::Firefox,
+                profile: profile.map(str::to_owned),
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_parse_chromium_source() {
+        assert_eq!(
+            "edge:Profile 1".parse::<BrowserCookieSource>(),
+            Ok(BrowserCookieSource {
+                browser: Browser::Chromium(ChromiumBrowser::Edge),
+                profile: Some("Profile 1".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_parse_source_unknown_browser() {
+        assert!("netscape".parse::<BrowserCookieSource>().is_err());
+    }
+
+    #[test]
+    pub fn test_parse_profiles_ini() {
+        let profiles_ini = "[Install4F96D1932A9F858E]\r
+Default=Profiles/2b3c.default-release\r
+Locked=1\r
+\r
+[Profile1]\r
+Name=default\r
+IsRelative=1\r
+Path=Profiles/1a2b.default\r
+Default=1\r
+\r
+[Profile0]\r
+Name=default-release\r
+IsRelative=1\r
+Path=Profiles/2b3c.default-release\r
+\r
+[Profile2]\r
+Name=work\r
+IsRelative=0\r
+Path=/data/firefox/work\r
+\r
+[General]\r
+StartWithLastProfile=1\r
+";
+        let root_dir = Path::new("/home/user/.mozilla/firefox");
+        let profiles = parse_profiles_ini(profiles_ini, root_dir);
+
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["default-release", "default", "work"]);
+        assert_eq!(
+            profiles[0].path,
+            root_dir.join("Profiles/2b3c.default-release")
+        );
+        assert!(profiles[0].default);
+        assert_eq!(profiles[2].path, PathBuf::from("/data/firefox/work"));
+        assert!(!profiles[2].default);
+    }
+
+    #[cfg(feature = "browser-cookies")]
+    #[test]
+    pub fn test_read_firefox_cookies() {
+        let dir = tempfile::tempdir().unwrap();
+        let database_path = dir.path().join("cookies.sqlite");
+        let _ = std::fs::remove_file(&database_path);
+
+        let connection = rusqlite::Connection::open(&database_path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE moz_cookies (id INTEGER PRIMARY KEY, name TEXT, value TEXT, host TEXT, \
+                 path TEXT, expiry INTEGER, isSecure INTEGER, isHttpOnly INTEGER, sameSite INTEGER);
+                 INSERT INTO moz_cookies (name, value, host, path, expiry, isSecure, isHttpOnly, sameSite) VALUES
+                   ('identity', 'abc', '.bandcamp.com', '/', 1919434332, 1, 1, 0),
+                   ('SID', 'def', '.google.com', '/', 1919434332, 1, 0, 1);",
+            )
+            .unwrap();
+        drop(connection);
+
+        let cookies = read_firefox_cookies(&database_path).unwrap();
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].name, "identity");
+        assert_eq!(cookies[0].host.as_deref(), Some(".bandcamp.com"));
+        assert_eq!(cookies[0].http_only.as_deref(), Some("true"));
+        assert_eq!(cookies[0].same_site.as_deref(), Some("no_restriction"));
+    }
+
+    #[cfg(feature = "browser-cookies")]
+    #[test]
+    pub fn test_database_copy_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let database_path = dir.path().join("cookies.sqlite");
+        rusqlite::Connection::open(&database_path)
+            .unwrap()
+            .execute_batch("CREATE TABLE moz_cookies (id INTEGER PRIMARY KEY);")
+            .unwrap();
+
+        let mut copy_dir = PathBuf::new();
+        let result: Result<(), _> = with_database_copy(&database_path, |connection| {
+            copy_dir = Path::new(connection.path().unwrap())
+                .parent()
+                .unwrap()
+                .to_owned();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = std::fs::metadata(&copy_dir).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o700);
+            }
+            Err(BrowserCookieError::NoProfileDirectory)
+        });
+
+        assert!(result.is_err());
+        assert!(!copy_dir.as_os_str().is_empty());
+        assert!(!copy_dir.exists());
+    }
+}
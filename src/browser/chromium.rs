@@ -0,0 +1,510 @@
+use std::path::{Path, PathBuf};
+
+use crate::{cookies::RawCookie, error::BrowserCookieError};
+
+// Chrome and the browsers built on it, which all keep their cookies the same way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChromiumBrowser {
+    Chrome,
+    Chromium,
+    Edge,
+}
+
+impl ChromiumBrowser {
+    // Holds a folder per profile, "Default" being the first one
+    fn user_data_dir(self) -> Option<PathBuf> {
+        let (windows, macos, linux) = match self {
+            Self::Chrome => ("Google/Chrome/User Data", "Google/Chrome", "google-chrome"),
+            Self::Chromium => ("Chromium/User Data", "Chromium", "chromium"),
+            Self::Edge => (
+                "Microsoft/Edge/User Data",
+                "Microsoft Edge",
+                "microsoft-edge",
+            ),
+        };
+
+        if cfg!(windows) {
+            std::env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join(windows))
+        } else {
+            let home = PathBuf::from(std::env::var_os("HOME")?);
+            Some(if cfg!(target_os = "macos") {
+                home.join("Library/Application Support").join(macos)
+            } else {
+                home.join(".config").join(linux)
+            })
+        }
+    }
+
+    // The keychain service and account the cookie password is kept under on macOS
+    #[cfg_attr(
+        any(not(target_os = "macos"), not(feature = "browser-cookies")),
+        allow(dead_code)
+    )]
+    const fn keychain_entry(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Chrome => ("Chrome Safe Storage", "Chrome"),
+            Self::Chromium => ("Chromium Safe Storage", "Chromium"),
+            Self::Edge => ("Microsoft Edge Safe Storage", "Microsoft Edge"),
+        }
+    }
+
+    // The `application` attribute of the cookie password in the secret service on Linux
+    #[cfg_attr(
+        any(windows, target_os = "macos", not(feature = "browser-cookies")),
+        allow(dead_code)
+    )]
+    const fn secret_application(self) -> &'static str {
+        match self {
+            Self::Chrome => "chrome",
+            Self::Chromium => "chromium",
+            Self::Edge => "microsoft-edge",
+        }
+    }
+}
+
+// Chromium counts microseconds since 1601, and uses 0 for session cookies
+const WINDOWS_TO_UNIX_EPOCH_SECONDS: i64 = 11_644_473_600;
+
+#[cfg_attr(not(feature = "browser-cookies"), allow(dead_code))]
+fn chromium_time_to_unix(chromium_time: i64) -> Option<i64> {
+    (chromium_time > 0).then(|| chromium_time / 1_000_000 - WINDOWS_TO_UNIX_EPOCH_SECONDS)
+}
+
+// Since database version 24 the decrypted values start with a SHA-256 hash of the cookie's host
+#[cfg_attr(not(feature = "browser-cookies"), allow(dead_code))]
+const HOST_HASH_DATABASE_VERSION: i64 = 24;
+
+// Chrome 127 and later mark values "v20" on Windows, encrypted with a key only the browser's own
+// elevated service can unwrap
+#[cfg_attr(not(feature = "browser-cookies"), allow(dead_code))]
+const APP_BOUND_PREFIX: &[u8] = b"v20";
+
+// A profile is picked by its folder name (e.g. "Profile 1") or a path to its folder
+pub fn read_cookies(
+    browser: ChromiumBrowser,
+    profile: &str,
+) -> Result<Vec<RawCookie>, BrowserCookieError> {
+    let (user_data_dir, profile_dir) = if Path::new(profile).is_dir() {
+        let profile_dir = PathBuf::from(profile);
+        let user_data_dir = profile_dir.parent().unwrap_or(&profile_dir).to_owned();
+        (user_data_dir, profile_dir)
+    } else {
+        let user_data_dir = browser
+            .user_data_dir()
+            .ok_or(BrowserCookieError::NoProfileDirectory)?;
+        let profile_dir = user_data_dir.join(profile);
+        (user_data_dir, profile_dir)
+    };
+    if !profile_dir.is_dir() {
+        return Err(BrowserCookieError::ProfileNotFound(profile.to_owned()));
+    }
+
+    // moved into "Network" by Chromium 96
+    let database_path = [
+        profile_dir.join("Network/Cookies"),
+        profile_dir.join("Cookies"),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+    .ok_or_else(|| BrowserCookieError::NoCookieDatabase(profile_dir.join("Network/Cookies")))?;
+
+    read_database(browser, &user_data_dir, &database_path)
+}
+
+#[cfg(not(feature = "browser-cookies"))]
+#[allow(clippy::missing_const_for_fn)]
+fn read_database(
+    _: ChromiumBrowser,
+    _: &Path,
+    _: &Path,
+) -> Result<Vec<RawCookie>, BrowserCookieError> {
+    Err(BrowserCookieError::Unsupported)
+}
+
+#[cfg(feature = "browser-cookies")]
+fn read_database(
+    browser: ChromiumBrowser,
+    user_data_dir: &Path,
+    database_path: &Path,
+) -> Result<Vec<RawCookie>, BrowserCookieError> {
+    let key = encryption::CookieKey::for_browser(browser, user_data_dir)?;
+
+    super::with_database_copy(database_path, |connection| {
+        let database_version: i64 = connection
+            .query_row(
+                "SELECT CAST(value AS INTEGER) FROM meta WHERE key = 'version'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or_default();
+        let mut statement = connection.prepare(
+            "SELECT host_key, name, value, encrypted_value, path, expires_utc, is_secure, \
+             is_httponly, samesite FROM cookies \
+             WHERE host_key = 'bandcamp.com' OR host_key LIKE '%.bandcamp.com'",
+        )?;
+
+        let rows = statement.query_map([], |row| {
+            let mut cookie = RawCookie::new(row.get(1)?, row.get(2)?);
+            cookie.host = Some(row.get(0)?);
+            cookie.path = Some(row.get(4)?);
+            cookie.expires = chromium_time_to_unix(row.get(5)?).map(|expires| expires.to_string());
+            cookie.send_for = Some((row.get::<_, i64>(6)? != 0).to_string());
+            cookie.http_only = Some((row.get::<_, i64>(7)? != 0).to_string());
+            cookie.same_site = super::same_site_name(row.get(8)?).map(str::to_owned);
+            Ok((cookie, row.get::<_, Vec<u8>>(3)?))
+        })?;
+
+        let mut cookies = Vec::new();
+        for row in rows {
+            let (mut cookie, encrypted_value) = row?;
+            if encrypted_value.starts_with(APP_BOUND_PREFIX) {
+                return Err(BrowserCookieError::AppBoundEncryption);
+            }
+            if !encrypted_value.is_empty() {
+                let value = key.decrypt(&encrypted_value).and_then(|value| {
+                    let value = if database_version >= HOST_HASH_DATABASE_VERSION {
+                        value.get(32..).ok_or("value too short")?.to_vec()
+                    } else {
+                        value
+                    };
+                    String::from_utf8(value).map_err(|_| "value isn't UTF-8".to_owned())
+                });
+                match value {
+                    Ok(value) => cookie.value = value,
+                    Err(e) => {
+                        eprintln!(
+                            "Skipping the {} cookie, which couldn't be decrypted: {e}",
+                            cookie.name
+                        );
+                        continue;
+                    }
+                }
+            }
+            cookies.push(cookie);
+        }
+        Ok(cookies)
+    })
+}
+
+#[cfg(feature = "browser-cookies")]
+mod encryption {
+    use std::path::Path;
+    #[cfg(unix)]
+    use std::process::Command;
+
+    use super::ChromiumBrowser;
+    use crate::error::BrowserCookieError;
+
+    // How the cookie values of a browser are encrypted. On macOS and Linux that's AES-128-CBC,
+    // with a key derived from a password kept in the keychain or secret service. Values marked
+    // "v10" on Linux are encrypted with a fixed password instead. On Windows it's AES-256-GCM,
+    // with a key protected by DPAPI.
+    pub enum CookieKey {
+        #[cfg(unix)]
+        Cbc {
+            v10: [u8; 16],
+            v11: Option<[u8; 16]>,
+        },
+        #[cfg(windows)]
+        Gcm([u8; 32]),
+    }
+
+    #[cfg(unix)]
+    const CBC_SALT: &[u8] = b"saltysalt";
+    #[cfg(unix)]
+    const CBC_IV: [u8; 16] = [b' '; 16];
+
+    #[cfg(unix)]
+    fn derive_cbc_key(password: &[u8], iterations: u32) -> [u8; 16] {
+        let mut key = [0; 16];
+        pbkdf2::pbkdf2_hmac::<sha1::Sha1>(password, CBC_SALT, iterations, &mut key);
+        key
+    }
+
+    #[cfg(unix)]
+    fn decrypt_cbc(key: &[u8; 16], data: &[u8]) -> Result<Vec<u8>, String> {
+        use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+
+        cbc::Decryptor::<aes::Aes128>::new_from_slices(key, &CBC_IV)
+            .map_err(|e| e.to_string())?
+            .decrypt_padded_vec_mut::<Pkcs7>(data)
+            .map_err(|_| "wrong key or corrupted value".to_owned())
+    }
+
+    #[cfg(windows)]
+    fn decrypt_gcm(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, String> {
+        use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+
+        let (nonce, ciphertext) = data.split_at_checked(12).ok_or("value too short")?;
+        Aes256Gcm::new_from_slice(key)
+            .map_err(|e| e.to_string())?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "wrong key or corrupted value".to_owned())
+    }
+
+    impl CookieKey {
+        #[cfg(target_os = "macos")]
+        pub fn for_browser(browser: ChromiumBrowser, _: &Path) -> Result<Self, BrowserCookieError> {
+            let (service, account) = browser.keychain_entry();
+            let output = Command::new("security")
+                .args(["find-generic-password", "-w", "-s", service, "-a", account])
+                .output()?;
+            if !output.status.success() {
+                return Err(BrowserCookieError::NoEncryptionKey(service.to_owned()));
+            }
+
+            let password = String::from_utf8_lossy(&output.stdout);
+            Ok(Self::Cbc {
+                v10: derive_cbc_key(password.trim().as_bytes(), 1003),
+                v11: None,
+            })
+        }
+
+        // Without a secret service, or outside of a desktop session, only "v10" values can be
+        // decrypted
+        #[cfg(all(unix, not(target_os = "macos")))]
+        #[allow(clippy::unnecessary_wraps)]
+        pub fn for_browser(browser: ChromiumBrowser, _: &Path) -> Result<Self, BrowserCookieError> {
+            let v11 = Command::new("secret-tool")
+                .args(["lookup", "application", browser.secret_application()])
+                .output()
+                .ok()
+                .filter(|output| output.status.success() && !output.stdout.is_empty())
+                .map(|output| {
+                    derive_cbc_key(String::from_utf8_lossy(&output.stdout).trim().as_bytes(), 1)
+                });
+
+            Ok(Self::Cbc {
+                v10: derive_cbc_key(b"peanuts", 1),
+                v11,
+            })
+        }
+
+        #[cfg(windows)]
+        pub fn for_browser(
+            _: ChromiumBrowser,
+            user_data_dir: &Path,
+        ) -> Result<Self, BrowserCookieError> {
+            use base64::Engine;
+
+            let missing_key = || BrowserCookieError::NoEncryptionKey("Local State".to_owned());
+            let local_state: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(user_data_dir.join("Local State"))?)
+                    .map_err(|_| missing_key())?;
+            let encrypted_key = local_state
+                .pointer("/os_crypt/encrypted_key")
+                .and_then(serde_json::Value::as_str)
+                .ok_or_else(missing_key)?;
+            let encrypted_key = base64::engine::general_purpose::STANDARD
+                .decode(encrypted_key)
+                .map_err(|_| missing_key())?;
+            let encrypted_key = encrypted_key
+                .strip_prefix(b"DPAPI")
+                .ok_or_else(missing_key)?;
+
+            let key = dpapi_unprotect(encrypted_key)?;
+            Ok(Self::Gcm(key.try_into().map_err(|_| missing_key())?))
+        }
+
+        pub fn decrypt(&self, encrypted_value: &[u8]) -> Result<Vec<u8>, String> {
+            let (version, data) = encrypted_value
+                .split_at_checked(3)
+                .ok_or("value too short")?;
+            match (self, version) {
+                #[cfg(unix)]
+                (Self::Cbc { v10, .. }, b"v10") => decrypt_cbc(v10, data),
+                #[cfg(unix)]
+                (Self::Cbc { v11: Some(v11), .. }, b"v11") => decrypt_cbc(v11, data),
+                #[cfg(windows)]
+                (Self::Gcm(key), b"v10") => decrypt_gcm(key, data),
+                _ => Err(format!(
+                    "unsupported encryption \"{}\"",
+                    String::from_utf8_lossy(version)
+                )),
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    #[allow(unsafe_code)]
+    fn dpapi_unprotect(data: &[u8]) -> Result<Vec<u8>, BrowserCookieError> {
+        use windows_sys::Win32::{
+            Foundation::LocalFree,
+            Security::Cryptography::{CryptUnprotectData, CRYPT_INTEGER_BLOB},
+        };
+
+        let input = CRYPT_INTEGER_BLOB {
+            cbData: u32::try_from(data.len())
+                .map_err(|_| BrowserCookieError::NoEncryptionKey("Local State".to_owned()))?,
+            pbData: data.as_ptr().cast_mut(),
+        };
+        let mut output = CRYPT_INTEGER_BLOB {
+            cbData: 0,
+            pbData: std::ptr::null_mut(),
+        };
+
+        // SAFETY: `input` points at `data` for the duration of the call, which only reads it.
+        // On success `output` is allocated by the call, and freed with LocalFree as documented.
+        let succeeded = unsafe {
+            CryptUnprotectData(
+                &input,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                0,
+                &mut output,
+            )
+        };
+        if succeeded == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        // SAFETY: see above, `output` holds `cbData` bytes until it's freed
+        let key = unsafe {
+            let key = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+            LocalFree(output.pbData.cast());
+            key
+        };
+        Ok(key)
+    }
+
+    #[cfg(all(test, unix))]
+    mod tests {
+        use super::*;
+        use cbc::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+
+        fn encrypt_cbc(key: &[u8; 16], version: &[u8], value: &[u8]) -> Vec<u8> {
+            let encrypted = cbc::Encryptor::<aes::Aes128>::new_from_slices(key, &CBC_IV)
+                .unwrap()
+                .encrypt_padded_vec_mut::<Pkcs7>(value);
+            [version, &encrypted].concat()
+        }
+
+        // A cookies database of `version`, with a bandcamp.com cookie per name and encrypted value
+        fn cookie_database(
+            dir: &Path,
+            version: i64,
+            cookies: &[(&str, &[u8])],
+        ) -> std::path::PathBuf {
+            let database_path = dir.join("Cookies");
+            let connection = rusqlite::Connection::open(&database_path).unwrap();
+            connection
+                .execute_batch(
+                    "CREATE TABLE meta (key LONGVARCHAR PRIMARY KEY, value LONGVARCHAR);
+                     CREATE TABLE cookies (host_key TEXT, name TEXT, value TEXT, encrypted_value BLOB, \
+                     path TEXT, expires_utc INTEGER, is_secure INTEGER, is_httponly INTEGER, samesite INTEGER);
+                     INSERT INTO cookies VALUES ('.google.com', 'SID', 'def', X'', '/', 0, 1, 0, 1);",
+                )
+                .unwrap();
+            connection
+                .execute(
+                    "INSERT INTO meta VALUES ('version', ?1)",
+                    [version.to_string()],
+                )
+                .unwrap();
+            for (name, encrypted) in cookies {
+                connection
+                    .execute(
+                        "INSERT INTO cookies VALUES ('.bandcamp.com', ?1, '', ?2, '/', \
+                         13253932800000000, 1, 1, -1)",
+                        rusqlite::params![name, encrypted],
+                    )
+                    .unwrap();
+            }
+            database_path
+        }
+
+        #[test]
+        pub fn test_decrypt_cbc() {
+            let key = CookieKey::Cbc {
+                v10: derive_cbc_key(b"peanuts", 1),
+                v11: None,
+            };
+
+            let encrypted = encrypt_cbc(&derive_cbc_key(b"peanuts", 1), b"v10", b"abc");
+            assert_eq!(key.decrypt(&encrypted).unwrap(), b"abc");
+
+            let encrypted = encrypt_cbc(&derive_cbc_key(b"secret", 1), b"v11", b"abc");
+            assert!(key.decrypt(&encrypted).is_err());
+            assert!(key.decrypt(b"v1").is_err());
+        }
+
+        #[test]
+        pub fn test_decrypt_cbc_v11() {
+            let key = CookieKey::Cbc {
+                v10: derive_cbc_key(b"peanuts", 1),
+                v11: Some(derive_cbc_key(b"secret", 1)),
+            };
+
+            let encrypted = encrypt_cbc(&derive_cbc_key(b"secret", 1), b"v11", b"abc");
+            assert_eq!(key.decrypt(&encrypted).unwrap(), b"abc");
+
+            // a v10 value under the v11 key is a wrong key, not a crash
+            let encrypted = encrypt_cbc(&derive_cbc_key(b"secret", 1), b"v10", b"abc");
+            assert!(key.decrypt(&encrypted).is_err());
+        }
+
+        #[test]
+        pub fn test_read_database() {
+            let dir = tempfile::tempdir().unwrap();
+            let encrypted = encrypt_cbc(
+                &derive_cbc_key(b"peanuts", 1),
+                b"v10",
+                &[[0; 32].as_slice(), b"abc"].concat(),
+            );
+            let database_path = cookie_database(
+                dir.path(),
+                24,
+                &[("broken", b"v10".as_slice()), ("identity", &encrypted)],
+            );
+
+            let cookies =
+                super::super::read_database(ChromiumBrowser::Chromium, dir.path(), &database_path)
+                    .unwrap();
+            assert_eq!(cookies.len(), 1);
+            assert_eq!(cookies[0].name, "identity");
+            assert_eq!(cookies[0].value, "abc");
+            assert_eq!(cookies[0].expires.as_deref(), Some("1609459200"));
+            assert_eq!(cookies[0].same_site, None);
+        }
+
+        #[test]
+        pub fn test_read_database_without_host_hash() {
+            let dir = tempfile::tempdir().unwrap();
+            let encrypted = encrypt_cbc(&derive_cbc_key(b"peanuts", 1), b"v10", b"abc");
+            let database_path = cookie_database(dir.path(), 23, &[("identity", &encrypted)]);
+
+            let cookies =
+                super::super::read_database(ChromiumBrowser::Chromium, dir.path(), &database_path)
+                    .unwrap();
+            assert_eq!(cookies[0].value, "abc");
+        }
+
+        #[test]
+        pub fn test_read_database_app_bound() {
+            let dir = tempfile::tempdir().unwrap();
+            let encrypted = [b"v20".as_slice(), &[0; 48]].concat();
+            let database_path = cookie_database(dir.path(), 24, &[("identity", &encrypted)]);
+
+            assert_matches::assert_matches!(
+                super::super::read_database(ChromiumBrowser::Chrome, dir.path(), &database_path),
+                Err(BrowserCookieError::AppBoundEncryption)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_chromium_time_to_unix() {
+        assert_eq!(
+            chromium_time_to_unix(13_253_932_800_000_000),
+            Some(1_609_459_200)
+        );
+        assert_eq!(chromium_time_to_unix(0), None);
+    }
+}
@@ -1,9 +1,19 @@
-use std::{collections::HashMap, num::ParseIntError, sync::OnceLock};
-
+use std::{
+    collections::BTreeMap,
+    fs::{File, TryLockError},
+    num::ParseIntError,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use clap::ValueEnum;
 use regex_lite::Regex;
 use thiserror::Error;
 
-#[derive(Debug)]
+use crate::api::data::{DigitalItem, DownloadFormat};
+
+#[derive(Clone, Debug)]
 pub struct DownloadCacheRelease {
     release_id: String,
     title: String,
@@ -14,16 +24,45 @@ pub struct DownloadCacheRelease {
 impl DownloadCacheRelease {
     pub fn new(release_id: &str, title: &str, year: i32, artist: &str) -> Self {
         Self {
-            release_id: release_id.into(),
+            release_id: normalize_release_id(release_id),
             title: title.into(),
             year,
             artist: artist.into(),
         }
     }
+
+    pub fn release_id(&self) -> &str {
+        &self.release_id
+    }
+
+    pub fn artist(&self) -> &str {
+        &self.artist
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub const fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn from_digital_item(release_id: &str, digital_item: &DigitalItem) -> Self {
+        Self::new(
+            release_id,
+            &digital_item.title,
+            digital_item.release_year().unwrap_or_default(),
+            &digital_item.artist,
+        )
+    }
 }
 
 #[derive(Debug, Error)]
 pub enum CacheParsingError {
+    #[error(
+        "The download cache was written by a newer version of bandcamp-dl (schema version {0}, this one reads up to {CACHE_SCHEMA_VERSION})"
+    )]
+    NewerVersion(u32),
     #[error("Failed to match line \"{0}\" on expression")]
     RegexCaptureFail(String),
     #[error("Failed to get regex group {0}")]
@@ -32,6 +71,90 @@ pub enum CacheParsingError {
     ParseIntError(#[from] ParseIntError),
 }
 
+#[derive(Debug, Error)]
+pub enum CacheLockError {
+    #[error("The download cache {0} is being updated by another sync")]
+    InUse(String),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+// Held while a sync may rewrite the cache, so two syncs sharing a cache can't drop each other's
+// entries. The operating system releases it when the process ends, crashed or not.
+pub struct CacheLock {
+    _file: File,
+}
+
+impl CacheLock {
+    pub fn acquire(cache_file_path: &Path) -> Result<Self, CacheLockError> {
+        let file = File::create(Self::lock_path(cache_file_path))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => {
+                Err(CacheLockError::InUse(cache_file_path.display().to_string()))
+            }
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    // Waits for the lock rather than failing, for the state files a sync only holds it on while
+    // reading or rewriting them
+    pub fn wait(file_path: &Path) -> std::io::Result<Self> {
+        let file = File::create(Self::lock_path(file_path))?;
+        file.lock()?;
+        Ok(Self { _file: file })
+    }
+
+    // The lock of the cache along with those of its --cache-per-format caches that were ever
+    // synced, for what must not run while any sync of the library does
+    pub fn acquire_all(cache_file_path: &Path) -> Result<Vec<Self>, CacheLockError> {
+        let mut locks = vec![Self::acquire(cache_file_path)?];
+        for format in DownloadFormat::value_variants() {
+            let format_cache_path = format_scoped_cache_path(cache_file_path, *format);
+            if format_cache_path.exists() || Self::lock_path(&format_cache_path).exists() {
+                locks.push(Self::acquire(&format_cache_path)?);
+            }
+        }
+        Ok(locks)
+    }
+
+    fn lock_path(cache_file_path: &Path) -> PathBuf {
+        let mut lock_path = cache_file_path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        PathBuf::from(lock_path)
+    }
+}
+
+// e.g. "bandcamp-collection-downloader.flac.cache", for libraries kept in several formats
+pub fn format_scoped_cache_path(cache_file_path: &Path, format: DownloadFormat) -> PathBuf {
+    scoped_cache_path(cache_file_path, format.name())
+}
+
+// The cache file with `scope` put before its extension, so a part of the library kept apart
+// doesn't share the cache of the rest
+pub fn scoped_cache_path(cache_file_path: &Path, scope: &str) -> PathBuf {
+    let stem = cache_file_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let file_name = cache_file_path.extension().map_or_else(
+        || format!("{stem}.{scope}"),
+        |extension| format!("{stem}.{scope}.{}", extension.to_string_lossy()),
+    );
+    cache_file_path.with_file_name(file_name)
+}
+
+// Older versions stored purchases by their bare sale id ("199396767"), while download pages key
+// them with a type prefix ("p199396767"). Ids are always kept prefixed, so upgraded caches keep
+// matching and the prefixed form is what gets written back.
+pub fn normalize_release_id(release_id: &str) -> String {
+    if !release_id.is_empty() && release_id.bytes().all(|b| b.is_ascii_digit()) {
+        format!("p{release_id}")
+    } else {
+        release_id.to_owned()
+    }
+}
+
 pub fn read_download_cache_line(
     cache_line: &str,
 ) -> Result<DownloadCacheRelease, CacheParsingError> {
@@ -46,11 +169,12 @@ pub fn read_download_cache_line(
         .ok_or_else(|| CacheParsingError::RegexCaptureFail(cache_line.to_string()))?;
 
     let release = DownloadCacheRelease {
-        release_id: captures
-            .get(1)
-            .ok_or(CacheParsingError::RegexGroupFail(1))?
-            .as_str()
-            .to_owned(),
+        release_id: normalize_release_id(
+            captures
+                .get(1)
+                .ok_or(CacheParsingError::RegexGroupFail(1))?
+                .as_str(),
+        ),
         title: captures
             .get(2)
             .ok_or(CacheParsingError::RegexGroupFail(2))?
@@ -71,15 +195,90 @@ pub fn read_download_cache_line(
     Ok(release)
 }
 
-pub type DownloadCache = HashMap<String, DownloadCacheRelease>;
+// The releases of a cache file by id, which is the order they're written back in, so a rewritten
+// file only differs by the releases that changed. Lines that aren't releases, such as comments
+// added by hand, are kept as they were, and written back after the release they followed.
+#[derive(Clone, Debug, Default)]
+pub struct DownloadCache {
+    releases: BTreeMap<String, DownloadCacheRelease>,
+    // by the id of the release they followed, None for those before the first one
+    other_lines: BTreeMap<Option<String>, Vec<String>>,
+}
 
-pub fn read_download_cache(cache_data: &str) -> Result<DownloadCache, CacheParsingError> {
-    let lines: Result<Vec<_>, _> = cache_data.lines().map(read_download_cache_line).collect();
+impl DownloadCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn other_lines(&self) -> impl Iterator<Item = &str> {
+        self.other_lines.values().flatten().map(String::as_str)
+    }
+
+    // The other lines that aren't "#" comments, and so are likely damaged entries
+    pub fn unreadable_lines(&self) -> impl Iterator<Item = &str> {
+        self.other_lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+    }
+}
+
+impl Deref for DownloadCache {
+    type Target = BTreeMap<String, DownloadCacheRelease>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.releases
+    }
+}
+
+impl DerefMut for DownloadCache {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.releases
+    }
+}
 
-    Ok(lines?
-        .into_iter()
-        .map(|c| (c.release_id.clone(), c))
-        .collect())
+impl FromIterator<(String, DownloadCacheRelease)> for DownloadCache {
+    fn from_iter<I: IntoIterator<Item = (String, DownloadCacheRelease)>>(releases: I) -> Self {
+        Self {
+            releases: releases.into_iter().collect(),
+            other_lines: BTreeMap::new(),
+        }
+    }
+}
+
+// Written as the first line of the cache, which to bandcamp-collection-downloader is a line like
+// any other that isn't a release. Caches without it are version 0, which has the same lines.
+pub const CACHE_SCHEMA_VERSION: u32 = 1;
+const CACHE_HEADER: &str = "# bandcamp-dl download cache, schema version ";
+
+// Tolerates files edited on Windows: a leading BOM, CRLF (or stray CR) line endings and blank lines
+pub fn read_download_cache(cache_data: &str) -> Result<DownloadCache, CacheParsingError> {
+    let cache_data = cache_data.strip_prefix('\u{feff}').unwrap_or(cache_data);
+    let mut cache = DownloadCache::new();
+    let mut previous_release = None;
+    for line in cache_data
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+    {
+        if let Some(version) = line.strip_prefix(CACHE_HEADER) {
+            let version = version.trim().parse()?;
+            if version > CACHE_SCHEMA_VERSION {
+                return Err(CacheParsingError::NewerVersion(version));
+            }
+            continue;
+        }
+        match read_download_cache_line(line) {
+            Ok(release) => {
+                previous_release = Some(release.release_id.clone());
+                cache.insert(release.release_id.clone(), release);
+            }
+            Err(_) => cache
+                .other_lines
+                .entry(previous_release.clone())
+                .or_default()
+                .push(line.to_owned()),
+        }
+    }
+    Ok(cache)
 }
 
 pub fn serialize_download_cache_release(cache_release: &DownloadCacheRelease) -> String {
@@ -89,18 +288,77 @@ pub fn serialize_download_cache_release(cache_release: &DownloadCacheRelease) ->
     )
 }
 
+// The other lines follow the release they did when read, or come where it was once it's gone
 pub fn serialize_download_cache(cache_data: &DownloadCache) -> String {
-    cache_data
-        .values()
-        .map(serialize_download_cache_release)
-        .collect::<Vec<_>>()
-        .join("\n")
+    let mut lines = vec![format!("{CACHE_HEADER}{CACHE_SCHEMA_VERSION}")];
+    let mut other_lines = cache_data.other_lines.iter().peekable();
+    for (release_id, release) in cache_data.iter() {
+        while let Some((_, group)) = other_lines.next_if(|(previous, _)| {
+            previous
+                .as_ref()
+                .is_none_or(|previous| previous < release_id)
+        }) {
+            lines.extend(group.iter().cloned());
+        }
+        lines.push(serialize_download_cache_release(release));
+        if let Some((_, group)) =
+            other_lines.next_if(|(previous, _)| previous.as_ref() == Some(release_id))
+        {
+            lines.extend(group.iter().cloned());
+        }
+    }
+    lines.extend(other_lines.flat_map(|(_, group)| group.iter().cloned()));
+    lines.join("\n")
+}
+
+// The lines a rewrite of the cache would remove ("-") and add ("+"), in a patch like format and
+// ordered by release, or an empty string when nothing changes. Older versions wrote cache files
+// unordered, so moved lines don't count as changes.
+pub fn diff_download_cache_data(old_data: &str, new_data: &str, file_label: &str) -> String {
+    let lines = |data: &str| -> Vec<String> {
+        let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+        data.lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.trim().is_empty() && !line.starts_with(CACHE_HEADER))
+            .map(str::to_owned)
+            .collect()
+    };
+    let (old_lines, new_lines) = (lines(old_data), lines(new_data));
+
+    let mut changes: Vec<_> = old_lines
+        .iter()
+        .filter(|line| !new_lines.contains(line))
+        .map(|line| ('-', line))
+        .chain(
+            new_lines
+                .iter()
+                .filter(|line| !old_lines.contains(line))
+                .map(|line| ('+', line)),
+        )
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    changes.sort_by_cached_key(|(sign, line)| {
+        let release_id = line.split('|').next().unwrap_or_default();
+        (normalize_release_id(release_id.trim()), *sign == '+')
+    });
+
+    let mut diff = format!("--- a/{file_label}\n+++ b/{file_label}\n");
+    for (sign, line) in changes {
+        diff.push(sign);
+        diff.push_str(line);
+        diff.push('\n');
+    }
+    diff
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use assert_matches::assert_matches;
+    use test_case::test_case;
 
     #[test]
     pub fn test_read_download_cache_regular() {
@@ -119,19 +377,83 @@ mod tests {
     #[test]
     pub fn test_read_download_cache_invalid_cases() {
         assert_matches!(
-            read_download_cache("Hi this is a test"),
+            read_download_cache_line("Hi this is a test"),
             Err(CacheParsingError::RegexCaptureFail(_))
         );
         assert_matches!(
-            read_download_cache(r#"pewpew1234| "ABCD" (1234) by"#),
+            read_download_cache_line(r#"pewpew1234| "ABCD" (1234) by"#),
             Err(CacheParsingError::RegexCaptureFail(_))
         );
         assert_matches!(
-            read_download_cache(r#"pewpew1234| "ABCD" (hello)"#),
+            read_download_cache_line(r#"pewpew1234| "ABCD" (hello)"#),
             Err(CacheParsingError::RegexCaptureFail(_))
         );
     }
 
+    #[test]
+    pub fn test_read_download_cache_keeps_other_lines() {
+        let data = "# synced from the laptop\nr181302019| \"Silizium\" (2019) by Apparat\npewpew1234| \"ABCD\" (hello)\np199396767| \"Galerie\" (2022) by Anomalie";
+        let cache = read_download_cache(data).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.other_lines().collect::<Vec<_>>(),
+            ["# synced from the laptop", "pewpew1234| \"ABCD\" (hello)"]
+        );
+        assert_eq!(
+            cache.unreadable_lines().collect::<Vec<_>>(),
+            ["pewpew1234| \"ABCD\" (hello)"]
+        );
+        // each after the release it followed, as the releases are sorted
+        assert_eq!(
+            serialize_download_cache(&cache),
+            "# bandcamp-dl download cache, schema version 1\n# synced from the laptop\np199396767| \"Galerie\" (2022) by Anomalie\nr181302019| \"Silizium\" (2019) by Apparat\npewpew1234| \"ABCD\" (hello)"
+        );
+    }
+
+    #[test]
+    pub fn test_serialize_download_cache_keeps_comments_in_place() {
+        let data = "# bandcamp-dl download cache, schema version 1\np199396767| \"Galerie\" (2022) by Anomalie\n# bought at the show\np204514015| \"Toxic Violet Cubes\" (2021) by Camellia\nr181302019| \"Silizium\" (2019) by Apparat";
+        let mut cache = read_download_cache(data).unwrap();
+        assert_eq!(serialize_download_cache(&cache), data);
+
+        // in the same place once the release it followed is gone
+        cache.remove("p199396767");
+        cache.insert(
+            "p100000000".to_owned(),
+            DownloadCacheRelease::new("p100000000", "Debut", 2015, "Anomalie"),
+        );
+        assert_eq!(
+            serialize_download_cache(&cache),
+            "# bandcamp-dl download cache, schema version 1\np100000000| \"Debut\" (2015) by Anomalie\n# bought at the show\np204514015| \"Toxic Violet Cubes\" (2021) by Camellia\nr181302019| \"Silizium\" (2019) by Apparat"
+        );
+    }
+
+    #[test]
+    pub fn test_serialize_download_cache_is_sorted() {
+        let releases = [
+            DownloadCacheRelease::new("r181302019", "Silizium", 2019, "Apparat"),
+            DownloadCacheRelease::new("p204514015", "Toxic Violet Cubes", 2021, "Camellia"),
+            DownloadCacheRelease::new("p199396767", "Galerie", 2022, "Anomalie"),
+        ];
+        let forward: DownloadCache = releases
+            .iter()
+            .map(|release| (release.release_id().to_owned(), release.clone()))
+            .collect();
+        let backward: DownloadCache = releases
+            .iter()
+            .rev()
+            .map(|release| (release.release_id().to_owned(), release.clone()))
+            .collect();
+
+        let serialized = serialize_download_cache(&forward);
+        assert_eq!(serialized, serialize_download_cache(&backward));
+        assert!(serialized
+            .lines()
+            .nth(1)
+            .is_some_and(|line| line.starts_with("p199396767|")));
+    }
+
     #[test]
     pub fn test_read_download_cache_with_escaping() {
         let cache_line = r#"p204514015| "Toxic \"Violet\" Cubes [From BSWC2021 Grand Finals]" (2021) by かめりあ(Camellia)"#;
@@ -149,13 +471,28 @@ mod tests {
         assert_eq!(cache_release.artist, "かめりあ(Camellia)");
     }
 
+    #[test]
+    pub fn test_read_download_cache_version() {
+        let cache = DownloadCache::from_iter([(
+            "p199396767".to_owned(),
+            DownloadCacheRelease::new("p199396767", "Galerie", 2022, "Anomalie"),
+        )]);
+        let reread = read_download_cache(&serialize_download_cache(&cache)).unwrap();
+        assert_eq!(reread.len(), 1);
+        assert_eq!(reread.other_lines().count(), 0);
+
+        assert_matches!(
+            read_download_cache(
+                "# bandcamp-dl download cache, schema version 2\np199396767| \"Galerie\" (2022) by Anomalie"
+            ),
+            Err(CacheParsingError::NewerVersion(2))
+        );
+    }
+
     #[test]
     pub fn test_read_download_cache_from_file() {
         let data = include_str!("data/fake/bandcamp-collection-downloader.cache");
-        let cache = read_download_cache(data);
-
-        assert!(cache.is_ok());
-        let cache = cache.unwrap();
+        let cache = read_download_cache(data).unwrap();
 
         assert!(cache.contains_key("p199397400"));
         assert!(cache.contains_key("r181302019"));
@@ -169,6 +506,107 @@ mod tests {
         assert!(cache.contains_key("r178743158"));
     }
 
+    #[test]
+    pub fn test_read_download_cache_windows_encoding() {
+        let data = "\u{feff}p199396767| \"Galerie\" (2022) by Anomalie\r\n\r\nr181302019| \"Silizium\" (2019) by Apparat\r";
+        let cache = read_download_cache(data).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache["p199396767"].artist, "Anomalie");
+        assert_eq!(cache["r181302019"].artist, "Apparat");
+    }
+
+    #[test_case("p199396767", "p199396767"; "purchase")]
+    #[test_case("r181302019", "r181302019"; "redeemed")]
+    #[test_case("199396767", "p199396767"; "bare sale id")]
+    #[test_case("", ""; "empty")]
+    pub fn test_normalize_release_id(release_id: &str, expected: &str) {
+        assert_eq!(normalize_release_id(release_id), expected);
+    }
+
+    #[test]
+    pub fn test_read_download_cache_migrates_bare_ids() {
+        let data = "199396767| \"Galerie\" (2022) by Anomalie\nr181302019| \"Silizium\" (2019) by Apparat\np204514015| \"Toxic Violet Cubes\" (2021) by Camellia";
+        let cache = read_download_cache(data).unwrap();
+
+        let mut keys: Vec<_> = cache.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["p199396767", "p204514015", "r181302019"]);
+        assert_eq!(cache["p199396767"].release_id(), "p199396767");
+        assert!(serialize_download_cache(&cache).contains("p199396767| \"Galerie\""));
+    }
+
+    #[test]
+    pub fn test_read_download_cache_merges_both_styles() {
+        let data =
+            "199396767| \"Galerie\" (2022) by Anomalie\np199396767| \"Galerie\" (2022) by Anomalie";
+        let cache = read_download_cache(data).unwrap();
+
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains_key("p199396767"));
+    }
+
+    #[test]
+    pub fn test_format_scoped_cache_path() {
+        assert_eq!(
+            format_scoped_cache_path(
+                Path::new("/music/bandcamp-collection-downloader.cache"),
+                DownloadFormat::Mp3_V0
+            ),
+            Path::new("/music/bandcamp-collection-downloader.mp3-v0.cache")
+        );
+        assert_eq!(
+            format_scoped_cache_path(Path::new("cache"), DownloadFormat::Flac),
+            Path::new("cache.flac")
+        );
+        assert_eq!(
+            scoped_cache_path(Path::new("/music/library.cache"), "subscriptions"),
+            Path::new("/music/library.subscriptions.cache")
+        );
+    }
+
+    #[test]
+    pub fn test_cache_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file_path = dir.path().join("bandcamp-collection-downloader.cache");
+
+        let lock = CacheLock::acquire(&cache_file_path).unwrap();
+        assert_matches!(
+            CacheLock::acquire(&cache_file_path).err(),
+            Some(CacheLockError::InUse(_))
+        );
+        drop(lock);
+        assert!(CacheLock::acquire(&cache_file_path).is_ok());
+    }
+
+    #[test]
+    pub fn test_diff_download_cache_data() {
+        let old_data = "r181302019| \"Silizium\" (2019) by Apparat\r\n199396767| \"Galerie\" (2022) by Anomalie\r\n";
+        let mut cache = read_download_cache(old_data).unwrap();
+        cache.insert(
+            "p204514015".to_owned(),
+            DownloadCacheRelease::new("p204514015", "Toxic Violet Cubes", 2021, "Camellia"),
+        );
+
+        assert_eq!(
+            diff_download_cache_data(old_data, &serialize_download_cache(&cache), "my.cache"),
+            concat!(
+                "--- a/my.cache\n",
+                "+++ b/my.cache\n",
+                "-199396767| \"Galerie\" (2022) by Anomalie\n",
+                "+p199396767| \"Galerie\" (2022) by Anomalie\n",
+                "+p204514015| \"Toxic Violet Cubes\" (2021) by Camellia\n",
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_diff_download_cache_data_unchanged() {
+        let old_data = "p1| \"A\" (2020) by B\np2| \"C\" (2021) by D";
+        let new_data = "p2| \"C\" (2021) by D\np1| \"A\" (2020) by B";
+        assert_eq!(diff_download_cache_data(old_data, new_data, "my.cache"), "");
+    }
+
     #[test]
     pub fn test_serialize_normal_release() {
         let cache_release = DownloadCacheRelease {
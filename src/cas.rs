@@ -0,0 +1,157 @@
+use std::{
+    fmt::Write,
+    fs::{self, File, Metadata},
+    io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::archive;
+
+// Kept in the state folder, which has to be on the same filesystem as the tracks to hardlink them
+pub const BLOBS_FOLDER_NAME: &str = "blobs";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Absorbed {
+    // the first copy of its content, now also in the store
+    Stored,
+    // replaced by a link to identical content stored earlier, freeing its size
+    Linked(u64),
+    // a link into the store already
+    Unchanged,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DedupeReport {
+    pub tracks: usize,
+    pub linked: usize,
+    pub saved_bytes: u64,
+}
+
+impl DedupeReport {
+    pub fn summary(&self) -> String {
+        #[allow(clippy::cast_precision_loss)]
+        let saved_mb = self.saved_bytes as f64 / 1e6;
+        format!(
+            "{} of {} track(s) were duplicates, replaced by hardlinks saving {saved_mb:.1} MB",
+            self.linked, self.tracks
+        )
+    }
+}
+
+// Stores each distinct track once, by the SHA-256 of its content, with every copy in the release
+// folders being a hardlink to it. Singles later included on albums then only take space once.
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub const fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn absorb(&self, path: &Path) -> io::Result<Absorbed> {
+        let metadata = fs::metadata(path)?;
+        let hash = hash_file(path)?;
+        let blob_path = self.root.join(&hash[..2]).join(&hash);
+
+        let blob_metadata = match fs::metadata(&blob_path) {
+            Ok(blob_metadata) => blob_metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(blob_path.parent().unwrap_or(&self.root))?;
+                fs::hard_link(path, &blob_path)?;
+                return Ok(Absorbed::Stored);
+            }
+            Err(e) => return Err(e),
+        };
+        if is_same_file(&metadata, &blob_metadata) {
+            return Ok(Absorbed::Unchanged);
+        }
+
+        // linked aside first, so the track is never missing if this is interrupted
+        let partial_path = path.with_extension("cas-part");
+        fs::hard_link(&blob_path, &partial_path)?;
+        fs::rename(&partial_path, path)?;
+        Ok(Absorbed::Linked(metadata.len()))
+    }
+
+    // Hidden folders, such as the state and staging folders, are left alone
+    pub fn absorb_tree(&self, folder: &Path) -> io::Result<DedupeReport> {
+        let mut report = DedupeReport::default();
+        for path in archive::visible_files(folder)? {
+            if !archive::is_audio_file(&path) {
+                continue;
+            }
+
+            report.tracks += 1;
+            if let Absorbed::Linked(size) = self.absorb(&path)? {
+                report.linked += 1;
+                report.saved_bytes += size;
+            }
+        }
+        Ok(report)
+    }
+}
+
+// Hex encoded SHA-256
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+// Without file ids, a track already linked into the store is linked once more, which is harmless
+#[cfg(not(unix))]
+#[allow(clippy::missing_const_for_fn)]
+fn is_same_file(_: &Metadata, _: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_absorb_tree() {
+        let root = tempfile::tempdir().unwrap();
+        let single = root.path().join("Anomalie - Galerie");
+        let album = root.path().join("Anomalie - Odyssée");
+        fs::create_dir_all(&single).unwrap();
+        fs::create_dir_all(&album).unwrap();
+        fs::write(single.join("01 - Galerie.flac"), b"galerie").unwrap();
+        fs::write(album.join("01 - Intro.flac"), b"intro").unwrap();
+        fs::write(album.join("02 - Galerie.flac"), b"galerie").unwrap();
+        fs::write(album.join("cover.jpg"), b"galerie").unwrap();
+
+        let store = BlobStore::new(root.path().join(".bandcamp-dl").join(BLOBS_FOLDER_NAME));
+        let report = store.absorb_tree(root.path()).unwrap();
+        assert_eq!(
+            report,
+            DedupeReport {
+                tracks: 3,
+                linked: 1,
+                saved_bytes: 7,
+            }
+        );
+        assert_eq!(
+            fs::read(album.join("02 - Galerie.flac")).unwrap(),
+            b"galerie"
+        );
+
+        #[cfg(unix)]
+        assert_eq!(store.absorb_tree(root.path()).unwrap().linked, 0);
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use time::Date;
+
+use crate::{api::data::DownloadFormat, archive::ExtractorRegistry, error::ExtractionError};
+
+// What a downloaded release consisted of, to compare a download with the copy it replaces
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReleaseContents {
+    pub format: DownloadFormat,
+    pub size: u64,
+    // paths of the audio files in the archive, sorted
+    pub tracks: Vec<String>,
+}
+
+impl ReleaseContents {
+    pub fn read(
+        extractors: &ExtractorRegistry,
+        download: &Path,
+        format: DownloadFormat,
+    ) -> Result<Self, ExtractionError> {
+        let mut tracks: Vec<_> = extractors
+            .tracks(download)?
+            .iter()
+            .map(|track| track.to_string_lossy().into_owned())
+            .collect();
+        tracks.sort();
+
+        Ok(Self {
+            format,
+            size: std::fs::metadata(download)?.len(),
+            tracks,
+        })
+    }
+}
+
+// e.g. "p199396767-flac-changed-2024-05-01.txt", next to the release as it shares its folder with
+// the others
+pub fn changes_file_name(release_file_name: &str, date: Date) -> String {
+    let stem = release_file_name
+        .rsplit_once('.')
+        .map_or(release_file_name, |(stem, _)| stem);
+    format!("{stem}-changed-{date}.txt")
+}
+
+// `None` when nothing differs, such as for a release forced to be downloaded again as it was
+pub fn describe_changes(
+    title: &str,
+    previous: &ReleaseContents,
+    current: &ReleaseContents,
+) -> Option<String> {
+    let mut lines = Vec::new();
+    if previous.format != current.format {
+        lines.push(format!(
+            "Format: {} -> {}",
+            previous.format.name(),
+            current.format.name()
+        ));
+    }
+    if previous.size != current.size {
+        lines.push(format!("Size: {} -> {} bytes", previous.size, current.size));
+    }
+    if previous.tracks.len() != current.tracks.len() {
+        lines.push(format!(
+            "Tracks: {} -> {}",
+            previous.tracks.len(),
+            current.tracks.len()
+        ));
+    }
+    for track in &previous.tracks {
+        if !current.tracks.contains(track) {
+            lines.push(format!("- {track}"));
+        }
+    }
+    for track in &current.tracks {
+        if !previous.tracks.contains(track) {
+            lines.push(format!("+ {track}"));
+        }
+    }
+
+    if lines.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Changes to {title} since it was last downloaded:\n{}\n",
+        lines.join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    fn contents(format: DownloadFormat, size: u64, tracks: &[&str]) -> ReleaseContents {
+        ReleaseContents {
+            format,
+            size,
+            tracks: tracks.iter().map(|&track| track.to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    pub fn test_changes_file_name() {
+        let date = Date::from_calendar_date(2024, Month::May, 1).unwrap();
+        assert_eq!(
+            changes_file_name("p199396767-flac.zip", date),
+            "p199396767-flac-changed-2024-05-01.txt"
+        );
+    }
+
+    #[test]
+    pub fn test_describe_changes() {
+        let previous = contents(
+            DownloadFormat::Mp3_320,
+            1_000,
+            &["01 - Intro.mp3", "02 - Galerie.mp3"],
+        );
+        let current = contents(
+            DownloadFormat::Flac,
+            3_000,
+            &["01 - Intro.flac", "02 - Galerie (Remastered).flac"],
+        );
+
+        assert_eq!(
+            describe_changes("\"Galerie\" by Anomalie", &previous, &current).unwrap(),
+            "Changes to \"Galerie\" by Anomalie since it was last downloaded:\n\
+             Format: mp3-320 -> flac\n\
+             Size: 1000 -> 3000 bytes\n\
+             - 01 - Intro.mp3\n\
+             - 02 - Galerie.mp3\n\
+             + 01 - Intro.flac\n\
+             + 02 - Galerie (Remastered).flac\n"
+        );
+        assert_eq!(describe_changes("Galerie", &previous, &previous), None);
+    }
+}
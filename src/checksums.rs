@@ -0,0 +1,48 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{self, Versioned};
+
+// The SHA-256 of each download as it was committed, by where it was written, so a copy can later
+// be told apart from a damaged one. Kept beside the download cache like volumes.json, and by path
+// rather than release id, as with --cache-per-format a release is downloaded once per format.
+pub const CHECKSUMS_FILE_NAME: &str = "checksums.json";
+
+pub type ChecksumMap = BTreeMap<PathBuf, String>;
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ChecksumFile {
+    sha256: ChecksumMap,
+}
+
+impl Versioned for ChecksumFile {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+pub fn read_checksums(checksum_data: &str) -> Result<ChecksumMap, serde_json::Error> {
+    models::read_versioned::<ChecksumFile>(checksum_data).map(|file| file.sha256)
+}
+
+pub fn serialize_checksums(checksums: &ChecksumMap) -> Result<String, serde_json::Error> {
+    let file = ChecksumFile {
+        sha256: checksums.clone(),
+    };
+    serde_json::to_string_pretty(&models::versioned_value(&file)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_checksums_round_trip() {
+        let checksums = ChecksumMap::from([(
+            PathBuf::from("/music/Anomalie - Galaxie.zip"),
+            "ab".repeat(32),
+        )]);
+        let serialized = serialize_checksums(&checksums).unwrap();
+        assert_eq!(read_checksums(&serialized).unwrap(), checksums);
+    }
+}
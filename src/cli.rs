@@ -1,34 +1,348 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use tokio::task::JoinSet;
-use trauma::{download::Download, downloader::DownloaderBuilder};
+use anyhow::Context;
+use indicatif::HumanBytes;
+use reqwest::Url;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
 
 use crate::{
-    api::{self},
+    api::{self, StatDownload},
+    archive::{self, ExtractorRegistry, UpdateReport},
+    backpressure::WriterQueue,
+    bands,
+    browser::BrowserCookieSource,
     cache::{self, serialize_download_cache, DownloadCache, DownloadCacheRelease},
+    cas::{self, BlobStore},
+    changes::{self, ReleaseContents},
+    checksums::{self, ChecksumMap},
+    config::{self, Config},
+    confirm::{Answer, Confirmation},
+    controls::{self, KeyListener, RunControl},
+    cookies,
+    download::{self, ChunkedDownloader, Download},
+    error::{DigitalDownloadError, DownloadError, DownloadSizeError, InformationRetrievalError},
+    filter::{self, ExcludeList, ItemFilter},
+    friday::FridayOutlook,
+    journal::{self, EventKind, Journal},
+    layout::{self, LayoutProblem},
+    loudness::{self, LoudnessScanner},
+    markers::{self, MarkerKind, MaxAges, ProbeMarkers},
+    middlewares::{RequestMetrics, StatsExport},
+    models,
+    normalize::{ReleaseTags, TagNormalizer, TagTemplate},
+    notify::{Notifier, SyncEvent},
+    planner::{
+        DownloadPlanner, OfflineQueue, Plan, PlanEntry, PlannedDownload, QueuedDownload, Quota,
+    },
+    postprocess::{CompletedRelease, PostProcessor},
+    progress::{DownloadProgress, ProgressReporter},
+    report::{self, SyncReport},
+    schedule::{self, TimeBudget, WatchSchedule},
+    scratch::{self, ScratchKind},
+    session,
+    snapshot::{self, CollectionSnapshot},
+    staging::{self, StagingArea},
+    tagging::ArtEmbedder,
+    template::{self, NameTemplate, TemplateFields},
+    timestamp,
+    volumes::{self, VolumeMap, VolumeRouter},
 };
-use clap::Parser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+mod audit;
+mod auth;
+mod baseline;
+mod estimate;
+mod history;
+mod list;
+mod local;
+mod queue;
+mod schema;
+mod search;
+mod single;
+mod stream_rip;
+#[cfg(feature = "self-update")]
+mod update;
+mod whoami;
+mod wishlist;
 
 #[derive(Parser, Debug, PartialEq, Eq)]
 #[command(name = "bandcamp-dl")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    #[arg(short, long, value_hint = clap::ValueHint::FilePath)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Kept as two separate flattens, as clap never fills in an optional flatten of a struct
+    // that flattens another one itself
+    #[command(flatten)]
+    library: Option<LibraryArgs>,
+
+    #[command(flatten)]
+    sync: SyncArgs,
+
+    #[arg(long, global = true, env = "BANDCAMP_DL_TMP_DIR")]
+    #[arg(value_hint = clap::ValueHint::DirPath)]
+    #[arg(
+        help = "Folder for temporary data, such as downloads being staged and copies of browser cookie databases, e.g. on a scratch disk. Defaults to the system temp folder"
+    )]
+    tmp_dir: Option<PathBuf>,
+}
+
+impl Cli {
+    // Fills in what the command line leaves to the --profile, for the commands that take one
+    fn apply_profile(&mut self) -> anyhow::Result<()> {
+        let Some(command) = &mut self.command else {
+            return match &mut self.library {
+                Some(library) => apply_sync_profile(library, &mut self.sync),
+                None => Ok(()),
+            };
+        };
+        match command {
+            Command::Sync(args) | Command::Resolve(args) => {
+                apply_sync_profile(&mut args.library, &mut args.sync)
+            }
+            Command::Watch(args) => apply_sync_profile(&mut args.library, &mut args.sync),
+            Command::Download(args) => apply_sync_profile(&mut args.library, &mut args.sync),
+            Command::ExecutePlan(args) => apply_sync_profile(&mut args.library, &mut args.sync),
+            Command::Estimate(args) => {
+                let audio_format = args.library.apply_profile()?;
+                args.audio_format = args.audio_format.or(audio_format);
+                Ok(())
+            }
+            Command::Audit(args) => {
+                let audio_format = args.library.apply_profile()?;
+                args.audio_format = args.audio_format.or(audio_format);
+                Ok(())
+            }
+            Command::Baseline(args) => args.library.apply_profile().map(drop),
+            Command::List(args) => args.library.apply_profile().map(drop),
+            Command::Export(args) => args.library.apply_profile().map(drop),
+            Command::Whoami(args) => args.library.apply_profile().map(drop),
+            Command::Wishlist(args) => args.library.apply_profile().map(drop),
+            Command::Status(args) => args.apply_profile(),
+            Command::History(args) => args.local.apply_profile().map(drop),
+            Command::Cache(local::CacheCommand::List(args)) => args.local.apply_profile().map(drop),
+            Command::Verify(args) => args.local.apply_profile().map(drop),
+            Command::Dedupe(args) => args.local.apply_profile().map(drop),
+            Command::Stats(args) => args.local.apply_profile().map(drop),
+            Command::Renames(args) => args.local.apply_profile().map(drop),
+            Command::Clean(args) => args.local.apply_profile().map(drop),
+            Command::Auth(_) | Command::Search(_) | Command::Schema(_) => Ok(()),
+            #[cfg(feature = "self-update")]
+            Command::SelfUpdate(_) => Ok(()),
+        }
+    }
+}
+
+fn apply_sync_profile(library: &mut LibraryArgs, sync: &mut SyncArgs) -> anyhow::Result<()> {
+    let audio_format = library.apply_profile()?;
+    sync.audio_format = sync.audio_format.or(audio_format);
+    Ok(())
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+enum Command {
+    #[command(
+        about = "Download the releases of the collection missing from the download cache, the same as running without a subcommand"
+    )]
+    Sync(Box<SyncCommandArgs>),
+
+    #[command(
+        about = "Print the download links of the releases missing from the download cache, one per line, without downloading them"
+    )]
+    Resolve(Box<SyncCommandArgs>),
+
+    #[command(
+        about = "Keep running, syncing again after an interval (e.g. \"6h\") or whenever a --schedule matches, the same as `sync --watch`"
+    )]
+    Watch(Box<WatchCommandArgs>),
+
+    #[command(subcommand, visible_alias = "cookies")]
+    #[command(about = "Inspect the authentication cookies used for Bandcamp")]
+    Auth(auth::AuthCommand),
+
+    #[command(
+        about = "Check that the titles, artists and years in the download cache still match what Bandcamp reports, such as after typo fixes or renames, without downloading anything"
+    )]
+    Audit(audit::AuditArgs),
+
+    #[command(
+        about = "Mark every item currently in the collection as already downloaded, without downloading anything"
+    )]
+    Baseline(baseline::BaselineArgs),
+
+    #[command(
+        about = "Download a single release of the collection by its album or track page, without going through the whole collection"
+    )]
+    Download(Box<single::DownloadArgs>),
+
+    #[command(
+        about = "Download the releases queued by a sync with --offline-queue, resolving the links that expired since again"
+    )]
+    ExecutePlan(Box<queue::ExecutePlanArgs>),
+
+    #[command(
+        about = "Show what past syncs decided about each item: when it was discovered, downloaded, skipped or failed, and when it was hidden or unhidden"
+    )]
+    History(history::HistoryArgs),
+
+    #[command(subcommand)]
+    #[command(about = "Look at the download cache, without contacting Bandcamp")]
+    Cache(local::CacheCommand),
+
+    #[command(
+        about = "List the releases of the collection, and whether each one was downloaded yet"
+    )]
+    List(list::ListArgs),
+
+    #[command(
+        about = "Print the releases of the collection as JSON, the same as `list --format json`"
+    )]
+    Export(list::ExportArgs),
+
+    #[command(
+        about = "Check that every archive in the download folder opens and has tracks in it, without contacting Bandcamp"
+    )]
+    Verify(local::VerifyArgs),
+
+    #[command(
+        about = "Replace duplicate tracks in the download folder by hardlinks to a single stored copy, without contacting Bandcamp"
+    )]
+    Dedupe(local::DedupeArgs),
+
+    #[command(
+        about = "Summarize the download cache and the journal of past syncs, without contacting Bandcamp"
+    )]
+    Stats(local::StatsArgs),
+
+    #[command(
+        about = "Show how many releases of the collection are still to be downloaded, either online or from the last collection snapshot with --local-only"
+    )]
+    Status(local::StatusArgs),
+
+    #[command(
+        about = "Find artists whose releases were bought under several names, from the last collection snapshot, and suggest merging their folders"
+    )]
+    Renames(local::RenamesArgs),
+
+    #[command(
+        about = "Remove the temporary data syncs left behind: stale \".part\" files, the scratch folders of interrupted extractions, and offline queues whose links expired once all of their releases are downloaded"
+    )]
+    Clean(local::CleanArgs),
+
+    #[command(about = "Check that the cookies are of a logged in session, and print whose it is")]
+    Whoami(whoami::WhoamiArgs),
+
+    #[command(
+        about = "Sample a few pending releases to estimate how long and how large the next sync would be"
+    )]
+    Estimate(estimate::EstimateArgs),
+
+    #[command(
+        about = "List the items on the wishlist, optionally downloading those that are free or name your price"
+    )]
+    Wishlist(wishlist::WishlistArgs),
+
+    #[command(
+        about = "Search Bandcamp for artists, albums and tracks, optionally saving the cover art of those found, without logging in"
+    )]
+    Search(search::SearchArgs),
+
+    #[command(
+        about = "Print the JSON Schema of one of the machine readable outputs, for validating or generating code against it"
+    )]
+    Schema(schema::SchemaArgs),
+
+    #[cfg(feature = "self-update")]
+    #[command(
+        about = "Replace this binary by the latest release on GitHub, once its checksum and signature check out"
+    )]
+    SelfUpdate(update::SelfUpdateArgs),
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+struct SyncCommandArgs {
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    #[command(flatten)]
+    sync: SyncArgs,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+struct WatchCommandArgs {
+    #[arg(value_parser = schedule::parse_duration, group = "watch_mode")]
+    #[arg(required_unless_present_any = ["watch", "schedule"])]
+    #[arg(help = "How long to wait between syncs (e.g. \"6h\"), unless syncing on a --schedule")]
+    interval: Option<Duration>,
+
+    #[command(flatten)]
+    library: LibraryArgs,
+
+    #[command(flatten)]
+    sync: SyncArgs,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+struct LibraryArgs {
+    #[arg(short, long, value_hint = clap::ValueHint::AnyPath)]
+    #[arg(
+        help = "Cookie file to read, either in the `JSON` format exported from `Get \"cookies.txt\" Locally` (see: https://github.com/kairi003/Get-cookies.txt-LOCALLY) or in the Netscape `cookies.txt` format. May also be a directory of such exports, whose Bandcamp cookies are merged, keeping the latest expiring one of each."
+    )]
+    #[arg(required_unless_present_any = [
+        "cookies_from_browser",
+        "use_session",
+        "identity_cookie",
+        "profile",
+    ])]
+    cookie_file: Option<std::path::PathBuf>,
+
+    #[arg(long, value_name = "BROWSER[:PROFILE]", conflicts_with = "cookie_file")]
+    #[arg(
+        help = "Read the cookies straight from a browser instead of a cookie file: firefox, chrome, chromium or edge. A profile other than the default one can be picked by name or folder, e.g. \"firefox:work\" or \"chrome:Profile 1\". Needs a build with the \"browser-cookies\" feature"
+    )]
+    cookies_from_browser: Option<BrowserCookieSource>,
+
+    #[arg(
+        long,
+        value_name = "VALUE",
+        env = "BANDCAMP_IDENTITY",
+        hide_env_values = true
+    )]
+    #[arg(
+        help = "Value of Bandcamp's `identity` cookie, for machines without a browser to export cookies from. Only used when no cookie file, browser or saved session is given"
+    )]
+    identity_cookie: Option<String>,
+
+    #[arg(long, conflicts_with = "use_session")]
     #[arg(
-        help = "Cookie file to read, in the `JSON` format exported from `Get \"cookies.txt\" Locally` (see: https://github.com/kairi003/Get-cookies.txt-LOCALLY)."
+        help = "Save the Bandcamp session read from --cookie-file, --cookies-from-browser or --identity-cookie to the OS keyring, so later runs can use --use-session instead. Only the `identity` cookie is kept. Needs a build with the \"keyring\" feature"
     )]
-    cookie_file: std::path::PathBuf,
+    save_session: bool,
+
+    #[arg(long, conflicts_with_all = ["cookie_file", "cookies_from_browser"])]
+    #[arg(help = "Use the Bandcamp session saved to the OS keyring with --save-session")]
+    use_session: bool,
 
     #[arg(long)]
     #[arg(help = "Don't download hidden items in the collection")]
     skip_hidden: bool,
 
-    #[arg(long, value_enum, default_value_t = api::data::DownloadFormat::Flac)]
-    #[arg(help = "The audio format requested for newly downloaded audio")]
-    audio_format: api::data::DownloadFormat,
-
     #[arg(short, long, value_hint = clap::ValueHint::DirPath)]
-    #[arg(help = "Folder to download files to. Defaults to current directory")]
-    download_folder: Option<std::path::PathBuf>,
+    #[arg(
+        help = "Folder to download files to. Defaults to current directory. May be given several times to spread new releases over multiple drives, by free space; the cache and other state stay in the first one"
+    )]
+    download_folder: Vec<std::path::PathBuf>,
 
     #[arg(long, value_hint = clap::ValueHint::FilePath)]
     #[arg(
@@ -36,134 +350,4559 @@ pub struct Cli {
     )]
     cache_file: Option<std::path::PathBuf>,
 
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    #[arg(
+        help = "Folder for state kept between runs, such as the last collection snapshot. Defaults to \".bandcamp-dl\" in download_folder"
+    )]
+    state_dir: Option<std::path::PathBuf>,
+
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    #[arg(
+        help = "Configuration file to read, in the TOML format. With a --profile, defaults to \"bandcamp-dl/config.toml\" in the user's configuration folder"
+    )]
+    config: Option<std::path::PathBuf>,
+
+    #[arg(long, value_name = "NAME")]
+    #[arg(
+        help = "Use the `[profiles.<NAME>]` section of the configuration file, which may set the `cookie_file`, `download_folder`, `cache_file` and `audio_format` of an account. Options given on the command line take precedence"
+    )]
+    profile: Option<String>,
+
     #[arg(long)]
-    #[arg(help = "Fetch information but don't download anything")]
-    dry_run: bool,
-}
+    #[arg(
+        help = "Reject Bandcamp responses from servers whose certificate public key doesn't match one of the `tls_pins` in the configuration file (pins use curl's \"sha256//<base64>\" format)"
+    )]
+    verify_tls_pins: bool,
 
-pub async fn run_program(cli: Cli) -> anyhow::Result<()> {
-    let download_folder = cli
-        .download_folder
-        .unwrap_or_else(|| std::env::current_dir().expect("error getting cwd"));
-    let cache_file_path = cli
-        .cache_file
-        .unwrap_or_else(|| download_folder.join("./bandcamp-collection-downloader.cache"));
+    #[arg(long, value_name = "MARKER=DURATION", value_parser = markers::parse_max_age)]
+    #[arg(
+        help = "How long to remember that a release had nothing to download before probing it again: no-digital-item (7 days by default) for pages without a digital download, unavailable (30 days by default) for pages that are gone. e.g. \"unavailable=14d\", or \"unavailable=0\" to probe them on every sync. May be given once for each"
+    )]
+    max_age: Vec<(markers::MarkerKind, Duration)>,
 
-    println!("Download folder: {download_folder:?}");
+    #[arg(long, default_value_t = DEFAULT_SCRAPE_CONCURRENCY, value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(
+        help = "Number of release pages fetched at the same time while looking for new releases"
+    )]
+    scrape_concurrency: u16,
 
-    let mut download_cache = if std::fs::exists(&cache_file_path)? {
-        println!("Download cache exists. Parsing...");
-        let download_cache_data = std::fs::read_to_string(&cache_file_path)?;
-        cache::read_download_cache(&download_cache_data)?
-    } else {
-        DownloadCache::new()
+    #[arg(long, value_name = "PATTERN", value_parser = filter::parse_pattern)]
+    #[arg(
+        help = "Only look at the items whose artist matches this regular expression, case insensitively, so a plain name matches anywhere in the artist. With a \"glob:\" prefix, a glob the whole artist has to match instead, such as \"glob:The *\""
+    )]
+    filter_artist: Option<String>,
+
+    #[arg(long, value_name = "PATTERN", value_parser = filter::parse_pattern)]
+    #[arg(
+        help = "Only look at the items whose title matches this regular expression, case insensitively, or with a \"glob:\" prefix, this glob of the whole title"
+    )]
+    filter_title: Option<String>,
+
+    #[arg(long, value_name = "DATE", value_parser = filter::parse_date)]
+    #[arg(help = "Only look at the items bought on or after this day, as YYYY-MM-DD")]
+    purchased_after: Option<time::Date>,
+
+    #[arg(long, value_name = "DATE", value_parser = filter::parse_date)]
+    #[arg(help = "Only look at the items bought before this day, as YYYY-MM-DD")]
+    purchased_before: Option<time::Date>,
+
+    #[arg(long, value_enum)]
+    #[arg(help = "Only look at the albums, or only at the tracks bought on their own")]
+    only: Option<filter::ItemKind>,
+
+    #[arg(long, conflicts_with = "exclude_subscriptions")]
+    #[arg(
+        help = "Only look at the items received through artist subscriptions, keeping them apart in a \"subscriptions\" folder of each download folder, with their own cache and state"
+    )]
+    only_subscriptions: bool,
+
+    #[arg(long)]
+    #[arg(help = "Leave out the items received through artist subscriptions")]
+    exclude_subscriptions: bool,
+
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    #[arg(
+        help = "File of items never to download, one per line: a sale id such as \"p199396767\", or \"artist: <pattern>\" or \"title: <pattern>\" matched like --filter-artist and --filter-title. Lines starting with # are comments"
+    )]
+    exclude_file: Option<std::path::PathBuf>,
+}
+
+fn read_config(config_path: Option<&Path>) -> anyhow::Result<Config> {
+    match config_path {
+        Some(config_path) => Ok(config::read_config(&std::fs::read_to_string(config_path)?)?),
+        None => Ok(Config::default()),
+    }
+}
+
+// The `[profiles.<NAME>]` section of the configuration file, which is then also the one read for
+// everything else when --config leaves it to the default location
+fn read_profile(config_path: &mut Option<PathBuf>, name: &str) -> anyhow::Result<config::Profile> {
+    if config_path.is_none() {
+        *config_path = config::default_config_path();
+    }
+    let Some(path) = config_path.as_deref() else {
+        anyhow::bail!(
+            "No configuration file to read profile \"{name}\" from, pass it with --config"
+        );
+    };
+    let mut config = read_config(Some(path))
+        .with_context(|| format!("Failed reading the configuration file {}", path.display()))?;
+    let Some(profile) = config.profiles.remove(name) else {
+        let names: Vec<_> = config.profiles.keys().map(String::as_str).collect();
+        anyhow::bail!(
+            "No profile named \"{name}\" in the configuration file, it has: {}",
+            if names.is_empty() {
+                "none".to_owned()
+            } else {
+                names.join(", ")
+            }
+        );
     };
+    Ok(profile)
+}
 
-    // build app context
-    let cookie_data = std::fs::read_to_string(cli.cookie_file)?;
-    let api_context = Arc::new(api::BandcampAPIContext::new(&cookie_data)?);
+fn client_options(config: Config, verify_tls_pins: bool) -> anyhow::Result<api::ClientOptions> {
+    if verify_tls_pins && config.tls_pins.is_empty() {
+        anyhow::bail!("--verify-tls-pins requires `tls_pins` to be set in the configuration file");
+    }
 
-    println!("Retrieving Bandcamp Summary...");
-    let fan_summary = api_context.get_summary().await?;
+    Ok(api::ClientOptions {
+        tls_pins: verify_tls_pins.then_some(config.tls_pins),
+        accept_language: config
+            .accept_language
+            .unwrap_or_else(|| api::DEFAULT_ACCEPT_LANGUAGE.to_owned()),
+        metrics: None,
+    })
+}
 
-    println!("Retrieving all releases...");
-    let releases = api_context
-        .get_all_releases(&fan_summary, !cli.skip_hidden)
-        .await?;
+impl LibraryArgs {
+    // The first of the download folders, which also holds the cache and state
+    fn download_folder(&self) -> PathBuf {
+        let download_folder = self
+            .download_folder
+            .first()
+            .cloned()
+            .unwrap_or_else(|| std::env::current_dir().expect("error getting cwd"));
+        self.scoped_folder(download_folder)
+    }
 
-    // finding releases not found in regular scopes
-    println!("Finding new releases...");
-    let items_to_download = find_new_releases(releases, &download_cache, &api_context).await?;
+    fn download_folders(&self) -> Vec<PathBuf> {
+        if self.download_folder.is_empty() {
+            vec![self.download_folder()]
+        } else {
+            self.download_folder
+                .iter()
+                .map(|folder| self.scoped_folder(folder.clone()))
+                .collect()
+        }
+    }
 
-    if items_to_download.is_empty() {
-        println!("No new releases to fetch, exiting");
-        return Ok(());
+    fn scoped_folder(&self, download_folder: PathBuf) -> PathBuf {
+        if self.only_subscriptions {
+            download_folder.join(filter::SUBSCRIPTIONS_FOLDER_NAME)
+        } else {
+            download_folder
+        }
     }
 
-    // fetch all download links
-    println!("Fetching releases in {}...", cli.audio_format);
+    fn max_ages(&self) -> MaxAges {
+        let mut max_ages = MaxAges::default();
+        for &(kind, max_age) in &self.max_age {
+            max_ages.set(kind, max_age);
+        }
+        max_ages
+    }
 
-    let mut retrieve_download_links_tasks = JoinSet::new();
-    for (key, digital_item) in items_to_download {
-        let api_context = Arc::clone(&api_context);
-        retrieve_download_links_tasks.spawn(async move {
-            let result = api_context
-                .get_digital_download_link(&digital_item, cli.audio_format)
-                .await;
-            (result, digital_item, key)
-        });
+    fn item_filter(&self) -> anyhow::Result<ItemFilter> {
+        let exclusions = match &self.exclude_file {
+            Some(exclude_file) => {
+                let exclude_data = std::fs::read_to_string(exclude_file)
+                    .with_context(|| format!("Failed reading {}", exclude_file.display()))?;
+                ExcludeList::parse(&exclude_data)
+                    .with_context(|| format!("Failed to parse {}", exclude_file.display()))?
+            }
+            None => ExcludeList::default(),
+        };
+
+        Ok(
+            ItemFilter::new(self.filter_artist.as_deref(), self.filter_title.as_deref())?
+                .purchased_between(self.purchased_after, self.purchased_before)
+                .only(self.only)
+                .subscription_items(
+                    (self.only_subscriptions || self.exclude_subscriptions)
+                        .then_some(self.only_subscriptions),
+                )
+                .excluding(exclusions),
+        )
     }
 
-    let mut downloads = Vec::new();
+    fn collection_scopes(&self) -> Vec<api::CollectionScope> {
+        if self.skip_hidden {
+            vec![api::CollectionScope::Collection]
+        } else {
+            vec![
+                api::CollectionScope::Collection,
+                api::CollectionScope::Hidden,
+            ]
+        }
+    }
 
-    while let Some(result) = retrieve_download_links_tasks.join_next().await {
-        let (result, digital_item, key) = result?;
-        let url = result?;
+    fn read_config(&self) -> anyhow::Result<Config> {
+        read_config(self.config.as_deref())
+    }
 
-        let cached_item =
-            DownloadCacheRelease::new(&key, &digital_item.title, 2022, &digital_item.artist); // TODO year
-        download_cache.insert(key.clone(), cached_item);
+    // Fills in what the command line leaves to the --profile, returning its audio format
+    fn apply_profile(&mut self) -> anyhow::Result<Option<api::data::DownloadFormat>> {
+        let Some(name) = &self.profile else {
+            return Ok(None);
+        };
+        let profile = read_profile(&mut self.config, name)?;
 
-        if !cli.dry_run {
-            let mut download = Download::try_from(url.as_str()).unwrap();
-            download.filename = format!("{key}-{0}.zip", cli.audio_format);
-            downloads.push(download);
+        // the identity cookie only comes after a cookie file
+        if self.cookie_file.is_none() && self.cookies_from_browser.is_none() && !self.use_session {
+            self.cookie_file = profile.cookie_file;
         }
+        if self.download_folder.is_empty() {
+            self.download_folder = profile.download_folder;
+        }
+        self.cache_file = self.cache_file.take().or(profile.cache_file);
+        Ok(profile.audio_format)
+    }
 
-        println!(
-            "Download link for \"{}\" by {} ({}): {}",
-            digital_item.title, digital_item.artist, key, url
-        );
+    fn client_options(&self) -> anyhow::Result<api::ClientOptions> {
+        client_options(self.read_config()?, self.verify_tls_pins)
     }
 
-    if cli.dry_run {
-        println!("Dry run, so not downloading anything...");
-        return Ok(());
+    // The subscription items' cache and state are kept apart even when given explicitly, as the
+    // default ones already are by being in the subscriptions folder
+    fn cache_file_path(&self, download_folder: &Path) -> PathBuf {
+        match &self.cache_file {
+            Some(cache_file) if self.only_subscriptions => {
+                cache::scoped_cache_path(cache_file, filter::SUBSCRIPTIONS_FOLDER_NAME)
+            }
+            Some(cache_file) => cache_file.clone(),
+            None => download_folder.join(DEFAULT_CACHE_FILE_NAME),
+        }
     }
 
-    let downloader = DownloaderBuilder::new().directory(download_folder).build();
-    downloader.download(&downloads).await;
+    fn state_dir(&self, download_folder: &Path) -> PathBuf {
+        match &self.state_dir {
+            Some(state_dir) if self.only_subscriptions => {
+                state_dir.join(filter::SUBSCRIPTIONS_FOLDER_NAME)
+            }
+            Some(state_dir) => state_dir.clone(),
+            None => default_state_dir(download_folder),
+        }
+    }
 
-    println!("Updating download cache...");
-    std::fs::write(cache_file_path, serialize_download_cache(&download_cache))?;
+    fn read_cookies(&self) -> anyhow::Result<String> {
+        if self.use_session {
+            let identity =
+                session::load_identity().context("Failed reading the saved Bandcamp session")?;
+            return Ok(session::identity_cookie_data(&identity)?);
+        }
 
-    Ok(())
-}
+        let cookie_data = self.read_cookie_source()?;
+        if self.save_session {
+            save_session(&cookie_data)?;
+        }
+        Ok(cookie_data)
+    }
 
-async fn find_new_releases(
-    releases: api::SaleIdUrlMap,
-    download_cache: &cache::DownloadCache,
-    api_context: &Arc<api::BandcampAPIContext>,
-) -> Result<HashMap<String, api::data::DigitalItem>, anyhow::Error> {
-    let mut digital_item_tasks = JoinSet::new();
-    for (key, item_url) in &releases {
-        if !download_cache.contains_key(key) {
-            let api_context_clone = Arc::clone(api_context);
-
-            // Clone `item_url` and `key` for use in the async block
-            let item_url_clone = item_url.clone();
-            let key_clone = key.clone();
-
-            digital_item_tasks.spawn(async move {
-                let result = api_context_clone
-                    .get_digital_download_item(&item_url_clone)
-                    .await;
-                (result, key_clone)
-            });
+    fn read_cookie_source(&self) -> anyhow::Result<String> {
+        match (&self.cookie_file, &self.cookies_from_browser) {
+            (_, Some(source)) => {
+                let raw_cookies = source
+                    .read_cookies()
+                    .with_context(|| format!("Failed reading the cookies of {}", source.browser))?;
+                Ok(serde_json::to_string(&cookies::merge_raw_cookies(
+                    raw_cookies,
+                ))?)
+            }
+            (Some(cookie_file), None) => read_cookie_data(cookie_file),
+            (None, None) => match &self.identity_cookie {
+                Some(identity) => Ok(session::identity_cookie_data(&cookies::identity_cookie(
+                    identity,
+                ))?),
+                None => anyhow::bail!(
+                    "Either --cookie-file, --cookies-from-browser, --use-session, --identity-cookie or a --profile with a `cookie_file` is needed"
+                ),
+            },
         }
     }
 
-    let mut items_to_download = HashMap::new();
-    while let Some(task_result) = digital_item_tasks.join_next().await {
-        let (digital_item_result, key) = task_result?;
-        if let Some(item_data) = digital_item_result? {
-            println!(
-                "New item: \"{}\" by \"{}\" ({})",
-                item_data.title, item_data.artist, key
-            );
-            items_to_download.insert(key, item_data);
+    // Dry runs decide nothing, so they leave the journal alone
+    fn open_journal(&self, enabled: bool) -> Journal {
+        if !enabled {
+            return Journal::disabled();
         }
+
+        let journal_path = self
+            .state_dir(&self.download_folder())
+            .join(journal::JOURNAL_FILE_NAME);
+        Journal::open(&journal_path).unwrap_or_else(|e| {
+            eprintln!("Failed opening the journal {}: {e}", journal_path.display());
+            Journal::disabled()
+        })
     }
+}
 
-    Ok(items_to_download)
+#[derive(Args, Clone, Debug, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+struct SyncArgs {
+    #[arg(long, value_enum)]
+    #[arg(
+        help = "The audio format requested for newly downloaded audio. Defaults to the --profile's `audio_format`, or flac"
+    )]
+    audio_format: Option<api::data::DownloadFormat>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Keep a separate download cache for the --audio-format, e.g. \"bandcamp-collection-downloader.flac.cache\", for keeping the same collection in several formats from one shared cache location"
+    )]
+    cache_per_format: bool,
+
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "plan")]
+    #[arg(
+        help = "Fetch information but don't download anything. With --dry-run=layout, also show the path each new release would be saved to, flagging files that already exist or would collide"
+    )]
+    dry_run: Option<DryRun>,
+
+    #[arg(long, requires = "dry_run")]
+    #[arg(
+        help = "With --dry-run, show the lines the download cache file would lose and gain, in a diff like format"
+    )]
+    cache_diff: bool,
+
+    #[arg(long, value_name = "FILE", requires = "dry_run", value_hint = clap::ValueHint::FilePath)]
+    #[arg(
+        help = "With --dry-run, write the releases that would be downloaded to this file, as JSON"
+    )]
+    plan_json: Option<std::path::PathBuf>,
+
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    #[arg(conflicts_with_all = ["dry_run", "print_urls_only", "stream_rip", "include_wishlist", "watch", "schedule"])]
+    #[arg(
+        help = "Find and resolve the new releases, but write their download links to this file instead of downloading them, for `execute-plan` to download later, such as from another network"
+    )]
+    offline_queue: Option<std::path::PathBuf>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Only print the download links of new releases, one per line, without downloading them"
+    )]
+    print_urls_only: bool,
+
+    #[arg(long, value_parser = schedule::parse_duration)]
+    #[arg(
+        help = "Stop starting new downloads that aren't expected to finish within this time of the sync starting (e.g. \"90m\" or \"2h\"), downloading smaller releases first"
+    )]
+    time_budget: Option<Duration>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Only fetch this many of the new releases in this run, for working through a large backlog in batches. Releases are taken in order of their sale id"
+    )]
+    limit: Option<usize>,
+
+    #[arg(long, default_value_t = 0)]
+    #[arg(help = "Skip this many of the new releases before fetching any, in the same order")]
+    offset: usize,
+
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(
+        help = "Number of parallel connections used to download releases larger than --chunked-download-threshold. With 1, they're still downloaded in a single range, which can be resumed"
+    )]
+    download_connections: u16,
+
+    #[arg(long, default_value_t = DEFAULT_RESOLVE_CONCURRENCY, value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(
+        help = "Number of download links resolved at the same time. A release Bandcamp is still preparing waits for its next try without taking one of them"
+    )]
+    resolve_concurrency: u16,
+
+    #[arg(long, default_value_t = 1024)]
+    #[arg(
+        help = "Size in MB above which a release is downloaded in parallel chunks, if the server supports range requests. The chunks are kept as \".part\" files in the staging folder when a download fails or is interrupted, and resumed by the next sync"
+    )]
+    chunked_download_threshold: u64,
+
+    #[arg(long, value_name = "MB", default_value_t = 1024)]
+    #[arg(
+        help = "Most MB of completed downloads kept in the staging folder when it's on another filesystem than download_folder, which are then moved over before downloading more. When download_folder is a network mount (NFS, SMB...), fewer releases are also downloaded at once while it writes slower than they come in"
+    )]
+    max_staged: u64,
+
+    #[arg(long, value_name = "TEMPLATE")]
+    #[arg(
+        help = "Where each release is saved within its download folder, with \"/\" separating folders, e.g. \"{artist}/{year} - {title}\". Takes the {artist}, {title}, {year} (or {release_year}), {release_date} (as YYYY-MM-DD), {item_type} and {sale_id} fields, with the modifiers of --stream-folder-template, and \".zip\" is added to the name. Without it, releases are saved side by side as \"<sale id>-(<format>).zip\""
+    )]
+    path_template: Option<NameTemplate>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Also save owned items that offer no download as per-track stream captures. These are lossy MP3-128 copies, not the purchased quality, and are kept apart in a \"lossy-stream\" folder"
+    )]
+    stream_rip: bool,
+
+    #[arg(long, value_name = "TEMPLATE", default_value = template::DEFAULT_FOLDER_TEMPLATE)]
+    #[arg(
+        help = "Name of the folder each --stream-rip item is saved to. Takes the {artist} and {title} fields, each optionally followed by modifiers applied in order: |lower, |upper, |slug for lowercase ASCII words joined by dashes, and |truncate(N) to keep at most N characters, e.g. \"{artist|lower} - {title|slug|truncate(60)}\""
+    )]
+    stream_folder_template: NameTemplate,
+
+    #[arg(long)]
+    #[arg(
+        help = "Also download the wishlist items that are free, or name your price with nothing to pay, into a \"wishlist\" folder. They're not recorded in the download cache"
+    )]
+    include_wishlist: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "After the sync, store each distinct track of the download folders once, by its hash, and replace duplicates by hardlinks. Only finds tracks already extracted, such as by a post-processor"
+    )]
+    dedupe_tracks: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "Transliterate non-ASCII characters of artists and titles to ASCII in generated file and folder names, and in the names of the files --extract unpacks, for FAT32 drives and players that can't display them"
+    )]
+    ascii_filenames: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "Write a summary of the run, with cover art and links, to \"bandcamp-dl-report.html\" in the download folder"
+    )]
+    html_report: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "Count the audio files in each downloaded archive against the release's track list on Bandcamp. Incomplete ones are left out of the download cache, and downloaded again on the next sync. Preorders, which only come with the tracks out so far, and track durations aren't checked"
+    )]
+    verify_tracks: bool,
+
+    #[arg(long, value_name = "PROGRAM", value_hint = clap::ValueHint::CommandName)]
+    #[arg(
+        help = "Run this program after each release is downloaded, in the folder it was saved to. It gets the release as a line of JSON on stdin, with its \"version\", \"release_id\", \"title\", \"artist\", \"year\", \"format\", \"page_url\" and \"path\". May be given several times, to run several programs in order"
+    )]
+    post_processor: Vec<std::path::PathBuf>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Unpack each downloaded album into an \"<artist>/<album>\" folder next to its archive, or with --path-template one named like the archive, and check every file of the archive made it there. Single tracks aren't archives, and are left as they are"
+    )]
+    extract: bool,
+
+    #[arg(long, requires = "extract")]
+    #[arg(
+        help = "With --extract, delete each archive once it was fully unpacked. A release downloaded again can then no longer be compared with its earlier version"
+    )]
+    delete_archives: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "Save each release's cover art next to it, as \"cover.jpg\" in the folder --extract unpacks it to, or named like the archive otherwise"
+    )]
+    save_art: bool,
+
+    #[arg(long, value_enum, default_value_t = api::data::ArtSize::Original, requires = "save_art")]
+    #[arg(
+        help = "Size of the cover art --save-art saves, the original upload (which may be a PNG) or the width in pixels Bandcamp scales it to"
+    )]
+    art_size: api::data::ArtSize,
+
+    #[arg(long, requires_all = ["save_art", "extract"], value_parser = feature_flag("embed-art", cfg!(feature = "embed-art")))]
+    #[arg(
+        help = "With --save-art and --extract, also embed the cover art into each unpacked audio file as its front cover, replacing the one it came with. Needs a build with the \"embed-art\" feature"
+    )]
+    embed_art: bool,
+
+    #[arg(long, requires = "extract", value_parser = feature_flag("normalize-tags", cfg!(feature = "normalize-tags")))]
+    #[arg(
+        help = "With --extract, rewrite the tags of each unpacked track the same way whatever the label: the album artist and album from Bandcamp, the year from its release date, and track numbers with their total, in the order of the file names and by disc for releases unpacked into a folder per disc. Needs a build with the \"normalize-tags\" feature"
+    )]
+    normalize_tags: bool,
+
+    #[arg(long, value_name = "FIELD=TEMPLATE", requires = "normalize_tags")]
+    #[arg(
+        help = "With --normalize-tags, also set FIELD of each track to TEMPLATE, which takes the fields and modifiers of --path-template, e.g. \"COMMENT=bandcamp {sale_id}\" or \"GROUPING={artist|upper}\". Fields are named as in Vorbis comments, such as LABEL or GROUPING, and carried over to each format's own. Can be given several times"
+    )]
+    tag_template: Vec<TagTemplate>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Print how many requests were made to each kind of Bandcamp endpoint at the end of the run, with their error counts and latencies. Downloads themselves aren't counted"
+    )]
+    stats: bool,
+
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    #[arg(help = "Write the request statistics of --stats to this file, as JSON")]
+    stats_json: Option<std::path::PathBuf>,
+
+    #[arg(long, value_name = "SALE_ID")]
+    #[arg(
+        help = "Download this item (e.g. \"p199396767\") again even though the cache has it, overwriting the existing file. May be given several times"
+    )]
+    force_item: Vec<String>,
+
+    #[arg(long, value_name = "NAME")]
+    #[arg(
+        help = "Download every item by this artist again even though the cache has them, overwriting the existing files. Matches the artist name case insensitively, and may be given several times"
+    )]
+    force_artist: Vec<String>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Also look up the releases the cache has, and download those again whose archive in a download folder no longer matches the size Bandcamp lists, such as after the artist replaced some tracks. With --extract, only the tracks that changed are replaced, and what changed is reported for each release"
+    )]
+    check_updated: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "Download new releases even when a file of theirs is already in a download folder. Without it, such a file is taken as downloaded and recorded in the cache, for libraries downloaded before there was a cache"
+    )]
+    overwrite: bool,
+
+    #[arg(long)]
+    #[arg(
+        help = "With several download folders, keep each artist's new releases on the folder their earlier ones landed on, as long as it has room"
+    )]
+    pin_artists: bool,
+
+    #[arg(long, requires = "pin_artists")]
+    #[arg(
+        help = "With --pin-artists, keep releases together by Bandcamp's band id rather than by artist name, so an artist that renamed themselves stays on one folder"
+    )]
+    group_by_band_id: bool,
+
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    #[arg(
+        help = "Folder downloads are written to, as \".part\" files, until they complete and check out. Defaults to a folder of --tmp-dir when it's given, then to the system temp folder when it's on the same filesystem as download_folder, and to \".bandcamp-dl/staging\" in download_folder otherwise"
+    )]
+    staging_dir: Option<std::path::PathBuf>,
+
+    #[arg(short = 'y', long, visible_alias = "confirm")]
+    #[arg(help = "Go ahead with large or destructive operations without asking first")]
+    assume_yes: bool,
+
+    #[arg(long, default_value_t = 50)]
+    #[arg(
+        help = "Size in GB above which a first sync (with an empty download cache) asks for confirmation"
+    )]
+    confirm_first_sync_above: u64,
+
+    #[arg(long, value_parser = schedule::parse_duration, conflicts_with = "dry_run", group = "watch_mode")]
+    #[arg(
+        help = "Keep running, syncing again after this long (e.g. \"6h\"). A failed sync is reported and retried at the next interval"
+    )]
+    watch: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "CRON",
+        conflicts_with = "dry_run",
+        group = "watch_mode"
+    )]
+    #[arg(
+        help = "Keep running, syncing whenever this cron expression matches, in UTC (e.g. \"0 3 * * *\" for every night at 3:00). Takes the usual five fields: minute, hour, day of month, month and day of week"
+    )]
+    schedule: Option<schedule::cron::CronSchedule>,
+
+    #[arg(long, value_parser = schedule::parse_duration, requires = "watch_mode")]
+    #[arg(
+        help = "In watch mode, wait up to this long (e.g. \"15m\") more before each sync, picked at random"
+    )]
+    jitter: Option<Duration>,
+
+    #[arg(long, requires = "watch_mode")]
+    #[arg(
+        help = "Show a desktop notification after each sync in watch mode, including failures such as expired cookies. Needs a build with the \"notifications\" feature"
+    )]
+    notify: bool,
+
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, requires = "watch_mode")]
+    #[arg(
+        help = "In watch mode, append the outcome of each sync to this file as a line of JSON, with when the next Bandcamp Friday is"
+    )]
+    notify_json: Option<std::path::PathBuf>,
+
+    #[arg(long, conflicts_with_all = ["print_urls_only", "stream_rip", "include_wishlist", "html_report", "watch", "schedule"])]
+    #[arg(
+        help = "Don't contact Bandcamp, only list the releases missing from the download cache according to the last collection snapshot"
+    )]
+    offline: bool,
+}
+
+impl SyncArgs {
+    // Rather than only showing or queueing what would be downloaded
+    const fn downloads(&self) -> bool {
+        self.dry_run.is_none() && !self.print_urls_only && self.offline_queue.is_none()
+    }
+
+    // The options only a sync of the collection uses, for the commands that download releases found
+    // some other way to reject with reject_options
+    const fn collection_options(&self) -> [(&'static str, bool); 6] {
+        [
+            ("--watch", self.watch.is_some()),
+            ("--schedule", self.schedule.is_some()),
+            ("--offline", self.offline),
+            ("--stream-rip", self.stream_rip),
+            ("--include-wishlist", self.include_wishlist),
+            ("--check-updated", self.check_updated),
+        ]
+    }
+
+    fn audio_format(&self) -> api::data::DownloadFormat {
+        self.audio_format.unwrap_or(api::data::DownloadFormat::Flac)
+    }
+
+    // A --path-template already names the release, so its archive is unpacked into a folder of the
+    // same name
+    fn extraction_folder(&self, archive: &Path, release: &DownloadCacheRelease) -> PathBuf {
+        if self.path_template.is_some() {
+            return archive.with_extension("");
+        }
+        archive.with_file_name(layout::release_folder(
+            release.artist(),
+            release.title(),
+            self.ascii_filenames,
+        ))
+    }
+
+    fn release_path(&self, planned: &PlannedDownload) -> PathBuf {
+        layout::release_path(
+            self.path_template.as_ref(),
+            &planned.sale_id,
+            &planned.item,
+            planned.format,
+            self.ascii_filenames,
+        )
+    }
+
+    // Where a release of the cache was saved by the --path-template, as far as the cache tells.
+    // Without one, paths are named by sale id and can't be taken by another release.
+    fn cached_release_path(&self, release: &DownloadCacheRelease) -> Option<PathBuf> {
+        let fields = TemplateFields {
+            artist: release.artist(),
+            title: release.title(),
+            year: (release.year() != 0).then_some(release.year()),
+            // the cache only has the year
+            release_date: None,
+            item_type: "",
+            sale_id: release.release_id(),
+        };
+        self.path_template
+            .as_ref()
+            .map(|template| layout::render_release_path(template, &fields, self.ascii_filenames))
+    }
+}
+
+// Where each planned release is saved, apart from the other releases of the plan and those
+// downloaded before
+fn release_paths(
+    cli: &SyncArgs,
+    planned_downloads: &[PlannedDownload],
+    download_cache: &DownloadCache,
+) -> HashMap<String, PathBuf> {
+    layout::assign_release_paths(
+        planned_downloads
+            .iter()
+            .map(|planned| (planned.sale_id.clone(), cli.release_path(planned))),
+        download_cache.values().filter_map(|release| {
+            let path = cli.cached_release_path(release)?;
+            Some((release.release_id().to_owned(), path))
+        }),
+    )
+}
+
+// For a flag only a build with `feature` acts on, which is refused while parsing otherwise rather
+// than ignored
+fn feature_flag(
+    feature: &'static str,
+    built_with: bool,
+) -> impl Fn(&str) -> Result<bool, String> + Clone + Send + Sync + 'static {
+    move |value| {
+        let set = value
+            .parse()
+            .map_err(|e: std::str::ParseBoolError| e.to_string())?;
+        if set && !built_with {
+            return Err(format!("this build doesn't have the \"{feature}\" feature"));
+        }
+        Ok(set)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum DryRun {
+    Plan,
+    Layout,
+}
+
+const DEFAULT_CACHE_FILE_NAME: &str = "./bandcamp-collection-downloader.cache";
+
+fn default_state_dir(download_folder: &Path) -> PathBuf {
+    download_folder.join(".bandcamp-dl")
+}
+
+struct PendingDownload {
+    download: Download,
+    // where it's saved within its download folder, while `download` is named flat in staging
+    path: PathBuf,
+    expected_bytes: Option<u64>,
+    release: DownloadCacheRelease,
+    planned: PlannedDownload,
+    // the cover art fetched along, by its name in staging
+    art: Option<String>,
+}
+
+// Number of downloads started together when a time budget is set, so throughput is measured
+// before committing to the larger releases
+const TIME_BUDGET_BATCH_SIZE: usize = 4;
+
+// Most downloads started together from the links resolved so far
+const DOWNLOAD_BATCH_SIZE: usize = 8;
+
+// Completed downloads the writer may fall behind by before downloading waits for it
+const COMMIT_QUEUE_DEPTH: usize = 2 * DOWNLOAD_BATCH_SIZE;
+
+// Resolved links waiting for a download to start, before resolving pauses
+const RESOLVED_LINKS_CAPACITY: usize = 16;
+
+const DEFAULT_RESOLVE_CONCURRENCY: u16 = 8;
+
+// Release pages fetched together while looking for new releases, rather than every page of a
+// large collection requested at once
+const DEFAULT_SCRAPE_CONCURRENCY: u16 = 8;
+
+// Status output, silenced when only the download links should be printed
+macro_rules! status {
+    ($verbose:expr, $($arg:tt)*) => {
+        if $verbose {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Printed to stderr, so it stays out of the links of --print-urls-only
+const LEGACY_SYNC_WARNING: &str = "Running without a subcommand is deprecated and will stop working in a future version. Use `bandcamp-dl sync` with the same options instead";
+
+pub async fn run_program(mut cli: Cli) -> anyhow::Result<()> {
+    if let Some(tmp_dir) = cli.tmp_dir.take() {
+        staging::set_temp_dir(tmp_dir);
+    }
+
+    cli.apply_profile()?;
+
+    match (cli.command, cli.library) {
+        (Some(Command::Sync(args)), _) => run_sync(args.library, args.sync).await,
+        (Some(Command::Resolve(args)), _) => {
+            let sync = SyncArgs {
+                print_urls_only: true,
+                ..args.sync
+            };
+            run_sync(args.library, sync).await
+        }
+        (Some(Command::Watch(args)), _) => {
+            let sync = SyncArgs {
+                watch: args.sync.watch.or(args.interval),
+                ..args.sync
+            };
+            run_sync(args.library, sync).await
+        }
+        (Some(Command::Auth(command)), _) => auth::run(command).await,
+        (Some(Command::Audit(args)), _) => audit::run(args).await,
+        (Some(Command::Baseline(args)), _) => baseline::run(args).await,
+        (Some(Command::Download(args)), _) => single::run(*args).await,
+        (Some(Command::ExecutePlan(args)), _) => queue::run(*args).await,
+        (Some(Command::History(args)), _) => history::run(&args),
+        (Some(Command::Cache(command)), _) => local::run_cache(command),
+        (Some(Command::List(args)), _) => list::run(args).await,
+        (Some(Command::Export(args)), _) => list::run(args.into()).await,
+        (Some(Command::Verify(args)), _) => local::run_verify(&args),
+        (Some(Command::Dedupe(args)), _) => local::run_dedupe(&args),
+        (Some(Command::Stats(args)), _) => local::run_stats(&args),
+        (Some(Command::Status(args)), _) => local::run_status(args).await,
+        (Some(Command::Renames(args)), _) => local::run_renames(&args),
+        (Some(Command::Clean(args)), _) => local::run_clean(&args),
+        (Some(Command::Whoami(args)), _) => whoami::run(args).await,
+        (Some(Command::Estimate(args)), _) => estimate::run(args).await,
+        (Some(Command::Wishlist(args)), _) => wishlist::run(args).await,
+        (Some(Command::Search(args)), _) => search::run(args).await,
+        (Some(Command::Schema(args)), _) => schema::run(&args),
+        #[cfg(feature = "self-update")]
+        (Some(Command::SelfUpdate(args)), _) => update::run(&args).await,
+        (None, Some(library)) => {
+            eprintln!("{LEGACY_SYNC_WARNING}");
+            run_sync(library, cli.sync).await
+        }
+        (None, None) => Err(Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "a subcommand, --cookie-file, --cookies-from-browser, --use-session or --identity-cookie is required",
+            )
+            .into()),
+    }
+}
+
+fn save_session(cookie_data: &str) -> anyhow::Result<()> {
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let identity = cookies::find_identity_cookie(cookies::parse_raw_cookies(cookie_data)?, now)
+        .with_context(|| {
+            format!(
+                "No unexpired `{}` cookie to save to the OS keyring",
+                cookies::IDENTITY_COOKIE_NAME
+            )
+        })?;
+    session::save_identity(&identity).context("Failed saving the Bandcamp session")?;
+    Ok(())
+}
+
+// A directory is taken to hold exports from several browsers or profiles, which are merged into a
+// single export
+fn read_cookie_data(cookie_path: &Path) -> anyhow::Result<String> {
+    if !cookie_path.is_dir() {
+        return Ok(std::fs::read_to_string(cookie_path)?);
+    }
+
+    let mut export_paths = std::fs::read_dir(cookie_path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    export_paths.retain(|path| {
+        path.is_file()
+            && path.extension().is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("json") || extension.eq_ignore_ascii_case("txt")
+            })
+    });
+    export_paths.sort();
+
+    if export_paths.is_empty() {
+        anyhow::bail!(
+            "No cookie exports (*.json or *.txt) found in {}",
+            cookie_path.display()
+        );
+    }
+
+    let mut raw_cookies = Vec::new();
+    for export_path in export_paths {
+        let cookie_data = std::fs::read_to_string(&export_path)?;
+        raw_cookies.extend(
+            cookies::parse_raw_cookies(&cookie_data).with_context(|| {
+                format!("Failed to parse cookie export {}", export_path.display())
+            })?,
+        );
+    }
+
+    Ok(serde_json::to_string(&cookies::merge_raw_cookies(
+        raw_cookies,
+    ))?)
+}
+
+fn read_download_cache_file(
+    cache_file_path: &Path,
+    verbose: bool,
+) -> anyhow::Result<DownloadCache> {
+    if std::fs::exists(cache_file_path)? {
+        status!(verbose, "Download cache exists. Parsing...");
+        let download_cache_data = std::fs::read_to_string(cache_file_path)?;
+        let download_cache = cache::read_download_cache(&download_cache_data)
+            .with_context(|| format!("Failed reading {}", cache_file_path.display()))?;
+        let unreadable = download_cache.unreadable_lines().count();
+        if unreadable > 0 {
+            eprintln!(
+                "{unreadable} line(s) of {} aren't releases, and are kept as they are",
+                cache_file_path.display()
+            );
+        }
+        Ok(download_cache)
+    } else {
+        Ok(DownloadCache::new())
+    }
+}
+
+// Written aside first, as an interrupted write would lose the whole cache
+fn write_download_cache_file(
+    cache_file_path: &Path,
+    download_cache: &DownloadCache,
+) -> std::io::Result<()> {
+    staging::write_atomically(cache_file_path, serialize_download_cache(download_cache))
+}
+
+async fn open_session(
+    library: &LibraryArgs,
+    metrics: Option<Arc<RequestMetrics>>,
+    verbose: bool,
+) -> anyhow::Result<(
+    Arc<api::BandcampAPIContext>,
+    api::data::ParsedFanCollectionSummary,
+)> {
+    let cookie_data = library.read_cookies()?;
+    let api_context = Arc::new(api::BandcampAPIContext::with_options(
+        &cookie_data,
+        &api::ClientOptions {
+            metrics,
+            ..library.client_options()?
+        },
+    )?);
+
+    status!(verbose, "Checking the Bandcamp session...");
+    let fan_summary = api_context
+        .verify_session()
+        .await
+        .context("Failed checking the Bandcamp session")?;
+    Ok((api_context, fan_summary))
+}
+
+async fn find_new_library_items(
+    library: &LibraryArgs,
+    download_cache: &DownloadCache,
+    journal: &Journal,
+    metrics: Option<Arc<RequestMetrics>>,
+    verbose: bool,
+) -> anyhow::Result<(
+    Arc<api::BandcampAPIContext>,
+    HashMap<String, api::data::DigitalItem>,
+)> {
+    let (api_context, fan_summary) = open_session(library, metrics, verbose).await?;
+
+    status!(verbose, "Retrieving all releases...");
+    let collection = api_context
+        .get_collection(&fan_summary, &library.collection_scopes())
+        .await?;
+
+    let snapshot =
+        CollectionSnapshot::new(&fan_summary, collection, time::OffsetDateTime::now_utc());
+    // only along with the journal, as the next run compares against the snapshot to tell what was
+    // hidden since, which a run recording nothing would leave out
+    if journal.is_enabled() {
+        record_scope_changes(library, &snapshot, journal);
+        // a stale snapshot only matters once offline, so this isn't worth failing the run over
+        if let Err(e) = write_collection_snapshot(library, &snapshot) {
+            eprintln!("Failed saving the collection snapshot: {e}");
+        }
+    }
+
+    // finding releases not found in regular scopes
+    status!(verbose, "Finding new releases...");
+    let markers_path = markers_file_path(library);
+    let mut probe_markers = read_probe_markers(&markers_path);
+    let read_markers = probe_markers.clone();
+    let item_filter = library.item_filter()?;
+    let releases = item_filter.apply(&snapshot.download_urls, &snapshot.items);
+    status!(
+        verbose && releases.len() < snapshot.download_urls.len(),
+        "Only looking at the {} of {} release(s) matching the filters",
+        releases.len(),
+        snapshot.download_urls.len()
+    );
+    let max_ages = library.max_ages();
+    let new_items = find_new_releases(
+        &releases,
+        download_cache,
+        &api_context,
+        &mut probe_markers,
+        &max_ages,
+        usize::from(library.scrape_concurrency),
+        verbose,
+    )
+    .await?;
+    // like the snapshot, left alone by the runs that record nothing, such as dry runs and `status`
+    if journal.is_enabled() {
+        for release_id in probe_markers.prune(&max_ages, time::OffsetDateTime::now_utc()) {
+            journal.record(
+                &release_id,
+                EventKind::Pruned,
+                Some("probe marker expired".to_owned()),
+            );
+        }
+        if let Err(e) = write_probe_markers(&markers_path, &read_markers, &probe_markers) {
+            eprintln!("Failed saving {}: {e}", markers_path.display());
+        }
+    }
+
+    Ok((api_context, new_items))
+}
+
+// Against the snapshot of the previous sync, before it's replaced. Without one there's nothing
+// to compare with, which isn't worth mentioning.
+fn record_scope_changes(library: &LibraryArgs, snapshot: &CollectionSnapshot, journal: &Journal) {
+    let Ok(previous) = read_collection_snapshot(&snapshot_file_path(library)) else {
+        return;
+    };
+    for (sale_id, hidden) in snapshot.scope_changes(&previous) {
+        let event = if hidden {
+            EventKind::Hidden
+        } else {
+            EventKind::Unhidden
+        };
+        journal.record(sale_id, event, None);
+    }
+}
+
+fn markers_file_path(library: &LibraryArgs) -> PathBuf {
+    library
+        .state_dir(&library.download_folder())
+        .join(markers::MARKERS_FILE_NAME)
+}
+
+// Losing the markers only means probing those releases again, so a broken file starts over
+fn read_probe_markers(markers_path: &Path) -> ProbeMarkers {
+    let Ok(Some(markers_data)) = read_state_file(markers_path) else {
+        return ProbeMarkers::default();
+    };
+    markers::read_markers(&markers_data).unwrap_or_else(|e| {
+        eprintln!(
+            "Ignoring {}, which couldn't be read: {e}",
+            markers_path.display()
+        );
+        ProbeMarkers::default()
+    })
+}
+
+// Only this run's changes to the markers it read are written, on top of what the file holds by
+// then, so syncs sharing the state folder keep each other's markers
+fn write_probe_markers(
+    markers_path: &Path,
+    read: &ProbeMarkers,
+    probe_markers: &ProbeMarkers,
+) -> anyhow::Result<()> {
+    if read == probe_markers {
+        return Ok(());
+    }
+    if let Some(state_dir) = markers_path.parent() {
+        std::fs::create_dir_all(state_dir)?;
+    }
+    let _lock = cache::CacheLock::wait(markers_path)?;
+    let mut current = if std::fs::exists(markers_path)? {
+        markers::read_markers(&std::fs::read_to_string(markers_path)?).unwrap_or_default()
+    } else {
+        ProbeMarkers::default()
+    };
+    current.apply_changes(read, probe_markers);
+    staging::write_atomically(markers_path, markers::serialize_markers(&current)?)?;
+    Ok(())
+}
+
+fn snapshot_file_path(library: &LibraryArgs) -> PathBuf {
+    library
+        .state_dir(&library.download_folder())
+        .join(snapshot::SNAPSHOT_FILE_NAME)
+}
+
+fn write_collection_snapshot(
+    library: &LibraryArgs,
+    snapshot: &CollectionSnapshot,
+) -> anyhow::Result<()> {
+    write_state_file(
+        &snapshot_file_path(library),
+        snapshot::serialize_snapshot(snapshot)?,
+    )
+}
+
+// The state folder may be shared by syncs running at once, such as those of each format with
+// --cache-per-format, so its files are only read and written under their lock. Reads return None
+// for a file that isn't there.
+fn read_state_file(path: &Path) -> std::io::Result<Option<String>> {
+    if !std::fs::exists(path)? {
+        return Ok(None);
+    }
+    let _lock = cache::CacheLock::wait(path)?;
+    std::fs::read_to_string(path).map(Some)
+}
+
+fn write_state_file(path: &Path, data: String) -> anyhow::Result<()> {
+    if let Some(state_dir) = path.parent() {
+        std::fs::create_dir_all(state_dir)?;
+    }
+    let _lock = cache::CacheLock::wait(path)?;
+    staging::write_atomically(path, data)?;
+    Ok(())
+}
+
+// Adds to what the file holds now rather than replacing it with what was read earlier, so syncs
+// sharing it keep each other's entries
+fn extend_state_file<K: Ord, V>(
+    path: &Path,
+    entries: impl IntoIterator<Item = (K, V)>,
+    read: impl FnOnce(&str) -> serde_json::Result<BTreeMap<K, V>>,
+    serialize: impl FnOnce(&BTreeMap<K, V>) -> serde_json::Result<String>,
+) -> anyhow::Result<()> {
+    if let Some(state_dir) = path.parent() {
+        std::fs::create_dir_all(state_dir)?;
+    }
+    let _lock = cache::CacheLock::wait(path)?;
+    let mut current = if std::fs::exists(path)? {
+        read(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("Failed reading {}", path.display()))?
+    } else {
+        BTreeMap::new()
+    };
+    current.extend(entries);
+    staging::write_atomically(path, serialize(&current)?)?;
+    Ok(())
+}
+
+// The session of the commands that can do without one
+enum Session {
+    LoggedIn(
+        api::BandcampAPIContext,
+        api::data::ParsedFanCollectionSummary,
+    ),
+    // the fan of the last collection snapshot, for what they show on their public page
+    LoggedOut(api::BandcampAPIContext, CollectionSnapshot),
+}
+
+// Missing or expired cookies fall back to a logged out session, saying what it leaves out. Without
+// a collection snapshot there's no telling whose public page to go by, so they fail as usual.
+async fn open_session_or_public(library: &LibraryArgs, left_out: &str) -> anyhow::Result<Session> {
+    let error = match library.read_cookies() {
+        Ok(cookie_data) => {
+            let api_context =
+                api::BandcampAPIContext::with_options(&cookie_data, &library.client_options()?)?;
+            match api_context.verify_session().await {
+                Ok(summary) => return Ok(Session::LoggedIn(api_context, summary)),
+                Err(e @ InformationRetrievalError::NotLoggedIn) => anyhow::Error::from(e),
+                Err(e) => return Err(e).context("Failed checking the Bandcamp session"),
+            }
+        }
+        Err(e) => e,
+    };
+    let Ok(snapshot) = read_collection_snapshot(&snapshot_file_path(library)) else {
+        return Err(error);
+    };
+
+    eprintln!(
+        "{error:#}\nLogged out, going by the public page of {} instead, without {left_out}, as that needs a logged in session",
+        snapshot.username
+    );
+    let api_context = api::BandcampAPIContext::new_without_cookies(&library.client_options()?)?;
+    Ok(Session::LoggedOut(api_context, snapshot))
+}
+
+fn read_collection_snapshot(snapshot_path: &Path) -> anyhow::Result<CollectionSnapshot> {
+    let Some(snapshot_data) = read_state_file(snapshot_path)? else {
+        anyhow::bail!(
+            "No collection snapshot at {}, run an online sync first",
+            snapshot_path.display()
+        );
+    };
+    snapshot::read_snapshot(&snapshot_data)
+        .with_context(|| format!("Failed to parse {}", snapshot_path.display()))
+}
+
+fn offline_sync(library: &LibraryArgs, cli: &SyncArgs) -> anyhow::Result<()> {
+    let download_cache = read_download_cache_file(&sync_cache_file_path(library, cli), true)?;
+    let snapshot = read_collection_snapshot(&snapshot_file_path(library))?;
+
+    println!(
+        "Offline, using the collection snapshot of {} from {}",
+        snapshot.username,
+        snapshot
+            .taken_at()
+            .map_or_else(|| "an unknown time".to_owned(), timestamp::format_local)
+    );
+
+    let pending = snapshot.pending_releases(&download_cache);
+    if pending.is_empty() {
+        println!("No new releases in the snapshot");
+        return Ok(());
+    }
+
+    for release in &pending {
+        match release.item {
+            Some(item) => println!(
+                "New item: \"{}\" by \"{}\" ({})",
+                item.item_title, item.band_name, release.sale_id
+            ),
+            None => println!("New item: {} ({})", release.sale_id, release.download_url),
+        }
+    }
+    println!(
+        "{} release(s) to download once Bandcamp is reachable again",
+        pending.len()
+    );
+
+    Ok(())
+}
+
+async fn run_sync(library: LibraryArgs, cli: SyncArgs) -> anyhow::Result<()> {
+    if cli.offline {
+        return offline_sync(&library, &cli);
+    }
+
+    let control = Arc::new(RunControl::default());
+    if let Some(interval) = cli.watch {
+        return watch(&library, &cli, &control, &WatchSchedule::Every(interval)).await;
+    }
+    if let Some(cron_schedule) = cli.schedule.clone() {
+        return watch(
+            &library,
+            &cli,
+            &control,
+            &WatchSchedule::Cron(cron_schedule),
+        )
+        .await;
+    }
+
+    sync_once(&library, &cli, &control, ReleaseSource::Collection).await?;
+    Ok(())
+}
+
+// Syncs over and over, a failed cycle being reported without stopping the ones after it. A cron
+// schedule waits for its first match before the first sync, so syncs only ever run at the
+// expected times. Quitting from the keyboard ends watching once that sync is saved.
+async fn watch(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    control: &Arc<RunControl>,
+    watch_schedule: &WatchSchedule,
+) -> anyhow::Result<()> {
+    let notifier = Notifier::new(cli.notify);
+    let announced_fridays = library.read_config()?.bandcamp_fridays;
+    if matches!(watch_schedule, WatchSchedule::Cron(_)) {
+        wait_for_next_sync(cli, watch_schedule).await?;
+    }
+
+    loop {
+        let result = sync_once(library, cli, control, ReleaseSource::Collection).await;
+        let now = time::OffsetDateTime::now_utc();
+        let friday = FridayOutlook::from_today(now.date(), &announced_fridays);
+        match &result {
+            Ok(downloaded) => {
+                let mut body = format!("Downloaded {downloaded} new release(s)");
+                // only worth bringing up when there's something new to look at
+                if let Some(friday) = friday.describe().filter(|_| *downloaded > 0) {
+                    body = format!("{body}. {friday}");
+                }
+                notifier.notify("bandcamp-dl sync finished", &body);
+            }
+            Err(e) => {
+                eprintln!("Sync failed: {e:#}");
+                notifier.notify("bandcamp-dl sync failed", &format!("{e:#}"));
+            }
+        }
+
+        if let Some(notify_json) = &cli.notify_json {
+            let event = SyncEvent {
+                finished_at: now.unix_timestamp(),
+                downloaded: result.as_ref().ok().copied(),
+                error: result.as_ref().err().map(|e| format!("{e:#}")),
+                bandcamp_friday: friday,
+            };
+            if let Err(e) = event.append_to(notify_json) {
+                eprintln!("Failed writing to {}: {e}", notify_json.display());
+            }
+        }
+
+        if control.is_quitting() {
+            return Ok(());
+        }
+        wait_for_next_sync(cli, watch_schedule).await?;
+    }
+}
+
+async fn wait_for_next_sync(cli: &SyncArgs, watch_schedule: &WatchSchedule) -> anyhow::Result<()> {
+    let delay = watch_schedule
+        .delay_after(time::OffsetDateTime::now_utc())
+        .context("The --schedule expression never matches again")?;
+    let delay = schedule::with_jitter(delay, cli.jitter.unwrap_or_default());
+
+    status!(
+        !cli.print_urls_only,
+        "Next sync in {}",
+        schedule::format_duration(delay)
+    );
+    tokio::time::sleep(delay).await;
+    Ok(())
+}
+
+// Returns the number of releases downloaded
+async fn sync_once(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    control: &Arc<RunControl>,
+    source: ReleaseSource<'_>,
+) -> anyhow::Result<usize> {
+    let metrics = (cli.stats || cli.stats_json.is_some()).then(Arc::<RequestMetrics>::default);
+    let result = sync_with_stream_rip(library, cli, control, metrics.clone(), source).await;
+
+    // also after a failed sync, which is when they're the most telling
+    if let Some(metrics) = metrics {
+        report_request_metrics(&metrics, cli)?;
+    }
+    result
+}
+
+// The stream rip and wishlist only follow a sync of the collection, which the other sources
+// reject with SyncArgs::collection_options
+async fn sync_with_stream_rip(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    control: &Arc<RunControl>,
+    metrics: Option<Arc<RequestMetrics>>,
+    source: ReleaseSource<'_>,
+) -> anyhow::Result<usize> {
+    let started = std::time::SystemTime::now();
+    let SyncOutcome {
+        api_context,
+        downloaded,
+    } = sync_releases(library, cli, control, metrics, source).await?;
+
+    if cli.stream_rip && !control.is_quitting() {
+        stream_rip::run(
+            &api_context,
+            &library.collection_scopes(),
+            &library.download_folder(),
+            cli.dry_run.is_some(),
+            cli.print_urls_only,
+            &cli.stream_folder_template,
+            cli.ascii_filenames,
+        )
+        .await?;
+    }
+
+    // before deduplicating, which makes the tracks of a folder hardlinks shared with other folders.
+    // Tagging replaces a track rather than writing through its links, so it doesn't change the
+    // copies of other releases either way.
+    // both go through every new track, ffmpeg once or twice for each, so they're kept off the
+    // runtime's threads
+    let config = library.read_config()?;
+    if config.replaygain && cli.downloads() {
+        let scanner = LoudnessScanner::new(config.ffmpeg.unwrap_or_else(|| "ffmpeg".into()));
+        let download_folders = library.download_folders();
+        tokio::task::spawn_blocking(move || {
+            download_folders
+                .iter()
+                .try_for_each(|download_folder| tag_new_tracks(&scanner, download_folder, started))
+        })
+        .await??;
+    }
+
+    if cli.dedupe_tracks && cli.downloads() {
+        let stores: Vec<_> = library
+            .download_folders()
+            .into_iter()
+            .map(|download_folder| {
+                let blobs_folder = library
+                    .state_dir(&download_folder)
+                    .join(cas::BLOBS_FOLDER_NAME);
+                (download_folder, BlobStore::new(blobs_folder))
+            })
+            .collect();
+        tokio::task::spawn_blocking(move || {
+            stores.iter().try_for_each(|(download_folder, store)| {
+                let report = store.absorb_tree(download_folder)?;
+                println!("{}: {}", download_folder.display(), report.summary());
+                anyhow::Ok(())
+            })
+        })
+        .await??;
+    }
+
+    if cli.include_wishlist && !control.is_quitting() {
+        let summary = api_context.verify_session().await?;
+        let items = api_context.get_wishlist(summary.fan_id).await?;
+        status!(
+            !cli.print_urls_only,
+            "Checking {} wishlist item(s) for free downloads...",
+            items.len()
+        );
+        wishlist::download_free_items(
+            &api_context,
+            &items,
+            &library.download_folder(),
+            cli.audio_format(),
+            cli.dry_run.is_some(),
+            cli.print_urls_only,
+        )
+        .await?;
+    }
+
+    Ok(downloaded)
+}
+
+fn report_request_metrics(metrics: &RequestMetrics, cli: &SyncArgs) -> anyhow::Result<()> {
+    let endpoints = metrics.endpoints();
+    if cli.stats {
+        println!("Requests made to Bandcamp:");
+        for (endpoint, stats) in &endpoints {
+            println!(
+                "  {endpoint:<20} {:>5} request(s), {} error(s), {} ms mean, {} ms max",
+                stats.requests,
+                stats.errors,
+                stats.mean_latency_ms(),
+                stats.max_latency_ms
+            );
+        }
+    }
+
+    if let Some(stats_path) = &cli.stats_json {
+        let stats = models::versioned_value(&StatsExport { endpoints })?;
+        std::fs::write(stats_path, serde_json::to_string_pretty(&stats)?)
+            .with_context(|| format!("Failed writing {}", stats_path.display()))?;
+    }
+    Ok(())
+}
+
+// Fails naming the options given, of those that don't apply to `what`
+fn reject_options(options: &[(&str, bool)], what: &str) -> anyhow::Result<()> {
+    let given: Vec<_> = options
+        .iter()
+        .filter_map(|&(option, given)| given.then_some(option))
+        .collect();
+    match given[..] {
+        [] => Ok(()),
+        [option] => anyhow::bail!("{option} doesn't apply to {what}"),
+        _ => anyhow::bail!("{} don't apply to {what}", given.join(", ")),
+    }
+}
+
+fn sync_cache_file_path(library: &LibraryArgs, cli: &SyncArgs) -> PathBuf {
+    let cache_file_path = library.cache_file_path(&library.download_folder());
+    if cli.cache_per_format {
+        cache::format_scoped_cache_path(&cache_file_path, cli.audio_format())
+    } else {
+        cache_file_path
+    }
+}
+
+struct SyncOutcome {
+    api_context: Arc<api::BandcampAPIContext>,
+    downloaded: usize,
+}
+
+// Where a sync gets the releases to download from
+#[derive(Clone, Copy)]
+enum ReleaseSource<'a> {
+    // every release of the collection missing from the download cache
+    Collection,
+    // a single owned release, by its album or track page, downloaded even when it's in the cache
+    ItemUrl(&'a str),
+    // the releases of an --offline-queue file still missing from the cache, with their links
+    Queue(&'a OfflineQueue),
+}
+
+// Goes straight to the item, rather than through the whole collection
+async fn find_release_by_url(
+    library: &LibraryArgs,
+    item_url: &str,
+    metrics: Option<Arc<RequestMetrics>>,
+    verbose: bool,
+) -> anyhow::Result<(
+    Arc<api::BandcampAPIContext>,
+    HashMap<String, api::data::DigitalItem>,
+)> {
+    let (api_context, fan_summary) = open_session(library, metrics, verbose).await?;
+
+    status!(verbose, "Looking up {item_url}...");
+    let Some(owned_item) = api_context
+        .lookup_owned_item(&fan_summary, item_url)
+        .await?
+    else {
+        anyhow::bail!("{item_url} isn't in the collection");
+    };
+    let Some((sale_id, download_url)) = api_context
+        .find_download_url(&fan_summary, &owned_item, &library.collection_scopes())
+        .await?
+    else {
+        anyhow::bail!("{item_url} has no download");
+    };
+    let Some(digital_item) = api_context.get_digital_download_item(&download_url).await? else {
+        anyhow::bail!("{item_url} has no digital download");
+    };
+
+    Ok((api_context, HashMap::from([(sale_id, digital_item)])))
+}
+
+async fn find_releases(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    source: ReleaseSource<'_>,
+    lookup_cache: &DownloadCache,
+    journal: &Journal,
+    metrics: Option<Arc<RequestMetrics>>,
+) -> anyhow::Result<(
+    Arc<api::BandcampAPIContext>,
+    HashMap<String, api::data::DigitalItem>,
+)> {
+    let verbose = !cli.print_urls_only;
+    match source {
+        ReleaseSource::Collection if cli.check_updated => {
+            let (api_context, items) =
+                find_new_library_items(library, &DownloadCache::new(), journal, metrics, verbose)
+                    .await?;
+            let items = keep_updated_releases(library, cli, items, lookup_cache, verbose);
+            Ok((api_context, items))
+        }
+        ReleaseSource::Collection => {
+            find_new_library_items(library, lookup_cache, journal, metrics, verbose).await
+        }
+        ReleaseSource::ItemUrl(item_url) => {
+            find_release_by_url(library, item_url, metrics, verbose).await
+        }
+        ReleaseSource::Queue(queue) => {
+            let (api_context, _) = open_session(library, metrics, verbose).await?;
+            Ok((api_context, find_queued_items(queue, lookup_cache, verbose)))
+        }
+    }
+}
+
+// Those downloaded since they were queued are left out, like any release in the cache
+fn find_queued_items(
+    queue: &OfflineQueue,
+    lookup_cache: &DownloadCache,
+    verbose: bool,
+) -> HashMap<String, api::data::DigitalItem> {
+    let items: HashMap<_, _> = queue
+        .items()
+        .into_iter()
+        .filter(|(sale_id, _)| !lookup_cache.contains_key(&cache::normalize_release_id(sale_id)))
+        .collect();
+    status!(
+        verbose && items.len() < queue.downloads.len(),
+        "Skipping {} queued release(s) already in the download cache",
+        queue.downloads.len() - items.len()
+    );
+    items
+}
+
+// With --limit or --offset, a large backlog is worked through over several runs
+fn plan_downloads(
+    cli: &SyncArgs,
+    items_to_download: HashMap<String, api::data::DigitalItem>,
+    verbose: bool,
+) -> Vec<PlannedDownload> {
+    let new_releases = items_to_download.len();
+    let planned_downloads = DownloadPlanner::new(cli.audio_format())
+        .with(Quota::new(cli.offset, cli.limit))
+        .plan(items_to_download);
+    status!(
+        verbose && planned_downloads.len() < new_releases,
+        "Fetching {} of the {new_releases} new release(s) in this run",
+        planned_downloads.len()
+    );
+    planned_downloads
+}
+
+async fn sync_releases(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    control: &Arc<RunControl>,
+    metrics: Option<Arc<RequestMetrics>>,
+    source: ReleaseSource<'_>,
+) -> anyhow::Result<SyncOutcome> {
+    let budget = cli.time_budget.map(TimeBudget::new);
+    let verbose = !cli.print_urls_only;
+    let download_folder = library.download_folder();
+    let cache_file_path = sync_cache_file_path(library, cli);
+
+    status!(verbose, "Download folder: {}", download_folder.display());
+
+    // dropped once the function returns, after the cache was written
+    let _cache_lock = if cli.downloads() {
+        Some(cache::CacheLock::acquire(&cache_file_path)?)
+    } else {
+        None
+    };
+    let mut download_cache = read_download_cache_file(&cache_file_path, verbose)?;
+    let lookup_cache = bypass_forced_releases(&download_cache, cli);
+    status!(
+        verbose && lookup_cache.len() < download_cache.len(),
+        "Downloading {} cached release(s) again",
+        download_cache.len() - lookup_cache.len()
+    );
+    let journal = library.open_journal(cli.downloads());
+    let (api_context, items_to_download) =
+        find_releases(library, cli, source, &lookup_cache, &journal, metrics).await?;
+
+    if items_to_download.is_empty() {
+        status!(verbose, "No new releases to fetch");
+        return Ok(SyncOutcome {
+            api_context,
+            downloaded: 0,
+        });
+    }
+
+    let planned_downloads = plan_releases(
+        library,
+        cli,
+        items_to_download,
+        &journal,
+        &mut download_cache,
+    )?;
+    let release_count = planned_downloads.len();
+    let release_paths = release_paths(cli, &planned_downloads, &download_cache);
+
+    // download links are resolved in the background, and downloaded as they come in
+    status!(verbose, "Fetching releases in {}...", cli.audio_format());
+    let mut report = SyncReport::default();
+    let mut resolved_links = match source {
+        ReleaseSource::Queue(queue) => queued_links(queue, planned_downloads),
+        _ => spawn_link_resolution(&api_context, planned_downloads, cli),
+    };
+
+    if let Some(queue_path) = &cli.offline_queue {
+        write_offline_queue(library, queue_path, &mut resolved_links).await?;
+        return Ok(SyncOutcome {
+            api_context,
+            downloaded: 0,
+        });
+    }
+    if cli.print_urls_only || cli.dry_run.is_some() {
+        preview_downloads(
+            library,
+            cli,
+            &download_cache,
+            &mut resolved_links,
+            &release_paths,
+            &mut report,
+        )
+        .await?;
+        return Ok(SyncOutcome {
+            api_context,
+            downloaded: 0,
+        });
+    }
+
+    let staging = Arc::new(open_staging(library, cli, &download_folder));
+    let journal = Arc::new(journal);
+    let stage = DownloadStage::new(
+        &api_context,
+        &staging,
+        &journal,
+        control,
+        release_paths,
+        cli,
+        release_count,
+    );
+    let writer = CommitStage::start(library, cli, &staging, &journal, &stage, download_cache)?;
+    let downloaded_count = download_releases(
+        &stage,
+        writer,
+        control,
+        budget,
+        &mut resolved_links,
+        cli,
+        &mut report,
+    )
+    .await?;
+
+    if cli.html_report {
+        write_html_report(&report, &download_folder, verbose)?;
+    }
+
+    Ok(SyncOutcome {
+        api_context,
+        downloaded: downloaded_count,
+    })
+}
+
+// What's left to download of the new releases, once told to go ahead with a first sync
+fn plan_releases(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    items_to_download: HashMap<String, api::data::DigitalItem>,
+    journal: &Journal,
+    download_cache: &mut DownloadCache,
+) -> anyhow::Result<Vec<PlannedDownload>> {
+    record_discovered(journal, &items_to_download);
+    let first_sync = download_cache.is_empty();
+    let planned_downloads = plan_downloads(cli, items_to_download, !cli.print_urls_only);
+    let planned_downloads =
+        adopt_existing_files(library, cli, planned_downloads, journal, download_cache)?;
+    if first_sync && cli.downloads() {
+        confirm_first_sync(cli, &planned_downloads)?;
+    }
+    Ok(planned_downloads)
+}
+
+// Takes the releases not in the cache whose file already is in a download folder, with a size
+// close to what Bandcamp lists, as downloaded, and returns the others. Releases downloaded again
+// on purpose are always downloaded.
+fn adopt_existing_files(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    planned_downloads: Vec<PlannedDownload>,
+    journal: &Journal,
+    download_cache: &mut DownloadCache,
+) -> anyhow::Result<Vec<PlannedDownload>> {
+    if cli.overwrite {
+        return Ok(planned_downloads);
+    }
+
+    let download_folders = library.download_folders();
+    let mut missing = Vec::new();
+    let mut adopted = 0;
+    for planned in planned_downloads {
+        let existing = (!download_cache.contains_key(&planned.sale_id))
+            .then(|| find_existing_file(&download_folders, cli, &planned))
+            .flatten();
+        let Some(path) = existing else {
+            missing.push(planned);
+            continue;
+        };
+
+        if !cli.downloads() {
+            status!(
+                !cli.print_urls_only,
+                "Would skip {}, already at {}",
+                planned.sale_id,
+                path.display()
+            );
+            continue;
+        }
+        println!(
+            "Skipping {}, already at {}",
+            planned.sale_id,
+            path.display()
+        );
+        journal.record(
+            &planned.sale_id,
+            EventKind::Skipped,
+            Some(format!("already at {}", path.display())),
+        );
+        download_cache.insert(
+            planned.sale_id.clone(),
+            DownloadCacheRelease::from_digital_item(&planned.sale_id, &planned.item),
+        );
+        adopted += 1;
+    }
+
+    // right away, as nothing may be left to download
+    if adopted > 0 {
+        write_download_cache_file(&sync_cache_file_path(library, cli), download_cache)?;
+    }
+    Ok(missing)
+}
+
+// A file as the release would be saved, not much smaller than Bandcamp lists it. An empty one is
+// what an interrupted download by some other tool can leave. With --extract, the folder it would
+// be extracted to counts too once it holds some tracks, as the archive may have been deleted.
+fn find_existing_file(
+    download_folders: &[PathBuf],
+    cli: &SyncArgs,
+    planned: &PlannedDownload,
+) -> Option<PathBuf> {
+    let release_path = cli.release_path(planned);
+    let release = DownloadCacheRelease::from_digital_item(&planned.sale_id, &planned.item);
+    download_folders.iter().find_map(|folder| {
+        let path = folder.join(&release_path);
+        let is_archive = std::fs::metadata(&path).is_ok_and(|metadata| {
+            metadata.len() > 0
+                && download::check_size(metadata.len(), None, planned.expected_bytes()).is_ok()
+        });
+        if is_archive {
+            return Some(path);
+        }
+        let extraction_folder = cli.extraction_folder(&path, &release);
+        (cli.extract
+            && archive::audio_files(&extraction_folder).is_ok_and(|tracks| !tracks.is_empty()))
+        .then_some(extraction_folder)
+    })
+}
+
+// With --check-updated, the releases of the cache are looked up along with the new ones, and only
+// kept when their archive changed on Bandcamp since. One no longer in a download folder, such as
+// after --delete-archives, can't be compared and is left as it is.
+fn keep_updated_releases(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    items: HashMap<String, api::data::DigitalItem>,
+    lookup_cache: &DownloadCache,
+    verbose: bool,
+) -> HashMap<String, api::data::DigitalItem> {
+    let download_folders = library.download_folders();
+    let mut updated = 0;
+    let kept: HashMap<_, _> = items
+        .into_iter()
+        .filter(|(sale_id, item)| {
+            if !lookup_cache.contains_key(&cache::normalize_release_id(sale_id)) {
+                return true;
+            }
+            let planned = PlannedDownload {
+                sale_id: sale_id.clone(),
+                item: item.clone(),
+                format: cli.audio_format(),
+            };
+            let changed = archive_changed(&download_folders, cli, &planned);
+            updated += usize::from(changed);
+            changed
+        })
+        .collect();
+    status!(
+        verbose && updated > 0,
+        "Downloading {updated} cached release(s) again, as they changed on Bandcamp"
+    );
+    kept
+}
+
+fn archive_changed(
+    download_folders: &[PathBuf],
+    cli: &SyncArgs,
+    planned: &PlannedDownload,
+) -> bool {
+    let Some(expected) = planned.expected_bytes() else {
+        return false;
+    };
+    let release_path = cli.release_path(planned);
+    download_folders
+        .iter()
+        .find_map(|folder| std::fs::metadata(folder.join(&release_path)).ok())
+        .is_some_and(|metadata| download::differs_from_size_hint(metadata.len(), expected))
+}
+
+// The cache as new releases are looked up against, without the releases forced to be downloaded
+// again. Their entries in the real cache are simply replaced once they're downloaded.
+fn bypass_forced_releases(download_cache: &DownloadCache, cli: &SyncArgs) -> DownloadCache {
+    if cli.force_item.is_empty() && cli.force_artist.is_empty() {
+        return download_cache.clone();
+    }
+
+    let forced_items: Vec<_> = cli
+        .force_item
+        .iter()
+        .map(|item| cache::normalize_release_id(item))
+        .collect();
+    let forced_artists: Vec<_> = cli
+        .force_artist
+        .iter()
+        .map(|artist| artist.to_lowercase())
+        .collect();
+
+    download_cache
+        .iter()
+        .filter(|(release_id, release)| {
+            !forced_items.contains(release_id)
+                && !forced_artists.contains(&release.artist().to_lowercase())
+        })
+        .map(|(release_id, release)| (release_id.clone(), release.clone()))
+        .collect()
+}
+
+// What releases are kept together by when pinning artists: their band when its id is known, and
+// their artist name otherwise
+fn pin_key(band_ids: &HashMap<String, i64>, release: &DownloadCacheRelease) -> String {
+    band_ids.get(release.release_id()).map_or_else(
+        || release.artist().to_owned(),
+        |band_id| format!("band {band_id}"),
+    )
+}
+
+// Which download folder each completed release goes to. With a single folder there's nothing to
+// balance or remember.
+struct VolumePlacement {
+    router: VolumeRouter,
+    volumes: VolumeMap,
+    volumes_path: Option<PathBuf>,
+    // with --group-by-band-id, from the collection snapshot
+    band_ids: HashMap<String, i64>,
+}
+
+impl VolumePlacement {
+    fn open(
+        library: &LibraryArgs,
+        cli: &SyncArgs,
+        download_cache: &DownloadCache,
+    ) -> anyhow::Result<Self> {
+        let folders = library.download_folders();
+        if folders.len() == 1 {
+            return Ok(Self {
+                router: VolumeRouter::new(vec![(library.download_folder(), None)], false),
+                volumes: VolumeMap::new(),
+                volumes_path: None,
+                band_ids: HashMap::new(),
+            });
+        }
+
+        let volumes_path = library
+            .state_dir(&library.download_folder())
+            .join(volumes::VOLUMES_FILE_NAME);
+        let volumes = match read_state_file(&volumes_path)? {
+            Some(volume_data) => volumes::read_volume_map(&volume_data)
+                .with_context(|| format!("Failed reading {}", volumes_path.display()))?,
+            None => VolumeMap::new(),
+        };
+
+        let mut router = VolumeRouter::new(
+            folders
+                .into_iter()
+                .map(|folder| {
+                    let free_bytes = volumes::available_space(&folder);
+                    (folder, free_bytes)
+                })
+                .collect(),
+            cli.pin_artists,
+        );
+        let band_ids = if cli.group_by_band_id {
+            match read_collection_snapshot(&snapshot_file_path(library)) {
+                Ok(snapshot) => bands::band_ids(&snapshot.items),
+                Err(e) => {
+                    eprintln!("Grouping releases by artist name instead of band id: {e}");
+                    HashMap::new()
+                }
+            }
+        } else {
+            HashMap::new()
+        };
+        for (release_id, folder) in &volumes {
+            if let Some(release) = download_cache.get(release_id) {
+                router.pin_artist(&pin_key(&band_ids, release), folder);
+            }
+        }
+
+        Ok(Self {
+            router,
+            volumes,
+            volumes_path: Some(volumes_path),
+            band_ids,
+        })
+    }
+
+    fn route(&mut self, release: &DownloadCacheRelease, size: u64) -> PathBuf {
+        let folder = self
+            .router
+            .route(&pin_key(&self.band_ids, release), size)
+            .to_owned();
+        if self.volumes_path.is_some() {
+            self.volumes
+                .insert(release.release_id().to_owned(), folder.clone());
+        }
+        folder
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(volumes_path) = &self.volumes_path {
+            extend_state_file(
+                volumes_path,
+                self.volumes.clone(),
+                volumes::read_volume_map,
+                volumes::serialize_volume_map,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Tags the tracks a post-processor extracted during this run. An album that fails only warns, as
+// its tracks are there either way.
+fn tag_new_tracks(
+    scanner: &LoudnessScanner,
+    download_folder: &Path,
+    since: std::time::SystemTime,
+) -> anyhow::Result<()> {
+    let albums = loudness::find_new_tracks(download_folder, since)?;
+    let mut tagged = 0;
+    for (folder, tracks) in &albums {
+        match scanner.tag_album(tracks) {
+            Ok(()) => tagged += tracks.len(),
+            Err(e) => println!("Failed tagging {} with ReplayGain: {e}", folder.display()),
+        }
+    }
+    if tagged > 0 {
+        println!(
+            "{}: tagged {tagged} track(s) with ReplayGain",
+            download_folder.display()
+        );
+    }
+    Ok(())
+}
+
+// Where completed downloads are checked and handed over to a writer of their own, which moves them
+// out of staging while the next ones download. Downloading waits for it to catch up once it's a
+// few releases behind, or when moving them is a copy, once they add up to --max-staged.
+struct CommitStage {
+    sender: mpsc::Sender<PendingDownload>,
+    queue: watch::Sender<WriterQueue>,
+    writer: tokio::task::JoinHandle<anyhow::Result<Committer>>,
+}
+
+impl CommitStage {
+    fn start(
+        library: &LibraryArgs,
+        cli: &SyncArgs,
+        staging: &Arc<StagingArea>,
+        journal: &Arc<Journal>,
+        stage: &DownloadStage,
+        download_cache: DownloadCache,
+    ) -> anyhow::Result<Self> {
+        let placement = VolumePlacement::open(library, cli, &download_cache)?;
+        let queue = WriterQueue::for_destination(
+            &library.download_folder(),
+            staging.path(),
+            cli.max_staged.saturating_mul(1_000_000),
+        );
+        if let Some(filesystem) = queue.network_filesystem() {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let write_rate = queue.write_rate().map_or_else(
+                || "unknown".to_owned(),
+                |write_rate| format!("{}/s", HumanBytes(write_rate as u64)),
+            );
+            println!("Download folder is a network mount ({filesystem}), writing at {write_rate}");
+        }
+
+        let checksums_path = library
+            .state_dir(&library.download_folder())
+            .join(checksums::CHECKSUMS_FILE_NAME);
+
+        let (queue, _) = watch::channel(queue);
+        let (sender, receiver) = mpsc::channel(COMMIT_QUEUE_DEPTH);
+        let committer = Committer {
+            staging: Arc::clone(staging),
+            journal: Arc::clone(journal),
+            placement,
+            download_cache,
+            cache_file_path: sync_cache_file_path(library, cli),
+            checksums: ChecksumMap::new(),
+            checksums_path,
+            cli: cli.clone(),
+            extractors: ExtractorRegistry::with_ascii_filenames(cli.ascii_filenames),
+            post_processors: cli
+                .post_processor
+                .iter()
+                .cloned()
+                .map(PostProcessor::new)
+                .collect(),
+            progress: Arc::clone(&stage.progress),
+            reporter: stage.api_context.progress_reporter(),
+            queue: queue.clone(),
+            downloaded: Vec::new(),
+        };
+        Ok(Self {
+            sender,
+            queue,
+            writer: tokio::task::spawn_blocking(move || committer.run(receiver)),
+        })
+    }
+
+    // Most releases to download at once, fewer while the download folder falls behind
+    fn batch_size(&self, most: usize) -> usize {
+        self.queue.borrow().batch_size(most)
+    }
+
+    // Before downloading `bytes` more, rather than after, so staging never holds more than
+    // --max-staged on top of a single release
+    async fn make_room(&self, stage: &DownloadStage<'_>, bytes: u64) {
+        let mut queue = self.queue.subscribe();
+        if queue.borrow().has_room(bytes) {
+            return;
+        }
+        stage.println(&format!(
+            "Waiting for {} of downloads to be moved to the download folder before downloading more...",
+            HumanBytes(queue.borrow().queued_bytes())
+        ));
+        // or until the writer is gone, which leaves nothing to wait for
+        tokio::select! {
+            _ = queue.wait_for(|queue| queue.has_room(bytes)) => {}
+            () = self.sender.closed() => {}
+        }
+    }
+
+    // Takes downloads that completed in `elapsed`, waiting while the writer is too far behind
+    async fn push(
+        &self,
+        stage: &DownloadStage<'_>,
+        completed: Vec<PendingDownload>,
+        elapsed: Duration,
+        cli: &SyncArgs,
+        report: &mut SyncReport,
+    ) {
+        let completed = verify_track_counts(stage, stage.staging, completed, cli, report).await;
+        let completed = fetch_cover_art(stage, stage.staging, completed, cli).await;
+
+        let bytes = staged_bytes(stage.staging, &completed);
+        self.queue
+            .send_modify(|queue| queue.record_downloaded(bytes, elapsed));
+        for p in completed {
+            // the writer only stops early on failing, which `finish` reports
+            if self.sender.send(p).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    // Returns how many downloads made it into the cache
+    async fn finish(self, report: &mut SyncReport) -> anyhow::Result<usize> {
+        drop(self.sender);
+        let committer = self.writer.await??;
+        for release_id in &committer.downloaded {
+            report.mark_downloaded(release_id);
+        }
+        Ok(committer.downloaded.len())
+    }
+}
+
+// Moves completed downloads out of staging on a blocking thread, writing the cache after each
+// group of them so an interrupted run keeps what it committed
+struct Committer {
+    staging: Arc<StagingArea>,
+    journal: Arc<Journal>,
+    placement: VolumePlacement,
+    download_cache: DownloadCache,
+    cache_file_path: PathBuf,
+    // of what this sync wrote
+    checksums: ChecksumMap,
+    checksums_path: PathBuf,
+    cli: SyncArgs,
+    extractors: ExtractorRegistry,
+    post_processors: Vec<PostProcessor>,
+    progress: Arc<DownloadProgress>,
+    reporter: Arc<dyn ProgressReporter>,
+    queue: watch::Sender<WriterQueue>,
+    // release ids, in the order they were committed
+    downloaded: Vec<String>,
+}
+
+impl Committer {
+    // Commits whatever has come in since the last group, until the downloads are done
+    fn run(mut self, mut receiver: mpsc::Receiver<PendingDownload>) -> anyhow::Result<Self> {
+        while let Some(p) = receiver.blocking_recv() {
+            let mut completed = vec![p];
+            while let Ok(p) = receiver.try_recv() {
+                completed.push(p);
+            }
+            self.commit(completed);
+        }
+
+        self.placement.save()?;
+        self.save()?;
+        Ok(self)
+    }
+
+    // The cache, along with the checksums of what's in it
+    fn save(&self) -> anyhow::Result<()> {
+        write_download_cache_file(&self.cache_file_path, &self.download_cache)?;
+        extend_state_file(
+            &self.checksums_path,
+            self.checksums.clone(),
+            checksums::read_checksums,
+            checksums::serialize_checksums,
+        )
+    }
+
+    fn commit(&mut self, completed: Vec<PendingDownload>) {
+        let bytes = staged_bytes(&self.staging, &completed);
+        let started = Instant::now();
+        let progress = Arc::clone(&self.progress);
+        let downloaded = progress.suspend(|| self.commit_downloads(completed));
+        let elapsed = started.elapsed();
+
+        if !downloaded.is_empty() {
+            // written again once done, so a failure here only warns
+            if let Err(e) = self.save() {
+                self.progress
+                    .suspend(|| println!("Failed updating the download cache: {e}"));
+            }
+        }
+        // only once the cache has them, so the journal never lists a download the cache lost
+        for (release_id, checksum) in downloaded {
+            self.journal.record(
+                &release_id,
+                EventKind::Downloaded,
+                checksum.map(|hash| format!("sha256 {hash}")),
+            );
+            self.downloaded.push(release_id);
+        }
+        self.queue
+            .send_modify(|queue| queue.record_written(bytes, elapsed));
+    }
+
+    // Moves the completed downloads out of staging and into the cache, returning the ids of those
+    // that made it along with their checksums
+    fn commit_downloads(
+        &mut self,
+        completed: Vec<PendingDownload>,
+    ) -> Vec<(String, Option<String>)> {
+        let mut downloaded = Vec::new();
+        for p in completed {
+            let release_id = p.release.release_id().to_owned();
+            let size = std::fs::metadata(self.staging.partial_path(&p.download.filename))
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+            let destination = self.placement.route(&p.release, size);
+            // a release already in the cache is being downloaded again, and may have changed since
+            let previous = self
+                .download_cache
+                .contains_key(&release_id)
+                .then(|| {
+                    previous_contents(
+                        &self.extractors,
+                        &destination,
+                        &p.path,
+                        &p.release,
+                        p.planned.format,
+                    )
+                })
+                .flatten();
+            let final_path = destination.join(&p.path);
+            if let Err(e) = self.staging.commit(&p.download.filename, &final_path) {
+                println!(
+                    "Failed moving {} to {}: {e}",
+                    p.download.filename,
+                    final_path.display()
+                );
+                self.journal
+                    .record(&release_id, EventKind::Failed, Some(e.to_string()));
+                continue;
+            }
+
+            if let Some(previous) = previous {
+                record_changes(
+                    &self.extractors,
+                    &previous,
+                    &final_path,
+                    &p.release,
+                    p.planned.format,
+                );
+            }
+
+            let checksum = self.finish_release(&p, &final_path);
+            downloaded.push((release_id.clone(), checksum));
+            self.download_cache.insert(release_id, p.release);
+        }
+        downloaded
+    }
+
+    // Runs what comes after a release is in place, returning its checksum. The release itself is
+    // there either way, so a failing step only warns.
+    fn finish_release(&mut self, p: &PendingDownload, final_path: &Path) -> Option<String> {
+        let release_id = p.release.release_id();
+        let completed_release =
+            CompletedRelease::new(release_id, &p.planned.item, p.planned.format, final_path);
+        for post_processor in &self.post_processors {
+            if let Err(e) = post_processor.run(&completed_release) {
+                println!(
+                    "Post-processor {} failed for {}: {e}",
+                    post_processor.program().display(),
+                    p.download.filename
+                );
+            }
+        }
+
+        // so a copy can later be told apart from a damaged one
+        let checksum = cas::hash_file(final_path).ok();
+        if let Some(hash) = &checksum {
+            self.checksums.insert(final_path.to_owned(), hash.clone());
+        }
+        let extraction = self
+            .cli
+            .extract
+            .then(|| self.cli.extraction_folder(final_path, &p.release))
+            .and_then(|folder| {
+                extract_download(
+                    &self.extractors,
+                    final_path,
+                    &folder,
+                    self.cli.delete_archives,
+                )
+                .map(|extracted| (folder, extracted))
+            });
+        if let Some((folder, extracted)) = &extraction {
+            self.reporter
+                .item_extracted(release_id, extracted.extracted().count());
+            let normalizer = TagNormalizer::new(self.cli.normalize_tags);
+            if normalizer.enabled() {
+                let entries: Vec<_> = extracted.extracted().cloned().collect();
+                normalize_tags(
+                    &normalizer,
+                    folder,
+                    &entries,
+                    &p.planned,
+                    &self.cli.tag_template,
+                );
+            }
+        }
+        let extracted_to = extraction.map(|(folder, _)| folder);
+        if let Some(art) = &p.art {
+            let art_path = cover_art_path(art, final_path, extracted_to.as_deref());
+            let embedder = ArtEmbedder::new(self.cli.embed_art);
+            commit_cover_art(
+                &self.staging,
+                &embedder,
+                art,
+                &art_path,
+                extracted_to.as_deref(),
+            );
+        }
+        checksum
+    }
+}
+
+fn staged_bytes(staging: &StagingArea, downloads: &[PendingDownload]) -> u64 {
+    downloads
+        .iter()
+        .filter_map(|p| std::fs::metadata(staging.partial_path(&p.download.filename)).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+// What was unpacked into `folder`, when the download was an archive. Like a post-processor, a
+// failed extraction only warns, and keeps the archive.
+fn extract_download(
+    extractors: &ExtractorRegistry,
+    download: &Path,
+    folder: &Path,
+    delete_archive: bool,
+) -> Option<UpdateReport> {
+    let updating = folder.exists();
+    match extractors.extract_release(download, folder) {
+        Ok(Some(extracted)) => {
+            if updating {
+                println!("Updated {}: {}", folder.display(), extracted.summary());
+            }
+            if delete_archive {
+                if let Err(e) = std::fs::remove_file(download) {
+                    println!("Failed deleting {}: {e}", download.display());
+                }
+            }
+            Some(extracted)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            println!(
+                "Failed extracting {} into {}: {e}",
+                download.display(),
+                folder.display()
+            );
+            None
+        }
+    }
+}
+
+// Named "cover" in the folder a release was unpacked to, replacing the smaller one Bandcamp puts
+// in its archives, or like the release otherwise, as several share a folder
+fn cover_art_path(art_filename: &str, release_path: &Path, extracted_to: Option<&Path>) -> PathBuf {
+    let extension = Path::new(art_filename)
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    extracted_to.map_or_else(
+        || release_path.with_extension(extension.as_ref()),
+        |folder| folder.join(format!("cover.{extension}")),
+    )
+}
+
+// Embedding into the unpacked files too, with --embed-art. Failing files only warn, as they're
+// complete either way.
+fn commit_cover_art(
+    staging: &StagingArea,
+    embedder: &ArtEmbedder,
+    art_filename: &str,
+    art_path: &Path,
+    extracted_to: Option<&Path>,
+) {
+    if let Err(e) = staging.commit(art_filename, art_path) {
+        println!("Failed moving cover art to {}: {e}", art_path.display());
+        return;
+    }
+    let Some(folder) = extracted_to.filter(|_| embedder.enabled()) else {
+        return;
+    };
+
+    let report = std::fs::read(art_path).and_then(|art| embedder.embed_tree(folder, &art));
+    match report {
+        Ok(report) => {
+            for (path, e) in report.failed {
+                println!("Failed embedding cover art into {}: {e}", path.display());
+            }
+        }
+        Err(e) => println!("Failed embedding cover art into {}: {e}", folder.display()),
+    }
+}
+
+// Like embedding cover art, failing files only warn
+fn normalize_tags(
+    normalizer: &TagNormalizer,
+    folder: &Path,
+    entries: &[PathBuf],
+    planned: &PlannedDownload,
+    tag_templates: &[TagTemplate],
+) {
+    let item = &planned.item;
+    let fields = TemplateFields {
+        artist: &item.artist,
+        title: &item.title,
+        year: item.release_year(),
+        release_date: item.release_date(),
+        item_type: &item.item_type,
+        sale_id: &planned.sale_id,
+    };
+    let tags = ReleaseTags {
+        album_artist: item.artist.clone(),
+        album: item.title.clone(),
+        year: item.release_year(),
+        custom: tag_templates
+            .iter()
+            .map(|tag_template| tag_template.render(&fields))
+            .collect(),
+    };
+
+    let report = normalizer.normalize_release(folder, entries, &tags);
+    for (path, e) in report.skipped_fields {
+        println!("Left a --tag-template field out of {}: {e}", path.display());
+    }
+    for (path, e) in report.failed {
+        println!("Failed normalizing the tags of {}: {e}", path.display());
+    }
+}
+
+// The earlier download of a release, where it's saved now, or else under its flat name in any
+// format
+fn previous_contents(
+    extractors: &ExtractorRegistry,
+    folder: &Path,
+    release_path: &Path,
+    release: &DownloadCacheRelease,
+    format: api::data::DownloadFormat,
+) -> Option<ReleaseContents> {
+    let flat_paths = <api::data::DownloadFormat as clap::ValueEnum>::value_variants()
+        .iter()
+        .map(|&format| {
+            let path = layout::release_file_name(release.release_id(), format);
+            (PathBuf::from(path), format)
+        });
+    std::iter::once((release_path.to_owned(), format))
+        .chain(flat_paths)
+        .find_map(|(path, format)| {
+            let path = folder.join(path);
+            path.is_file()
+                .then(|| ReleaseContents::read(extractors, &path, format).ok())
+                .flatten()
+        })
+}
+
+// Written next to the release, to tell a remaster from a metadata fix
+fn record_changes(
+    extractors: &ExtractorRegistry,
+    previous: &ReleaseContents,
+    final_path: &Path,
+    release: &DownloadCacheRelease,
+    format: api::data::DownloadFormat,
+) {
+    let filename = final_path.file_name().unwrap_or_default().to_string_lossy();
+    let current = match ReleaseContents::read(extractors, final_path, format) {
+        Ok(current) => current,
+        Err(e) => {
+            println!("Couldn't read the contents of {filename} to compare them: {e}");
+            return;
+        }
+    };
+    let title = format!("\"{}\" by {}", release.title(), release.artist());
+    let Some(changes) = changes::describe_changes(&title, previous, &current) else {
+        return;
+    };
+
+    let changes_path = final_path.with_file_name(changes::changes_file_name(
+        &filename,
+        time::OffsetDateTime::now_utc().date(),
+    ));
+    match std::fs::write(&changes_path, changes) {
+        Ok(()) => println!(
+            "{title} changed since it was last downloaded, see {}",
+            changes_path.display()
+        ),
+        Err(e) => println!("Failed writing {}: {e}", changes_path.display()),
+    }
+}
+
+// Drops the downloads with fewer audio files than the release has tracks. As they never make it
+// into the cache, the next sync downloads them again.
+async fn verify_track_counts(
+    stage: &DownloadStage<'_>,
+    staging: &StagingArea,
+    completed: Vec<PendingDownload>,
+    cli: &SyncArgs,
+    report: &mut SyncReport,
+) -> Vec<PendingDownload> {
+    if !cli.verify_tracks {
+        return completed;
+    }
+
+    let extractors = ExtractorRegistry::default();
+    let mut verified = Vec::new();
+    for p in completed {
+        let filename = &p.download.filename;
+        let Some(page_url) = p.planned.item.page_url.as_deref() else {
+            verified.push(p);
+            continue;
+        };
+        let expected = match stage.api_context.get_tralbum_data(page_url).await {
+            // a preorder only comes with the tracks released ahead of the rest
+            Ok(tralbum) if tralbum.album_is_preorder == Some(true) => {
+                verified.push(p);
+                continue;
+            }
+            Ok(tralbum) => tralbum.trackinfo.len(),
+            Err(e) => {
+                stage.println(&format!(
+                    "Couldn't look up the track list of {filename}, not verifying it: {e}"
+                ));
+                verified.push(p);
+                continue;
+            }
+        };
+        let staged_path = staging.partial_path(filename);
+        let found = match extractors.count_tracks(&staged_path) {
+            Ok(found) => found,
+            Err(e) => {
+                stage.println(&format!(
+                    "Couldn't read the contents of {filename}, not verifying it: {e}"
+                ));
+                verified.push(p);
+                continue;
+            }
+        };
+
+        if found >= expected {
+            verified.push(p);
+            continue;
+        }
+        stage.println(&format!(
+            "{filename} looks incomplete, with {found} of {expected} tracks. It will be downloaded again on the next sync"
+        ));
+        stage.journal.record(
+            p.release.release_id(),
+            EventKind::Failed,
+            Some(format!("incomplete archive: {found} of {expected} tracks")),
+        );
+        report.mark_incomplete(p.release.release_id());
+        if let Err(e) = std::fs::remove_file(&staged_path) {
+            stage.println(&format!("Failed removing {}: {e}", staged_path.display()));
+        }
+    }
+    verified
+}
+
+// Staged like the releases, so a cover is only moved next to a release that made it. The covers
+// of a batch are fetched all at once, and a release whose cover can't be fetched is kept without
+// one.
+async fn fetch_cover_art(
+    stage: &DownloadStage<'_>,
+    staging: &StagingArea,
+    mut completed: Vec<PendingDownload>,
+    cli: &SyncArgs,
+) -> Vec<PendingDownload> {
+    if !cli.save_art {
+        return completed;
+    }
+
+    let covers: Vec<_> = completed
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.planned.item.art_id > 0)
+        .map(|(index, p)| (index, p.planned.item.art_id))
+        .collect();
+    let art_size = cli.art_size;
+    let fetch = |(index, art_id)| {
+        let api_context = Arc::clone(stage.api_context);
+        async move { (index, api_context.get_art(art_id, art_size).await) }
+    };
+    let limit = covers.len();
+    let result = run_bounded(covers, limit, fetch, |(index, art)| {
+        let p = &mut completed[index];
+        let filename = &p.download.filename;
+        let art = match art {
+            Ok(art) => art,
+            Err(e) => {
+                stage.println(&format!("Couldn't fetch the cover art of {filename}: {e}"));
+                return Ok(());
+            }
+        };
+        let art_filename = format!("{filename}.cover.{}", art_extension(&art));
+        match std::fs::write(staging.partial_path(&art_filename), art) {
+            Ok(()) => p.art = Some(art_filename),
+            Err(e) => {
+                stage.println(&format!("Failed saving the cover art of {filename}: {e}"));
+            }
+        }
+        Ok(())
+    })
+    .await;
+    if let Err(e) = result {
+        stage.println(&format!("Failed fetching cover art: {e}"));
+    }
+    completed
+}
+
+// Originals are uploaded as JPEG or PNG, while the scaled sizes are always JPEG
+fn art_extension(art: &[u8]) -> &'static str {
+    if art.starts_with(b"\x89PNG") {
+        "png"
+    } else {
+        "jpg"
+    }
+}
+
+// A dry run (or a run only printing links) resolves everything, but stops short of downloading
+async fn preview_downloads(
+    library: &LibraryArgs,
+    cli: &SyncArgs,
+    download_cache: &DownloadCache,
+    resolved_links: &mut mpsc::Receiver<anyhow::Result<ResolvedLink>>,
+    release_paths: &HashMap<String, PathBuf>,
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    let mut downloads = Vec::new();
+    while let Some(link) = resolved_links.recv().await {
+        downloads.push(accept_link(link?, release_paths, cli, report));
+    }
+    status!(
+        !cli.print_urls_only && cli.dry_run.is_some(),
+        "Dry run, so not downloading anything..."
+    );
+
+    if let Some(plan_path) = &cli.plan_json {
+        let plan = Plan {
+            releases: downloads
+                .iter()
+                .map(|p| PlanEntry::new(&p.planned, &p.path.to_string_lossy()))
+                .collect(),
+        };
+        let plan = models::versioned_value(&plan)?;
+        std::fs::write(plan_path, serde_json::to_string_pretty(&plan)?)
+            .with_context(|| format!("Failed writing {}", plan_path.display()))?;
+    }
+    if cli.dry_run == Some(DryRun::Layout) {
+        print_layout(
+            VolumePlacement::open(library, cli, download_cache)?,
+            &downloads,
+        );
+    }
+    if cli.cache_diff {
+        print_cache_diff(
+            &sync_cache_file_path(library, cli),
+            downloads.into_iter().map(|p| p.release).collect(),
+        )?;
+    }
+    Ok(())
+}
+
+// Where every release would be saved, with the download folders balanced by expected sizes
+fn print_layout(mut placement: VolumePlacement, downloads: &[PendingDownload]) {
+    let files: Vec<_> = downloads
+        .iter()
+        .map(|p| {
+            let folder = placement.route(&p.release, p.expected_bytes.unwrap_or_default());
+            (p.release.release_id().to_owned(), folder.join(&p.path))
+        })
+        .collect();
+
+    println!("Files that would be written:");
+    let mut problems = 0;
+    for file in layout::check_layout(files, Path::exists) {
+        let path = file.path.display();
+        match file.problem {
+            None => println!("  {path}"),
+            Some(LayoutProblem::Exists) => {
+                problems += 1;
+                println!("  {path} (already exists, would be overwritten)");
+            }
+            Some(LayoutProblem::CollidesWith(other)) => {
+                problems += 1;
+                println!("  {path} (collides with {other})");
+            }
+        }
+    }
+    if problems > 0 {
+        println!("{problems} layout problem(s) found");
+    }
+}
+
+// How the cache file would change once these releases are downloaded, for reviewing a shared
+// cache before letting a run write to it
+fn print_cache_diff(
+    cache_file_path: &Path,
+    releases: Vec<DownloadCacheRelease>,
+) -> anyhow::Result<()> {
+    let old_data = if std::fs::exists(cache_file_path)? {
+        std::fs::read_to_string(cache_file_path)?
+    } else {
+        String::new()
+    };
+
+    let mut download_cache = cache::read_download_cache(&old_data)?;
+    for release in releases {
+        download_cache.insert(release.release_id().to_owned(), release);
+    }
+
+    let file_label = cache_file_path.file_name().map_or_else(
+        || cache_file_path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let diff = cache::diff_download_cache_data(
+        &old_data,
+        &serialize_download_cache(&download_cache),
+        &file_label,
+    );
+    if diff.is_empty() {
+        println!("The download cache would stay unchanged");
+    } else {
+        print!("{diff}");
+    }
+    Ok(())
+}
+
+fn write_html_report(
+    report: &SyncReport,
+    download_folder: &Path,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let report_path = download_folder.join(report::HTML_REPORT_FILE_NAME);
+    std::fs::write(
+        &report_path,
+        report.render_html(time::OffsetDateTime::now_utc()),
+    )?;
+    status!(verbose, "Wrote sync report to {}", report_path.display());
+    Ok(())
+}
+
+fn record_discovered(journal: &Journal, items: &HashMap<String, api::data::DigitalItem>) {
+    for (key, digital_item) in items {
+        journal.record(
+            key,
+            EventKind::Discovered,
+            Some(format!(
+                "\"{}\" by {}",
+                digital_item.title, digital_item.artist
+            )),
+        );
+    }
+}
+
+struct ResolvedLink {
+    url: String,
+    planned: PlannedDownload,
+}
+
+// Resolves with --resolve-concurrency stat requests in flight at a time, and waits while the
+// channel is full, so a huge run doesn't hold every resolved link in memory before downloading
+// starts. The releases Bandcamp is still preparing wait for their next try apart, leaving their
+// slot to the next release.
+fn spawn_link_resolution(
+    api_context: &Arc<api::BandcampAPIContext>,
+    planned_downloads: Vec<PlannedDownload>,
+    cli: &SyncArgs,
+) -> mpsc::Receiver<anyhow::Result<ResolvedLink>> {
+    let (sender, receiver) = mpsc::channel(RESOLVED_LINKS_CAPACITY);
+    let api_context = Arc::clone(api_context);
+    let concurrency = usize::from(cli.resolve_concurrency);
+
+    tokio::spawn(async move {
+        // each with the link to ask about, once Bandcamp named one
+        let mut queue: VecDeque<(PlannedDownload, Option<String>)> = planned_downloads
+            .into_iter()
+            .map(|planned| (planned, None))
+            .collect();
+        let mut stat_tasks = JoinSet::new();
+        let mut preparing = JoinSet::new();
+        loop {
+            while stat_tasks.len() < concurrency {
+                let Some((planned, link)) = queue.pop_front() else {
+                    break;
+                };
+                let api_context = Arc::clone(&api_context);
+                stat_tasks.spawn(async move {
+                    let stat = stat_download_link(&api_context, &planned, link).await;
+                    (planned, stat)
+                });
+            }
+
+            let link = tokio::select! {
+                Some(result) = stat_tasks.join_next() => match result {
+                    Ok((planned, Ok(StatDownload::Ready(url)))) => {
+                        api_context.progress().item_resolved(&planned.sale_id);
+                        Ok(ResolvedLink { url, planned })
+                    }
+                    Ok((planned, Ok(StatDownload::Preparing(url)))) => {
+                        preparing.spawn(async move {
+                            tokio::time::sleep(api::STAT_RETRY_DELAY).await;
+                            (planned, Some(url))
+                        });
+                        continue;
+                    }
+                    Ok((_, Err(e))) => Err(e.into()),
+                    Err(e) => Err(e.into()),
+                },
+                Some(result) = preparing.join_next() => match result {
+                    Ok(retry) => {
+                        queue.push_front(retry);
+                        continue;
+                    }
+                    Err(e) => Err(e.into()),
+                },
+                else => break,
+            };
+            // the receiving side is gone, e.g. after failing on an earlier link
+            if sender.send(link).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+async fn stat_download_link(
+    api_context: &api::BandcampAPIContext,
+    planned: &PlannedDownload,
+    link: Option<String>,
+) -> Result<StatDownload, DigitalDownloadError> {
+    let link = match link {
+        Some(link) => link,
+        None => {
+            api::get_unqualified_digital_download_link(&planned.item, planned.format)?.to_owned()
+        }
+    };
+    api_context.stat_digital_download_link(&link).await
+}
+
+// The links as they were queued, in the format they were resolved for. Those that expired since are
+// resolved again once downloading them fails.
+fn queued_links(
+    queue: &OfflineQueue,
+    planned_downloads: Vec<PlannedDownload>,
+) -> mpsc::Receiver<anyhow::Result<ResolvedLink>> {
+    let (sender, receiver) = mpsc::channel(planned_downloads.len().max(1));
+    for mut planned in planned_downloads {
+        let link = match queue.get(&planned.sale_id) {
+            Some(queued) => {
+                planned.format = queued.format;
+                Ok(ResolvedLink {
+                    url: queued.url.clone(),
+                    planned,
+                })
+            }
+            None => Err(anyhow::anyhow!("{} isn't queued", planned.sale_id)),
+        };
+        // the channel fits every link, and is only read once they're all in
+        let _ = sender.try_send(link);
+    }
+    receiver
+}
+
+// Everything the downloads need but the network they're made from, so they can happen elsewhere
+fn open_staging(library: &LibraryArgs, cli: &SyncArgs, download_folder: &Path) -> StagingArea {
+    let staging = StagingArea::for_destination(download_folder, cli.staging_dir.as_deref());
+    record_scratch(library, ScratchKind::Staging, staging.path());
+    staging
+}
+
+// Recorded in the state folder, so `clean` finds what a sync left behind wherever it is
+fn record_scratch(library: &LibraryArgs, kind: ScratchKind, path: &Path) {
+    let state_dir = library.state_dir(&library.download_folder());
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let now = time::OffsetDateTime::now_utc();
+    if let Err(e) = scratch::update_registry(&state_dir, |registry| {
+        registry.record(kind, &path, now);
+    }) {
+        eprintln!(
+            "Failed recording {} in {}: {e}",
+            path.display(),
+            state_dir.join(scratch::SCRATCH_FILE_NAME).display()
+        );
+    }
+}
+
+async fn write_offline_queue(
+    library: &LibraryArgs,
+    queue_path: &Path,
+    resolved_links: &mut mpsc::Receiver<anyhow::Result<ResolvedLink>>,
+) -> anyhow::Result<()> {
+    let mut downloads = BTreeMap::new();
+    while let Some(link) = resolved_links.recv().await {
+        let ResolvedLink { url, planned } = link?;
+        let queued = QueuedDownload {
+            item: planned.item,
+            format: planned.format,
+            url,
+        };
+        downloads.insert(planned.sale_id, queued);
+    }
+
+    let queued = downloads.len();
+    let queue = OfflineQueue::new(time::OffsetDateTime::now_utc().unix_timestamp(), downloads);
+    staging::write_atomically(queue_path, queue.serialize()?)
+        .with_context(|| format!("Failed writing {}", queue_path.display()))?;
+    record_scratch(library, ScratchKind::OfflineQueue, queue_path);
+    println!(
+        "Queued {queued} release(s) in {}, download them with `bandcamp-dl execute-plan {}`",
+        queue_path.display(),
+        queue_path.display()
+    );
+    Ok(())
+}
+
+// Prints the link and adds it to the report, returning what is left to download
+fn accept_link(
+    link: ResolvedLink,
+    release_paths: &HashMap<String, PathBuf>,
+    cli: &SyncArgs,
+    report: &mut SyncReport,
+) -> PendingDownload {
+    let ResolvedLink { url, planned } = link;
+    let (key, digital_item) = (&planned.sale_id, &planned.item);
+    report.add(key, digital_item, planned.format);
+
+    if cli.print_urls_only {
+        println!("{url}");
+    } else {
+        println!(
+            "Download link for \"{}\" by {} ({}): {}",
+            digital_item.title, digital_item.artist, key, url
+        );
+    }
+
+    let download = Download::new(
+        key.clone(),
+        Url::parse(&url).unwrap(),
+        layout::release_file_name(key, planned.format),
+    );
+    PendingDownload {
+        download,
+        path: release_paths
+            .get(key)
+            .cloned()
+            .unwrap_or_else(|| cli.release_path(&planned)),
+        expected_bytes: planned.expected_bytes(),
+        release: DownloadCacheRelease::from_digital_item(key, digital_item),
+        planned,
+        art: None,
+    }
+}
+
+// A first sync fetches the whole collection, which may be far more than expected
+fn confirm_first_sync(cli: &SyncArgs, planned_downloads: &[PlannedDownload]) -> anyhow::Result<()> {
+    let total_bytes: u64 = planned_downloads
+        .iter()
+        .filter_map(PlannedDownload::expected_bytes)
+        .sum();
+    if total_bytes <= cli.confirm_first_sync_above * 1_000_000_000 {
+        return Ok(());
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let total_gb = total_bytes as f64 / 1e9;
+    let question = format!(
+        "This first sync will download {} release(s), about {total_gb:.1} GB. Continue?",
+        planned_downloads.len()
+    );
+    match Confirmation::new(cli.assume_yes).ask(&question)? {
+        Answer::Yes => Ok(()),
+        Answer::No => anyhow::bail!("First sync cancelled"),
+        Answer::NotInteractive => anyhow::bail!(
+            "Not starting a first sync of about {total_gb:.1} GB without confirmation, pass --assume-yes to go ahead"
+        ),
+    }
+}
+
+// What downloading takes, along with the API context for resolving links again that expired
+// on the way
+struct DownloadStage<'a> {
+    api_context: &'a Arc<api::BandcampAPIContext>,
+    chunked_downloader: ChunkedDownloader,
+    // for the lengths finished downloads are checked against
+    client: reqwest::Client,
+    staging: &'a StagingArea,
+    journal: &'a Journal,
+    control: &'a RunControl,
+    release_paths: HashMap<String, PathBuf>,
+    // shared with the writer, which prints above the bars too
+    progress: Arc<DownloadProgress>,
+}
+
+impl<'a> DownloadStage<'a> {
+    fn new(
+        api_context: &'a Arc<api::BandcampAPIContext>,
+        staging: &'a StagingArea,
+        journal: &'a Journal,
+        control: &'a RunControl,
+        release_paths: HashMap<String, PathBuf>,
+        cli: &SyncArgs,
+        releases: usize,
+    ) -> Self {
+        let progress = Arc::new(
+            DownloadProgress::new(releases).with_reporter(api_context.progress_reporter()),
+        );
+        Self {
+            api_context,
+            chunked_downloader: ChunkedDownloader::new(
+                api_context.download_client().clone(),
+                staging.path().to_owned(),
+                usize::from(cli.download_connections),
+                cli.chunked_download_threshold.saturating_mul(1_000_000),
+            )
+            .with_progress(Arc::clone(&progress) as Arc<dyn ProgressReporter>),
+            client: api_context.download_client().clone(),
+            staging,
+            journal,
+            control,
+            release_paths,
+            progress,
+        }
+    }
+
+    // Status lines go above the progress bars, rather than through them
+    fn println(&self, line: &str) {
+        self.progress.suspend(|| println!("{line}"));
+    }
+
+    fn accept(
+        &self,
+        link: ResolvedLink,
+        cli: &SyncArgs,
+        report: &mut SyncReport,
+    ) -> PendingDownload {
+        self.progress
+            .suspend(|| accept_link(link, &self.release_paths, cli, report))
+    }
+
+    // Against the length the server announced while downloading, or the size hint without it. A
+    // truncated file is kept for the retry to resume, any other is removed.
+    fn verify(
+        &self,
+        p: &PendingDownload,
+        content_length: Option<u64>,
+    ) -> Result<(), DownloadSizeError> {
+        let path = self.staging.partial_path(&p.download.filename);
+        let received = std::fs::metadata(&path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+
+        let result = download::check_size(received, content_length, p.expected_bytes);
+        if result.as_ref().is_err_and(|e| !e.is_truncated()) {
+            let _ = std::fs::remove_file(&path);
+        }
+        result
+    }
+
+    fn record_failure(&self, p: &PendingDownload, e: &DownloadError) {
+        let filename = &p.download.filename;
+        if matches!(e, DownloadError::Interrupted) {
+            self.println(&format!("Skipped {filename}"));
+            self.journal.record(
+                p.release.release_id(),
+                EventKind::Skipped,
+                Some("skipped from the keyboard".to_owned()),
+            );
+            return;
+        }
+        self.println(&format!("Failed downloading {filename}: {e}"));
+        self.journal.record(
+            p.release.release_id(),
+            EventKind::Failed,
+            Some(e.to_string()),
+        );
+    }
+
+    // The bytes were reported as they were written
+    fn report_downloaded(&self, p: &PendingDownload) {
+        self.progress.item_downloaded(p.release.release_id());
+    }
+}
+
+// What came of downloading a batch
+#[derive(Default)]
+struct BatchOutcome {
+    completed: Vec<PendingDownload>,
+    // failed on an expired link
+    expired: Vec<PendingDownload>,
+    // ended with fewer or other bytes than expected
+    incomplete: Vec<(PendingDownload, DownloadSizeError)>,
+}
+
+impl BatchOutcome {
+    fn finish(
+        &mut self,
+        stage: &DownloadStage<'_>,
+        p: PendingDownload,
+        content_length: Option<u64>,
+    ) {
+        match stage.verify(&p, content_length) {
+            Ok(()) => {
+                stage.report_downloaded(&p);
+                self.completed.push(p);
+            }
+            Err(e) => self.incomplete.push((p, e)),
+        }
+    }
+}
+
+// Returns the downloads that completed successfully. Links can expire between being resolved and
+// downloaded on long runs, so those that did are resolved again and retried once, as are those
+// that came out incomplete.
+async fn download_all(
+    stage: &DownloadStage<'_>,
+    pending: Vec<PendingDownload>,
+) -> Vec<PendingDownload> {
+    let BatchOutcome {
+        mut completed,
+        expired,
+        incomplete,
+    } = download_once(stage, pending).await;
+    // what's left is downloaded again on the next sync
+    if (expired.is_empty() && incomplete.is_empty()) || stage.control.is_quitting() {
+        return completed;
+    }
+
+    // their links are still good
+    let mut retries = Vec::new();
+    for (p, e) in incomplete {
+        stage.println(&format!(
+            "Download of {} is incomplete ({e}), retrying",
+            p.download.filename
+        ));
+        retries.push(p);
+    }
+    retries.extend(renew_expired_links(stage, expired).await);
+
+    let second_attempt = download_once(stage, retries).await;
+    completed.extend(second_attempt.completed);
+    for p in second_attempt.expired {
+        stage.println(&format!(
+            "Failed downloading {}: the download link expired again",
+            p.download.filename
+        ));
+        stage.journal.record(
+            p.release.release_id(),
+            EventKind::Failed,
+            Some("download link expired".to_owned()),
+        );
+    }
+    for (p, e) in second_attempt.incomplete {
+        stage.println(&format!("Failed downloading {}: {e}", p.download.filename));
+        stage.journal.record(
+            p.release.release_id(),
+            EventKind::Failed,
+            Some(e.to_string()),
+        );
+    }
+    completed
+}
+
+// The downloads whose link could be resolved again
+async fn renew_expired_links(
+    stage: &DownloadStage<'_>,
+    expired: Vec<PendingDownload>,
+) -> Vec<PendingDownload> {
+    let mut renewed = Vec::new();
+    for mut p in expired {
+        let filename = p.download.filename.clone();
+        let link = stage
+            .api_context
+            .get_digital_download_link(&p.planned.item, p.planned.format)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|url| Ok(Url::parse(&url)?));
+        match link {
+            Ok(url) => {
+                stage.println(&format!(
+                    "Download link of {filename} expired, retrying with a new one"
+                ));
+                p.download = Download::new(p.download.release_id.clone(), url, filename);
+                renewed.push(p);
+            }
+            Err(e) => {
+                stage.println(&format!(
+                    "Failed resolving a new download link for {filename}: {e}"
+                ));
+                stage.journal.record(
+                    p.release.release_id(),
+                    EventKind::Failed,
+                    Some(e.to_string()),
+                );
+            }
+        }
+    }
+    renewed
+}
+
+// A skip or quit from the keyboard gives up on downloads without failing them. Nothing of those
+// reaches the cache, so the next sync downloads them again.
+async fn download_once(stage: &DownloadStage<'_>, pending: Vec<PendingDownload>) -> BatchOutcome {
+    stage.control.forget_skips();
+    let (chunked, mut pending): (Vec<_>, Vec<_>) = pending
+        .into_iter()
+        .partition(|p| stage.chunked_downloader.should_chunk(p.expected_bytes));
+
+    // large releases one at a time, as each of them already uses several connections
+    let mut outcome = BatchOutcome::default();
+    for p in chunked {
+        let filename = &p.download.filename;
+        stage.println(&format!("Downloading {filename} in chunks..."));
+        let file = [(p.download.clone(), p.expected_bytes)];
+        let partial_path = stage.staging.partial_path(filename);
+        let download = stage
+            .chunked_downloader
+            .download(&p.download, &partial_path);
+        let result = tokio::select! {
+            result = stage.progress.follow(&file, download) => result,
+            _ = stage.control.interrupted() => Err(DownloadError::Interrupted),
+        };
+        match result {
+            Ok(total_bytes) => outcome.finish(stage, p, Some(total_bytes)),
+            Err(DownloadError::RangesNotSupported) => {
+                stage.println(&format!("Server doesn't support range requests for {filename}, downloading it in one piece"));
+                pending.push(p);
+            }
+            Err(e) if download::is_expired_link_error(&e) => outcome.expired.push(p),
+            Err(e) => stage.record_failure(&p, &e),
+        }
+    }
+
+    if pending.is_empty() {
+        return outcome;
+    }
+
+    let downloads: Vec<_> = pending
+        .iter()
+        .map(|p| Download {
+            filename: staging::partial_file_name(&p.download.filename),
+            ..p.download.clone()
+        })
+        .collect();
+    let files: Vec<_> = downloads
+        .iter()
+        .cloned()
+        .zip(pending.iter().map(|p| p.expected_bytes))
+        .collect();
+    let reporter: Arc<dyn ProgressReporter> = stage.progress.clone();
+    let results = stage
+        .progress
+        .follow(
+            &files,
+            download::download_files_interruptible(
+                &stage.client,
+                stage.staging.path(),
+                downloads,
+                stage.control,
+                &reporter,
+            ),
+        )
+        .await;
+
+    let mut pending: HashMap<_, _> = pending
+        .into_iter()
+        .map(|p| (p.download.filename.clone(), p))
+        .collect();
+
+    for (download, result) in results {
+        let filename = staging::completed_file_name(&download.filename);
+        let Some(p) = pending.remove(filename) else {
+            continue;
+        };
+        match result {
+            Ok(content_length) => outcome.finish(stage, p, content_length),
+            Err(e) if download::is_expired_link_error(&e) => outcome.expired.push(p),
+            Err(e) => stage.record_failure(&p, &e),
+        }
+    }
+    outcome
+}
+
+// Keys are only listened to while downloading, leaving the terminal alone otherwise
+// Returns how many downloads made it into the cache. The writer is finished either way, so what
+// was downloaded before an error is kept.
+async fn download_releases(
+    stage: &DownloadStage<'_>,
+    writer: CommitStage,
+    control: &Arc<RunControl>,
+    budget: Option<TimeBudget>,
+    resolved_links: &mut mpsc::Receiver<anyhow::Result<ResolvedLink>>,
+    cli: &SyncArgs,
+    report: &mut SyncReport,
+) -> anyhow::Result<usize> {
+    let key_listener = KeyListener::start(Arc::clone(control)).inspect(|_| {
+        stage.println(controls::HOTKEYS_HELP);
+    });
+
+    // smallest releases go first with a time budget, which needs all of them to be known up front
+    let downloaded = async {
+        if let Some(budget) = budget {
+            let mut downloads = Vec::new();
+            while let Some(link) = resolved_links.recv().await {
+                downloads.push(stage.accept(link?, cli, report));
+            }
+            download_within_budget(stage, &writer, downloads, budget, cli, report).await;
+            Ok(())
+        } else {
+            download_as_resolved(stage, &writer, resolved_links, cli, report).await
+        }
+    }
+    .await;
+    drop(key_listener);
+
+    stage.println("Updating download cache...");
+    let downloaded_count = writer.finish(report).await?;
+    downloaded.map(|()| downloaded_count)
+}
+
+// Starts downloading with whatever links are resolved so far, rather than waiting for all of them
+async fn download_as_resolved(
+    stage: &DownloadStage<'_>,
+    writer: &CommitStage,
+    resolved_links: &mut mpsc::Receiver<anyhow::Result<ResolvedLink>>,
+    cli: &SyncArgs,
+    report: &mut SyncReport,
+) -> anyhow::Result<()> {
+    while let Some(link) = resolved_links.recv().await {
+        if !stage.control.wait_until_resumed().await {
+            break;
+        }
+        let mut batch = vec![stage.accept(link?, cli, report)];
+        let batch_size = writer.batch_size(DOWNLOAD_BATCH_SIZE);
+        while batch.len() < batch_size {
+            let Ok(link) = resolved_links.try_recv() else {
+                break;
+            };
+            batch.push(stage.accept(link?, cli, report));
+        }
+        writer
+            .make_room(stage, batch.iter().filter_map(|p| p.expected_bytes).sum())
+            .await;
+        let started = Instant::now();
+        let completed = download_all(stage, batch).await;
+        writer
+            .push(stage, completed, started.elapsed(), cli, report)
+            .await;
+    }
+
+    Ok(())
+}
+
+async fn download_within_budget(
+    stage: &DownloadStage<'_>,
+    writer: &CommitStage,
+    mut pending: Vec<PendingDownload>,
+    mut budget: TimeBudget,
+    cli: &SyncArgs,
+    report: &mut SyncReport,
+) {
+    pending.sort_by_key(|p| p.expected_bytes.unwrap_or(u64::MAX));
+
+    let mut skipped = Vec::new();
+    let mut pending = pending.into_iter().peekable();
+    while pending.peek().is_some() {
+        if !stage.control.wait_until_resumed().await {
+            // what's left wasn't skipped for the budget, so it isn't recorded as such
+            return;
+        }
+        let now = Instant::now();
+        let (batch, over_budget): (Vec<_>, Vec<_>) = pending
+            .by_ref()
+            .take(writer.batch_size(TIME_BUDGET_BATCH_SIZE))
+            .partition(|p| budget.can_start(now, p.expected_bytes));
+        skipped.extend(over_budget);
+        if batch.is_empty() {
+            break;
+        }
+
+        let batch_bytes: u64 = batch.iter().filter_map(|p| p.expected_bytes).sum();
+        writer.make_room(stage, batch_bytes).await;
+        let started = Instant::now();
+        let batch_completed = download_all(stage, batch).await;
+        let elapsed = started.elapsed();
+        budget.record_downloaded(batch_bytes, elapsed);
+        stage.println(&format!(
+            "Downloaded batch in {elapsed:.0?}, {:.0?} of the time budget left",
+            budget.remaining(Instant::now())
+        ));
+        writer
+            .push(stage, batch_completed, elapsed, cli, report)
+            .await;
+    }
+
+    skipped.extend(pending);
+    for p in &skipped {
+        stage.journal.record(
+            p.release.release_id(),
+            EventKind::Skipped,
+            Some("time budget exhausted".to_owned()),
+        );
+    }
+    if !skipped.is_empty() {
+        stage.println(&format!(
+            "Time budget exhausted, leaving {} release(s) for the next run",
+            skipped.len()
+        ));
+    }
+}
+
+// Runs the task made from each input, at most `limit` of them at a time, handing each outcome to
+// `handle` as it completes
+async fn run_bounded<I, T, Fut>(
+    inputs: I,
+    limit: usize,
+    task: impl Fn(I::Item) -> Fut,
+    mut handle: impl FnMut(T) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    I: IntoIterator,
+    Fut: std::future::Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut inputs = inputs.into_iter();
+    let mut tasks = JoinSet::new();
+    loop {
+        while tasks.len() < limit {
+            let Some(input) = inputs.next() else {
+                break;
+            };
+            tasks.spawn(task(input));
+        }
+
+        let Some(result) = tasks.join_next().await else {
+            return Ok(());
+        };
+        handle(result?)?;
+    }
+}
+
+// Releases recently found to have nothing to download are skipped until their marker runs out
+async fn find_new_releases(
+    releases: &api::SaleIdUrlMap,
+    download_cache: &cache::DownloadCache,
+    api_context: &Arc<api::BandcampAPIContext>,
+    probe_markers: &mut ProbeMarkers,
+    max_ages: &MaxAges,
+    scrape_concurrency: usize,
+    verbose: bool,
+) -> Result<HashMap<String, api::data::DigitalItem>, anyhow::Error> {
+    let now = time::OffsetDateTime::now_utc();
+    let mut skipped = 0;
+    let mut to_scrape = Vec::new();
+    for (key, item_url) in releases {
+        if !download_cache.contains_key(&cache::normalize_release_id(key)) {
+            if probe_markers.is_fresh(key, max_ages, now) {
+                skipped += 1;
+                continue;
+            }
+            to_scrape.push((key.clone(), item_url.clone()));
+        }
+    }
+    status!(
+        verbose && skipped > 0,
+        "Skipping {skipped} release(s) recently found to have nothing to download"
+    );
+
+    let mut items_to_download = HashMap::new();
+    let scrape = |(key, item_url): (String, String)| {
+        let api_context = Arc::clone(api_context);
+        async move { (api_context.get_digital_download_item(&item_url).await, key) }
+    };
+    run_bounded(
+        to_scrape,
+        scrape_concurrency,
+        scrape,
+        |(digital_item_result, key)| {
+            let kind = match digital_item_result {
+                Ok(Some(item_data)) => {
+                    status!(
+                        verbose,
+                        "New item: \"{}\" by \"{}\" ({})",
+                        item_data.title,
+                        item_data.artist,
+                        key
+                    );
+                    probe_markers.clear(&key);
+                    items_to_download.insert(key, item_data);
+                    return Ok(());
+                }
+                Ok(None) => MarkerKind::NoDigitalItem,
+                Err(InformationRetrievalError::Unavailable) => MarkerKind::Unavailable,
+                Err(e) => return Err(e.into()),
+            };
+            status!(
+                verbose,
+                "Nothing to download for {key} ({}), checking again in {:?}",
+                kind.name(),
+                max_ages.get(kind)
+            );
+            probe_markers.mark(&key, kind, now);
+            Ok(())
+        },
+    )
+    .await?;
+
+    Ok(items_to_download)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    pub fn test_parse_legacy_sync() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--dry-run"]).unwrap();
+
+        assert_eq!(cli.command, None);
+        assert_eq!(
+            cli.library.and_then(|library| library.cookie_file),
+            Some(PathBuf::from("cookies.json"))
+        );
+        assert_eq!(cli.sync.dry_run, Some(DryRun::Plan));
+    }
+
+    #[test]
+    pub fn test_parse_sync_subcommand() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "sync", "-c", "cookies.json", "--dry-run"])
+            .unwrap();
+
+        let Some(Command::Sync(args)) = cli.command else {
+            panic!("expected the sync subcommand");
+        };
+        assert_eq!(
+            args.library.cookie_file,
+            Some(PathBuf::from("cookies.json"))
+        );
+        assert_eq!(args.sync.dry_run, Some(DryRun::Plan));
+    }
+
+    #[test]
+    pub fn test_parse_cookies_from_browser() {
+        let cli =
+            Cli::try_parse_from(["bandcamp-dl", "--cookies-from-browser", "firefox:work"]).unwrap();
+        let library = cli.library.unwrap();
+
+        assert_eq!(library.cookie_file, None);
+        assert_eq!(
+            library
+                .cookies_from_browser
+                .and_then(|source| source.profile),
+            Some("work".to_owned())
+        );
+
+        assert!(Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--cookies-from-browser",
+            "firefox"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_parse_sessions() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "--use-session"]).unwrap();
+        let library = cli.library.unwrap();
+        assert!(library.use_session);
+        assert_eq!(library.cookie_file, None);
+
+        let cli =
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--save-session"]).unwrap();
+        assert!(cli.library.unwrap().save_session);
+
+        assert!(Cli::try_parse_from(["bandcamp-dl", "--save-session"]).is_err());
+        assert!(
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--use-session"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["bandcamp-dl", "whoami", "--use-session"]).is_ok());
+    }
+
+    #[test]
+    pub fn test_parse_identity_cookie() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "--identity-cookie", "7%09abc"]).unwrap();
+        let library = cli.library.unwrap();
+
+        assert_eq!(library.identity_cookie.as_deref(), Some("7%09abc"));
+        assert_eq!(library.cookie_file, None);
+    }
+
+    #[test]
+    pub fn test_parse_download_folders() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "-d",
+            "/mnt/a",
+            "-d",
+            "/mnt/b",
+        ])
+        .unwrap();
+        let library = cli.library.unwrap();
+
+        assert_eq!(library.download_folder(), PathBuf::from("/mnt/a"));
+        assert_eq!(
+            library.download_folders(),
+            vec![PathBuf::from("/mnt/a"), PathBuf::from("/mnt/b")]
+        );
+    }
+
+    #[test]
+    pub fn test_parse_subscriptions() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["bandcamp-dl", "-c", "cookies.json", "-d", "/mnt/a"]
+                    .iter()
+                    .chain(args),
+            )
+            .map(|cli| cli.library.unwrap())
+        };
+
+        let library = parse(&["--only-subscriptions"]).unwrap();
+        assert_eq!(
+            library.download_folders(),
+            vec![PathBuf::from("/mnt/a/subscriptions")]
+        );
+        assert_eq!(
+            library.cache_file_path(&library.download_folder()),
+            PathBuf::from("/mnt/a/subscriptions/bandcamp-collection-downloader.cache")
+        );
+        assert!(library.item_filter().unwrap().is_active());
+
+        let library = parse(&[
+            "--only-subscriptions",
+            "--cache-file",
+            "/var/bandcamp.cache",
+            "--state-dir",
+            "/var/bandcamp",
+        ])
+        .unwrap();
+        assert_eq!(
+            library.cache_file_path(&library.download_folder()),
+            PathBuf::from("/var/bandcamp.subscriptions.cache")
+        );
+        assert_eq!(
+            library.state_dir(&library.download_folder()),
+            PathBuf::from("/var/bandcamp/subscriptions")
+        );
+
+        let library = parse(&[
+            "--exclude-subscriptions",
+            "--cache-file",
+            "/var/bandcamp.cache",
+        ])
+        .unwrap();
+        assert_eq!(library.download_folder(), PathBuf::from("/mnt/a"));
+        assert_eq!(
+            library.cache_file_path(&library.download_folder()),
+            PathBuf::from("/var/bandcamp.cache")
+        );
+        assert!(parse(&["--only-subscriptions", "--exclude-subscriptions"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_limit() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--limit",
+            "50",
+            "--offset",
+            "100",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.sync.limit, Some(50));
+        assert_eq!(cli.sync.offset, 100);
+        assert!(Cli::try_parse_from(["bandcamp-dl", "-c", "c.json", "--limit", "-1"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_group_by_band_id() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+        };
+
+        assert!(parse(&["--pin-artists", "--group-by-band-id"]).is_ok());
+        assert!(parse(&["--group-by-band-id"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_public() {
+        let parse =
+            |args: &[&str]| Cli::try_parse_from(std::iter::once(&"bandcamp-dl").chain(args));
+
+        assert!(parse(&["list", "--public", "someone"]).is_ok());
+        assert!(parse(&["export", "--public", "https://bandcamp.com/someone"]).is_ok());
+        assert!(parse(&["wishlist", "--public", "someone"]).is_ok());
+        assert!(parse(&["list"]).is_err());
+        assert!(parse(&["whoami"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_tmp_dir() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once(&"bandcamp-dl").chain(args)).map(|cli| cli.tmp_dir)
+        };
+
+        assert_eq!(
+            parse(&["sync", "-c", "cookies.json", "--tmp-dir", "/scratch"]).unwrap(),
+            Some(PathBuf::from("/scratch"))
+        );
+        assert_eq!(
+            parse(&["clean", "--tmp-dir", "/scratch", "--older-than", "12h"]).unwrap(),
+            Some(PathBuf::from("/scratch"))
+        );
+        assert_eq!(
+            parse(&["-c", "cookies.json", "--tmp-dir", "/scratch"]).unwrap(),
+            Some(PathBuf::from("/scratch"))
+        );
+        assert!(parse(&["clean", "--dry-run"]).is_ok());
+        assert!(parse(&["clean", "--older-than", "soon"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_delete_archives() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| (cli.sync.extract, cli.sync.delete_archives))
+        };
+
+        assert_eq!(parse(&["--extract"]).unwrap(), (true, false));
+        assert_eq!(
+            parse(&["--extract", "--delete-archives"]).unwrap(),
+            (true, true)
+        );
+        assert!(parse(&["--delete-archives"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_art_size() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| (cli.sync.save_art, cli.sync.art_size))
+        };
+
+        assert_eq!(
+            parse(&["--save-art"]).unwrap(),
+            (true, api::data::ArtSize::Original)
+        );
+        assert_eq!(
+            parse(&["--save-art", "--art-size", "1200"]).unwrap(),
+            (true, api::data::ArtSize::Large)
+        );
+        assert!(parse(&["--art-size", "700"]).is_err());
+        assert!(parse(&["--save-art", "--art-size", "500"]).is_err());
+    }
+
+    #[test]
+    pub fn test_extend_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let checksums_path = dir
+            .path()
+            .join("state")
+            .join(checksums::CHECKSUMS_FILE_NAME);
+        let extend = |path: &str| {
+            extend_state_file(
+                &checksums_path,
+                [(PathBuf::from(path), "ab".repeat(32))],
+                checksums::read_checksums,
+                checksums::serialize_checksums,
+            )
+        };
+
+        // as two syncs of different formats would, each knowing only of its own download
+        extend("/music/flac/Anomalie - Galerie.zip").unwrap();
+        extend("/music/mp3/Anomalie - Galerie.zip").unwrap();
+        assert_eq!(
+            read_state_file(&checksums_path)
+                .unwrap()
+                .map(|data| checksums::read_checksums(&data).unwrap().len()),
+            Some(2)
+        );
+        assert_eq!(
+            read_state_file(&dir.path().join("missing.json")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_write_probe_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let markers_path = dir.path().join(markers::MARKERS_FILE_NAME);
+        let now = time::OffsetDateTime::now_utc();
+        // two syncs reading the same empty file, each marking a release of its own
+        let read = read_probe_markers(&markers_path);
+        let mut first = read.clone();
+        first.mark("p1", MarkerKind::NoDigitalItem, now);
+        let mut second = read.clone();
+        second.mark("p2", MarkerKind::Unavailable, now);
+
+        write_probe_markers(&markers_path, &read, &first).unwrap();
+        write_probe_markers(&markers_path, &read, &second).unwrap();
+        let written = read_probe_markers(&markers_path);
+        assert!(written.get("p1").is_some());
+        assert!(written.get("p2").is_some());
+
+        write_probe_markers(&dir.path().join("unchanged.json"), &read, &read).unwrap();
+        assert!(!dir.path().join("unchanged.json").exists());
+    }
+
+    #[test]
+    pub fn test_commit_cover_art() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = StagingArea::for_destination(dir.path(), Some(&dir.path().join("staging")));
+        let release_path = dir.path().join("Anomalie - Galerie.zip");
+
+        assert_eq!(art_extension(b"\x89PNG\r\n"), "png");
+        assert_eq!(art_extension(b"\xff\xd8\xff"), "jpg");
+
+        let embedder = ArtEmbedder::new(false);
+        let art_path = cover_art_path("p1-flac.zip.cover.jpg", &release_path, None);
+        assert_eq!(art_path, dir.path().join("Anomalie - Galerie.jpg"));
+        std::fs::write(staging.partial_path("p1-flac.zip.cover.jpg"), b"jpg").unwrap();
+        commit_cover_art(
+            &staging,
+            &embedder,
+            "p1-flac.zip.cover.jpg",
+            &art_path,
+            None,
+        );
+        assert!(art_path.is_file());
+
+        let folder = dir.path().join("Anomalie").join("Galerie");
+        let art_path = cover_art_path("p1-flac.zip.cover.png", &release_path, Some(&folder));
+        assert_eq!(art_path, folder.join("cover.png"));
+        std::fs::write(staging.partial_path("p1-flac.zip.cover.png"), b"png").unwrap();
+        commit_cover_art(
+            &staging,
+            &embedder,
+            "p1-flac.zip.cover.png",
+            &art_path,
+            Some(&folder),
+        );
+        assert!(art_path.is_file());
+    }
+
+    #[test]
+    pub fn test_parse_embed_art() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.embed_art)
+        };
+
+        assert_eq!(
+            parse(&["--save-art", "--extract", "--embed-art"]).ok(),
+            cfg!(feature = "embed-art").then_some(true)
+        );
+        assert!(!parse(&["--save-art", "--extract"]).unwrap());
+        assert!(parse(&["--save-art", "--embed-art"]).is_err());
+        assert!(parse(&["--extract", "--embed-art"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_normalize_tags() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.normalize_tags)
+        };
+
+        assert_eq!(
+            parse(&["--extract", "--normalize-tags"]).ok(),
+            cfg!(feature = "normalize-tags").then_some(true)
+        );
+        assert!(!parse(&["--extract"]).unwrap());
+        assert!(parse(&["--normalize-tags"]).is_err());
+    }
+
+    #[cfg(feature = "normalize-tags")]
+    #[test]
+    pub fn test_parse_tag_templates() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| (cli.sync.normalize_tags, cli.sync.tag_template))
+        };
+
+        let (normalize_tags, tag_templates) = parse(&[
+            "--extract",
+            "--normalize-tags",
+            "--tag-template",
+            "comment=bandcamp {sale_id}",
+            "--tag-template",
+            "GROUPING={artist|upper}",
+        ])
+        .unwrap();
+        assert!(normalize_tags);
+        assert_eq!(
+            tag_templates
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["COMMENT=bandcamp {sale_id}", "GROUPING={artist|upper}"]
+        );
+        assert!(parse(&["--normalize-tags"]).is_err());
+        assert!(parse(&["--extract", "--tag-template", "COMMENT=x"]).is_err());
+        assert!(parse(&["--extract", "--normalize-tags", "--tag-template", "COMMENT"]).is_err());
+    }
+
+    #[test]
+    pub fn test_pin_key() {
+        let band_ids = HashMap::from([("p1".to_owned(), 42)]);
+        let release =
+            |release_id: &str| DownloadCacheRelease::new(release_id, "Galerie", 2021, "Anomalie");
+
+        assert_eq!(pin_key(&band_ids, &release("p1")), "band 42");
+        assert_eq!(pin_key(&band_ids, &release("p2")), "Anomalie");
+    }
+
+    #[test]
+    pub fn test_parse_filters() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--filter-artist",
+            "^anomalie$",
+            "--filter-title",
+            "galerie",
+            "--purchased-after",
+            "2024-01-01",
+            "--only",
+            "albums",
+        ])
+        .unwrap();
+        let library = cli.library.unwrap();
+
+        assert_eq!(library.filter_artist.as_deref(), Some("^anomalie$"));
+        assert_eq!(
+            library.purchased_after.map(|date| date.to_string()),
+            Some("2024-01-01".to_owned())
+        );
+        assert_eq!(library.only, Some(filter::ItemKind::Albums));
+        assert!(library.item_filter().unwrap().is_active());
+        assert!(
+            Cli::try_parse_from(["bandcamp-dl", "-c", "c.json", "--filter-title", "(oops"])
+                .is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["bandcamp-dl", "-c", "c.json", "--purchased-before", "soon"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    pub fn test_bypass_forced_releases() {
+        let download_cache: DownloadCache = [
+            (
+                "p199396767".to_owned(),
+                DownloadCacheRelease::new("p199396767", "Velours", 2021, "Anomalie"),
+            ),
+            (
+                "r181302019".to_owned(),
+                DownloadCacheRelease::new("r181302019", "LP5", 2019, "Apparat"),
+            ),
+            (
+                "p95387000".to_owned(),
+                DownloadCacheRelease::new("p95387000", "Walls", 2019, "Apparat"),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--force-item",
+            "199396767",
+        ])
+        .unwrap();
+        let lookup_cache = bypass_forced_releases(&download_cache, &cli.sync);
+        assert_eq!(lookup_cache.len(), 2);
+        assert!(!lookup_cache.contains_key("p199396767"));
+
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--force-artist",
+            "apparat",
+        ])
+        .unwrap();
+        let lookup_cache = bypass_forced_releases(&download_cache, &cli.sync);
+        assert_eq!(lookup_cache.keys().collect::<Vec<_>>(), ["p199396767"]);
+    }
+
+    #[test]
+    pub fn test_parse_subcommand() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "baseline", "-c", "cookies.json"]).unwrap();
+
+        assert!(matches!(cli.command, Some(Command::Baseline(_))));
+        assert_eq!(cli.library, None);
+    }
+
+    #[test]
+    pub fn test_parse_sync_options_conflict_with_subcommands() {
+        assert!(Cli::try_parse_from([
+            "bandcamp-dl",
+            "--dry-run",
+            "baseline",
+            "-c",
+            "cookies.json"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_parse_local_commands_without_cookies() {
+        for args in [
+            &["bandcamp-dl", "cache", "list", "-d", "/music"][..],
+            &["bandcamp-dl", "stats"],
+            &["bandcamp-dl", "status", "--local-only"],
+            &["bandcamp-dl", "history"],
+            &["bandcamp-dl", "verify", "-d", "/music"],
+            &["bandcamp-dl", "dedupe"],
+            &["bandcamp-dl", "schema", "event-log"],
+            &["bandcamp-dl", "renames", "-d", "/music"],
+            &["bandcamp-dl", "cookies", "export", "-c", "cookies.json"],
+        ] {
+            let cli = Cli::try_parse_from(args).unwrap();
+            assert!(cli.command.is_some());
+            assert_eq!(cli.library, None);
+        }
+
+        assert!(Cli::try_parse_from(["bandcamp-dl", "status"]).is_err());
+        assert!(Cli::try_parse_from(["bandcamp-dl", "status", "-c", "cookies.json"]).is_ok());
+        assert!(Cli::try_parse_from([
+            "bandcamp-dl",
+            "status",
+            "--local-only",
+            "-c",
+            "cookies.json"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_parse_audit() {
+        let cli =
+            Cli::try_parse_from(["bandcamp-dl", "audit", "-c", "cookies.json", "--fix"]).unwrap();
+
+        assert!(matches!(cli.command, Some(Command::Audit(_))));
+        assert!(Cli::try_parse_from(["bandcamp-dl", "audit"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "audit",
+            "-c",
+            "cookies.json",
+            "-d",
+            "/mnt/a",
+            "--cache-per-format",
+            "--audio-format",
+            "mp3-v0",
+        ])
+        .unwrap();
+        let Some(Command::Audit(args)) = cli.command else {
+            panic!("expected the audit subcommand");
+        };
+        assert_eq!(
+            args.cache_file_path(),
+            PathBuf::from("/mnt/a/bandcamp-collection-downloader.mp3-v0.cache")
+        );
+    }
+
+    #[test]
+    pub fn test_parse_whoami() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "whoami", "-c", "cookies.json"]).unwrap();
+
+        assert!(matches!(cli.command, Some(Command::Whoami(_))));
+        assert_eq!(cli.library, None);
+        assert!(Cli::try_parse_from(["bandcamp-dl", "whoami"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_estimate() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "estimate",
+            "-c",
+            "cookies.json",
+            "--samples",
+            "3",
+            "--resolve-concurrency",
+            "16",
+        ])
+        .unwrap();
+
+        let Some(Command::Estimate(args)) = cli.command else {
+            panic!("not an estimate");
+        };
+        assert_eq!(args.resolve_concurrency, 16);
+        assert!(Cli::try_parse_from([
+            "bandcamp-dl",
+            "estimate",
+            "-c",
+            "cookies.json",
+            "--samples",
+            "0"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_parse_list() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "list",
+            "-c",
+            "cookies.json",
+            "--pending",
+            "--format",
+            "csv",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Some(Command::List(_))));
+    }
+
+    #[test]
+    pub fn test_parse_export() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once(&"bandcamp-dl").chain(args)).unwrap()
+        };
+
+        let Some(Command::Export(export)) =
+            parse(&["export", "-c", "cookies.json", "--pending"]).command
+        else {
+            panic!("expected the export subcommand");
+        };
+        let Some(Command::List(list)) = parse(&[
+            "list",
+            "-c",
+            "cookies.json",
+            "--pending",
+            "--format",
+            "json",
+        ])
+        .command
+        else {
+            panic!("expected the list subcommand");
+        };
+        assert_eq!(list::ListArgs::from(export), list);
+        assert!(Cli::try_parse_from(["bandcamp-dl", "export", "--format", "csv"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_auth_listen() {
+        let cli =
+            Cli::try_parse_from(["bandcamp-dl", "auth", "listen", "-c", "cookies.json"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Auth(_))));
+        assert!(Cli::try_parse_from(["bandcamp-dl", "auth", "listen", "--port", "9000"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_download() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "download",
+            "https://anomalie.bandcamp.com/album/galerie",
+            "-c",
+            "cookies.json",
+            "--audio-format",
+            "mp3-320",
+        ])
+        .unwrap();
+
+        assert!(matches!(cli.command, Some(Command::Download(_))));
+        assert!(Cli::try_parse_from(["bandcamp-dl", "download", "-c", "cookies.json"]).is_err());
+    }
+
+    #[test]
+    pub fn test_reject_options() {
+        assert!(reject_options(&[("--stream-rip", false)], "a single release").is_ok());
+        assert_eq!(
+            reject_options(
+                &[("--stream-rip", true), ("--limit", false)],
+                "a single release"
+            )
+            .unwrap_err()
+            .to_string(),
+            "--stream-rip doesn't apply to a single release"
+        );
+        assert_eq!(
+            reject_options(&[("--watch", true), ("--offline", true)], "a queue")
+                .unwrap_err()
+                .to_string(),
+            "--watch, --offline don't apply to a queue"
+        );
+    }
+
+    #[test]
+    pub fn test_parse_offline_queue() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.offline_queue)
+        };
+
+        assert_eq!(
+            parse(&["--offline-queue", "plan.json"]).unwrap(),
+            Some(PathBuf::from("plan.json"))
+        );
+        assert!(parse(&["--offline-queue", "plan.json", "--dry-run"]).is_err());
+        assert!(parse(&["--offline-queue", "plan.json", "--print-urls-only"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "execute-plan",
+            "plan.json",
+            "-c",
+            "cookies.json",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Some(Command::ExecutePlan(_))));
+        assert!(
+            Cli::try_parse_from(["bandcamp-dl", "execute-plan", "-c", "cookies.json"]).is_err()
+        );
+    }
+
+    #[test]
+    pub fn test_parse_stream_folder_template() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.stream_folder_template)
+        };
+
+        assert_eq!(parse(&[]).unwrap(), NameTemplate::default());
+        assert_eq!(
+            parse(&["--stream-folder-template", "{artist|lower} - {title|slug}"])
+                .unwrap()
+                .render(&TemplateFields {
+                    artist: "Anomalie",
+                    title: "Galerie",
+                    ..TemplateFields::default()
+                }),
+            "anomalie - galerie"
+        );
+        assert!(parse(&["--stream-folder-template", "{title|reverse}"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_path_template() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.path_template.map(|template| template.to_string()))
+        };
+
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(
+            parse(&["--path-template", "{artist}/{year} - {title}"]).unwrap(),
+            Some("{artist}/{year} - {title}".to_owned())
+        );
+        assert!(parse(&["--path-template", "{artist}/{genre}"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_wishlist() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "wishlist",
+            "-c",
+            "cookies.json",
+            "--download-free",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Some(Command::Wishlist(_))));
+
+        let cli = Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--include-wishlist"])
+            .unwrap();
+        assert!(cli.sync.include_wishlist);
+        assert!(Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--include-wishlist",
+            "--offline"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_parse_history() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "history", "--item", "p199396767"]).unwrap();
+
+        assert!(matches!(cli.command, Some(Command::History(_))));
+        assert_eq!(cli.library, None);
+    }
+
+    #[test]
+    pub fn test_parse_resolve() {
+        let cli = Cli::try_parse_from(["bandcamp-dl", "resolve", "-c", "cookies.json"]).unwrap();
+
+        assert!(matches!(cli.command, Some(Command::Resolve(_))));
+        assert_eq!(cli.library, None);
+    }
+
+    #[test]
+    pub fn test_parse_watch_subcommand() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "watch",
+            "6h",
+            "-c",
+            "cookies.json",
+            "--notify",
+        ])
+        .unwrap();
+        let Some(Command::Watch(args)) = cli.command else {
+            panic!("expected the watch subcommand");
+        };
+        assert_eq!(args.interval, Some(Duration::from_hours(6)));
+        assert!(args.sync.notify);
+
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["bandcamp-dl", "watch", "-c", "cookies.json"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+        assert!(parse(&["--schedule", "0 3 * * *"]).is_ok());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["6h", "--schedule", "0 3 * * *"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_watch() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--watch",
+            "6h",
+            "--notify",
+        ])
+        .unwrap();
+        assert_eq!(cli.sync.watch, Some(Duration::from_hours(6)));
+        assert!(cli.sync.notify);
+
+        assert!(Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--notify"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_schedule() {
+        let cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--schedule",
+            "0 3 * * *",
+            "--jitter",
+            "15m",
+            "--notify",
+        ])
+        .unwrap();
+        assert_eq!(cli.sync.schedule, Some("0 3 * * *".parse().unwrap()));
+        assert_eq!(cli.sync.jitter, Some(Duration::from_mins(15)));
+
+        for args in [
+            ["--schedule", "0 3 * *", "--notify"],
+            ["--schedule", "0 3 * * *", "--watch=6h"],
+            ["--jitter", "15m", "--dry-run"],
+        ] {
+            assert!(Cli::try_parse_from(
+                ["bandcamp-dl", "-c", "cookies.json"]
+                    .into_iter()
+                    .chain(args)
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    pub fn test_parse_dry_run_layout() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.dry_run)
+        };
+
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(
+            parse(&["--dry-run", "--cache-diff"]).unwrap(),
+            Some(DryRun::Plan)
+        );
+        assert_eq!(parse(&["--dry-run=layout"]).unwrap(), Some(DryRun::Layout));
+        assert!(parse(&["--dry-run=everything"]).is_err());
+        assert!(parse(&["--dry-run", "--plan-json", "plan.json"]).is_ok());
+        assert!(parse(&["--plan-json", "plan.json"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_scrape_concurrency() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.library.unwrap().scrape_concurrency)
+        };
+
+        assert_eq!(parse(&[]).unwrap(), DEFAULT_SCRAPE_CONCURRENCY);
+        assert_eq!(parse(&["--scrape-concurrency", "2"]).unwrap(), 2);
+        assert!(parse(&["--scrape-concurrency", "0"]).is_err());
+    }
+
+    #[test]
+    pub fn test_find_existing_file() {
+        use api::data::{DigitalItem, DownloadData, DownloadFormat};
+
+        let folder = tempfile::tempdir().unwrap();
+        let planned = PlannedDownload {
+            sale_id: "p1".to_owned(),
+            item: DigitalItem {
+                downloads: Some(HashMap::from([(
+                    DownloadFormat::Flac,
+                    DownloadData {
+                        size_mb: Some("0.001MB".to_owned()),
+                        description: String::new(),
+                        encoding_name: "flac".to_owned(),
+                        url: "https://bandcamp.com/download?format=flac".to_owned(),
+                    },
+                )])),
+                package_release_date: None,
+                title: "Galerie".to_owned(),
+                artist: "Anomalie".to_owned(),
+                download_type: "a".to_owned(),
+                download_type_str: "album".to_owned(),
+                item_type: "album".to_owned(),
+                art_id: 0,
+                page_url: None,
+            },
+            format: DownloadFormat::Flac,
+        };
+        let folders = [PathBuf::from("/does/not/exist"), folder.path().to_owned()];
+        let cli = Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"])
+            .unwrap()
+            .sync;
+        let path = folder
+            .path()
+            .join(layout::release_file_name("p1", DownloadFormat::Flac));
+
+        assert_eq!(find_existing_file(&folders, &cli, &planned), None);
+        std::fs::write(&path, [0; 100]).unwrap();
+        assert_eq!(find_existing_file(&folders, &cli, &planned), None);
+        std::fs::write(&path, [0; 1_000]).unwrap();
+        assert_eq!(
+            find_existing_file(&folders, &cli, &planned),
+            Some(path.clone())
+        );
+
+        // only taken once it holds some tracks, and only with --extract
+        std::fs::remove_file(path).unwrap();
+        let extracted = folder.path().join("Anomalie").join("Galerie");
+        std::fs::create_dir_all(&extracted).unwrap();
+        let extract = Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--extract"])
+            .unwrap()
+            .sync;
+        assert_eq!(find_existing_file(&folders, &extract, &planned), None);
+        std::fs::write(extracted.join("01 Velours.flac"), [0; 10]).unwrap();
+        assert_eq!(find_existing_file(&folders, &cli, &planned), None);
+        assert_eq!(
+            find_existing_file(&folders, &extract, &planned),
+            Some(extracted)
+        );
+    }
+
+    #[test]
+    pub fn test_apply_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            r#"
+            [profiles.label]
+            cookie_file = "label-cookies.json"
+            download_folder = ["/mnt/label", "/mnt/overflow"]
+            audio_format = "mp3-320"
+            "#,
+        )
+        .unwrap();
+        let config = config_path.to_str().unwrap();
+
+        // left to the default configuration file
+        assert!(Cli::try_parse_from(["bandcamp-dl", "--profile", "label"]).is_ok());
+
+        let mut cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "sync",
+            "--config",
+            config,
+            "--profile",
+            "label",
+            "-d",
+            "/mnt/elsewhere",
+        ])
+        .unwrap();
+        cli.apply_profile().unwrap();
+        let Some(Command::Sync(args)) = cli.command else {
+            panic!("not a sync");
+        };
+        assert_eq!(
+            args.library.cookie_file,
+            Some(PathBuf::from("label-cookies.json"))
+        );
+        assert_eq!(
+            args.library.download_folder,
+            [PathBuf::from("/mnt/elsewhere")]
+        );
+        assert_eq!(args.sync.audio_format(), api::data::DownloadFormat::Mp3_320);
+
+        let mut cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "estimate",
+            "--config",
+            config,
+            "--profile",
+            "label",
+        ])
+        .unwrap();
+        cli.apply_profile().unwrap();
+        let Some(Command::Estimate(args)) = cli.command else {
+            panic!("not an estimate");
+        };
+        assert_eq!(args.audio_format, Some(api::data::DownloadFormat::Mp3_320));
+
+        let mut cli = Cli::try_parse_from([
+            "bandcamp-dl",
+            "stats",
+            "--config",
+            config,
+            "--profile",
+            "label",
+        ])
+        .unwrap();
+        cli.apply_profile().unwrap();
+        let Some(Command::Stats(args)) = cli.command else {
+            panic!("not stats");
+        };
+        assert_eq!(args.local.download_folder(), PathBuf::from("/mnt/label"));
+
+        let mut cli =
+            Cli::try_parse_from(["bandcamp-dl", "--config", config, "--profile", "household"])
+                .unwrap();
+        let error = cli.apply_profile().unwrap_err();
+        assert!(error.to_string().contains("it has: label"), "{error}");
+    }
+
+    #[test]
+    pub fn test_parse_resolve_concurrency() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json"].iter().chain(args))
+                .map(|cli| cli.sync.resolve_concurrency)
+        };
+
+        assert_eq!(parse(&[]).unwrap(), DEFAULT_RESOLVE_CONCURRENCY);
+        assert_eq!(parse(&["--resolve-concurrency", "16"]).unwrap(), 16);
+        assert!(parse(&["--resolve-concurrency", "0"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_offline_conflicts_with_downloads() {
+        assert!(
+            Cli::try_parse_from(["bandcamp-dl", "-c", "cookies.json", "--offline"])
+                .unwrap()
+                .sync
+                .offline
+        );
+        assert!(Cli::try_parse_from([
+            "bandcamp-dl",
+            "-c",
+            "cookies.json",
+            "--offline",
+            "--stream-rip"
+        ])
+        .is_err());
+    }
 }
@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use clap::Args;
+
+use crate::{
+    api::data::DownloadFormat,
+    cache::{self, DownloadCacheRelease},
+    error::InformationRetrievalError,
+};
+
+use super::{
+    open_session, print_cache_diff, read_download_cache_file, run_bounded,
+    write_download_cache_file, LibraryArgs,
+};
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct AuditArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[arg(long)]
+    #[arg(
+        help = "Rewrite the cache entries that differ with what Bandcamp reports now, rather than only showing them"
+    )]
+    fix: bool,
+
+    #[arg(long, value_enum, requires = "cache_per_format")]
+    #[arg(
+        help = "The audio format whose --cache-per-format cache to check. Defaults to the --profile's `audio_format`, or flac"
+    )]
+    pub(super) audio_format: Option<DownloadFormat>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Check the separate download cache a sync with --cache-per-format keeps for the --audio-format"
+    )]
+    cache_per_format: bool,
+}
+
+impl AuditArgs {
+    pub(super) fn cache_file_path(&self) -> std::path::PathBuf {
+        let cache_file_path = self
+            .library
+            .cache_file_path(&self.library.download_folder());
+        if self.cache_per_format {
+            let audio_format = self.audio_format.unwrap_or(DownloadFormat::Flac);
+            cache::format_scoped_cache_path(&cache_file_path, audio_format)
+        } else {
+            cache_file_path
+        }
+    }
+}
+
+pub async fn run(args: AuditArgs) -> anyhow::Result<()> {
+    let cache_file_path = args.cache_file_path();
+    let download_cache = read_download_cache_file(&cache_file_path, true)?;
+    let (api_context, summary) = open_session(&args.library, None, true).await?;
+
+    println!("Retrieving all releases...");
+    let collection = api_context
+        .get_collection(&summary, &args.library.collection_scopes())
+        .await?;
+
+    let to_compare: Vec<_> = collection
+        .download_urls
+        .into_iter()
+        .filter_map(|(sale_id, download_url)| {
+            let cached = download_cache.get(&cache::normalize_release_id(&sale_id))?;
+            Some((cached.clone(), download_url))
+        })
+        .collect();
+
+    println!("Comparing {} cached release(s)...", to_compare.len());
+    let mut outdated = Vec::new();
+    let mut unchecked = 0;
+    let mut failed = Vec::new();
+    let scrape = |(cached, download_url): (DownloadCacheRelease, String)| {
+        let api_context = Arc::clone(&api_context);
+        async move {
+            let digital_item = api_context.get_digital_download_item(&download_url).await;
+            (cached, digital_item)
+        }
+    };
+    let scrape_concurrency = usize::from(args.library.scrape_concurrency);
+    run_bounded(
+        to_compare,
+        scrape_concurrency,
+        scrape,
+        |(cached, digital_item)| {
+            match digital_item {
+                Ok(Some(digital_item)) => {
+                    let current =
+                        DownloadCacheRelease::from_digital_item(cached.release_id(), &digital_item);
+                    outdated.extend(updated_release(&cached, &current));
+                }
+                Ok(None) | Err(InformationRetrievalError::Unavailable) => unchecked += 1,
+                Err(e) => failed.push((cached, e)),
+            }
+            Ok(())
+        },
+    )
+    .await?;
+    if unchecked > 0 {
+        println!("{unchecked} cached release(s) have nothing to download anymore, so couldn't be checked");
+    }
+    for (cached, e) in &failed {
+        eprintln!(
+            "Failed checking \"{}\" by {}: {e}",
+            cached.title(),
+            cached.artist()
+        );
+    }
+
+    if outdated.is_empty() {
+        if failed.is_empty() {
+            println!("Every cached release matches what Bandcamp reports");
+        } else {
+            println!("Every other cached release matches what Bandcamp reports");
+        }
+        return checked(&failed);
+    }
+    println!(
+        "{} cached release(s) differ from what Bandcamp reports:",
+        outdated.len()
+    );
+    print_cache_diff(&cache_file_path, outdated.clone())?;
+    if !args.fix {
+        println!("Run again with --fix to rewrite them");
+        return checked(&failed);
+    }
+
+    // read again under the lock, so entries added by a sync in the meantime are kept
+    let _cache_lock = cache::CacheLock::acquire(&cache_file_path)?;
+    let mut download_cache = read_download_cache_file(&cache_file_path, false)?;
+    for release in outdated {
+        download_cache.insert(release.release_id().to_owned(), release);
+    }
+    write_download_cache_file(&cache_file_path, &download_cache)?;
+    println!("Rewrote {}", cache_file_path.display());
+
+    checked(&failed)
+}
+
+// Releases that couldn't be fetched are only reported once the others were compared, rather than
+// stopping the audit at the first one
+fn checked(failed: &[(DownloadCacheRelease, InformationRetrievalError)]) -> anyhow::Result<()> {
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        anyhow::bail!("{} cached release(s) couldn't be checked", failed.len())
+    }
+}
+
+// The entry as Bandcamp describes the release now, when that differs from the cached one. A year
+// Bandcamp doesn't report isn't taken as a change.
+fn updated_release(
+    cached: &DownloadCacheRelease,
+    current: &DownloadCacheRelease,
+) -> Option<DownloadCacheRelease> {
+    let year = if current.year() == 0 {
+        cached.year()
+    } else {
+        current.year()
+    };
+    let changed = current.title() != cached.title()
+        || current.artist() != cached.artist()
+        || year != cached.year();
+
+    changed.then(|| {
+        DownloadCacheRelease::new(cached.release_id(), current.title(), year, current.artist())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_updated_release() {
+        let cached = DownloadCacheRelease::new("p1", "Galerei", 2021, "Anomalie");
+
+        let updated = updated_release(
+            &cached,
+            &DownloadCacheRelease::new("p1", "Galerie", 0, "Anomalie"),
+        )
+        .unwrap();
+        assert_eq!(
+            (updated.title(), updated.year(), updated.artist()),
+            ("Galerie", 2021, "Anomalie")
+        );
+        assert!(updated_release(
+            &cached,
+            &DownloadCacheRelease::new("p1", "Galerei", 0, "Anomalie")
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{Args, Subcommand};
+use cookie_store::CookieExpiration;
+use tokio::{io::BufReader, net::TcpListener};
+
+use crate::{
+    api::{BandcampAPIContext, ClientOptions},
+    cookies::{self, RawCookie},
+    listener::{self, Reply},
+    timestamp,
+};
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum AuthCommand {
+    #[command(about = "Print the Bandcamp cookies loaded from a cookie file")]
+    Export(ExportArgs),
+
+    #[command(
+        about = "Wait for fresh cookies posted by a companion browser extension, and update the stored session with them"
+    )]
+    Listen(ListenArgs),
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct ExportArgs {
+    #[arg(short, long, value_hint = clap::ValueHint::AnyPath)]
+    #[arg(
+        help = "Cookie file or directory of cookie files to read, in the same format accepted by the sync command"
+    )]
+    cookie_file: std::path::PathBuf,
+
+    #[arg(long)]
+    #[arg(help = "Hide cookie values, so the output can be shared safely")]
+    redact: bool,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct ListenArgs {
+    #[arg(long, default_value_t = listener::DEFAULT_PORT)]
+    #[arg(help = "Port to listen on, on localhost only")]
+    port: u16,
+
+    #[arg(long, env = "BANDCAMP_DL_LISTEN_TOKEN", hide_env_values = true)]
+    #[arg(
+        help = "Token the extension has to send along, as \"Authorization: Bearer TOKEN\". A new one is made up on every start when not given"
+    )]
+    token: Option<String>,
+
+    #[arg(short, long, value_hint = clap::ValueHint::FilePath, required_unless_present = "save_session")]
+    #[arg(help = "Cookie file to replace with the posted cookies")]
+    cookie_file: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(help = "Save the posted session to the OS keyring, as --save-session does")]
+    save_session: bool,
+
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    #[arg(help = "Configuration file to read, in the TOML format")]
+    config: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Check the posted session with Bandcamp only over connections whose certificate public key matches one of the `tls_pins` in the configuration file"
+    )]
+    verify_tls_pins: bool,
+}
+
+pub async fn run(command: AuthCommand) -> anyhow::Result<()> {
+    match command {
+        AuthCommand::Export(args) => export(&args),
+        AuthCommand::Listen(args) => listen(args).await,
+    }
+}
+
+fn export(args: &ExportArgs) -> anyhow::Result<()> {
+    let cookie_data = super::read_cookie_data(&args.cookie_file)?;
+    let cookie_store = cookies::read_json_file(&cookie_data, "https://bandcamp.com")?;
+
+    let mut loaded_cookies: Vec<_> = cookie_store.iter_any().collect();
+    loaded_cookies.sort_by(|a, b| {
+        (String::from(&a.domain), a.path.as_ref(), a.name()).cmp(&(
+            String::from(&b.domain),
+            b.path.as_ref(),
+            b.name(),
+        ))
+    });
+
+    println!("Loaded {} cookie(s) for bandcamp.com", loaded_cookies.len());
+    for cookie in &loaded_cookies {
+        let value = if args.redact {
+            cookies::redact_value(cookie.value())
+        } else {
+            cookie.value().to_owned()
+        };
+        let expires = match &cookie.expires {
+            CookieExpiration::AtUtc(date) if cookie.is_expired() => {
+                format!("{} (expired)", timestamp::format_local(*date))
+            }
+            CookieExpiration::AtUtc(date) => timestamp::format_local(*date),
+            CookieExpiration::SessionEnd => "session".to_owned(),
+        };
+
+        println!(
+            "{}={} domain={} path={} expires={}",
+            cookie.name(),
+            value,
+            String::from(&cookie.domain),
+            cookie.path.as_ref(),
+            expires
+        );
+    }
+
+    let has_identity = loaded_cookies
+        .iter()
+        .any(|c| c.name() == cookies::IDENTITY_COOKIE_NAME && !c.is_expired());
+    if !has_identity {
+        println!(
+            "No unexpired `{}` cookie found, Bandcamp will not recognize this session",
+            cookies::IDENTITY_COOKIE_NAME
+        );
+    }
+
+    Ok(())
+}
+
+// Serves one connection at a time until interrupted, as sessions are only ever refreshed now and
+// then
+async fn listen(args: ListenArgs) -> anyhow::Result<()> {
+    if args.cookie_file.as_deref().is_some_and(Path::is_dir) {
+        anyhow::bail!("The cookie file to replace can't be a directory");
+    }
+    let token = args.token.clone().unwrap_or_else(|| {
+        std::iter::repeat_with(fastrand::alphanumeric)
+            .take(32)
+            .collect()
+    });
+
+    let tcp_listener = TcpListener::bind(("127.0.0.1", args.port))
+        .await
+        .with_context(|| format!("Failed listening on port {}", args.port))?;
+    println!(
+        "Waiting for cookies posted to http://127.0.0.1:{}{}",
+        args.port,
+        listener::COOKIES_PATH
+    );
+    if args.token.is_none() {
+        println!("Token: {token}");
+    }
+
+    let client_options = super::client_options(
+        super::read_config(args.config.as_deref())?,
+        args.verify_tls_pins,
+    )?;
+    loop {
+        // such as running out of file descriptors, which passes as connections are closed
+        let stream = match tcp_listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("Failed accepting a connection: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let mut stream = BufReader::new(stream);
+        let request = match tokio::time::timeout(
+            listener::REQUEST_TIMEOUT,
+            listener::read_request(&mut stream),
+        )
+        .await
+        {
+            Ok(Ok(request)) => request,
+            Ok(Err(e)) => {
+                let _ = listener::write_response(&mut stream, 400, &e.to_string()).await;
+                continue;
+            }
+            Err(_) => continue,
+        };
+
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let (status, body) = match listener::respond_to(&request, &token, now) {
+            Reply::Preflight => (204, String::new()),
+            Reply::Rejected(status, reason) => {
+                eprintln!("Rejected a request to {}: {reason}", request.path);
+                (status, reason)
+            }
+            Reply::Accepted(raw_cookies) => {
+                match update_session(&args, &client_options, &raw_cookies).await {
+                    Ok(username) => {
+                        println!("Updated the session of {username}");
+                        (200, format!("Updated the session of {username}"))
+                    }
+                    Err(e) => {
+                        eprintln!("Failed updating the session: {e:#}");
+                        (400, format!("{e:#}"))
+                    }
+                }
+            }
+        };
+        if let Err(e) = listener::write_response(&mut stream, status, &body).await {
+            eprintln!("Failed answering a request: {e}");
+        }
+    }
+}
+
+// Checked with Bandcamp first, so a stale export doesn't replace a working session
+async fn update_session(
+    args: &ListenArgs,
+    client_options: &ClientOptions,
+    raw_cookies: &[RawCookie],
+) -> anyhow::Result<String> {
+    let cookie_data = serde_json::to_string(raw_cookies)?;
+    let summary = BandcampAPIContext::with_options(&cookie_data, client_options)?
+        .verify_session()
+        .await?;
+
+    if let Some(cookie_file) = &args.cookie_file {
+        // written aside first, so an interrupted write doesn't leave a truncated file behind, and
+        // readable by the user only, as it holds their session
+        let partial_path = cookie_file.with_extension("part");
+        let _ = std::fs::remove_file(&partial_path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&partial_path)?
+            .write_all(cookie_data.as_bytes())?;
+        std::fs::rename(partial_path, cookie_file)?;
+    }
+    if args.save_session {
+        super::save_session(&cookie_data)?;
+    }
+    Ok(summary.collection_summary.username)
+}
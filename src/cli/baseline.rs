@@ -0,0 +1,82 @@
+use clap::Args;
+
+use crate::{cache::DownloadCacheRelease, journal::EventKind};
+
+use super::{
+    find_new_library_items, print_cache_diff, read_download_cache_file, write_download_cache_file,
+    LibraryArgs,
+};
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct BaselineArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[arg(long)]
+    #[arg(help = "Show which items would be marked, without writing the cache")]
+    dry_run: bool,
+
+    #[arg(long, requires = "dry_run")]
+    #[arg(
+        help = "With --dry-run, show the lines the download cache file would lose and gain, in a diff like format"
+    )]
+    cache_diff: bool,
+}
+
+pub async fn run(args: BaselineArgs) -> anyhow::Result<()> {
+    let download_folder = args.library.download_folder();
+    let cache_file_path = args.library.cache_file_path(&download_folder);
+
+    let mut download_cache = read_download_cache_file(&cache_file_path, true)?;
+    // a dry run records nothing, like that of a sync
+    let journal = args.library.open_journal(!args.dry_run);
+    let (_, new_items) =
+        find_new_library_items(&args.library, &download_cache, &journal, None, true).await?;
+
+    if new_items.is_empty() {
+        println!("Every item is already in the download cache, exiting");
+        return Ok(());
+    }
+
+    for (key, digital_item) in &new_items {
+        download_cache.insert(
+            key.clone(),
+            DownloadCacheRelease::from_digital_item(key, digital_item),
+        );
+    }
+
+    if args.dry_run {
+        println!(
+            "Dry run, so not marking {} item(s) as downloaded...",
+            new_items.len()
+        );
+        if args.cache_diff {
+            print_cache_diff(
+                &cache_file_path,
+                new_items
+                    .iter()
+                    .map(|(key, digital_item)| {
+                        DownloadCacheRelease::from_digital_item(key, digital_item)
+                    })
+                    .collect(),
+            )?;
+        }
+        return Ok(());
+    }
+
+    println!(
+        "Marking {} item(s) as downloaded in {}...",
+        new_items.len(),
+        cache_file_path.display()
+    );
+    for key in new_items.keys() {
+        journal.record(
+            key,
+            EventKind::Skipped,
+            Some("marked as downloaded by baseline".to_owned()),
+        );
+    }
+    write_download_cache_file(&cache_file_path, &download_cache)?;
+
+    Ok(())
+}
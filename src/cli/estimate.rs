@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use clap::Args;
+
+use crate::{
+    api::{self, data::DownloadFormat, BandcampAPIContext},
+    cache,
+    estimate::{ReleaseSample, RunEstimate, RunSettings},
+    schedule,
+};
+
+use super::{
+    read_download_cache_file, LibraryArgs, DEFAULT_RESOLVE_CONCURRENCY, DOWNLOAD_BATCH_SIZE,
+};
+
+// Enough of each sampled release to get past the connection's slow start
+const THROUGHPUT_SAMPLE_BYTES: u64 = 8_000_000;
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct EstimateArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[arg(long, value_enum)]
+    #[arg(
+        help = "The audio format the sync would download. Defaults to the --profile's `audio_format`, or flac"
+    )]
+    pub(super) audio_format: Option<DownloadFormat>,
+
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(
+        help = "Number of pending releases to sample, picked at random. Each one has its download link resolved and the first few MB of it downloaded"
+    )]
+    samples: u16,
+
+    #[arg(long, default_value_t = DEFAULT_RESOLVE_CONCURRENCY, value_parser = clap::value_parser!(u16).range(1..))]
+    #[arg(help = "The --resolve-concurrency the sync would resolve download links with")]
+    pub(super) resolve_concurrency: u16,
+}
+
+pub async fn run(args: EstimateArgs) -> anyhow::Result<()> {
+    let audio_format = args.audio_format.unwrap_or(DownloadFormat::Flac);
+    let download_cache = read_download_cache_file(
+        &args
+            .library
+            .cache_file_path(&args.library.download_folder()),
+        false,
+    )?;
+    let api_context = BandcampAPIContext::with_options(
+        &args.library.read_cookies()?,
+        &args.library.client_options()?,
+    )?;
+    let summary = api_context.verify_session().await?;
+
+    println!("Retrieving all releases...");
+    let collection = api_context
+        .get_collection(&summary, &args.library.collection_scopes())
+        .await?;
+    // the releases a sync would look at, so the estimate covers the same ones
+    let releases = args
+        .library
+        .item_filter()?
+        .apply(&collection.download_urls, &collection.items);
+    let mut pending: Vec<_> = releases
+        .iter()
+        .filter(|(sale_id, _)| !download_cache.contains_key(&cache::normalize_release_id(sale_id)))
+        .map(|(_, item_url)| item_url.as_str())
+        .collect();
+    if pending.is_empty() {
+        println!("No new releases to fetch");
+        return Ok(());
+    }
+
+    fastrand::shuffle(&mut pending);
+    let mut samples = Vec::new();
+    for item_url in pending.iter().take(usize::from(args.samples)) {
+        match sample_release(&api_context, item_url, audio_format).await {
+            Ok(Some(sample)) => samples.push(sample),
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed sampling {item_url}: {e:#}"),
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let settings = RunSettings {
+        resolve_concurrency: usize::from(args.resolve_concurrency),
+        download_concurrency: DOWNLOAD_BATCH_SIZE,
+        requests_per_second: api::RATE_LIMIT_REQUESTS as f64 / api::RATE_LIMIT_PERIOD.as_secs_f64(),
+        requests_per_release: 2,
+    };
+    let Some(estimate) = RunEstimate::project(pending.len(), &samples, &settings) else {
+        anyhow::bail!("None of the sampled releases could be measured");
+    };
+
+    #[allow(clippy::cast_precision_loss)]
+    let total_gb = estimate.total_bytes as f64 / 1e9;
+    println!(
+        "{} release(s) to download in {}, about {total_gb:.1} GB going by {} sample(s)",
+        estimate.releases,
+        audio_format,
+        samples.len()
+    );
+    println!(
+        "Resolving download links: about {}",
+        schedule::format_duration(estimate.resolve_time)
+    );
+    println!(
+        "Downloading: about {}",
+        schedule::format_duration(estimate.download_time)
+    );
+    println!(
+        "Expected run time: about {}",
+        schedule::format_duration(estimate.total_time())
+    );
+    Ok(())
+}
+
+// `None` for a release with nothing to download in the format
+async fn sample_release(
+    api_context: &BandcampAPIContext,
+    item_url: &str,
+    format: DownloadFormat,
+) -> anyhow::Result<Option<ReleaseSample>> {
+    let started = Instant::now();
+    let Some(digital_item) = api_context.get_digital_download_item(item_url).await? else {
+        return Ok(None);
+    };
+    let Some(download_data) = digital_item
+        .downloads
+        .as_ref()
+        .and_then(|downloads| downloads.get(&format))
+    else {
+        return Ok(None);
+    };
+    let expected_bytes = download_data.size_bytes();
+    let download_url = api_context
+        .get_digital_download_link(&digital_item, format)
+        .await?;
+    let resolve_time = started.elapsed();
+
+    let bytes_per_second =
+        match measure_throughput(api_context.download_client(), &download_url).await {
+            Ok(bytes_per_second) => bytes_per_second,
+            Err(e) => {
+                eprintln!("Failed measuring the download speed of {item_url}: {e}");
+                None
+            }
+        };
+
+    Ok(Some(ReleaseSample {
+        resolve_time,
+        expected_bytes,
+        bytes_per_second,
+    }))
+}
+
+// Downloaded straight to nowhere, stopping after THROUGHPUT_SAMPLE_BYTES. Through the client a
+// sync downloads with, so its proxy and timeouts are measured too.
+#[allow(clippy::cast_precision_loss)]
+async fn measure_throughput(
+    client: &reqwest::Client,
+    download_url: &str,
+) -> reqwest::Result<Option<f64>> {
+    let mut response = client.get(download_url).send().await?.error_for_status()?;
+
+    let started = Instant::now();
+    let mut received = 0;
+    while received < THROUGHPUT_SAMPLE_BYTES {
+        let Some(chunk) = response.chunk().await? else {
+            break;
+        };
+        received += chunk.len() as u64;
+    }
+    let elapsed = started.elapsed().max(Duration::from_millis(1));
+
+    Ok((received > 0).then(|| received as f64 / elapsed.as_secs_f64()))
+}
@@ -0,0 +1,81 @@
+use clap::Args;
+
+use crate::{
+    cache,
+    journal::{self, JournalEvent},
+    timestamp,
+};
+
+use super::local::LocalArgs;
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct HistoryArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+
+    #[arg(long)]
+    #[arg(help = "Only show the events of this item, by its sale id (e.g. \"p199396767\")")]
+    item: Option<String>,
+}
+
+pub fn run(args: &HistoryArgs) -> anyhow::Result<()> {
+    let journal_path = args.local.state_dir().join(journal::JOURNAL_FILE_NAME);
+
+    if !std::fs::exists(&journal_path)? {
+        println!("No journal at {} yet", journal_path.display());
+        return Ok(());
+    }
+
+    let item = args.item.as_deref().map(cache::normalize_release_id);
+    let events: Vec<_> = journal::read_journal(&std::fs::read_to_string(&journal_path)?)?
+        .into_iter()
+        .filter(|event| item.as_ref().is_none_or(|item| *item == event.item))
+        .collect();
+
+    if events.is_empty() {
+        println!("No matching events");
+    }
+    for event in &events {
+        println!("{}", format_event(event));
+    }
+
+    Ok(())
+}
+
+fn format_event(event: &JournalEvent) -> String {
+    let timestamp = event
+        .timestamp()
+        .map_or_else(|| event.timestamp.to_string(), timestamp::format_local);
+    let line = format!("{timestamp}  {:<12} {:<10}", event.item, event.event.name());
+
+    event.detail.as_ref().map_or_else(
+        || line.trim_end().to_owned(),
+        |detail| format!("{line} {detail}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::EventKind;
+
+    #[test]
+    pub fn test_format_event() {
+        let mut event = JournalEvent {
+            timestamp: 1_609_459_200,
+            item: "p199396767".to_owned(),
+            event: EventKind::Skipped,
+            detail: Some("time budget exhausted".to_owned()),
+        };
+        assert_eq!(
+            format_event(&event),
+            "2021-01-01 00:00 UTC  p199396767   skipped    time budget exhausted"
+        );
+
+        event.detail = None;
+        assert_eq!(
+            format_event(&event),
+            "2021-01-01 00:00 UTC  p199396767   skipped"
+        );
+    }
+}
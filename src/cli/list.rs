@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use clap::Args;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{
+    api::{data, endpoints, BandcampAPIContext},
+    cache::{self, DownloadCache},
+    models::{self, Versioned},
+    timestamp,
+};
+
+use super::{open_session_or_public, read_download_cache_file, LibraryArgs, Session};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+#[command(mut_arg("cookie_file", |arg| arg.required_unless_present("public")))]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[arg(long, conflicts_with = "public")]
+    #[arg(help = "Only list the releases that aren't in the download cache yet")]
+    pending: bool,
+
+    #[arg(long, value_name = "FAN")]
+    #[arg(
+        help = "List the collection a fan shows on their public page, by username or page url, without logging in. Hidden items are left out, and as it's not the collection the download cache is of, every item's status is \"unknown\""
+    )]
+    public: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+    #[arg(help = "How to print the collection")]
+    format: ListFormat,
+}
+
+// `list --format json` under the name scripts used before `list` had formats
+#[derive(Args, Debug, PartialEq, Eq)]
+#[command(mut_arg("cookie_file", |arg| arg.required_unless_present("public")))]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[arg(long, conflicts_with = "public")]
+    #[arg(help = "Only export the releases that aren't in the download cache yet")]
+    pending: bool,
+
+    #[arg(long, value_name = "FAN")]
+    #[arg(
+        help = "Export the collection a fan shows on their public page, by username or page url, without logging in, as `list --public` does"
+    )]
+    public: Option<String>,
+}
+
+impl From<ExportArgs> for ListArgs {
+    fn from(args: ExportArgs) -> Self {
+        Self {
+            library: args.library,
+            pending: args.pending,
+            public: args.public,
+            format: ListFormat::Json,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub(super) struct ListedItem {
+    artist: String,
+    title: String,
+    item_type: String,
+    sale_id: String,
+    // as RFC 3339 in UTC when Bandcamp's date could be read, as given otherwise
+    purchased: String,
+    // "downloaded", "pending" or "no download". Logged out listings have no "no download", and
+    // another fan's items are all "unknown".
+    status: &'static str,
+}
+
+// What `list --format json` and `export` print. Version 0 was the bare list of items.
+#[derive(Serialize, JsonSchema)]
+pub(super) struct ListExport {
+    items: Vec<ListedItem>,
+}
+
+impl Versioned for ListExport {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+const COLUMNS: [&str; 6] = [
+    "artist",
+    "title",
+    "item_type",
+    "sale_id",
+    "purchased",
+    "status",
+];
+
+impl ListedItem {
+    // Without the download urls, as when logged out, every item is taken to have a download.
+    // Without a download cache, as for another fan's items, whether one was downloaded isn't known.
+    fn new(
+        item: &data::CollectionItem,
+        download_urls: Option<&HashMap<String, String>>,
+        download_cache: Option<&DownloadCache>,
+    ) -> Self {
+        let sale_id = item.sale_key().unwrap_or_default();
+        let status = match download_cache {
+            None => "unknown",
+            Some(_) if download_urls.is_some_and(|urls| !urls.contains_key(&sale_id)) => {
+                "no download"
+            }
+            Some(download_cache)
+                if download_cache.contains_key(&cache::normalize_release_id(&sale_id)) =>
+            {
+                "downloaded"
+            }
+            Some(_) => "pending",
+        };
+        let purchased = item.purchased.as_deref().unwrap_or_default();
+
+        Self {
+            artist: item.band_name.clone(),
+            title: item.item_title.clone(),
+            item_type: item.item_type.clone().unwrap_or_default(),
+            sale_id,
+            purchased: data::parse_bandcamp_timestamp(purchased)
+                .map_or_else(|| purchased.to_owned(), timestamp::to_rfc3339),
+            status,
+        }
+    }
+
+    fn fields(&self) -> [&str; 6] {
+        [
+            &self.artist,
+            &self.title,
+            &self.item_type,
+            &self.sale_id,
+            &self.purchased,
+            self.status,
+        ]
+    }
+}
+
+pub async fn run(args: ListArgs) -> anyhow::Result<()> {
+    let library = &args.library;
+    let mut items = if let Some(fan) = &args.public {
+        let api_context = BandcampAPIContext::new_without_cookies(&library.client_options()?)?;
+        public_items(&api_context, fan, None).await?
+    } else {
+        let download_cache =
+            read_download_cache_file(&library.cache_file_path(&library.download_folder()), false)?;
+        collection_items(library, &download_cache).await?
+    };
+
+    items.retain(|item| !args.pending || item.status == "pending");
+    items.sort_by(|a, b| (&a.artist, &a.title).cmp(&(&b.artist, &b.title)));
+
+    match args.format {
+        ListFormat::Table => {
+            print!("{}", format_table(&items));
+            println!("{} release(s)", items.len());
+        }
+        ListFormat::Json => {
+            let export = models::versioned_value(&ListExport { items })?;
+            println!("{}", serde_json::to_string_pretty(&export)?);
+        }
+        ListFormat::Csv => print!("{}", format_csv(&items)),
+    }
+    Ok(())
+}
+
+async fn collection_items(
+    library: &LibraryArgs,
+    download_cache: &DownloadCache,
+) -> anyhow::Result<Vec<ListedItem>> {
+    let (api_context, summary) =
+        match open_session_or_public(library, "hidden items or telling which have no download")
+            .await?
+        {
+            Session::LoggedIn(api_context, summary) => (api_context, summary),
+            Session::LoggedOut(api_context, snapshot) => {
+                return public_items(&api_context, &snapshot.username, Some(download_cache)).await;
+            }
+        };
+    let collection = api_context
+        .get_collection(&summary, &library.collection_scopes())
+        .await?;
+
+    Ok(collection
+        .items
+        .iter()
+        .map(|item| ListedItem::new(item, Some(&collection.download_urls), Some(download_cache)))
+        .collect())
+}
+
+// Needs no session, so it works with expired cookies or none at all
+async fn public_items(
+    api_context: &BandcampAPIContext,
+    fan: &str,
+    download_cache: Option<&DownloadCache>,
+) -> anyhow::Result<Vec<ListedItem>> {
+    let items = api_context
+        .public_collection(&endpoints::fan_page_url(fan))
+        .await?;
+
+    Ok(items
+        .iter()
+        .map(|item| ListedItem::new(item, None, download_cache))
+        .collect())
+}
+
+fn format_table(items: &[ListedItem]) -> String {
+    let mut widths = COLUMNS.map(|column| column.chars().count());
+    for item in items {
+        for (width, field) in widths.iter_mut().zip(item.fields()) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+
+    let format_row = |fields: [&str; 6]| {
+        let cells: Vec<_> = fields
+            .iter()
+            .zip(widths)
+            .map(|(field, width)| format!("{field:<width$}"))
+            .collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+    std::iter::once(format_row(COLUMNS))
+        .chain(items.iter().map(|item| format_row(item.fields())))
+        .collect()
+}
+
+fn format_csv(items: &[ListedItem]) -> String {
+    let format_row = |fields: [&str; 6]| {
+        let cells: Vec<_> = fields
+            .iter()
+            .map(|field| {
+                if field.contains([',', '"', '\n']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    (*field).to_owned()
+                }
+            })
+            .collect();
+        format!("{}\n", cells.join(","))
+    };
+    std::iter::once(format_row(COLUMNS))
+        .chain(items.iter().map(|item| format_row(item.fields())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed_items() -> Vec<ListedItem> {
+        vec![
+            ListedItem {
+                artist: "Anomalie".to_owned(),
+                title: "Galerie".to_owned(),
+                item_type: "album".to_owned(),
+                sale_id: "p1".to_owned(),
+                purchased: "2021-01-01T00:00:00Z".to_owned(),
+                status: "downloaded",
+            },
+            ListedItem {
+                artist: "Camellia".to_owned(),
+                title: "Toxic Violet Cubes, Part 1".to_owned(),
+                item_type: "track".to_owned(),
+                sale_id: "p2".to_owned(),
+                purchased: String::new(),
+                status: "pending",
+            },
+        ]
+    }
+
+    #[test]
+    pub fn test_listed_item() {
+        let item: data::CollectionItem = serde_json::from_value(serde_json::json!({
+            "sale_item_id": 1,
+            "sale_item_type": "p",
+            "band_name": "Anomalie",
+            "item_title": "Galerie",
+            "item_type": "album",
+            "purchased": "01 Jan 2021 00:00:00 GMT",
+        }))
+        .unwrap();
+        let download_urls =
+            HashMap::from([("p1".to_owned(), "https://bandcamp.com/download".to_owned())]);
+
+        let download_cache = DownloadCache::new();
+
+        let listed = ListedItem::new(&item, Some(&download_urls), Some(&download_cache));
+        assert_eq!(listed.sale_id, "p1");
+        assert_eq!(listed.purchased, "2021-01-01T00:00:00Z");
+        assert_eq!(listed.status, "pending");
+
+        let listed = ListedItem::new(&item, Some(&HashMap::new()), Some(&download_cache));
+        assert_eq!(listed.status, "no download");
+        let listed = ListedItem::new(&item, None, Some(&download_cache));
+        assert_eq!(listed.status, "pending");
+        let listed = ListedItem::new(&item, None, None);
+        assert_eq!(listed.status, "unknown");
+    }
+
+    #[test]
+    pub fn test_format_table() {
+        assert_eq!(
+            format_table(&listed_items()),
+            "artist    title                       item_type  sale_id  purchased             status\n\
+             Anomalie  Galerie                     album      p1       2021-01-01T00:00:00Z  downloaded\n\
+             Camellia  Toxic Violet Cubes, Part 1  track      p2                             pending\n"
+        );
+    }
+
+    #[test]
+    pub fn test_format_csv() {
+        assert_eq!(
+            format_csv(&listed_items()),
+            "artist,title,item_type,sale_id,purchased,status\n\
+             Anomalie,Galerie,album,p1,2021-01-01T00:00:00Z,downloaded\n\
+             Camellia,\"Toxic Violet Cubes, Part 1\",track,p2,,pending\n"
+        );
+    }
+}
@@ -0,0 +1,598 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use clap::{Args, Subcommand, ValueEnum};
+use indicatif::HumanBytes;
+
+use crate::{
+    api::data::DownloadFormat,
+    archive::{self, ExtractorRegistry},
+    bands,
+    cache::{self, CacheLock, DownloadCache},
+    cas::{self, BlobStore},
+    checksums::{self, ChecksumMap},
+    config::Profile,
+    journal::{self, EventKind, Journal, JournalEvent},
+    planner::OfflineQueue,
+    schedule,
+    scratch::{self, ScratchKind, ScratchRegistry},
+    snapshot, staging, timestamp,
+};
+
+use super::{
+    default_state_dir, find_new_library_items, read_collection_snapshot, read_download_cache_file,
+    read_profile, read_state_file, LibraryArgs, DEFAULT_CACHE_FILE_NAME,
+    DEFAULT_SCRAPE_CONCURRENCY,
+};
+
+// Where the state of earlier syncs is kept. Enough for the commands that only look at it, which
+// don't need any cookies.
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct LocalArgs {
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath)]
+    #[arg(help = "Folder files are downloaded to. Defaults to current directory")]
+    download_folder: Option<PathBuf>,
+
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    #[arg(
+        help = "Path to cache file. Defaults to \"bandcamp-collection-downloader.cache\" in download_folder"
+    )]
+    cache_file: Option<PathBuf>,
+
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    #[arg(
+        help = "Folder for state kept between runs. Defaults to \".bandcamp-dl\" in download_folder"
+    )]
+    state_dir: Option<PathBuf>,
+
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    #[arg(
+        help = "Configuration file to read the --profile from, in the TOML format. Defaults to \"bandcamp-dl/config.toml\" in the user's configuration folder"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(long, value_name = "NAME")]
+    #[arg(
+        help = "Use the first `download_folder` and the `cache_file` of the `[profiles.<NAME>]` section of the configuration file. Options given on the command line take precedence"
+    )]
+    profile: Option<String>,
+}
+
+impl LocalArgs {
+    // Fills in what the command line leaves to the --profile, returning the rest of it
+    pub(super) fn apply_profile(&mut self) -> anyhow::Result<Option<Profile>> {
+        let Some(name) = &self.profile else {
+            return Ok(None);
+        };
+        let mut profile = read_profile(&mut self.config, name)?;
+
+        // the first one is where the cache and the other state are kept
+        if self.download_folder.is_none() && !profile.download_folder.is_empty() {
+            self.download_folder = Some(profile.download_folder.swap_remove(0));
+        }
+        self.cache_file = self.cache_file.take().or_else(|| profile.cache_file.take());
+        Ok(Some(profile))
+    }
+
+    pub(super) fn download_folder(&self) -> PathBuf {
+        self.download_folder
+            .clone()
+            .unwrap_or_else(|| std::env::current_dir().expect("error getting cwd"))
+    }
+
+    pub(super) fn state_dir(&self) -> PathBuf {
+        self.state_dir
+            .clone()
+            .unwrap_or_else(|| default_state_dir(&self.download_folder()))
+    }
+
+    // A sync with the same folders, for the commands that also look at the collection online
+    fn library(&self, cookie_file: PathBuf) -> LibraryArgs {
+        LibraryArgs {
+            cookie_file: Some(cookie_file),
+            cookies_from_browser: None,
+            identity_cookie: None,
+            save_session: false,
+            use_session: false,
+            skip_hidden: false,
+            download_folder: self.download_folder.iter().cloned().collect(),
+            cache_file: self.cache_file.clone(),
+            state_dir: self.state_dir.clone(),
+            config: self.config.clone(),
+            profile: None,
+            verify_tls_pins: false,
+            max_age: Vec::new(),
+            scrape_concurrency: DEFAULT_SCRAPE_CONCURRENCY,
+            filter_artist: None,
+            filter_title: None,
+            purchased_after: None,
+            purchased_before: None,
+            only: None,
+            only_subscriptions: false,
+            exclude_subscriptions: false,
+            exclude_file: None,
+        }
+    }
+
+    fn cache_file_path(&self) -> PathBuf {
+        self.cache_file
+            .clone()
+            .unwrap_or_else(|| self.download_folder().join(DEFAULT_CACHE_FILE_NAME))
+    }
+
+    fn download_cache(&self) -> anyhow::Result<DownloadCache> {
+        read_download_cache_file(&self.cache_file_path(), false)
+    }
+
+    fn checksums(&self) -> anyhow::Result<ChecksumMap> {
+        let checksums_path = self.state_dir().join(checksums::CHECKSUMS_FILE_NAME);
+        match read_state_file(&checksums_path)? {
+            Some(checksum_data) => Ok(checksums::read_checksums(&checksum_data)?),
+            None => Ok(ChecksumMap::new()),
+        }
+    }
+
+    fn journal(&self) -> anyhow::Result<Vec<JournalEvent>> {
+        let journal_path = self.state_dir().join(journal::JOURNAL_FILE_NAME);
+        if !std::fs::exists(&journal_path)? {
+            return Ok(Vec::new());
+        }
+        Ok(journal::read_journal(&std::fs::read_to_string(
+            &journal_path,
+        )?)?)
+    }
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum CacheCommand {
+    #[command(about = "List the releases in the download cache, by artist")]
+    List(CacheListArgs),
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct CacheListArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+
+    #[arg(long)]
+    #[arg(help = "Only list the releases of this artist, matched case insensitively")]
+    artist: Option<String>,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct DedupeArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct RenamesArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct CleanArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+
+    #[arg(long, value_parser = schedule::parse_duration, default_value = "1d")]
+    #[arg(
+        help = "Only remove what was left untouched for this long (e.g. \"12h\"), so the downloads of a sync running elsewhere aren't taken for leftovers"
+    )]
+    older_than: Duration,
+
+    #[arg(long)]
+    #[arg(help = "Only print what would be removed")]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct StatusArgs {
+    #[command(flatten)]
+    pub(super) local: LocalArgs,
+
+    #[arg(short, long, value_hint = clap::ValueHint::AnyPath)]
+    #[arg(required_unless_present_any = ["local_only", "profile"])]
+    #[arg(
+        help = "Cookie file to read, in the same format accepted by the sync command, to compare the cache with the collection as it is now"
+    )]
+    cookie_file: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "cookie_file")]
+    #[arg(
+        help = "Don't contact Bandcamp, and compare the cache with the collection snapshot of the last sync instead"
+    )]
+    local_only: bool,
+}
+
+pub fn run_cache(command: CacheCommand) -> anyhow::Result<()> {
+    match command {
+        CacheCommand::List(args) => list_cache(&args),
+    }
+}
+
+fn list_cache(args: &CacheListArgs) -> anyhow::Result<()> {
+    let download_cache = args.local.download_cache()?;
+    let artist = args.artist.as_deref().map(str::to_lowercase);
+
+    let mut releases: Vec<_> = download_cache
+        .values()
+        .filter(|release| {
+            artist
+                .as_ref()
+                .is_none_or(|artist| *artist == release.artist().to_lowercase())
+        })
+        .collect();
+    releases
+        .sort_by(|a, b| (a.artist(), a.year(), a.title()).cmp(&(b.artist(), b.year(), b.title())));
+
+    for release in &releases {
+        println!(
+            "{:<12} {} - {} ({})",
+            release.release_id(),
+            release.artist(),
+            release.title(),
+            release.year()
+        );
+    }
+    println!("{} release(s)", releases.len());
+
+    Ok(())
+}
+
+// How many events of each kind the journal has, by name
+fn count_events(events: &[JournalEvent]) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for event in events {
+        *counts.entry(event.event.name()).or_default() += 1;
+    }
+    counts
+}
+
+pub fn run_dedupe(args: &DedupeArgs) -> anyhow::Result<()> {
+    let store = BlobStore::new(args.local.state_dir().join(cas::BLOBS_FOLDER_NAME));
+    let report = store.absorb_tree(&args.local.download_folder())?;
+    println!("{}", report.summary());
+    Ok(())
+}
+
+// Every archive in the download folder has to open and hold at least one track. Hidden folders,
+// such as the state and staging folders, are left out.
+pub fn run_verify(args: &VerifyArgs) -> anyhow::Result<()> {
+    let extractors = ExtractorRegistry::default();
+    let checksums = args.local.checksums()?;
+    let mut checked = 0;
+    let mut broken = 0;
+
+    for path in archive::visible_files(&args.local.download_folder())? {
+        if !matches!(ExtractorRegistry::detect(&path), Ok(Some(_))) {
+            continue;
+        }
+
+        checked += 1;
+        match extractors.count_tracks(&path) {
+            Ok(0) => println!("{}: no tracks", path.display()),
+            Ok(_) if !matches_checksum(&checksums, &path) => {
+                println!("{}: changed since it was downloaded", path.display());
+            }
+            Ok(_) => continue,
+            Err(e) => println!("{}: {e}", path.display()),
+        }
+        broken += 1;
+    }
+
+    println!("Checked {checked} archive(s)");
+    if broken > 0 {
+        anyhow::bail!("{broken} archive(s) are broken or empty");
+    }
+    Ok(())
+}
+
+// Archives downloaded before checksums were kept have none to match
+fn matches_checksum(checksums: &ChecksumMap, path: &Path) -> bool {
+    checksums
+        .get(path)
+        .is_none_or(|expected| cas::hash_file(path).is_ok_and(|hash| &hash == expected))
+}
+
+pub fn run_stats(args: &StatsArgs) -> anyhow::Result<()> {
+    let download_cache = args.local.download_cache()?;
+    let artists: BTreeSet<_> = download_cache
+        .values()
+        .map(|release| release.artist().to_lowercase())
+        .collect();
+    println!(
+        "Download cache: {} release(s) by {} artist(s)",
+        download_cache.len(),
+        artists.len()
+    );
+
+    let events = args.local.journal()?;
+    if events.is_empty() {
+        println!("Journal: no events yet");
+        return Ok(());
+    }
+    let counts: Vec<_> = count_events(&events)
+        .into_iter()
+        .map(|(kind, count)| format!("{count} {kind}"))
+        .collect();
+    println!("Journal: {}", counts.join(", "));
+
+    let last_download = events
+        .iter()
+        .rev()
+        .find(|event| event.event == EventKind::Downloaded)
+        .and_then(JournalEvent::timestamp);
+    if let Some(last_download) = last_download {
+        println!("Last download: {}", timestamp::format_local(last_download));
+    }
+
+    Ok(())
+}
+
+impl StatusArgs {
+    pub(super) fn apply_profile(&mut self) -> anyhow::Result<()> {
+        let profile = self.local.apply_profile()?;
+        if !self.local_only {
+            self.cookie_file = self
+                .cookie_file
+                .take()
+                .or_else(|| profile.and_then(|profile| profile.cookie_file));
+            if self.cookie_file.is_none() {
+                anyhow::bail!(
+                    "Either --cookie-file, --local-only or a --profile with a `cookie_file` is needed"
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn run_status(args: StatusArgs) -> anyhow::Result<()> {
+    let download_cache = args.local.download_cache()?;
+
+    let Some(cookie_file) = args.cookie_file else {
+        let snapshot =
+            read_collection_snapshot(&args.local.state_dir().join(snapshot::SNAPSHOT_FILE_NAME))?;
+        println!(
+            "Collection snapshot of {} from {}",
+            snapshot.username,
+            snapshot
+                .taken_at()
+                .map_or_else(|| "an unknown time".to_owned(), timestamp::format_local)
+        );
+        println!(
+            "{} release(s) in the download cache, {} not downloaded yet",
+            download_cache.len(),
+            snapshot.pending_releases(&download_cache).len()
+        );
+        return Ok(());
+    };
+
+    // only looking, so nothing is recorded or saved for the next sync
+    let library = args.local.library(cookie_file);
+    let (_, new_items) =
+        find_new_library_items(&library, &download_cache, &Journal::disabled(), None, false)
+            .await?;
+    println!(
+        "{} release(s) in the download cache, {} not downloaded yet",
+        download_cache.len(),
+        new_items.len()
+    );
+
+    Ok(())
+}
+
+pub fn run_renames(args: &RenamesArgs) -> anyhow::Result<()> {
+    let snapshot =
+        read_collection_snapshot(&args.local.state_dir().join(snapshot::SNAPSHOT_FILE_NAME))?;
+    let renames = bands::find_renames(&snapshot.items);
+    if renames.is_empty() {
+        println!("No artist was bought from under more than one name");
+    }
+    for rename in renames {
+        println!("{}", rename.describe());
+    }
+    Ok(())
+}
+
+// Looks in the download folder, the staging folders it defaults to, and those the state folder
+// recorded. Refuses while a sync of the library holds the cache lock.
+pub fn run_clean(args: &CleanArgs) -> anyhow::Result<()> {
+    let download_folder = args.local.download_folder();
+    let _cache_locks = CacheLock::acquire_all(&args.local.cache_file_path())?;
+    let download_caches = LibraryCaches::read(&args.local)?;
+    let cutoff = SystemTime::now()
+        .checked_sub(args.older_than)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    scratch::update_registry(&args.local.state_dir(), |registry| {
+        let mut leftovers = BTreeSet::new();
+        let mut staging_dirs = vec![
+            staging::temp_staging_dir(&download_folder),
+            staging::local_staging_dir(&download_folder),
+        ];
+        for entry in registry.entries().to_vec() {
+            match entry.kind {
+                ScratchKind::Staging if entry.path.is_dir() => staging_dirs.push(entry.path),
+                ScratchKind::OfflineQueue if entry.path.is_file() => {
+                    if expired_queue(&entry.path, cutoff, &download_caches) {
+                        leftovers.insert(entry.path);
+                    }
+                }
+                kind => registry.forget(kind, &entry.path),
+            }
+        }
+        leftovers.extend(scratch::stale_artifacts(
+            &download_folder,
+            &staging_dirs,
+            cutoff,
+        )?);
+        for staging_dir in &staging_dirs {
+            leftovers.extend(scratch::stale_staged_artifacts(staging_dir, cutoff)?);
+        }
+
+        remove_leftovers(registry, &leftovers, args.dry_run)
+    })?
+}
+
+// The download cache of a library, and those kept for each format by syncs with
+// --cache-per-format
+struct LibraryCaches {
+    shared: DownloadCache,
+    per_format: HashMap<DownloadFormat, DownloadCache>,
+}
+
+impl LibraryCaches {
+    fn read(local: &LocalArgs) -> anyhow::Result<Self> {
+        let cache_file_path = local.cache_file_path();
+        let mut per_format = HashMap::new();
+        for format in DownloadFormat::value_variants() {
+            let format_cache_path = cache::format_scoped_cache_path(&cache_file_path, *format);
+            if format_cache_path.exists() {
+                per_format.insert(
+                    *format,
+                    read_download_cache_file(&format_cache_path, false)?,
+                );
+            }
+        }
+        Ok(Self {
+            shared: local.download_cache()?,
+            per_format,
+        })
+    }
+
+    fn for_format(&self, format: DownloadFormat) -> &DownloadCache {
+        self.per_format.get(&format).unwrap_or(&self.shared)
+    }
+}
+
+// A queue is only taken as expired once every release of it was downloaded, as `execute-plan`
+// still resolves the expired links of the others again
+fn expired_queue(queue_path: &Path, cutoff: SystemTime, download_caches: &LibraryCaches) -> bool {
+    let queue = match std::fs::read_to_string(queue_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|queue_data| Ok(OfflineQueue::read(&queue_data)?))
+    {
+        Ok(queue) => queue,
+        Err(e) => {
+            println!(
+                "Keeping {}, which couldn't be read: {e}",
+                queue_path.display()
+            );
+            return false;
+        }
+    };
+    let resolved_at = SystemTime::UNIX_EPOCH
+        + Duration::from_secs(u64::try_from(queue.resolved_at).unwrap_or_default());
+    if resolved_at >= cutoff {
+        return false;
+    }
+
+    let pending = queue
+        .downloads
+        .iter()
+        .filter(|(sale_id, queued)| {
+            !download_caches
+                .for_format(queued.format)
+                .contains_key(&cache::normalize_release_id(sale_id))
+        })
+        .count();
+    if pending > 0 {
+        println!(
+            "Keeping {}, as {pending} of its release(s) aren't downloaded yet",
+            queue_path.display()
+        );
+    }
+    pending == 0
+}
+
+fn remove_leftovers(
+    registry: &mut ScratchRegistry,
+    leftovers: &BTreeSet<PathBuf>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let mut freed = 0;
+    for path in leftovers {
+        // gone since, such as a segment joined by a sync that just finished
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if dry_run {
+            println!("Would remove {}", path.display());
+            continue;
+        }
+        if metadata.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else {
+            std::fs::remove_file(path)?;
+            freed += metadata.len();
+        }
+        registry.forget(ScratchKind::OfflineQueue, path);
+        println!("Removed {}", path.display());
+    }
+
+    if dry_run {
+        println!("{} leftover(s) would be removed", leftovers.len());
+    } else {
+        println!(
+            "Removed {} leftover(s), freeing {}",
+            leftovers.len(),
+            HumanBytes(freed)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_count_events() {
+        let events = [
+            JournalEvent::new("p1", EventKind::Discovered, None),
+            JournalEvent::new("p1", EventKind::Downloaded, None),
+            JournalEvent::new("p2", EventKind::Discovered, None),
+            JournalEvent::new("p2", EventKind::Failed, Some("HTTP status 500".to_owned())),
+        ];
+
+        assert_eq!(
+            count_events(&events).into_iter().collect::<Vec<_>>(),
+            [("discovered", 2), ("downloaded", 1), ("failed", 1)]
+        );
+    }
+
+    #[test]
+    pub fn test_matches_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Anomalie - Galaxie.zip");
+        std::fs::write(&path, b"archive").unwrap();
+        let mut checksums = ChecksumMap::new();
+        assert!(matches_checksum(&checksums, &path));
+
+        checksums.insert(path.clone(), cas::hash_file(&path).unwrap());
+        assert!(matches_checksum(&checksums, &path));
+
+        std::fs::write(&path, b"damaged").unwrap();
+        assert!(!matches_checksum(&checksums, &path));
+    }
+}
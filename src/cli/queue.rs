@@ -0,0 +1,46 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use clap::Args;
+
+use crate::{controls::RunControl, planner::OfflineQueue};
+
+use super::{reject_options, sync_once, LibraryArgs, ReleaseSource, SyncArgs};
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct ExecutePlanArgs {
+    #[arg(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    #[arg(help = "The file written by a sync with --offline-queue")]
+    plan: PathBuf,
+
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[command(flatten)]
+    pub(super) sync: SyncArgs,
+}
+
+pub async fn run(args: ExecutePlanArgs) -> anyhow::Result<()> {
+    let sync = &args.sync;
+    reject_options(
+        &[
+            sync.collection_options().as_slice(),
+            &[("--offline-queue", sync.offline_queue.is_some())],
+        ]
+        .concat(),
+        "releases that are already queued",
+    )?;
+
+    let queue_data = std::fs::read_to_string(&args.plan)
+        .with_context(|| format!("Failed reading {}", args.plan.display()))?;
+    let queue = OfflineQueue::read(&queue_data).with_context(|| {
+        format!(
+            "{} isn't a queue written by --offline-queue",
+            args.plan.display()
+        )
+    })?;
+
+    let control = Arc::new(RunControl::default());
+    sync_once(&args.library, sync, &control, ReleaseSource::Queue(&queue)).await?;
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use clap::Args;
+use schemars::Schema;
+
+use crate::{journal::JournalEvent, middlewares::StatsExport, models, planner::Plan};
+
+use super::list::ListExport;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum SchemaKind {
+    // `list --format json`, or `export`
+    List,
+    // `--plan-json`
+    Plan,
+    // `--stats-json`
+    Stats,
+    // each line of the journal
+    EventLog,
+}
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct SchemaArgs {
+    #[arg(value_enum)]
+    #[arg(
+        help = "The output to describe: list for `list --format json` and `export`, plan for --plan-json, stats for --stats-json, or event-log for each line of the journal"
+    )]
+    kind: SchemaKind,
+}
+
+pub fn run(args: &SchemaArgs) -> anyhow::Result<()> {
+    print!("{}", schema_json(args.kind)?);
+    Ok(())
+}
+
+fn schema(kind: SchemaKind) -> Schema {
+    match kind {
+        SchemaKind::List => models::versioned_schema::<ListExport>(),
+        SchemaKind::Plan => models::versioned_schema::<Plan>(),
+        SchemaKind::Stats => models::versioned_schema::<StatsExport>(),
+        SchemaKind::EventLog => models::versioned_schema::<JournalEvent>(),
+    }
+}
+
+// As shipped in the schemas folder of the repository
+fn schema_json(kind: SchemaKind) -> serde_json::Result<String> {
+    Ok(serde_json::to_string_pretty(&schema(kind))? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(SchemaKind::List, include_str!("../../schemas/list.json"))]
+    #[test_case(SchemaKind::Plan, include_str!("../../schemas/plan.json"))]
+    #[test_case(SchemaKind::Stats, include_str!("../../schemas/stats.json"))]
+    #[test_case(SchemaKind::EventLog, include_str!("../../schemas/event-log.json"))]
+    pub fn test_shipped_schemas_are_current(kind: SchemaKind, shipped: &str) {
+        // regenerate with `bandcamp-dl schema <kind> > schemas/<kind>.json`
+        assert_eq!(schema_json(kind).unwrap(), shipped);
+    }
+
+    #[test]
+    pub fn test_schema_describes_fields() {
+        let schema = schema(SchemaKind::EventLog);
+        let properties = schema.get("properties").unwrap();
+
+        for field in ["schema_version", "timestamp", "item", "event", "detail"] {
+            assert!(properties.get(field).is_some(), "{field} isn't described");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::{
+    api::{data, BandcampAPIContext},
+    layout,
+};
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct SearchArgs {
+    #[arg(help = "What to look for, as typed into the search box of Bandcamp's pages")]
+    query: String,
+
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    #[arg(
+        help = "Also save the cover art of the albums and tracks found to this folder, named \"<artist> - <title>\""
+    )]
+    art_folder: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = data::ArtSize::Original, requires = "art_folder")]
+    #[arg(
+        help = "Size of the cover art --art-folder saves, the original upload (which may be a PNG) or the width in pixels Bandcamp scales it to"
+    )]
+    art_size: data::ArtSize,
+
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    #[arg(help = "Configuration file to read, in the TOML format")]
+    config: Option<PathBuf>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Reject Bandcamp responses from servers whose certificate public key doesn't match one of the `tls_pins` in the configuration file"
+    )]
+    verify_tls_pins: bool,
+}
+
+// Needs no session, as Bandcamp's search and image server answer anyone
+pub async fn run(args: SearchArgs) -> anyhow::Result<()> {
+    let api_context = BandcampAPIContext::new_without_cookies(&super::client_options(
+        super::read_config(args.config.as_deref())?,
+        args.verify_tls_pins,
+    )?)?;
+
+    let results = api_context.search(&args.query).await?;
+    if results.is_empty() {
+        println!("Nothing found for \"{}\"", args.query);
+        return Ok(());
+    }
+    for result in &results {
+        println!("{}", format_result(result));
+    }
+
+    if let Some(art_folder) = &args.art_folder {
+        save_art(&api_context, &results, art_folder, args.art_size).await?;
+    }
+    Ok(())
+}
+
+fn format_result(result: &data::SearchResult) -> String {
+    let name = result.band_name.as_ref().map_or_else(
+        || result.name.clone(),
+        |band_name| format!("{band_name} - {}", result.name),
+    );
+    let url = result
+        .url()
+        .map(|url| format!(" ({url})"))
+        .unwrap_or_default();
+    format!("{}: {name}{url}", result.kind())
+}
+
+// Artists have a picture rather than cover art, so only albums and tracks are saved. A cover that
+// can't be fetched is skipped.
+async fn save_art(
+    api_context: &BandcampAPIContext,
+    results: &[data::SearchResult],
+    art_folder: &Path,
+    art_size: data::ArtSize,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(art_folder)?;
+    for result in results {
+        let (Some(band_name), Some(art_id)) = (&result.band_name, result.art_id) else {
+            continue;
+        };
+        let name =
+            layout::sanitize_path_component(&format!("{band_name} - {}", result.name), false);
+        let art = match api_context.get_art(art_id, art_size).await {
+            Ok(art) => art,
+            Err(e) => {
+                eprintln!("Couldn't fetch the cover art of {name}: {e}");
+                continue;
+            }
+        };
+        let art_path = art_folder.join(format!("{name}.{}", super::art_extension(&art)));
+        std::fs::write(&art_path, art)?;
+        println!("Saved {}", art_path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_format_result() {
+        let results: Vec<data::SearchResult> = serde_json::from_value(serde_json::json!([
+            {
+                "type": "b",
+                "name": "Anomalie",
+                "item_url_root": "https://anomalie.bandcamp.com",
+            },
+            {
+                "type": "t",
+                "name": "Velours",
+                "band_name": "Anomalie",
+            },
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            format_result(&results[0]),
+            "artist: Anomalie (https://anomalie.bandcamp.com)"
+        );
+        assert_eq!(format_result(&results[1]), "track: Anomalie - Velours");
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use clap::Args;
+
+use crate::controls::RunControl;
+
+use super::{reject_options, sync_once, LibraryArgs, ReleaseSource, SyncArgs};
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct DownloadArgs {
+    #[arg(value_name = "URL")]
+    #[arg(help = "The album or track page of an item in the collection")]
+    item_url: String,
+
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[command(flatten)]
+    pub(super) sync: SyncArgs,
+}
+
+pub async fn run(args: DownloadArgs) -> anyhow::Result<()> {
+    let sync = &args.sync;
+    reject_options(
+        &[
+            sync.collection_options().as_slice(),
+            &[
+                ("--limit", sync.limit.is_some()),
+                ("--offset", sync.offset > 0),
+                ("--force-item", !sync.force_item.is_empty()),
+                ("--force-artist", !sync.force_artist.is_empty()),
+            ],
+        ]
+        .concat(),
+        "a single release, which is downloaded even when it's in the download cache",
+    )?;
+
+    let control = Arc::new(RunControl::default());
+    sync_once(
+        &args.library,
+        sync,
+        &control,
+        ReleaseSource::ItemUrl(&args.item_url),
+    )
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::{path::Path, sync::Arc};
+
+use reqwest::Url;
+
+use crate::{
+    api::{BandcampAPIContext, CollectionScope},
+    download::{self, Download},
+    progress::{DownloadProgress, ProgressReporter},
+    staging, stream,
+    template::NameTemplate,
+};
+
+// Tracks already saved are skipped, so nothing is recorded in the download cache, and an item
+// that later gets a proper download is still picked up by the regular sync
+pub async fn run(
+    api_context: &BandcampAPIContext,
+    scopes: &[CollectionScope],
+    download_folder: &Path,
+    dry_run: bool,
+    print_urls_only: bool,
+    folder_template: &NameTemplate,
+    ascii_filenames: bool,
+) -> anyhow::Result<()> {
+    let verbose = !print_urls_only;
+
+    let summary = api_context.verify_session().await?;
+    let items = api_context.get_stream_only_items(&summary, scopes).await?;
+    if items.is_empty() {
+        if verbose {
+            println!("No stream-only items in the collection");
+        }
+        return Ok(());
+    }
+
+    if verbose {
+        println!(
+            "Stream rip: saving {} stream-only item(s) as lossy MP3-128 stream captures",
+            items.len()
+        );
+    }
+
+    let stream_folder = download_folder.join(stream::STREAM_FOLDER_NAME);
+    for item in items {
+        let Some(item_url) = &item.item_url else {
+            continue;
+        };
+
+        let tralbum = match api_context.get_tralbum_data(item_url).await {
+            Ok(tralbum) => tralbum,
+            Err(e) => {
+                eprintln!(
+                    "Failed reading the tracks of \"{}\" by {}: {e}",
+                    item.item_title, item.band_name
+                );
+                continue;
+            }
+        };
+
+        let album_folder = stream_folder.join(stream::album_folder_name(
+            &tralbum,
+            folder_template,
+            ascii_filenames,
+        ));
+        let tracks: Vec<_> = stream::stream_tracks(&tralbum, ascii_filenames)
+            .into_iter()
+            .filter(|track| !album_folder.join(&track.filename).exists())
+            .collect();
+        if tracks.is_empty() {
+            continue;
+        }
+
+        if print_urls_only {
+            for track in &tracks {
+                println!("{}", track.url);
+            }
+            continue;
+        }
+
+        println!(
+            "Stream rip of \"{}\" by {}: {} track(s)",
+            tralbum.current.title,
+            tralbum.artist,
+            tracks.len()
+        );
+        if dry_run {
+            continue;
+        }
+
+        download_tracks(api_context, &album_folder, &tracks).await?;
+    }
+
+    Ok(())
+}
+
+// Each track is downloaded under a partial name and renamed once complete, so an interrupted rip
+// leaves nothing that looks finished
+async fn download_tracks(
+    api_context: &BandcampAPIContext,
+    album_folder: &Path,
+    tracks: &[stream::StreamTrack],
+) -> anyhow::Result<()> {
+    let downloads = tracks
+        .iter()
+        .map(|track| {
+            Ok(Download::new(
+                track.filename.clone(),
+                Url::parse(&track.url)?,
+                staging::partial_file_name(&track.filename),
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let files: Vec<_> = downloads
+        .iter()
+        .map(|download| (download.clone(), None))
+        .collect();
+
+    std::fs::create_dir_all(album_folder)?;
+    let progress = Arc::new(DownloadProgress::new(downloads.len()));
+    let reporter: Arc<dyn ProgressReporter> = progress.clone();
+    let results = progress
+        .follow(
+            &files,
+            download::download_files(
+                api_context.download_client(),
+                album_folder,
+                downloads,
+                &reporter,
+            ),
+        )
+        .await;
+    for (download, result) in results {
+        let partial_file_name = &download.filename;
+        let filename = staging::completed_file_name(partial_file_name);
+        match result {
+            Ok(_) => {
+                progress.item_downloaded(&download.release_id);
+                if let Err(e) = staging::complete_in_place(album_folder, partial_file_name) {
+                    progress.suspend(|| {
+                        println!("Failed renaming {partial_file_name} to {filename}: {e}");
+                    });
+                }
+            }
+            Err(e) => {
+                progress.suspend(|| println!("Failed stream rip of {filename}: {e}"));
+                let _ = std::fs::remove_file(album_folder.join(partial_file_name));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use anyhow::Context;
+use clap::Args;
+use reqwest::{header, Client};
+
+use crate::update::{self, Release, CHECKSUMS_ASSET_NAME, SIGNATURE_ASSET_NAME};
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct SelfUpdateArgs {
+    #[arg(long)]
+    #[arg(help = "Only tell whether a newer release is out, without installing it")]
+    check: bool,
+}
+
+pub async fn run(args: &SelfUpdateArgs) -> anyhow::Result<()> {
+    // GitHub's API turns requests without a user agent away
+    let client = Client::builder()
+        .user_agent(concat!("bandcamp-dl/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    let release_data = client
+        .get(update::LATEST_RELEASE_URL)
+        .header(header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let release: Release = serde_json::from_str(&release_data)
+        .context("Failed reading the latest release from GitHub")?;
+
+    let current_version = env!("CARGO_PKG_VERSION");
+    if !release.is_newer_than(current_version) {
+        println!("Already up to date ({current_version})");
+        return Ok(());
+    }
+    if args.check {
+        println!(
+            "{} is out, this is {current_version}. Run `bandcamp-dl self-update` to install it",
+            release.tag_name
+        );
+        return Ok(());
+    }
+
+    // checked before downloading the binary, as it's of no use in a build that can't verify it
+    let checksums = fetch_text(&client, &release, CHECKSUMS_ASSET_NAME).await?;
+    let signature = fetch_text(&client, &release, SIGNATURE_ASSET_NAME).await?;
+    update::verify_manifest(&checksums, &signature, &release.tag_name)?;
+
+    let asset_name = update::binary_asset_name();
+    println!("Downloading {asset_name} of {}...", release.tag_name);
+    let binary = client
+        .get(&release.asset(&asset_name)?.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    update::verify_checksum(&checksums, &asset_name, &binary)?;
+
+    let executable = std::env::current_exe()?;
+    update::replace_executable(&executable, &binary)
+        .with_context(|| format!("Failed replacing {}", executable.display()))?;
+    println!(
+        "Updated {} from {current_version} to {}",
+        executable.display(),
+        release.tag_name
+    );
+    Ok(())
+}
+
+async fn fetch_text(client: &Client, release: &Release, name: &str) -> anyhow::Result<String> {
+    Ok(client
+        .get(&release.asset(name)?.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
@@ -0,0 +1,25 @@
+use clap::Args;
+
+use crate::api::BandcampAPIContext;
+
+use super::LibraryArgs;
+
+#[derive(Args, Debug, PartialEq, Eq)]
+pub struct WhoamiArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+}
+
+pub async fn run(args: WhoamiArgs) -> anyhow::Result<()> {
+    let api_context = BandcampAPIContext::with_options(
+        &args.library.read_cookies()?,
+        &args.library.client_options()?,
+    )?;
+    let summary = api_context.verify_session().await?;
+
+    println!(
+        "Logged in as {} (fan id {}), {}",
+        summary.collection_summary.username, summary.fan_id, summary.collection_summary.url
+    );
+    Ok(())
+}
@@ -0,0 +1,208 @@
+use std::{path::Path, sync::Arc};
+
+use clap::Args;
+use reqwest::Url;
+
+use crate::{
+    api::{
+        data::{CollectionItem, DownloadFormat},
+        endpoints, BandcampAPIContext,
+    },
+    download::{self, Download},
+    layout,
+    progress::{DownloadProgress, ProgressReporter},
+    staging,
+};
+
+use super::{open_session_or_public, LibraryArgs, Session};
+
+// Kept apart from the collection, as wishlist items aren't owned and stay out of the download cache
+pub const WISHLIST_FOLDER_NAME: &str = "wishlist";
+
+#[derive(Args, Debug, PartialEq, Eq)]
+#[command(mut_arg("cookie_file", |arg| arg.required_unless_present("public")))]
+pub struct WishlistArgs {
+    #[command(flatten)]
+    pub(super) library: LibraryArgs,
+
+    #[arg(long, value_name = "FAN")]
+    #[arg(
+        help = "Read the wishlist of a fan from their public page, by username or page url, without logging in. Free downloads work the same"
+    )]
+    public: Option<String>,
+
+    #[arg(long)]
+    #[arg(
+        help = "Also download the items that are free, or name your price with nothing to pay, into a \"wishlist\" folder of the download folder"
+    )]
+    download_free: bool,
+
+    #[arg(long, value_enum, default_value_t = DownloadFormat::Flac)]
+    #[arg(help = "The audio format of free downloads")]
+    audio_format: DownloadFormat,
+}
+
+pub async fn run(args: WishlistArgs) -> anyhow::Result<()> {
+    let (api_context, fan_id) = if let Some(fan) = &args.public {
+        let api_context = BandcampAPIContext::new_without_cookies(&args.library.client_options()?)?;
+        let fan_id = api_context
+            .public_fan_id(&endpoints::fan_page_url(fan))
+            .await?;
+        (api_context, fan_id)
+    } else {
+        match open_session_or_public(&args.library, "the items kept private").await? {
+            Session::LoggedIn(api_context, summary) => (api_context, summary.fan_id),
+            Session::LoggedOut(api_context, snapshot) => (api_context, snapshot.fan_id),
+        }
+    };
+
+    let items = api_context.get_wishlist(fan_id).await?;
+    if items.is_empty() {
+        println!("The wishlist is empty");
+        return Ok(());
+    }
+    for item in &items {
+        match &item.item_url {
+            Some(item_url) => println!("{} - {} ({item_url})", item.band_name, item.item_title),
+            None => println!("{} - {}", item.band_name, item.item_title),
+        }
+    }
+
+    if args.download_free {
+        download_free_items(
+            &api_context,
+            &items,
+            &args.library.download_folder(),
+            args.audio_format,
+            false,
+            false,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+// Items whose file is already in the wishlist folder are skipped, as nothing is recorded in the
+// download cache for them
+pub async fn download_free_items(
+    api_context: &BandcampAPIContext,
+    items: &[CollectionItem],
+    download_folder: &Path,
+    audio_format: DownloadFormat,
+    dry_run: bool,
+    print_urls_only: bool,
+) -> anyhow::Result<()> {
+    let wishlist_folder = download_folder.join(WISHLIST_FOLDER_NAME);
+
+    let mut downloads = Vec::new();
+    for item in items {
+        let Some(item_url) = &item.item_url else {
+            continue;
+        };
+
+        let tralbum = match api_context.get_tralbum_data(item_url).await {
+            Ok(tralbum) => tralbum,
+            Err(e) => {
+                eprintln!(
+                    "Failed reading \"{}\" by {}: {e}",
+                    item.item_title, item.band_name
+                );
+                continue;
+            }
+        };
+        let (Some(download_page), Some(key)) = (&tralbum.free_download_page, tralbum.lookup_key())
+        else {
+            continue;
+        };
+
+        let filename = layout::release_file_name(&key, audio_format);
+        if wishlist_folder.join(&filename).exists() {
+            continue;
+        }
+
+        let download_link = match free_download_link(api_context, download_page, audio_format).await
+        {
+            Ok(download_link) => download_link,
+            Err(e) => {
+                eprintln!(
+                    "Failed resolving the free download of \"{}\" by {}: {e}",
+                    item.item_title, item.band_name
+                );
+                continue;
+            }
+        };
+
+        if print_urls_only {
+            println!("{download_link}");
+            continue;
+        }
+        println!(
+            "Free download of \"{}\" by {} from the wishlist",
+            item.item_title, item.band_name
+        );
+        if !dry_run {
+            downloads.push(Download::new(
+                key.clone(),
+                Url::parse(&download_link)?,
+                staging::partial_file_name(&filename),
+            ));
+        }
+    }
+
+    if downloads.is_empty() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&wishlist_folder)?;
+    let files: Vec<_> = downloads
+        .iter()
+        .map(|download| (download.clone(), None))
+        .collect();
+    let progress = Arc::new(DownloadProgress::new(downloads.len()));
+    let reporter: Arc<dyn ProgressReporter> = progress.clone();
+    let results = progress
+        .follow(
+            &files,
+            download::download_files(
+                api_context.download_client(),
+                &wishlist_folder,
+                downloads,
+                &reporter,
+            ),
+        )
+        .await;
+    for (download, result) in results {
+        let partial_file_name = &download.filename;
+        let filename = staging::completed_file_name(partial_file_name);
+        match result {
+            Ok(_) => {
+                progress.item_downloaded(&download.release_id);
+                if let Err(e) = staging::complete_in_place(&wishlist_folder, partial_file_name) {
+                    progress.suspend(|| {
+                        println!("Failed renaming {partial_file_name} to {filename}: {e}");
+                    });
+                }
+            }
+            Err(e) => {
+                progress.suspend(|| println!("Failed downloading {filename}: {e}"));
+                let _ = std::fs::remove_file(wishlist_folder.join(partial_file_name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn free_download_link(
+    api_context: &BandcampAPIContext,
+    download_page: &str,
+    audio_format: DownloadFormat,
+) -> anyhow::Result<String> {
+    let Some(digital_item) = api_context.get_digital_download_item(download_page).await? else {
+        anyhow::bail!("its download page has no digital item");
+    };
+
+    Ok(api_context
+        .get_digital_download_link(&digital_item, audio_format)
+        .await?)
+}
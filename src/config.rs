@@ -0,0 +1,151 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{Deserialize, Deserializer};
+use time::Date;
+
+use crate::{api::data::DownloadFormat, error::ConfigParsingError, filter, pinning::TlsPin};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Accepted public keys for Bandcamp's servers, only enforced with `--verify-tls-pins`
+    pub tls_pins: Vec<TlsPin>,
+    // Sent to Bandcamp instead of the default "en"
+    pub accept_language: Option<String>,
+    // Tag the tracks extracted into the download folders during a sync with ReplayGain
+    pub replaygain: bool,
+    // The ffmpeg measuring their loudness, instead of the one on the PATH
+    pub ffmpeg: Option<PathBuf>,
+    // Days Bandcamp announced as Bandcamp Fridays, as YYYY-MM-DD, for new release alerts
+    #[serde(deserialize_with = "deserialize_dates")]
+    pub bandcamp_fridays: Vec<Date>,
+    // Picked with `--profile`, by name, such as `[profiles.label]`
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+// The account and library of one profile. The command line overrides each of them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub cookie_file: Option<PathBuf>,
+    pub download_folder: Vec<PathBuf>,
+    pub cache_file: Option<PathBuf>,
+    pub audio_format: Option<DownloadFormat>,
+}
+
+// Where a --profile is looked up without --config: "bandcamp-dl/config.toml" in the configuration
+// folder of the user, as XDG_CONFIG_HOME, APPDATA or macOS have it
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(std::env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(config_dir.join("bandcamp-dl").join("config.toml"))
+}
+
+fn deserialize_dates<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Date>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|date| filter::parse_date(date).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+pub fn read_config(config_data: &str) -> Result<Config, ConfigParsingError> {
+    Ok(toml::from_str(config_data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_read_empty_config() {
+        let config = read_config("").unwrap();
+        assert!(config.tls_pins.is_empty());
+        assert_eq!(config.accept_language, None);
+        assert!(!config.replaygain);
+    }
+
+    #[test]
+    pub fn test_read_replaygain() {
+        let config = read_config("replaygain = true\nffmpeg = \"/opt/ffmpeg/bin/ffmpeg\"").unwrap();
+        assert!(config.replaygain);
+        assert_eq!(config.ffmpeg, Some(PathBuf::from("/opt/ffmpeg/bin/ffmpeg")));
+    }
+
+    #[test]
+    pub fn test_read_bandcamp_fridays() {
+        let config = read_config(r#"bandcamp_fridays = ["2021-02-05", "2021-03-05"]"#).unwrap();
+        assert_eq!(
+            config.bandcamp_fridays,
+            [
+                Date::from_calendar_date(2021, time::Month::February, 5).unwrap(),
+                Date::from_calendar_date(2021, time::Month::March, 5).unwrap(),
+            ]
+        );
+
+        assert!(read_config(r#"bandcamp_fridays = ["5 Feb 2021"]"#).is_err());
+    }
+
+    #[test]
+    pub fn test_read_accept_language() {
+        let config = read_config(r#"accept_language = "de-DE, en;q=0.5""#).unwrap();
+        assert_eq!(config.accept_language.as_deref(), Some("de-DE, en;q=0.5"));
+    }
+
+    #[test]
+    pub fn test_read_tls_pins() {
+        let config = read_config(
+            r#"tls_pins = [
+                "sha256//QB1t9KHqaWsK1OGJCyV/ip261DFvz9zb23ITp8YlRjw=",
+                "sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.tls_pins.len(), 2);
+        assert_eq!(
+            config.tls_pins[0].to_string(),
+            "sha256//QB1t9KHqaWsK1OGJCyV/ip261DFvz9zb23ITp8YlRjw="
+        );
+    }
+
+    #[test]
+    pub fn test_read_profiles() {
+        let config = read_config(
+            r#"
+            [profiles.personal]
+            cookie_file = "~/cookies/personal.json"
+
+            [profiles.label]
+            cookie_file = "/srv/label/cookies.json"
+            download_folder = ["/mnt/a/label", "/mnt/b/label"]
+            cache_file = "/srv/label/label.cache"
+            audio_format = "mp3-320"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.profiles.keys().collect::<Vec<_>>(),
+            ["label", "personal"]
+        );
+        let label = &config.profiles["label"];
+        assert_eq!(label.download_folder.len(), 2);
+        assert_eq!(label.audio_format, Some(DownloadFormat::Mp3_320));
+        assert!(config.profiles["personal"].download_folder.is_empty());
+
+        assert!(read_config("[profiles.label]\ncookies = \"cookies.json\"").is_err());
+    }
+
+    #[test]
+    pub fn test_read_invalid_tls_pin() {
+        assert!(read_config(r#"tls_pins = ["QB1t9KHqaWsK1OGJ"]"#).is_err());
+    }
+}
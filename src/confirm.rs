@@ -0,0 +1,72 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    // there's no terminal to ask on, e.g. when running from cron
+    NotInteractive,
+}
+
+// Gates operations that are costly or hard to undo behind a prompt, unless they were confirmed up
+// front with `--assume-yes`
+pub struct Confirmation {
+    assume_yes: bool,
+}
+
+impl Confirmation {
+    pub const fn new(assume_yes: bool) -> Self {
+        Self { assume_yes }
+    }
+
+    pub fn ask(&self, question: &str) -> io::Result<Answer> {
+        if self.assume_yes {
+            return Ok(Answer::Yes);
+        }
+
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Ok(Answer::NotInteractive);
+        }
+
+        ask_on(question, &mut stdin.lock(), &mut io::stderr())
+    }
+}
+
+fn ask_on(question: &str, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<Answer> {
+    write!(output, "{question} [y/N] ")?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Answer::Yes,
+        _ => Answer::No,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("y\n", Answer::Yes)]
+    #[test_case(" YES \r\n", Answer::Yes)]
+    #[test_case("n\n", Answer::No)]
+    #[test_case("\n", Answer::No; "default")]
+    #[test_case("", Answer::No; "end of input")]
+    #[test_case("sure\n", Answer::No)]
+    pub fn test_ask_on(input: &str, expected: Answer) {
+        let mut output = Vec::new();
+        let answer = ask_on("Download 60 GB?", &mut input.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(answer, expected);
+        assert_eq!(String::from_utf8(output).unwrap(), "Download 60 GB? [y/N] ");
+    }
+
+    #[test]
+    pub fn test_assume_yes() {
+        assert_eq!(Confirmation::new(true).ask("Delete?").unwrap(), Answer::Yes);
+    }
+}
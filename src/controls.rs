@@ -0,0 +1,322 @@
+use std::{
+    io::{self, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal,
+};
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    Pause,
+    Resume,
+    Skip,
+    Quit,
+}
+
+impl Hotkey {
+    pub const fn from_char(key: char) -> Option<Self> {
+        match key.to_ascii_lowercase() {
+            'p' => Some(Self::Pause),
+            'r' => Some(Self::Resume),
+            's' => Some(Self::Skip),
+            'q' => Some(Self::Quit),
+            _ => None,
+        }
+    }
+
+    const fn describe(self) -> &'static str {
+        match self {
+            Self::Pause => "Paused, no new downloads start until r is pressed",
+            Self::Resume => "Resumed",
+            Self::Skip => "Skipping the download that has been going the longest",
+            Self::Quit => "Quitting once the download cache is saved",
+        }
+    }
+}
+
+pub const HOTKEYS_HELP: &str =
+    "Keys: p to pause new downloads, r to resume, s to skip the current download, q to quit";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interruption {
+    // a single download is to be given up on
+    Skip,
+    // all of them are
+    Quit,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct ControlState {
+    paused: bool,
+    quitting: bool,
+    // pressed and not yet taken by a download
+    skips: u64,
+}
+
+// Lets a run be paused, skipped through or stopped from the outside, such as with hotkeys. Quitting
+// only stops downloads, what completed is still saved to the cache as usual.
+pub struct RunControl {
+    state: watch::Sender<ControlState>,
+}
+
+impl Default for RunControl {
+    fn default() -> Self {
+        Self {
+            state: watch::Sender::new(ControlState::default()),
+        }
+    }
+}
+
+impl RunControl {
+    pub fn apply(&self, hotkey: Hotkey) {
+        self.state.send_modify(|state| match hotkey {
+            Hotkey::Pause => state.paused = true,
+            Hotkey::Resume => state.paused = false,
+            Hotkey::Skip => state.skips += 1,
+            Hotkey::Quit => state.quitting = true,
+        });
+    }
+
+    pub fn is_quitting(&self) -> bool {
+        self.state.borrow().quitting
+    }
+
+    // Waits while paused, returning whether downloads may go on
+    pub async fn wait_until_resumed(&self) -> bool {
+        let mut state = self.state.subscribe();
+        let quitting = state
+            .wait_for(|state| !state.paused || state.quitting)
+            .await
+            .map_or(true, |state| state.quitting);
+        !quitting
+    }
+
+    // Resolves on the next skip or quit. Each skip is only taken by one of the downloads waiting
+    // for it.
+    pub async fn interrupted(&self) -> Interruption {
+        let mut state = self.state.subscribe();
+        loop {
+            let quitting = state
+                .wait_for(|state| state.skips > 0 || state.quitting)
+                .await
+                .map_or(true, |state| state.quitting);
+            if quitting {
+                return Interruption::Quit;
+            }
+
+            let mut taken = false;
+            self.state.send_if_modified(|state| {
+                taken = state.skips > 0;
+                state.skips = state.skips.saturating_sub(1);
+                false
+            });
+            if taken {
+                return Interruption::Skip;
+            }
+        }
+    }
+
+    // Skips pressed while nothing was downloading aren't meant for the downloads to come
+    pub fn forget_skips(&self) {
+        self.state.send_if_modified(|state| {
+            state.skips = 0;
+            false
+        });
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Reads hotkeys on a thread of its own, for as long as it's kept around. The terminal is put back
+// the way it was when it's dropped.
+pub struct KeyListener {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl KeyListener {
+    // `None` when there's no terminal to read keys from
+    pub fn start(control: Arc<RunControl>) -> Option<Self> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+        let terminal = match TerminalMode::single_keys() {
+            Ok(terminal) => terminal,
+            Err(e) => {
+                eprintln!("Keyboard controls are unavailable: {e}");
+                return None;
+            }
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            std::thread::spawn(move || listen(&control, &stop, terminal))
+        };
+        Some(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for KeyListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Ctrl+C arrives as a key while listening, and quits gracefully like q does. Pressing it again
+// exits right away, for a quit that takes too long.
+fn listen(control: &RunControl, stop: &AtomicBool, terminal: TerminalMode) {
+    while !stop.load(Ordering::Relaxed) {
+        if !event::poll(POLL_INTERVAL).unwrap_or(false) {
+            continue;
+        }
+        let Ok(Event::Key(key)) = event::read() else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        let hotkey = match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                if control.is_quitting() {
+                    drop(terminal);
+                    std::process::exit(130);
+                }
+                Hotkey::Quit
+            }
+            KeyCode::Char(key) => match Hotkey::from_char(key) {
+                Some(hotkey) => hotkey,
+                None => continue,
+            },
+            _ => continue,
+        };
+        eprint!("{}\r\n", hotkey.describe());
+        control.apply(hotkey);
+    }
+}
+
+// Whether the terminal is in raw mode for reading keys, which is also what any printing waits on
+static RAW_MODE: Mutex<bool> = Mutex::new(false);
+
+// Keys are read as they're pressed, without echoing them
+struct TerminalMode;
+
+impl TerminalMode {
+    fn single_keys() -> io::Result<Self> {
+        let mut raw_mode = RAW_MODE.lock().unwrap_or_else(PoisonError::into_inner);
+        terminal::enable_raw_mode()?;
+        *raw_mode = true;
+        drop(raw_mode);
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        let mut raw_mode = RAW_MODE.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = terminal::disable_raw_mode();
+        *raw_mode = false;
+    }
+}
+
+// Raw mode also keeps a newline from going back to the start of the line, so it's left while `f`
+// prints lines. Printing from several threads at once only leaves it for the first of them.
+pub fn with_line_output<R>(f: impl FnOnce() -> R) -> R {
+    let Ok(raw_mode) = RAW_MODE.try_lock() else {
+        return f();
+    };
+    if !*raw_mode {
+        return f();
+    }
+
+    let _ = terminal::disable_raw_mode();
+    let output = f();
+    let _ = terminal::enable_raw_mode();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::{pin, Pin},
+        task::{Context, Poll, Waker},
+    };
+
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case('p', Some(Hotkey::Pause))]
+    #[test_case('R', Some(Hotkey::Resume))]
+    #[test_case('s', Some(Hotkey::Skip))]
+    #[test_case('q', Some(Hotkey::Quit))]
+    #[test_case('x', None)]
+    pub fn test_hotkey_from_char(key: char, expected: Option<Hotkey>) {
+        assert_eq!(Hotkey::from_char(key), expected);
+    }
+
+    fn poll_once<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    pub fn test_pause_and_resume() {
+        let control = RunControl::default();
+        assert_eq!(
+            poll_once(pin!(control.wait_until_resumed())),
+            Poll::Ready(true)
+        );
+
+        control.apply(Hotkey::Pause);
+        let mut waiting = pin!(control.wait_until_resumed());
+        assert!(poll_once(waiting.as_mut()).is_pending());
+        control.apply(Hotkey::Resume);
+        assert_eq!(poll_once(waiting), Poll::Ready(true));
+
+        control.apply(Hotkey::Pause);
+        let mut waiting = pin!(control.wait_until_resumed());
+        assert!(poll_once(waiting.as_mut()).is_pending());
+        control.apply(Hotkey::Quit);
+        assert_eq!(poll_once(waiting), Poll::Ready(false));
+    }
+
+    #[test]
+    pub fn test_each_skip_interrupts_once() {
+        let control = RunControl::default();
+        let mut first = pin!(control.interrupted());
+        let mut second = pin!(control.interrupted());
+        assert!(poll_once(first.as_mut()).is_pending());
+        assert!(poll_once(second.as_mut()).is_pending());
+
+        control.apply(Hotkey::Skip);
+        assert_eq!(poll_once(first), Poll::Ready(Interruption::Skip));
+        assert!(poll_once(second.as_mut()).is_pending());
+
+        control.apply(Hotkey::Quit);
+        assert_eq!(poll_once(second), Poll::Ready(Interruption::Quit));
+    }
+
+    #[test]
+    pub fn test_forget_skips() {
+        let control = RunControl::default();
+        control.apply(Hotkey::Skip);
+        control.forget_skips();
+        assert!(poll_once(pin!(control.interrupted())).is_pending());
+    }
+}
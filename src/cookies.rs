@@ -1,9 +1,13 @@
-use crate::error::CookieJsonParsingError;
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::error::{CookieEntryError, CookieJsonParsingError};
 use cookie::{time::OffsetDateTime, Expiration, SameSite};
 use reqwest::Url;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub const IDENTITY_COOKIE_NAME: &str = "identity";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RawCookie {
     pub name: String,
     pub value: String,
@@ -13,7 +17,11 @@ pub struct RawCookie {
     #[serde(default)]
     pub path: Option<String>,
 
-    #[serde(default)]
+    #[serde(
+        default,
+        alias = "expirationDate",
+        deserialize_with = "string_or_number"
+    )]
     pub expires: Option<String>,
 
     #[serde(default)]
@@ -45,11 +53,45 @@ impl RawCookie {
     }
 }
 
+fn string_or_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    match Option::<serde_json::Value>::deserialize(deserializer)? {
+        Some(serde_json::Value::String(value)) => Ok(Some(value)),
+        Some(serde_json::Value::Number(value)) => Ok(Some(value.to_string())),
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(other) => Err(serde::de::Error::invalid_type(
+            serde::de::Unexpected::Other(&other.to_string()),
+            &"a string or a number",
+        )),
+    }
+}
+
+// Anything this large can't be a timestamp in seconds (it'd be past year 5000), so it's taken as
+// milliseconds since the epoch
+const MILLISECOND_TIMESTAMP_THRESHOLD: i64 = 100_000_000_000;
+
+fn parse_timestamp_seconds(timestamp: &str) -> Option<i64> {
+    let timestamp = timestamp.trim();
+    let seconds = if let Ok(seconds) = timestamp.parse::<i64>() {
+        seconds
+    } else {
+        let float_seconds = timestamp.parse::<f64>().ok().filter(|f| f.is_finite())?;
+        #[allow(clippy::cast_possible_truncation)]
+        let seconds = float_seconds.trunc() as i64;
+        seconds
+    };
+
+    if seconds.abs() >= MILLISECOND_TIMESTAMP_THRESHOLD {
+        Some(seconds / 1000)
+    } else {
+        Some(seconds)
+    }
+}
+
 fn parse_expiration(expire_str_option: Option<&str>) -> Option<Expiration> {
     expire_str_option.and_then(|expires| {
-        expires
-            .parse()
-            .ok()
+        parse_timestamp_seconds(expires)
             .and_then(|ts| OffsetDateTime::from_unix_timestamp(ts).ok())
             .map(Expiration::DateTime)
     })
@@ -105,6 +147,190 @@ impl From<RawCookie> for cookie::Cookie<'_> {
     }
 }
 
+pub fn redact_value(value: &str) -> String {
+    format!("<redacted, {} chars>", value.chars().count())
+}
+
+fn is_bandcamp_cookie(cookie: &RawCookie) -> bool {
+    cookie.host.as_deref().is_none_or(|host| {
+        let host = host
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_matches(['.', '/']);
+        host == "bandcamp.com" || host.ends_with(".bandcamp.com")
+    })
+}
+
+fn expiration_seconds(cookie: &RawCookie) -> Option<i64> {
+    cookie.expires.as_deref().and_then(parse_timestamp_seconds)
+}
+
+// Cookies with their position in the export, for pointing at them in errors
+type IndexedCookies = Vec<(usize, RawCookie)>;
+
+const NETSCAPE_HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+// The classic cookies.txt format, which curl, wget and yt-dlp also use. Recognized by its header,
+// or failing that by a line with the seven tab separated fields of a cookie.
+fn is_netscape_format(cookie_data: &str) -> bool {
+    cookie_data.lines().any(|line| {
+        let line = line.trim_end_matches('\r');
+        line.starts_with("# Netscape HTTP Cookie File")
+            || line.starts_with("# HTTP Cookie File")
+            || line.split('\t').count() == 7
+    })
+}
+
+// domain, include subdomains, path, secure, expires, name, value. Expiring at 0 means at the end
+// of the session.
+fn parse_netscape_line(line: &str) -> Result<RawCookie, String> {
+    let (line, http_only) = line
+        .strip_prefix(NETSCAPE_HTTP_ONLY_PREFIX)
+        .map_or((line, false), |line| (line, true));
+    let fields: Vec<_> = line.split('\t').collect();
+    let [domain, _include_subdomains, path, secure, expires, name, value] = fields[..] else {
+        return Err(format!(
+            "expected 7 tab separated fields, found {}",
+            fields.len()
+        ));
+    };
+
+    let mut cookie = RawCookie::new(name.to_owned(), value.to_owned());
+    cookie.host = Some(domain.to_owned());
+    cookie.path = Some(path.to_owned());
+    cookie.send_for = Some(secure.eq_ignore_ascii_case("TRUE").to_string());
+    cookie.http_only = Some(http_only.to_string());
+    cookie.expires = Some(expires.to_owned()).filter(|expires| expires != "0");
+    Ok(cookie)
+}
+
+fn parse_netscape_entries(cookie_data: &str) -> (IndexedCookies, Vec<CookieEntryError>) {
+    let lines = cookie_data.lines().map(|line| line.trim_end_matches('\r'));
+    let entries = lines.filter(|line| {
+        !line.trim().is_empty()
+            && (!line.starts_with('#') || line.starts_with(NETSCAPE_HTTP_ONLY_PREFIX))
+    });
+
+    let mut cookies = Vec::new();
+    let mut problems = Vec::new();
+    for (index, line) in entries.enumerate() {
+        match parse_netscape_line(line) {
+            Ok(cookie) => cookies.push((index, cookie)),
+            Err(reason) => problems.push(CookieEntryError {
+                index,
+                name: None,
+                reason,
+            }),
+        }
+    }
+
+    (cookies, problems)
+}
+
+// Exports saved by editors on Windows may start with a BOM, which serde doesn't skip. Entries
+// are read one by one, so a single broken cookie is reported by position and name instead of
+// failing the whole export.
+fn parse_cookie_entries(
+    cookie_data: &str,
+) -> Result<(IndexedCookies, Vec<CookieEntryError>), CookieJsonParsingError> {
+    let cookie_data = cookie_data.strip_prefix('\u{feff}').unwrap_or(cookie_data);
+    if is_netscape_format(cookie_data) {
+        return Ok(parse_netscape_entries(cookie_data));
+    }
+
+    let entries: Vec<serde_json::Value> = serde_json::from_str(cookie_data)?;
+
+    let mut cookies = Vec::new();
+    let mut problems = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let name = entry
+            .get("name")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_owned);
+        match serde_json::from_value(entry) {
+            Ok(cookie) => cookies.push((index, cookie)),
+            Err(e) => problems.push(CookieEntryError {
+                index,
+                name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    Ok((cookies, problems))
+}
+
+// Unreadable entries are skipped with a warning, unless nothing readable is left
+fn keep_readable<T>(
+    items: Vec<T>,
+    problems: Vec<CookieEntryError>,
+) -> Result<Vec<T>, CookieJsonParsingError> {
+    if problems.is_empty() {
+        return Ok(items);
+    }
+    if items.is_empty() {
+        return Err(CookieJsonParsingError::PartialFailures(problems));
+    }
+
+    eprintln!("Skipping {} unreadable cookie(s):", problems.len());
+    for problem in &problems {
+        eprintln!("  {problem}");
+    }
+    Ok(items)
+}
+
+pub fn parse_raw_cookies(cookie_data: &str) -> Result<Vec<RawCookie>, CookieJsonParsingError> {
+    let (cookies, problems) = parse_cookie_entries(cookie_data)?;
+    keep_readable(
+        cookies.into_iter().map(|(_, cookie)| cookie).collect(),
+        problems,
+    )
+}
+
+// Combines cookies exported from several browsers or profiles, keeping only Bandcamp's and, for
+// each name, the one that expires last (session cookies lose to any dated one)
+pub fn merge_raw_cookies(cookies: impl IntoIterator<Item = RawCookie>) -> Vec<RawCookie> {
+    let mut merged: HashMap<String, RawCookie> = HashMap::new();
+    for cookie in cookies.into_iter().filter(is_bandcamp_cookie) {
+        match merged.entry(cookie.name.clone()) {
+            Entry::Occupied(mut entry) => {
+                if expiration_seconds(&cookie) > expiration_seconds(entry.get()) {
+                    entry.insert(cookie);
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(cookie);
+            }
+        }
+    }
+
+    let mut merged: Vec<_> = merged.into_values().collect();
+    merged.sort_by(|a, b| a.name.cmp(&b.name));
+    merged
+}
+
+// The identity cookie on its own, e.g. as copied from the browser's developer tools, which is
+// enough for Bandcamp to recognize the session
+pub fn identity_cookie(value: &str) -> RawCookie {
+    let mut cookie = RawCookie::new(IDENTITY_COOKIE_NAME.to_owned(), value.trim().to_owned());
+    cookie.host = Some(".bandcamp.com".to_owned());
+    cookie.path = Some("/".to_owned());
+    cookie
+}
+
+// The cookie Bandcamp recognizes a logged in session by, unless it expired before `now` (a unix
+// timestamp, in seconds)
+pub fn find_identity_cookie(
+    cookies: impl IntoIterator<Item = RawCookie>,
+    now: i64,
+) -> Option<RawCookie> {
+    merge_raw_cookies(cookies).into_iter().find(|cookie| {
+        cookie.name == IDENTITY_COOKIE_NAME
+            && expiration_seconds(cookie).is_none_or(|expires| expires > now)
+    })
+}
+
+// Reads either a JSON export or a Netscape cookies.txt file, telling them apart by their contents
 pub fn read_json_file(
     cookie_data: &str,
     request_url: &str,
@@ -112,13 +338,25 @@ pub fn read_json_file(
     let request_url = Url::parse(request_url)
         .map_err(|err| CookieJsonParsingError::InvalidUrlProvided(err.to_string()))?;
 
-    let cookies: Vec<RawCookie> = serde_json::from_str(cookie_data)?;
+    let (raw_cookies, mut problems) = parse_cookie_entries(cookie_data)?;
+
+    let mut cookies = Vec::new();
+    for (index, raw_cookie) in raw_cookies {
+        let name = raw_cookie.name.clone();
+        match cookie_store::Cookie::try_from_raw_cookie(&raw_cookie.into(), &request_url) {
+            Ok(cookie) => cookies.push(cookie),
+            Err(e) => problems.push(CookieEntryError {
+                index,
+                name: Some(name),
+                reason: e.to_string(),
+            }),
+        }
+    }
 
     Ok(cookie_store::CookieStore::from_cookies(
-        cookies
+        keep_readable(cookies, problems)?
             .into_iter()
-            .map(cookie::Cookie::from)
-            .map(|c| cookie_store::Cookie::try_from_raw_cookie(&c, &request_url)),
+            .map(Ok::<_, cookie_store::CookieError>),
         false,
     )?)
 }
@@ -143,7 +381,6 @@ mod test {
     }
 
     #[test_case("Not a json file :(")]
-    #[test_case("[{\"test\": \"not a proper cookie\"}]")]
     #[test_case("{\"hello\": \"world\"}")]
     pub fn test_read_json_invalid(invalid_cookie_data: &str) {
         let result = read_json_file(invalid_cookie_data, "https://bandcamp.com");
@@ -154,6 +391,97 @@ mod test {
         assert_matches!(err, CookieJsonParsingError::JsonParsingError(_));
     }
 
+    #[test]
+    pub fn test_read_json_all_entries_invalid() {
+        let result = read_json_file(
+            r#"[{"test": "not a proper cookie"}, {"name": "identity", "value": 5}]"#,
+            "https://bandcamp.com",
+        );
+
+        let problems = assert_matches!(
+            result.err(),
+            Some(CookieJsonParsingError::PartialFailures(problems)) => problems
+        );
+        assert_eq!(problems.len(), 2);
+        assert_eq!((problems[0].index, problems[0].name.as_deref()), (0, None));
+        assert_eq!(
+            (problems[1].index, problems[1].name.as_deref()),
+            (1, Some("identity"))
+        );
+        assert!(problems[1]
+            .to_string()
+            .starts_with("cookie #1 (\"identity\"): "));
+    }
+
+    #[test]
+    pub fn test_read_json_skips_invalid_entries() {
+        let cookie_data = r#"[
+            {"name": "broken"},
+            {"name": "identity", "value": "abc", "host": "https://.bandcamp.com/"},
+            {"name": "SID", "value": "def", "host": ".google.com"}
+        ]"#;
+
+        let cookie_store = read_json_file(cookie_data, "https://bandcamp.com").unwrap();
+        assert_eq!(cookie_store.iter_any().count(), 1);
+        assert_eq!(parse_raw_cookies(cookie_data).unwrap().len(), 2);
+    }
+
+    #[test_case("\u{feff}[{\"name\": \"identity\", \"value\": \"abc\", \"host\": \"https://.bandcamp.com/\"}]"; "bom")]
+    #[test_case("[\r\n  {\"name\": \"identity\", \"value\": \"abc\", \"host\": \"https://.bandcamp.com/\"}\r\n]\r\n"; "crlf")]
+    #[test_case("\u{feff}[\r\n{\"name\": \"identity\", \"value\": \"abc\"}\r\n]"; "bom and crlf")]
+    pub fn test_read_json_windows_encoding(cookie_data: &str) {
+        let cookie_store = read_json_file(cookie_data, "https://bandcamp.com").unwrap();
+        assert_eq!(cookie_store.iter_any().count(), 1);
+    }
+
+    const NETSCAPE_COOKIES: &str = "# Netscape HTTP Cookie File\r
+# https://curl.se/docs/http-cookies.html\r
+\r
+#HttpOnly_.bandcamp.com\tTRUE\t/\tTRUE\t1919434332\tidentity\tabc\r
+.bandcamp.com\tTRUE\t/\tFALSE\t0\tclient_id\tdef\r
+.google.com\tTRUE\t/\tFALSE\t1919434332\tSID\tghi\r
+";
+
+    #[test]
+    pub fn test_parse_netscape_cookies() {
+        let cookies = parse_raw_cookies(NETSCAPE_COOKIES).unwrap();
+
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(
+            (cookies[0].name.as_str(), cookies[0].value.as_str()),
+            ("identity", "abc")
+        );
+        assert_eq!(cookies[0].host.as_deref(), Some(".bandcamp.com"));
+        assert_eq!(cookies[0].http_only.as_deref(), Some("true"));
+        assert_eq!(cookies[0].send_for.as_deref(), Some("true"));
+        assert_eq!(cookies[0].expires.as_deref(), Some("1919434332"));
+        assert_eq!(cookies[1].http_only.as_deref(), Some("false"));
+        assert_eq!(cookies[1].expires, None);
+    }
+
+    #[test]
+    pub fn test_read_netscape_file() {
+        let cookie_store = read_json_file(NETSCAPE_COOKIES, "https://bandcamp.com").unwrap();
+        let mut names: Vec<_> = cookie_store.iter_any().map(|c| c.name()).collect();
+        names.sort_unstable();
+
+        assert_eq!(names, ["client_id", "identity"]);
+    }
+
+    #[test]
+    pub fn test_read_netscape_file_reports_broken_lines() {
+        let cookie_data = "# Netscape HTTP Cookie File\n.bandcamp.com\tTRUE\t/\n";
+
+        let problems = assert_matches!(
+            read_json_file(cookie_data, "https://bandcamp.com").err(),
+            Some(CookieJsonParsingError::PartialFailures(problems)) => problems
+        );
+        assert_eq!(
+            problems[0].to_string(),
+            "cookie #0: expected 7 tab separated fields, found 3"
+        );
+    }
+
     #[test]
     fn test_parse_same_site() {
         assert_eq!(
@@ -183,6 +511,124 @@ mod test {
         );
     }
 
+    #[test_case("1609459200"; "integer seconds")]
+    #[test_case("1609459200.123"; "float seconds")]
+    #[test_case(" 1609459200.9 "; "float seconds with whitespace")]
+    #[test_case("1609459200000"; "integer milliseconds")]
+    #[test_case("1609459200123.5"; "float milliseconds")]
+    #[test_case("1.6094592e9"; "exponent seconds")]
+    fn test_parse_expiration_units(expires: &str) {
+        let expected_date = OffsetDateTime::from_unix_timestamp(1_609_459_200).unwrap();
+        assert_eq!(
+            parse_expiration(Some(expires)),
+            Some(Expiration::DateTime(expected_date))
+        );
+    }
+
+    #[test]
+    fn test_read_json_numeric_expiration_date() {
+        let cookie_data = r#"[{"name": "identity", "value": "abc", "host": "https://.bandcamp.com/", "expirationDate": 1919434332.5}]"#;
+        let cookies: Vec<RawCookie> = serde_json::from_str(cookie_data).unwrap();
+
+        assert_eq!(cookies[0].expires.as_deref(), Some("1919434332.5"));
+        assert_eq!(
+            Cookie::from(cookies.into_iter().next().unwrap()).expires(),
+            parse_expiration(Some("1919434332"))
+        );
+    }
+
+    fn raw_cookie(name: &str, value: &str, host: &str, expires: Option<&str>) -> RawCookie {
+        let mut cookie = RawCookie::new(name.to_owned(), value.to_owned());
+        cookie.host = Some(host.to_owned());
+        cookie.expires = expires.map(str::to_owned);
+        cookie
+    }
+
+    #[test]
+    fn test_merge_raw_cookies_newest_expiration_wins() {
+        let merged = merge_raw_cookies([
+            raw_cookie(
+                "identity",
+                "stale",
+                "https://.bandcamp.com/",
+                Some("1600000000"),
+            ),
+            raw_cookie("session", "only", "https://.bandcamp.com/", None),
+            raw_cookie("identity", "live", ".bandcamp.com", Some("1900000000000")),
+            raw_cookie("identity", "session", "bandcamp.com", None),
+        ]);
+
+        let merged: Vec<_> = merged
+            .iter()
+            .map(|c| (c.name.as_str(), c.value.as_str()))
+            .collect();
+        assert_eq!(merged, [("identity", "live"), ("session", "only")]);
+    }
+
+    #[test]
+    fn test_merge_raw_cookies_skips_other_sites() {
+        let merged = merge_raw_cookies([
+            raw_cookie("identity", "abc", "https://.bandcamp.com/", None),
+            raw_cookie("client_id", "def", "https://artist.bandcamp.com/", None),
+            raw_cookie(
+                "identity",
+                "ghi",
+                "https://.notbandcamp.com/",
+                Some("1900000000"),
+            ),
+            raw_cookie("SID", "jkl", ".google.com", None),
+        ]);
+
+        let names: Vec<_> = merged.iter().map(|c| c.value.as_str()).collect();
+        assert_eq!(names, ["def", "abc"]);
+    }
+
+    #[test]
+    fn test_identity_cookie() {
+        let cookie_data = serde_json::to_string(&[identity_cookie(" 7%09abc \n")]).unwrap();
+        let cookie_store = read_json_file(&cookie_data, "https://bandcamp.com").unwrap();
+
+        let cookie = cookie_store
+            .get("bandcamp.com", "/", IDENTITY_COOKIE_NAME)
+            .unwrap();
+        assert_eq!(cookie.value(), "7%09abc");
+    }
+
+    #[test]
+    fn test_find_identity_cookie() {
+        let cookies = || {
+            [
+                raw_cookie("client_id", "abc", "https://.bandcamp.com/", None),
+                raw_cookie("identity", "def", ".bandcamp.com", Some("1700000000")),
+            ]
+        };
+
+        let identity = find_identity_cookie(cookies(), 1_600_000_000).unwrap();
+        assert_eq!(identity.value, "def");
+        assert!(find_identity_cookie(cookies(), 1_800_000_000).is_none());
+    }
+
+    #[test]
+    fn test_merged_cookies_round_trip() {
+        let merged = merge_raw_cookies(
+            parse_raw_cookies(
+                r#"[{"name": "identity", "value": "abc", "host": "https://.bandcamp.com/", "expirationDate": 1919434332.5}]"#,
+            )
+            .unwrap(),
+        );
+
+        let cookie_data = serde_json::to_string(&merged).unwrap();
+        let cookie_store = read_json_file(&cookie_data, "https://bandcamp.com").unwrap();
+        assert_eq!(cookie_store.iter_any().count(), 1);
+    }
+
+    #[test]
+    fn test_redact_value() {
+        assert_eq!(redact_value(""), "<redacted, 0 chars>");
+        assert_eq!(redact_value("secret"), "<redacted, 6 chars>");
+        assert!(!redact_value("7%7B%22id%22").contains("7B"));
+    }
+
     #[test]
     pub fn ourcookie_new_ok() {
         let cookie_data = RawCookie::new("name".into(), "value".into());
@@ -0,0 +1,608 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use http::{header, StatusCode};
+use reqwest::{Client, Response, Url};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    task::JoinSet,
+};
+
+use crate::{
+    controls::{Interruption, RunControl},
+    error::{DownloadError, DownloadSizeError},
+    progress::{NoProgress, ProgressReporter},
+};
+
+type ByteRange = (u64, u64);
+
+// A file to download, and the name it's saved as in the folder it's downloaded to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Download {
+    // what its progress is reported under, the release id for a release
+    pub release_id: String,
+    pub url: Url,
+    pub filename: String,
+}
+
+impl Download {
+    pub const fn new(release_id: String, url: Url, filename: String) -> Self {
+        Self {
+            release_id,
+            url,
+            filename,
+        }
+    }
+}
+
+// Fetches a file as `connections` byte ranges in parallel, each into its own ".part" segment file
+// named after it, and joins the segments into the destination once all of them completed.
+// Segments outlive a failed or interrupted download, and are resumed from where they stopped by
+// the next attempt, as long as the server still has the same version of the file. Servers that
+// don't answer range requests with partial content are reported as `RangesNotSupported`, so
+// callers can fall back to a plain download.
+#[derive(Clone)]
+pub struct ChunkedDownloader {
+    client: Client,
+    directory: PathBuf,
+    connections: usize,
+    threshold_bytes: u64,
+    progress: Arc<dyn ProgressReporter>,
+}
+
+impl ChunkedDownloader {
+    pub fn new(
+        client: Client,
+        directory: PathBuf,
+        connections: usize,
+        threshold_bytes: u64,
+    ) -> Self {
+        Self {
+            client,
+            directory,
+            connections,
+            threshold_bytes,
+            progress: Arc::new(NoProgress),
+        }
+    }
+
+    // Told about the bytes of each segment as they're written
+    #[must_use]
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressReporter>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    // Also with a single connection, for the sake of resuming
+    pub fn should_chunk(&self, expected_bytes: Option<u64>) -> bool {
+        expected_bytes.is_some_and(|bytes| bytes > self.threshold_bytes)
+    }
+
+    // Returns the size of the downloaded file
+    pub async fn download(
+        &self,
+        download: &Download,
+        destination: &Path,
+    ) -> Result<u64, DownloadError> {
+        let (url, filename) = (&download.url, download.filename.as_str());
+        let (total_bytes, validator) = self.probe(url).await?;
+        let ranges = split_ranges(total_bytes, self.connections);
+        let segment_paths: Vec<_> = ranges
+            .iter()
+            .map(|range| {
+                self.directory
+                    .join(segment_file_name(filename, total_bytes, *range))
+            })
+            .collect();
+
+        // segments of another version of the file, even of the same size, are started over
+        let validator_path = self
+            .directory
+            .join(validator_file_name(filename, total_bytes));
+        let previous_validator = tokio::fs::read_to_string(&validator_path).await.ok();
+        if previous_validator != validator {
+            for segment_path in &segment_paths {
+                let _ = tokio::fs::remove_file(segment_path).await;
+            }
+            match &validator {
+                Some(validator) => tokio::fs::write(&validator_path, validator).await?,
+                None => {
+                    let _ = tokio::fs::remove_file(&validator_path).await;
+                }
+            }
+        }
+
+        self.download_segments(download, &ranges, &segment_paths, validator.as_deref())
+            .await?;
+        if let Err(e) = assemble(&segment_paths, destination).await {
+            let _ = tokio::fs::remove_file(destination).await;
+            return Err(e);
+        }
+
+        for segment_path in &segment_paths {
+            let _ = tokio::fs::remove_file(segment_path).await;
+        }
+        let _ = tokio::fs::remove_file(&validator_path).await;
+        Ok(total_bytes)
+    }
+
+    // The length of the whole file, and what tells its version apart for If-Range
+    async fn probe(&self, url: &Url) -> Result<(u64, Option<String>), DownloadError> {
+        let response = self
+            .client
+            .get(url.clone())
+            .header(header::RANGE, "bytes=0-0")
+            .send()
+            .await?;
+        let response = check_partial_content(response)?;
+
+        let total_bytes = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_content_range_total)
+            .ok_or(DownloadError::RangesNotSupported)?;
+        Ok((total_bytes, range_validator(&response)))
+    }
+
+    async fn download_segments(
+        &self,
+        download: &Download,
+        ranges: &[ByteRange],
+        segment_paths: &[PathBuf],
+        validator: Option<&str>,
+    ) -> Result<(), DownloadError> {
+        let mut segment_tasks = JoinSet::new();
+        for (index, (range, segment_path)) in ranges.iter().zip(segment_paths).enumerate() {
+            let client = self.client.clone();
+            let download = download.clone();
+            let range = *range;
+            let segment_path = segment_path.clone();
+            let validator = validator.map(str::to_owned);
+            let progress = Arc::clone(&self.progress);
+
+            segment_tasks.spawn(async move {
+                download_segment(
+                    &client,
+                    &download,
+                    index,
+                    range,
+                    &segment_path,
+                    validator.as_deref(),
+                    progress.as_ref(),
+                )
+                .await
+            });
+        }
+
+        // returning early drops the set, which aborts the remaining segments
+        while let Some(result) = segment_tasks.join_next().await {
+            result.map_err(std::io::Error::other)??;
+        }
+
+        Ok(())
+    }
+}
+
+// Downloads a file in one piece, picking up after the bytes an interrupted attempt left in
+// `destination` when the server answers range requests. Returns the length the server announced
+// for the whole file, if it did.
+pub async fn download_file(
+    client: &Client,
+    download: &Download,
+    destination: &Path,
+    progress: &dyn ProgressReporter,
+) -> Result<Option<u64>, DownloadError> {
+    let received = tokio::fs::metadata(destination)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+    let mut request = client.get(download.url.clone());
+    if received > 0 {
+        request = request.header(header::RANGE, format!("bytes={received}-"));
+    }
+    let mut response = request.send().await?;
+
+    let content_range_total = response
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_content_range_total);
+    let (mut file, total_bytes) = match response.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            progress.bytes_downloaded(&download.release_id, received);
+            (
+                OpenOptions::new().append(true).open(destination).await?,
+                content_range_total,
+            )
+        }
+        // nothing is left past the bytes already there
+        StatusCode::RANGE_NOT_SATISFIABLE if content_range_total == Some(received) => {
+            progress.bytes_downloaded(&download.release_id, received);
+            return Ok(Some(received));
+        }
+        _ => {
+            response.error_for_status_ref()?;
+            (File::create(destination).await?, response.content_length())
+        }
+    };
+
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+        progress.bytes_downloaded(&download.release_id, chunk.len() as u64);
+    }
+    file.flush().await?;
+    Ok(total_bytes)
+}
+
+// Downloads all of them at once into `directory`, returning how each went along with it
+pub async fn download_files(
+    client: &Client,
+    directory: &Path,
+    downloads: Vec<Download>,
+    progress: &Arc<dyn ProgressReporter>,
+) -> Vec<(Download, Result<Option<u64>, DownloadError>)> {
+    download_files_interruptible(
+        client,
+        directory,
+        downloads,
+        &RunControl::default(),
+        progress,
+    )
+    .await
+}
+
+// Like `download_files`, where a skip gives up on the download that has been going the longest,
+// and quitting on all of them. Those that completed before are kept either way.
+pub async fn download_files_interruptible(
+    client: &Client,
+    directory: &Path,
+    downloads: Vec<Download>,
+    control: &RunControl,
+    progress: &Arc<dyn ProgressReporter>,
+) -> Vec<(Download, Result<Option<u64>, DownloadError>)> {
+    let mut tasks = JoinSet::new();
+    // in the order they started
+    let mut running = Vec::new();
+    for download in downloads {
+        let client = client.clone();
+        let destination = directory.join(&download.filename);
+        let progress = Arc::clone(progress);
+        let task = {
+            let download = download.clone();
+            tasks.spawn(async move {
+                download_file(&client, &download, &destination, progress.as_ref()).await
+            })
+        };
+        running.push((task, download));
+    }
+
+    let mut results = Vec::new();
+    loop {
+        tokio::select! {
+            joined = tasks.join_next_with_id() => match joined {
+                None => break,
+                // one given up on is already among the results
+                Some(Ok((id, result))) => {
+                    if let Some(index) = running.iter().position(|(task, _)| task.id() == id) {
+                        results.push((running.remove(index).1, result));
+                    }
+                }
+                // a download only panics on a bug, and is then missing from the results
+                Some(Err(e)) => running.retain(|(task, _)| task.id() != e.id()),
+            },
+            interruption = control.interrupted(), if !running.is_empty() => {
+                let given_up = match interruption {
+                    Interruption::Skip => 1,
+                    Interruption::Quit => running.len(),
+                };
+                for (task, download) in running.drain(..given_up) {
+                    task.abort();
+                    results.push((download, Err(DownloadError::Interrupted)));
+                }
+            }
+        }
+    }
+    results
+}
+
+fn check_partial_content(response: Response) -> Result<Response, DownloadError> {
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => Ok(response),
+        StatusCode::RANGE_NOT_SATISFIABLE => Err(DownloadError::RangesNotSupported),
+        _ => {
+            response.error_for_status_ref()?;
+            Err(DownloadError::RangesNotSupported)
+        }
+    }
+}
+
+// Named by the size of the whole file and the range, so a segment is only resumed by a download
+// of the same file split the same way
+fn segment_file_name(filename: &str, total_bytes: u64, (start, end): ByteRange) -> String {
+    format!("{filename}.{start}-{end}-of-{total_bytes}.part")
+}
+
+// The name of the file a segment is part of, for a name of segment_file_name
+pub fn segment_source(segment_file_name: &str) -> Option<&str> {
+    let (filename, range) = segment_file_name.strip_suffix(".part")?.rsplit_once('.')?;
+    let (bounds, total_bytes) = range.split_once("-of-")?;
+    let (start, end) = bounds.split_once('-')?;
+    [start, end, total_bytes]
+        .iter()
+        .all(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+        .then_some(filename)
+}
+
+// Named like a segment, so it's cleaned up along with them
+fn validator_file_name(filename: &str, total_bytes: u64) -> String {
+    format!("{filename}.validator-of-{total_bytes}.part")
+}
+
+// A strong ETag, or else the Last-Modified date, as a weak ETag can't be sent in If-Range
+fn range_validator(response: &Response) -> Option<String> {
+    let header_value = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    header_value(header::ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header_value(header::LAST_MODIFIED))
+        .map(str::to_owned)
+}
+
+// Picks up after the bytes a previous attempt left in the segment file, if any. With a
+// `validator`, those are only resumed while the file is still of the version they came from.
+async fn download_segment(
+    client: &Client,
+    download: &Download,
+    index: usize,
+    (start, end): ByteRange,
+    segment_path: &Path,
+    validator: Option<&str>,
+    progress: &dyn ProgressReporter,
+) -> Result<(), DownloadError> {
+    let url = &download.url;
+    let expected = end - start + 1;
+    let mut received = tokio::fs::metadata(segment_path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+    if received == expected {
+        progress.bytes_downloaded(&download.release_id, received);
+        return Ok(());
+    }
+    // longer than the range can only be a leftover from something else
+    let mut segment = if received > expected {
+        received = 0;
+        File::create(segment_path).await?
+    } else {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path)
+            .await?
+    };
+
+    let mut request = client
+        .get(url.clone())
+        .header(header::RANGE, format!("bytes={}-{end}", start + received));
+    if let Some(validator) = validator.filter(|_| received > 0) {
+        request = request.header(header::IF_RANGE, validator);
+    }
+    let mut response = request.send().await?;
+    // the file changed since, and came whole rather than the rest of the range, so the segment
+    // starts over
+    if received > 0 && response.status() == StatusCode::OK {
+        segment = File::create(segment_path).await?;
+        received = 0;
+        response = client
+            .get(url.clone())
+            .header(header::RANGE, format!("bytes={start}-{end}"))
+            .send()
+            .await?;
+    }
+    let mut response = check_partial_content(response)?;
+    progress.bytes_downloaded(&download.release_id, received);
+
+    while let Some(chunk) = response.chunk().await? {
+        segment.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        progress.bytes_downloaded(&download.release_id, chunk.len() as u64);
+    }
+    segment.flush().await?;
+
+    if received == expected {
+        Ok(())
+    } else {
+        Err(DownloadError::IncompleteChunk {
+            index,
+            received,
+            expected,
+        })
+    }
+}
+
+async fn assemble(segment_paths: &[PathBuf], destination: &Path) -> Result<(), DownloadError> {
+    let mut file = File::create(destination).await?;
+    for segment_path in segment_paths {
+        let mut segment = File::open(segment_path).await?;
+        tokio::io::copy(&mut segment, &mut file).await?;
+    }
+    file.flush().await?;
+
+    Ok(())
+}
+
+// Inclusive byte ranges, as used by the `Range` header
+fn split_ranges(total_bytes: u64, connections: usize) -> Vec<ByteRange> {
+    let chunk_bytes = total_bytes.div_ceil(connections.max(1) as u64).max(1);
+
+    (0..total_bytes)
+        .step_by(usize::try_from(chunk_bytes).unwrap_or(usize::MAX))
+        .map(|start| (start, (start + chunk_bytes).min(total_bytes) - 1))
+        .collect()
+}
+
+// "bytes 0-0/1234" -> 1234, unknown totals ("bytes 0-0/*") are rejected
+fn parse_content_range_total(content_range: &str) -> Option<u64> {
+    content_range
+        .strip_prefix("bytes ")?
+        .split_once('/')?
+        .1
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Bandcamp rounds its size hints ("98.2MB"), so only a download well short of one is suspicious
+const SIZE_HINT_TOLERANCE_PERCENT: u64 = 10;
+
+// A dropped connection can end a download early without an error, leaving a truncated file that
+// looks complete. The length the server announces is authoritative, Bandcamp's size hint is only
+// checked without it.
+pub const fn check_size(
+    received: u64,
+    content_length: Option<u64>,
+    size_hint: Option<u64>,
+) -> Result<(), DownloadSizeError> {
+    match (content_length, size_hint) {
+        (Some(expected), _) if received != expected => {
+            Err(DownloadSizeError::ContentLength { received, expected })
+        }
+        (None, Some(hint)) if received < hint / 100 * (100 - SIZE_HINT_TOLERANCE_PERCENT) => {
+            Err(DownloadSizeError::SizeHint { received, hint })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Whether a file is further from Bandcamp's size hint than its rounding, in either direction, so
+// it's no longer the download the hint describes
+pub const fn differs_from_size_hint(size: u64, size_hint: u64) -> bool {
+    let tolerance = size_hint / 100 * SIZE_HINT_TOLERANCE_PERCENT;
+    size < size_hint - tolerance || size > size_hint + tolerance
+}
+
+// Download links are signed for a limited time, and answer 403 or 410 once they expired
+pub fn is_expired_link_status(status: StatusCode) -> bool {
+    matches!(status, StatusCode::FORBIDDEN | StatusCode::GONE)
+}
+
+pub fn is_expired_link_error(error: &DownloadError) -> bool {
+    matches!(
+        error,
+        DownloadError::HttpRequestError(e) if e.status().is_some_and(is_expired_link_status)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    pub fn test_split_ranges() {
+        assert_eq!(split_ranges(10, 3), [(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(split_ranges(12, 4), [(0, 2), (3, 5), (6, 8), (9, 11)]);
+        assert_eq!(split_ranges(2, 4), [(0, 0), (1, 1)]);
+        assert_eq!(split_ranges(5, 1), [(0, 4)]);
+        assert_eq!(split_ranges(0, 4), []);
+    }
+
+    #[test_case("bytes 0-0/1234", Some(1234))]
+    #[test_case("bytes 0-0/*", None)]
+    #[test_case("bytes */1234", Some(1234))]
+    #[test_case("items 0-0/1234", None)]
+    pub fn test_parse_content_range_total(content_range: &str, total: Option<u64>) {
+        assert_eq!(parse_content_range_total(content_range), total);
+    }
+
+    #[test]
+    pub fn test_should_chunk() {
+        let downloader = ChunkedDownloader::new(Client::new(), PathBuf::new(), 4, 1_000);
+        assert!(downloader.should_chunk(Some(1_001)));
+        assert!(!downloader.should_chunk(Some(1_000)));
+        assert!(!downloader.should_chunk(None));
+
+        let single_connection = ChunkedDownloader::new(Client::new(), PathBuf::new(), 1, 1_000);
+        assert!(single_connection.should_chunk(Some(1_000_000)));
+    }
+
+    #[test_case(1_000, Some(1_000), None, None)]
+    #[test_case(999, Some(1_000), Some(999), Some(true))]
+    #[test_case(1_001, Some(1_000), None, Some(false))]
+    #[test_case(950, None, Some(1_000), None ; "within the rounding of the hint")]
+    #[test_case(500, None, Some(1_000), Some(true) ; "far short of the hint")]
+    #[test_case(500, Some(500), Some(1_000), None ; "announced length over the hint")]
+    #[test_case(500, None, None, None)]
+    pub fn test_check_size(
+        received: u64,
+        content_length: Option<u64>,
+        size_hint: Option<u64>,
+        truncated: Option<bool>,
+    ) {
+        let result = check_size(received, content_length, size_hint);
+        assert_eq!(result.err().map(|e| e.is_truncated()), truncated);
+    }
+
+    #[test_case(1_000, 1_000, false)]
+    #[test_case(950, 1_000, false ; "rounded down")]
+    #[test_case(1_050, 1_000, false ; "rounded up")]
+    #[test_case(800, 1_000, true ; "smaller")]
+    #[test_case(1_200, 1_000, true ; "larger")]
+    pub fn test_differs_from_size_hint(size: u64, size_hint: u64, differs: bool) {
+        assert_eq!(differs_from_size_hint(size, size_hint), differs);
+    }
+
+    #[test]
+    pub fn test_range_validator() {
+        let response = |headers: &[(&str, &str)]| {
+            let mut builder = http::Response::builder();
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            Response::from(builder.body("").unwrap())
+        };
+        let last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+        assert_eq!(
+            range_validator(&response(&[
+                ("etag", "\"33a64df5\""),
+                ("last-modified", last_modified)
+            ])),
+            Some("\"33a64df5\"".to_owned())
+        );
+        assert_eq!(
+            range_validator(&response(&[
+                ("etag", "W/\"33a64df5\""),
+                ("last-modified", last_modified)
+            ])),
+            Some(last_modified.to_owned())
+        );
+        assert_eq!(range_validator(&response(&[])), None);
+        assert_eq!(
+            segment_source(&validator_file_name("p1-flac.zip", 10)),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_segment_file_name() {
+        assert_eq!(
+            segment_file_name("p1-flac.zip", 10, (4, 7)),
+            "p1-flac.zip.4-7-of-10.part"
+        );
+        assert_eq!(
+            segment_source("p1-flac.zip.4-7-of-10.part"),
+            Some("p1-flac.zip")
+        );
+        assert_eq!(segment_source("p1-flac.zip.part"), None);
+        assert_eq!(segment_source("notes.4-x-of-10.part"), None);
+    }
+}
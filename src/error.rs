@@ -2,6 +2,8 @@
 
 use thiserror::Error;
 
+use crate::pinning::TlsPin;
+
 #[derive(Debug, Error)]
 pub enum CookieJsonParsingError {
     #[error("Invalid store url provided: {0}")]
@@ -12,6 +14,87 @@ pub enum CookieJsonParsingError {
 
     #[error("Json parsing error: {0}")]
     JsonParsingError(#[from] serde_json::Error),
+
+    #[error("None of the cookies could be read: {}", format_cookie_entry_errors(.0))]
+    PartialFailures(Vec<CookieEntryError>),
+}
+
+#[derive(Debug, Error)]
+#[error("cookie #{index}{}: {reason}", .name.as_ref().map(|name| format!(" (\"{name}\")")).unwrap_or_default())]
+pub struct CookieEntryError {
+    pub index: usize,
+    pub name: Option<String>,
+    pub reason: String,
+}
+
+fn format_cookie_entry_errors(errors: &[CookieEntryError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[derive(Debug, Error)]
+pub enum BrowserCookieError {
+    #[error("No browser profiles found")]
+    NoProfileDirectory,
+
+    #[error("No browser profile named \"{0}\"")]
+    ProfileNotFound(String),
+
+    #[error("No cookie database at {}", .0.display())]
+    NoCookieDatabase(std::path::PathBuf),
+
+    #[error("Couldn't get the key the browser's cookies are encrypted with from {0}")]
+    NoEncryptionKey(String),
+
+    #[error(
+        "The browser's cookies use app-bound encryption, which can't be read from outside it; \
+         export them with a cookie extension instead"
+    )]
+    AppBoundEncryption,
+
+    #[error("This build can't read browser cookies, it needs the \"browser-cookies\" feature")]
+    Unsupported,
+
+    #[cfg(feature = "browser-cookies")]
+    #[error("Cookie database error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum SessionStoreError {
+    #[error("No Bandcamp session saved in the OS keyring, run once with --save-session first")]
+    NotSaved,
+
+    #[error("This build can't use the OS keyring, it needs the \"keyring\" feature")]
+    Unsupported,
+
+    #[cfg(feature = "keyring")]
+    #[error("Keyring error: {0}")]
+    KeyringError(#[from] keyring::Error),
+
+    #[error("Json parsing error: {0}")]
+    JsonParsingError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigParsingError {
+    #[error("Toml parsing error: {0}")]
+    TomlParsingError(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum TlsPinningError {
+    #[error("Couldn't read the public key of the TLS certificate presented by {0}")]
+    InvalidCertificate(String),
+
+    #[error("TLS certificate presented by {host} doesn't match any configured pin (its public key is pinned as \"{found}\")")]
+    PinMismatch { host: String, found: TlsPin },
 }
 
 #[derive(Debug, Error)]
@@ -21,6 +104,12 @@ pub enum ContextCreationError {
 
     #[error("HTTP client creation error: {0}")]
     ClientCreationError(#[from] reqwest::Error),
+
+    #[error("TLS configuration error: {0}")]
+    TlsConfigError(#[from] rustls::Error),
+
+    #[error("Invalid Accept-Language value \"{0}\"")]
+    InvalidAcceptLanguage(String),
 }
 
 #[derive(Debug, Error)]
@@ -36,6 +125,21 @@ pub enum InformationRetrievalError {
 
     #[error("Data blob not found")]
     DataBlobNotFound,
+
+    #[error("Tralbum data not found")]
+    TralbumDataNotFound,
+
+    #[error("The item's page is no longer available")]
+    Unavailable,
+
+    #[error("Bandcamp didn't recognize the session, the cookies may have expired. Without one, only `list`, `export` and `wishlist` of the public page, `search`, and free wishlist downloads work")]
+    NotLoggedIn,
+
+    #[error("Bandcamp answered the session check with {0}")]
+    SessionCheckFailed(reqwest::StatusCode),
+
+    #[error("Collection retrieval error: {0}")]
+    CollectionRetrievalError(#[from] ReleaseRetrievalError),
 }
 
 #[derive(Debug, Error)]
@@ -48,6 +152,12 @@ pub enum ReleaseRetrievalError {
 
     #[error("Json parse error: {0}")]
     JsonParseError(#[from] serde_json::Error),
+
+    #[error("Server reported more items available but returned no token to fetch them with")]
+    MissingPaginationToken,
+
+    #[error("None of the supported fancollection API versions is available")]
+    NoAvailableApiVersion,
 }
 
 #[derive(Error, Debug)]
@@ -76,3 +186,125 @@ pub enum DigitalDownloadError {
     #[error("Download link in requested format not found")]
     RequestedFormatLinkNotFound,
 }
+
+#[derive(Debug, Error)]
+pub enum DownloadSizeError {
+    #[error("Received {received} of the {expected} bytes the server announced")]
+    ContentLength { received: u64, expected: u64 },
+
+    #[error("Received {received} bytes, far less than the {hint} bytes Bandcamp lists")]
+    SizeHint { received: u64, hint: u64 },
+}
+
+impl DownloadSizeError {
+    // What was received can be completed by resuming, rather than starting over
+    pub const fn is_truncated(&self) -> bool {
+        match self {
+            Self::ContentLength { received, expected } => *received < *expected,
+            Self::SizeHint { .. } => true,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("Server doesn't support range requests")]
+    RangesNotSupported,
+
+    #[error("Chunk {index} ended after {received} of {expected} bytes")]
+    IncompleteChunk {
+        index: usize,
+        received: u64,
+        expected: u64,
+    },
+
+    #[error("HTTP requesting error: {0}")]
+    HttpRequestError(#[from] reqwest::Error),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Skipped from the keyboard")]
+    Interrupted,
+}
+
+#[derive(Debug, Error)]
+pub enum ExtractionError {
+    #[error("Unrecognized archive format")]
+    UnknownArchive,
+
+    #[error("No extractor available for {0} archives")]
+    UnsupportedArchive(crate::archive::ArchiveKind),
+
+    #[error("Only {extracted} of the {expected} files in the archive were extracted")]
+    Incomplete { extracted: usize, expected: usize },
+
+    #[error("Zip archive error: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum PostProcessError {
+    #[error("Post-processor exited with {0}")]
+    Failed(String),
+
+    #[error("Json serialization error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum SelfUpdateError {
+    #[error("This build has no key to check releases against, so it can't update itself")]
+    NoSigningKey,
+
+    #[error("The release has no {0}")]
+    MissingAsset(String),
+
+    #[error("The release checksums don't list {0}")]
+    MissingChecksum(String),
+
+    #[error("The checksum of {0} doesn't match the release checksums")]
+    ChecksumMismatch(String),
+
+    #[error("The signature of the release checksums isn't valid")]
+    BadSignature,
+
+    #[error("The signed release checksums are those of {0}, not of the release they came with")]
+    VersionMismatch(String),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum OfflineQueueError {
+    #[error("Json deserialization error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum LoudnessError {
+    #[error("ffmpeg exited with {0}")]
+    Failed(String),
+
+    #[error("ffmpeg didn't report the loudness")]
+    NoSummary,
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ExcludeListError {
+    #[error("Invalid pattern on line {line}: {source}")]
+    InvalidPattern {
+        line: usize,
+        source: regex_lite::Error,
+    },
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+// What was measured for one sampled release: how long its download link took to resolve, how
+// large Bandcamp says it is, and how fast a first part of it came in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReleaseSample {
+    pub resolve_time: Duration,
+    pub expected_bytes: Option<u64>,
+    pub bytes_per_second: Option<f64>,
+}
+
+// The parts of a sync that bound how fast it goes
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunSettings {
+    pub resolve_concurrency: usize,
+    pub download_concurrency: usize,
+    // Requests allowed by the rate limit, per second
+    pub requests_per_second: f64,
+    // Requests made to resolve the link of one release: its page and the stat download
+    pub requests_per_release: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunEstimate {
+    pub releases: usize,
+    pub total_bytes: u64,
+    pub resolve_time: Duration,
+    // As if downloads in parallel each got the sampled throughput, so a best case on slow links
+    pub download_time: Duration,
+}
+
+impl RunEstimate {
+    // `None` without a single sample to go by
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn project(
+        pending: usize,
+        samples: &[ReleaseSample],
+        settings: &RunSettings,
+    ) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mean_resolve_seconds = samples
+            .iter()
+            .map(|sample| sample.resolve_time.as_secs_f64())
+            .sum::<f64>()
+            / samples.len() as f64;
+        let concurrent_resolve_seconds =
+            pending as f64 * mean_resolve_seconds / settings.resolve_concurrency.max(1) as f64;
+        let rate_limited_resolve_seconds = if settings.requests_per_second > 0.0 {
+            (pending * settings.requests_per_release) as f64 / settings.requests_per_second
+        } else {
+            0.0
+        };
+        let resolve_seconds = concurrent_resolve_seconds.max(rate_limited_resolve_seconds);
+
+        let sizes: Vec<_> = samples.iter().filter_map(|s| s.expected_bytes).collect();
+        let mean_bytes = if sizes.is_empty() {
+            0.0
+        } else {
+            sizes.iter().sum::<u64>() as f64 / sizes.len() as f64
+        };
+        let total_bytes = mean_bytes * pending as f64;
+
+        let throughputs: Vec<_> = samples.iter().filter_map(|s| s.bytes_per_second).collect();
+        let download_seconds = if throughputs.is_empty() || total_bytes == 0.0 {
+            0.0
+        } else {
+            let mean_throughput = throughputs.iter().sum::<f64>() / throughputs.len() as f64;
+            let parallel_downloads = settings.download_concurrency.clamp(1, pending.max(1));
+            total_bytes / (mean_throughput * parallel_downloads as f64)
+        };
+
+        Some(Self {
+            releases: pending,
+            total_bytes: total_bytes as u64,
+            resolve_time: Duration::from_secs_f64(resolve_seconds),
+            download_time: Duration::from_secs_f64(download_seconds),
+        })
+    }
+
+    // Downloads start as links come in, but the slower of the two ends up setting the pace
+    pub fn total_time(&self) -> Duration {
+        self.resolve_time.max(self.download_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> RunSettings {
+        RunSettings {
+            resolve_concurrency: 8,
+            download_concurrency: 4,
+            requests_per_second: 1.0,
+            requests_per_release: 2,
+        }
+    }
+
+    #[test]
+    pub fn test_project_without_samples() {
+        assert_eq!(RunEstimate::project(10, &[], &settings()), None);
+    }
+
+    #[test]
+    pub fn test_project() {
+        let samples = [
+            ReleaseSample {
+                resolve_time: Duration::from_secs(2),
+                expected_bytes: Some(300_000_000),
+                bytes_per_second: Some(1_000_000.0),
+            },
+            ReleaseSample {
+                resolve_time: Duration::from_secs(4),
+                expected_bytes: Some(100_000_000),
+                bytes_per_second: Some(3_000_000.0),
+            },
+        ];
+
+        let estimate = RunEstimate::project(40, &samples, &settings()).unwrap();
+        assert_eq!(estimate.total_bytes, 8_000_000_000);
+        // 80 requests at one per second outweigh 40 * 3s spread over 8 resolutions
+        assert_eq!(estimate.resolve_time, Duration::from_secs(80));
+        assert_eq!(estimate.download_time, Duration::from_secs(1000));
+        assert_eq!(estimate.total_time(), Duration::from_secs(1000));
+    }
+
+    #[test]
+    pub fn test_project_without_throughput() {
+        let samples = [ReleaseSample {
+            resolve_time: Duration::from_secs(16),
+            expected_bytes: Some(100_000_000),
+            bytes_per_second: None,
+        }];
+
+        let estimate = RunEstimate::project(4, &samples, &settings()).unwrap();
+        assert_eq!(estimate.total_bytes, 400_000_000);
+        assert_eq!(estimate.resolve_time, Duration::from_secs(8));
+        assert_eq!(estimate.download_time, Duration::ZERO);
+    }
+}
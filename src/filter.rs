@@ -0,0 +1,472 @@
+use std::collections::{HashMap, HashSet};
+
+use regex_lite::{Regex, RegexBuilder};
+use time::{Date, Month};
+
+use crate::{
+    api::{data::CollectionItem, SaleIdUrlMap},
+    cache,
+    error::ExcludeListError,
+    planner::{BoxedPredicate, Filter, Named},
+};
+
+// Subscription drops can add up to far more than the purchases, so they may be synced on their own
+// into this folder of each download folder, with their own cache and state
+pub const SUBSCRIPTIONS_FOLDER_NAME: &str = "subscriptions";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ItemKind {
+    Albums,
+    Tracks,
+}
+
+// Restricts a sync to the items whose artist and title match these patterns, before their download
+// pages are fetched
+#[derive(Default)]
+pub struct ItemFilter {
+    artist: Option<Filter<BoxedPredicate<CollectionItem>>>,
+    title: Option<Filter<BoxedPredicate<CollectionItem>>>,
+    // bought on or after this day
+    purchased_after: Option<Date>,
+    // bought before this day
+    purchased_before: Option<Date>,
+    only: Option<ItemKind>,
+    // only the subscription items when true, none of them when false
+    subscription_items: Option<bool>,
+    exclusions: ExcludeList,
+}
+
+// Items never to download, read from an exclude file with one per line: a sale id such as
+// "p199396767", or "artist: <pattern>" or "title: <pattern>" for every item matching it the way
+// --filter-artist and --filter-title do. Blank lines and lines starting with '#' are skipped.
+#[derive(Default)]
+pub struct ExcludeList {
+    // keyed like the download cache
+    sale_ids: HashSet<String>,
+    artists: Vec<Pattern>,
+    titles: Vec<Pattern>,
+}
+
+impl ExcludeList {
+    pub fn parse(exclude_data: &str) -> Result<Self, ExcludeListError> {
+        let mut exclusions = Self::default();
+        for (index, line) in exclude_data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let pattern = |pattern: &str| {
+                Pattern::parse(pattern.trim()).map_err(|source| ExcludeListError::InvalidPattern {
+                    line: index + 1,
+                    source,
+                })
+            };
+            if let Some(artist) = line.strip_prefix("artist:") {
+                exclusions.artists.push(pattern(artist)?);
+            } else if let Some(title) = line.strip_prefix("title:") {
+                exclusions.titles.push(pattern(title)?);
+            } else {
+                exclusions
+                    .sale_ids
+                    .insert(cache::normalize_release_id(line));
+            }
+        }
+        Ok(exclusions)
+    }
+
+    pub fn excludes(&self, sale_id: &str, item: Option<&CollectionItem>) -> bool {
+        self.sale_ids
+            .contains(&cache::normalize_release_id(sale_id))
+            || item.is_some_and(|item| {
+                self.artists
+                    .iter()
+                    .any(|artist| artist.is_match(&item.band_name))
+                    || self
+                        .titles
+                        .iter()
+                        .any(|title| title.is_match(&item.item_title))
+            })
+    }
+}
+
+// An artist or title to match, case insensitively. With a "glob:" prefix it's a glob the whole name
+// has to match, such as "glob:The *", and otherwise a regular expression, so a plain name matches
+// anywhere in it.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Self, regex_lite::Error> {
+        let regex = pattern
+            .strip_prefix("glob:")
+            .map_or_else(|| pattern.to_owned(), glob_regex);
+        RegexBuilder::new(&regex)
+            .case_insensitive(true)
+            .build()
+            .map(Self)
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.0.is_match(name)
+    }
+}
+
+// '*' stands for any run of characters, '?' for any one, and "[...]" for one of a set, or one
+// outside it when it starts with '!'. A '[' that isn't closed is taken as is.
+fn glob_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match (c, rest.split_once(']')) {
+            ('*', _) => regex.push_str(".*"),
+            ('?', _) => regex.push('.'),
+            ('[', Some((set, after))) if !set.is_empty() => {
+                rest = after;
+                regex.push('[');
+                let set = set.strip_prefix('!').map_or(set, |set| {
+                    regex.push('^');
+                    set
+                });
+                for c in set.chars() {
+                    if c == '-' {
+                        regex.push(c);
+                    } else {
+                        regex.push_str(&regex_lite::escape(&c.to_string()));
+                    }
+                }
+                regex.push(']');
+            }
+            (c, _) => regex.push_str(&regex_lite::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+// For validating patterns as arguments are parsed
+pub fn parse_pattern(pattern: &str) -> Result<String, String> {
+    Pattern::parse(pattern)
+        .map(|_| pattern.to_owned())
+        .map_err(|e| e.to_string())
+}
+
+// Dates are given as YYYY-MM-DD
+pub fn parse_date(date: &str) -> Result<Date, String> {
+    let invalid = || format!("\"{date}\" isn't a date in the YYYY-MM-DD format");
+    let parts: Vec<_> = date.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    let (Ok(year), Ok(month), Ok(day)) = (year.parse(), month.parse::<u8>(), day.parse()) else {
+        return Err(invalid());
+    };
+    let month = Month::try_from(month).map_err(|_| invalid())?;
+    Date::from_calendar_date(year, month, day).map_err(|_| invalid())
+}
+
+impl Named for CollectionItem {
+    fn artist(&self) -> &str {
+        &self.band_name
+    }
+
+    fn title(&self) -> &str {
+        &self.item_title
+    }
+}
+
+impl ItemFilter {
+    pub fn new(artist: Option<&str>, title: Option<&str>) -> Result<Self, regex_lite::Error> {
+        Ok(Self {
+            artist: artist.map(Pattern::parse).transpose()?.map(Filter::artist),
+            title: title.map(Pattern::parse).transpose()?.map(Filter::title),
+            ..Self::default()
+        })
+    }
+
+    #[must_use]
+    pub const fn purchased_between(mut self, after: Option<Date>, before: Option<Date>) -> Self {
+        self.purchased_after = after;
+        self.purchased_before = before;
+        self
+    }
+
+    #[must_use]
+    pub const fn only(mut self, kind: Option<ItemKind>) -> Self {
+        self.only = kind;
+        self
+    }
+
+    #[must_use]
+    pub const fn subscription_items(mut self, subscription_items: Option<bool>) -> Self {
+        self.subscription_items = subscription_items;
+        self
+    }
+
+    #[must_use]
+    pub fn excluding(mut self, exclusions: ExcludeList) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    pub const fn is_active(&self) -> bool {
+        self.artist.is_some()
+            || self.title.is_some()
+            || self.purchased_after.is_some()
+            || self.purchased_before.is_some()
+            || self.only.is_some()
+            || self.subscription_items.is_some()
+    }
+
+    // Items without a known purchase date are left out once filtering by one
+    fn purchased_in_range(&self, item: &CollectionItem) -> bool {
+        if self.purchased_after.is_none() && self.purchased_before.is_none() {
+            return true;
+        }
+        item.purchase_date().is_some_and(|purchased| {
+            self.purchased_after.is_none_or(|after| purchased >= after)
+                && self
+                    .purchased_before
+                    .is_none_or(|before| purchased < before)
+        })
+    }
+
+    pub fn matches(&self, item: &CollectionItem) -> bool {
+        self.artist
+            .as_ref()
+            .is_none_or(|artist| artist.matches(item))
+            && self.title.as_ref().is_none_or(|title| title.matches(item))
+            && self.purchased_in_range(item)
+            && self
+                .only
+                .is_none_or(|kind| item.is_track() == Some(kind == ItemKind::Tracks))
+            && self
+                .subscription_items
+                .is_none_or(|subscription_items| item.is_subscription_item == subscription_items)
+    }
+
+    // Releases missing from the listed items can't be matched, so they're left out once filtering.
+    // Excluded releases are left out either way.
+    pub fn apply(&self, releases: &SaleIdUrlMap, items: &[CollectionItem]) -> SaleIdUrlMap {
+        let items_by_sale_id: HashMap<_, _> = items
+            .iter()
+            .filter_map(|item| Some((item.sale_key()?, item)))
+            .collect();
+
+        releases
+            .iter()
+            .filter(|(sale_id, _)| {
+                let item = items_by_sale_id.get(*sale_id).copied();
+                (!self.is_active() || item.is_some_and(|item| self.matches(item)))
+                    && !self.exclusions.excludes(sale_id, item)
+            })
+            .map(|(sale_id, item_url)| (sale_id.clone(), item_url.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection_item(sale_item_id: i64, band_name: &str, item_title: &str) -> CollectionItem {
+        serde_json::from_value(serde_json::json!({
+            "sale_item_id": sale_item_id,
+            "sale_item_type": "p",
+            "band_name": band_name,
+            "item_title": item_title,
+        }))
+        .unwrap()
+    }
+
+    // The download urls of sale ids p1 up to p`count`
+    fn releases(count: i64) -> SaleIdUrlMap {
+        (1..=count)
+            .map(|id| {
+                (
+                    format!("p{id}"),
+                    format!("https://bandcamp.com/download?id={id}"),
+                )
+            })
+            .collect()
+    }
+
+    fn purchased_on(mut item: CollectionItem, purchased: &str) -> CollectionItem {
+        item.purchased = Some(purchased.to_owned());
+        item
+    }
+
+    #[test]
+    pub fn test_apply() {
+        let items = [
+            collection_item(1, "Anomalie", "Galerie"),
+            collection_item(2, "Anomalie", "Odyssée"),
+            collection_item(3, "Camellia", "Toxic Violet Cubes"),
+        ];
+        let releases = releases(4);
+
+        let filter = ItemFilter::new(Some("anomalie"), Some("^gal")).unwrap();
+        let filtered = filter.apply(&releases, &items);
+        assert_eq!(filtered.keys().collect::<Vec<_>>(), ["p1"]);
+
+        let filter = ItemFilter::new(Some("Anomalie|Camellia"), None).unwrap();
+        assert_eq!(filter.apply(&releases, &items).len(), 3);
+        let filter = ItemFilter::new(Some("glob:anomalie"), Some("glob:*e")).unwrap();
+        assert_eq!(filter.apply(&releases, &items).len(), 2);
+        assert_eq!(ItemFilter::default().apply(&releases, &items).len(), 4);
+    }
+
+    #[test]
+    pub fn test_purchased_between() {
+        let items = [
+            purchased_on(
+                collection_item(1, "Anomalie", "Galerie"),
+                "31 Dec 2023 23:00:00 GMT",
+            ),
+            purchased_on(
+                collection_item(2, "Anomalie", "Odyssée"),
+                "01 Jan 2024 10:00:00 GMT",
+            ),
+            collection_item(3, "Camellia", "Toxic Violet Cubes"),
+        ];
+        let releases = releases(3);
+
+        let filter = ItemFilter::default().purchased_between(parse_date("2024-01-01").ok(), None);
+        assert_eq!(
+            filter.apply(&releases, &items).keys().collect::<Vec<_>>(),
+            ["p2"]
+        );
+        let filter = ItemFilter::default().purchased_between(None, parse_date("2024-01-01").ok());
+        assert_eq!(
+            filter.apply(&releases, &items).keys().collect::<Vec<_>>(),
+            ["p1"]
+        );
+    }
+
+    #[test]
+    pub fn test_only() {
+        let item = |sale_item_id: i64, tralbum_type: Option<&str>, item_type: &str| {
+            let mut item = collection_item(sale_item_id, "Anomalie", "Galerie");
+            item.tralbum_type = tralbum_type.map(str::to_owned);
+            item.item_type = Some(item_type.to_owned());
+            item
+        };
+        let items = [
+            item(1, Some("a"), "album"),
+            item(2, Some("t"), "track"),
+            item(3, Some("a"), "package"),
+            item(4, None, "track"),
+        ];
+        let releases = releases(4);
+
+        let mut albums: Vec<_> = ItemFilter::default()
+            .only(Some(ItemKind::Albums))
+            .apply(&releases, &items)
+            .into_keys()
+            .collect();
+        albums.sort();
+        assert_eq!(albums, ["p1", "p3"]);
+
+        let mut tracks: Vec<_> = ItemFilter::default()
+            .only(Some(ItemKind::Tracks))
+            .apply(&releases, &items)
+            .into_keys()
+            .collect();
+        tracks.sort();
+        assert_eq!(tracks, ["p2", "p4"]);
+    }
+
+    #[test]
+    pub fn test_subscription_items() {
+        let mut drop = collection_item(2, "Anomalie", "Monthly Drop");
+        drop.is_subscription_item = true;
+        let items = [collection_item(1, "Anomalie", "Galerie"), drop];
+        let releases = releases(2);
+
+        let only = ItemFilter::default().subscription_items(Some(true));
+        assert_eq!(
+            only.apply(&releases, &items).keys().collect::<Vec<_>>(),
+            ["p2"]
+        );
+        let excluded = ItemFilter::default().subscription_items(Some(false));
+        assert_eq!(
+            excluded.apply(&releases, &items).keys().collect::<Vec<_>>(),
+            ["p1"]
+        );
+    }
+
+    #[test]
+    pub fn test_exclusions() {
+        let exclusions = ExcludeList::parse(
+            "# too large to keep locally\n\
+             p1\n\
+             \n\
+             2\n\
+             title: sample pack\n",
+        )
+        .unwrap();
+        let items = [
+            collection_item(1, "Anomalie", "Galerie"),
+            collection_item(2, "Anomalie", "Odyssée"),
+            collection_item(3, "Camellia", "Sample Pack Vol. 1"),
+            collection_item(4, "Camellia", "Toxic Violet Cubes"),
+        ];
+        let releases = releases(5);
+
+        let mut kept: Vec<_> = ItemFilter::default()
+            .excluding(exclusions)
+            .apply(&releases, &items)
+            .into_keys()
+            .collect();
+        kept.sort();
+        assert_eq!(kept, ["p4", "p5"]);
+
+        assert!(matches!(
+            ExcludeList::parse("p1\nartist: (oops"),
+            Err(ExcludeListError::InvalidPattern { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    pub fn test_parse_date() {
+        assert_eq!(
+            parse_date("2024-02-29"),
+            Ok(Date::from_calendar_date(2024, Month::February, 29).unwrap())
+        );
+        for date in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-01",
+            "01/01/2024",
+            "yesterday",
+        ] {
+            assert!(parse_date(date).is_err(), "{date} was accepted");
+        }
+    }
+
+    #[test]
+    pub fn test_parse_pattern() {
+        assert_eq!(parse_pattern("^Anomalie$"), Ok("^Anomalie$".to_owned()));
+        assert!(parse_pattern("(unclosed").is_err());
+    }
+
+    #[test]
+    pub fn test_glob_pattern() {
+        let pattern = Pattern::parse("glob:the *").unwrap();
+        assert!(pattern.is_match("The Midnight"));
+        assert!(!pattern.is_match("Meet the Midnight"));
+
+        let pattern = Pattern::parse("glob:Vol. [1-3]?").unwrap();
+        assert!(pattern.is_match("vol. 2b"));
+        assert!(!pattern.is_match("Vol. 42"));
+        assert!(!pattern.is_match("Volt 2b"));
+
+        let pattern = Pattern::parse("glob:[!a]*").unwrap();
+        assert!(pattern.is_match("Camellia"));
+        assert!(!pattern.is_match("Anomalie"));
+
+        assert!(Pattern::parse("glob:[Live").unwrap().is_match("[live"));
+        assert!(Pattern::parse("glob:(unclosed").is_ok());
+    }
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use time::{Date, Duration, Month, Weekday};
+
+// How close the next Bandcamp Friday has to be for new release alerts to mention it
+const NEAR_DAYS: i64 = 7;
+
+// Bandcamp waives its share of sales on the days it announces, which aren't published anywhere
+// machine readable. The ones given in the config are taken as they are, and past those, every
+// first Friday of a month is taken to be one, as they've mostly been.
+pub fn next_bandcamp_friday(today: Date, announced: &[Date]) -> Date {
+    if let Some(announced) = announced.iter().filter(|date| **date >= today).min() {
+        return *announced;
+    }
+
+    let this_month = first_friday(today.year(), today.month());
+    if this_month >= today {
+        return this_month;
+    }
+
+    match today.month() {
+        Month::December => first_friday(today.year() + 1, Month::January),
+        month => first_friday(today.year(), month.next()),
+    }
+}
+
+fn first_friday(year: i32, month: Month) -> Date {
+    let first = Date::from_calendar_date(year, month, 1).expect("every month has a first day");
+    let days_until_friday = (7 + Weekday::Friday.number_days_from_monday()
+        - first.weekday().number_days_from_monday())
+        % 7;
+    first + Duration::days(i64::from(days_until_friday))
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FridayOutlook {
+    // e.g. "2021-02-05"
+    pub date: String,
+    pub days_until: i64,
+    pub near: bool,
+}
+
+impl FridayOutlook {
+    pub fn from_today(today: Date, announced: &[Date]) -> Self {
+        let date = next_bandcamp_friday(today, announced);
+        let days_until = (date - today).whole_days();
+        Self {
+            date: date.to_string(),
+            days_until,
+            near: days_until <= NEAR_DAYS,
+        }
+    }
+
+    // A line for new release alerts, when it's worth holding off on a purchase
+    pub fn describe(&self) -> Option<String> {
+        match self.days_until {
+            0 => Some("Today is Bandcamp Friday".to_owned()),
+            _ if self.near => Some(format!(
+                "Bandcamp Friday is in {} day(s), on {}",
+                self.days_until, self.date
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn date(year: i32, month: u8, day: u8) -> Date {
+        Date::from_calendar_date(year, Month::try_from(month).unwrap(), day).unwrap()
+    }
+
+    #[test_case(date(2021, 2, 1), date(2021, 2, 5); "later this month")]
+    #[test_case(date(2021, 2, 5), date(2021, 2, 5); "today")]
+    #[test_case(date(2021, 2, 6), date(2021, 3, 5); "next month")]
+    #[test_case(date(2021, 1, 1), date(2021, 1, 1); "month starting on a friday")]
+    #[test_case(date(2021, 12, 20), date(2022, 1, 7); "next year")]
+    pub fn test_next_bandcamp_friday(today: Date, expected: Date) {
+        assert_eq!(next_bandcamp_friday(today, &[]), expected);
+    }
+
+    #[test_case(date(2021, 2, 1), date(2021, 2, 12); "announced")]
+    #[test_case(date(2021, 2, 13), date(2021, 3, 5); "past the announced ones")]
+    pub fn test_next_announced_bandcamp_friday(today: Date, expected: Date) {
+        let announced = [date(2021, 1, 8), date(2021, 2, 12)];
+        assert_eq!(next_bandcamp_friday(today, &announced), expected);
+    }
+
+    #[test]
+    pub fn test_describe() {
+        assert_eq!(
+            FridayOutlook::from_today(date(2021, 2, 5), &[]).describe(),
+            Some("Today is Bandcamp Friday".to_owned())
+        );
+        assert_eq!(
+            FridayOutlook::from_today(date(2021, 2, 1), &[]).describe(),
+            Some("Bandcamp Friday is in 4 day(s), on 2021-02-05".to_owned())
+        );
+        assert_eq!(
+            FridayOutlook::from_today(date(2021, 2, 10), &[]).describe(),
+            None
+        );
+    }
+}
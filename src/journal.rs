@@ -0,0 +1,218 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    models::{self, Versioned},
+    timestamp,
+};
+
+pub const JOURNAL_FILE_NAME: &str = "journal.jsonl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Discovered,
+    Downloaded,
+    Skipped,
+    Failed,
+    Pruned,
+    // hidden from the collection page, or listed on it again, which doesn't change its sale id
+    Hidden,
+    Unhidden,
+}
+
+impl EventKind {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Discovered => "discovered",
+            Self::Downloaded => "downloaded",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+            Self::Pruned => "pruned",
+            Self::Hidden => "hidden",
+            Self::Unhidden => "unhidden",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct JournalEvent {
+    // unix timestamp, in seconds, recorded as RFC 3339 in UTC. Older journals have the number.
+    #[serde(with = "timestamp::rfc3339")]
+    #[schemars(with = "String", extend("format" = "date-time"))]
+    pub timestamp: i64,
+    pub item: String,
+    pub event: EventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl JournalEvent {
+    pub fn new(item: &str, event: EventKind, detail: Option<String>) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+            item: item.to_owned(),
+            event,
+            detail,
+        }
+    }
+
+    pub fn timestamp(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::from_unix_timestamp(self.timestamp).ok()
+    }
+}
+
+// Each line carries the version, as lines of several versions end up in the same journal
+impl Versioned for JournalEvent {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+pub fn serialize_event(event: &JournalEvent) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&models::versioned_value(event)?)
+}
+
+// Append-only record of what syncs decided about each item, one JSON event per line
+pub struct Journal {
+    file: Option<Mutex<File>>,
+}
+
+impl Journal {
+    pub fn open(journal_path: &Path) -> io::Result<Self> {
+        if let Some(state_dir) = journal_path.parent() {
+            std::fs::create_dir_all(state_dir)?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(journal_path)?;
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    // Records nothing, for runs that don't change anything such as dry runs
+    pub const fn disabled() -> Self {
+        Self { file: None }
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    // The journal is only there for looking back, so failing to write to it doesn't stop a sync
+    pub fn record(&self, item: &str, event: EventKind, detail: Option<String>) {
+        let Some(file) = &self.file else {
+            return;
+        };
+
+        let result = serialize_event(&JournalEvent::new(item, event, detail))
+            .map_err(io::Error::from)
+            .and_then(|line| {
+                let mut file = file
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                // a single write per line, so lines from concurrent runs don't interleave
+                file.write_all(format!("{line}\n").as_bytes())
+            });
+        if let Err(e) = result {
+            eprintln!("Failed writing to the journal: {e}");
+        }
+    }
+}
+
+// A run interrupted mid-write leaves a truncated last line, which is ignored
+pub fn read_journal(journal_data: &str) -> Result<Vec<JournalEvent>, serde_json::Error> {
+    let lines: Vec<_> = journal_data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+
+    let mut events = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match models::read_versioned(line) {
+            Ok(event) => events.push(event),
+            Err(e) if index + 1 == lines.len() && e.is_eof() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_read_journal() {
+        let events = read_journal(concat!(
+            r#"{"timestamp":1609459200,"item":"p199396767","event":"discovered","detail":"\"Galerie\" by Anomalie"}"#,
+            "\n\n",
+            r#"{"timestamp":1609459260,"item":"p199396767","event":"failed","detail":"HTTP status client error (403 Forbidden)"}"#,
+            "\n",
+            r#"{"timestamp":1609459320,"item":"p199396767","event":"downloaded"}"#,
+            "\n",
+        ))
+        .unwrap();
+
+        let kinds: Vec<_> = events.iter().map(|e| e.event).collect();
+        assert_eq!(
+            kinds,
+            [
+                EventKind::Discovered,
+                EventKind::Failed,
+                EventKind::Downloaded
+            ]
+        );
+        assert_eq!(events[2].detail, None);
+        assert_eq!(
+            events[0].timestamp(),
+            OffsetDateTime::from_unix_timestamp(1_609_459_200).ok()
+        );
+    }
+
+    #[test]
+    pub fn test_read_journal_truncated_last_line() {
+        let events = read_journal(concat!(
+            r#"{"timestamp":1609459200,"item":"p1","event":"skipped","detail":"time budget"}"#,
+            "\n",
+            r#"{"timestamp":1609459260,"item":"p2","eve"#,
+        ))
+        .unwrap();
+        assert_eq!(events.len(), 1);
+
+        assert!(read_journal(concat!(
+            r#"{"timestamp":1609459260,"item":"p2","eve"#,
+            "\n",
+            r#"{"timestamp":1609459200,"item":"p1","event":"skipped"}"#,
+        ))
+        .is_err());
+    }
+
+    #[test]
+    pub fn test_serialized_event() {
+        let event = JournalEvent {
+            timestamp: 1_609_459_200,
+            item: "p1".to_owned(),
+            event: EventKind::Pruned,
+            detail: None,
+        };
+        assert_eq!(
+            serialize_event(&event).unwrap(),
+            r#"{"event":"pruned","item":"p1","schema_version":1,"timestamp":"2021-01-01T00:00:00Z"}"#
+        );
+        // as recorded before timestamps were RFC 3339
+        assert_eq!(
+            read_journal(r#"{"timestamp":1609459200,"item":"p1","event":"pruned"}"#).unwrap(),
+            [event]
+        );
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    api::data::{DigitalItem, DownloadFormat},
+    template::{NameTemplate, TemplateFields},
+};
+
+pub fn release_file_name(sale_id: &str, format: DownloadFormat) -> String {
+    format!("{sale_id}-{format}.zip")
+}
+
+// Whether `file_name` could be one of release_file_name
+pub fn is_release_file_name(file_name: &str) -> bool {
+    let Some(stem) = file_name.strip_suffix(".zip") else {
+        return false;
+    };
+    DownloadFormat::value_variants().iter().any(|format| {
+        stem.strip_suffix(&format!("-{format}"))
+            .is_some_and(|sale_id| {
+                !sale_id.is_empty() && sale_id.bytes().all(|b| b.is_ascii_alphanumeric())
+            })
+    })
+}
+
+// Where a release is saved within a download folder: by its --path-template, whose "/" separate
+// folders, or else flat under its release_file_name
+pub fn release_path(
+    template: Option<&NameTemplate>,
+    sale_id: &str,
+    item: &DigitalItem,
+    format: DownloadFormat,
+    ascii: bool,
+) -> PathBuf {
+    let Some(template) = template else {
+        return PathBuf::from(release_file_name(sale_id, format));
+    };
+
+    let fields = TemplateFields {
+        artist: &item.artist,
+        title: &item.title,
+        year: item.release_year(),
+        release_date: item.release_date(),
+        item_type: &item.item_type,
+        sale_id,
+    };
+    render_release_path(template, &fields, ascii)
+}
+
+// The path a --path-template gives a release, from whatever is known of it
+pub fn render_release_path(
+    template: &NameTemplate,
+    fields: &TemplateFields,
+    ascii: bool,
+) -> PathBuf {
+    let sale_id = fields.sale_id;
+    let rendered = template.render_with(fields, |value| sanitize_path_component(&value, ascii));
+    let mut path: PathBuf = rendered
+        .split('/')
+        .map(|component| sanitize_path_component(component, ascii))
+        // which is also what's left of "." and ".."
+        .filter(|component| !component.is_empty())
+        .collect();
+    // a template rendering to nothing, such as "{year}" for a release without one
+    if path.as_os_str().is_empty() {
+        path.push(sale_id);
+    }
+    path.as_mut_os_string().push(".zip");
+    path
+}
+
+// The path of each planned release, by its id. A release whose path is that of another release of
+// the plan, or of one already downloaded in `taken`, gets its sale id added to the name instead,
+// e.g. "Anomalie/Galerie [p199396767].zip", so it can't overwrite the other. Paths are compared
+// case insensitively, as on Windows and macOS.
+pub fn assign_release_paths(
+    planned: impl IntoIterator<Item = (String, PathBuf)>,
+    taken: impl IntoIterator<Item = (String, PathBuf)>,
+) -> HashMap<String, PathBuf> {
+    let key = |path: &Path| path.to_string_lossy().to_lowercase();
+    let mut claimed: HashMap<String, String> = taken
+        .into_iter()
+        .map(|(release_id, path)| (key(&path), release_id))
+        .collect();
+
+    planned
+        .into_iter()
+        .map(|(release_id, path)| {
+            let path = match claimed.get(&key(&path)) {
+                Some(other) if *other != release_id => with_sale_id(&path, &release_id),
+                _ => path,
+            };
+            claimed.insert(key(&path), release_id.clone());
+            (release_id, path)
+        })
+        .collect()
+}
+
+// "Anomalie/Galerie.zip" to "Anomalie/Galerie [p199396767].zip"
+fn with_sale_id(path: &Path, sale_id: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!("{stem} [{sale_id}]");
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+}
+
+// Names Windows keeps for devices, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// A file or folder name that can be written on Windows as well as elsewhere: characters NTFS
+// rejects become "_", trailing dots and spaces are dropped, and device names such as "Aux" get a
+// leading "_". With `ascii`, non-ASCII characters are transliterated ("Odyssée" to "Odyssee") for
+// filesystems and players that can't handle them.
+pub fn sanitize_path_component(name: &str, ascii: bool) -> String {
+    let name = if ascii {
+        deunicode::deunicode(name)
+    } else {
+        name.to_owned()
+    };
+
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    let sanitized = sanitized
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_start();
+    let stem = sanitized.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        format!("_{sanitized}")
+    } else {
+        sanitized.to_owned()
+    }
+}
+
+// For the paths of archive entries: each component sanitized, and `None` for those that would
+// leave the folder they're extracted to, or have no name left
+pub fn sanitize_relative_path(path: &Path, ascii: bool) -> Option<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = sanitize_path_component(&name.to_string_lossy(), ascii);
+                if !name.is_empty() {
+                    sanitized.push(name);
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (!sanitized.as_os_str().is_empty()).then_some(sanitized)
+}
+
+// Where --extract unpacks an album, relative to its archive: "<artist>/<album>"
+pub fn release_folder(artist: &str, title: &str, ascii: bool) -> PathBuf {
+    Path::new(&sanitize_path_component(artist, ascii)).join(sanitize_path_component(title, ascii))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LayoutProblem {
+    // a file from outside this run, which would be overwritten
+    Exists,
+    // another release of this run, by its id. Compared case insensitively, as on Windows and macOS
+    CollidesWith(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PlannedFile {
+    pub release_id: String,
+    pub path: PathBuf,
+    pub problem: Option<LayoutProblem>,
+}
+
+// Looks for trouble in where a run would put its files, before anything is downloaded
+pub fn check_layout(
+    files: impl IntoIterator<Item = (String, PathBuf)>,
+    exists: impl Fn(&Path) -> bool,
+) -> Vec<PlannedFile> {
+    let mut claimed: HashMap<String, String> = HashMap::new();
+    files
+        .into_iter()
+        .map(|(release_id, path)| {
+            let key = path.to_string_lossy().to_lowercase();
+            let problem = match claimed.get(&key) {
+                Some(other) => Some(LayoutProblem::CollidesWith(other.clone())),
+                None if exists(&path) => Some(LayoutProblem::Exists),
+                None => None,
+            };
+            claimed.entry(key).or_insert_with(|| release_id.clone());
+
+            PlannedFile {
+                release_id,
+                path,
+                problem,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    pub fn test_release_file_name() {
+        assert_eq!(
+            release_file_name("p199396767", DownloadFormat::Flac),
+            "p199396767-(flac).zip"
+        );
+    }
+
+    #[test_case("p199396767-(flac).zip", true)]
+    #[test_case("b42-(mp3-320).zip", true)]
+    #[test_case("p199396767-(ogg).zip", false)]
+    #[test_case("-(flac).zip", false)]
+    #[test_case("my mix-(flac).zip", false)]
+    #[test_case("p199396767-(flac).zip.part", false)]
+    pub fn test_is_release_file_name(file_name: &str, expected: bool) {
+        assert_eq!(is_release_file_name(file_name), expected);
+    }
+
+    #[test]
+    pub fn test_release_path() {
+        let item: DigitalItem = serde_json::from_value(serde_json::json!({
+            "package_release_date": "01 Apr 2021 00:00:00 GMT",
+            "title": "Galerie: Live",
+            "artist": "AC/DC",
+            "download_type": "a",
+            "download_type_str": "album",
+            "item_type": "album",
+            "art_id": 0,
+        }))
+        .unwrap();
+        let path = |template: &str| {
+            let template: NameTemplate = template.parse().unwrap();
+            release_path(Some(&template), "p1", &item, DownloadFormat::Flac, false)
+        };
+
+        assert_eq!(
+            path("{artist}/{year} - {title}"),
+            Path::new("AC_DC").join("2021 - Galerie_ Live.zip")
+        );
+        assert_eq!(
+            path("../{item_type}//{sale_id}"),
+            Path::new("album").join("p1.zip")
+        );
+        assert_eq!(path("/"), Path::new("p1.zip"));
+        assert_eq!(
+            release_path(None, "p1", &item, DownloadFormat::Flac, false),
+            Path::new("p1-(flac).zip")
+        );
+    }
+
+    #[test]
+    pub fn test_assign_release_paths() {
+        let paths = assign_release_paths(
+            [
+                ("p1".to_owned(), PathBuf::from("Anomalie/Galerie.zip")),
+                ("p2".to_owned(), PathBuf::from("anomalie/galerie.zip")),
+                ("p3".to_owned(), PathBuf::from("Anomalie/Velours.zip")),
+                ("p4".to_owned(), PathBuf::from("Anomalie/Odyssée.zip")),
+            ],
+            [
+                ("p3".to_owned(), PathBuf::from("Anomalie/Velours.zip")),
+                ("p5".to_owned(), PathBuf::from("Anomalie/Odyssée.zip")),
+            ],
+        );
+
+        assert_eq!(paths["p1"], Path::new("Anomalie/Galerie.zip"));
+        assert_eq!(paths["p2"], Path::new("anomalie/galerie [p2].zip"));
+        // downloaded again, over its own file
+        assert_eq!(paths["p3"], Path::new("Anomalie/Velours.zip"));
+        assert_eq!(paths["p4"], Path::new("Anomalie/Odyssée [p4].zip"));
+    }
+
+    #[test_case("Galerie: Live?", "Galerie_ Live_"; "invalid characters")]
+    #[test_case("<Untitled> *|", "_Untitled_ __"; "more invalid characters")]
+    #[test_case("Vol. 2. . ", "Vol. 2"; "trailing dots and spaces")]
+    #[test_case("  Tab\tbed", "Tab_bed"; "control characters")]
+    #[test_case("Aux", "_Aux"; "device name")]
+    #[test_case("con.flac", "_con.flac"; "device name with extension")]
+    #[test_case("Console", "Console"; "device name prefix")]
+    #[test_case("...", ""; "only dots")]
+    pub fn test_sanitize_path_component(name: &str, expected: &str) {
+        assert_eq!(sanitize_path_component(name, false), expected);
+    }
+
+    #[test]
+    pub fn test_sanitize_relative_path() {
+        assert_eq!(
+            sanitize_relative_path(Path::new("CD 1/01 Odyssée: Part I.flac"), true),
+            Some(Path::new("CD 1").join("01 Odyssee_ Part I.flac"))
+        );
+        assert_eq!(
+            sanitize_relative_path(Path::new("./scans/./cover?.jpg"), false),
+            Some(Path::new("scans").join("cover_.jpg"))
+        );
+        assert_eq!(
+            sanitize_relative_path(Path::new("../evil.flac"), false),
+            None
+        );
+        assert_eq!(
+            sanitize_relative_path(Path::new("/etc/passwd"), false),
+            None
+        );
+        assert_eq!(sanitize_relative_path(Path::new("..."), false), None);
+    }
+
+    #[test]
+    pub fn test_release_folder() {
+        assert_eq!(
+            release_folder("AC/DC", "Galerie: Live.", false),
+            Path::new("AC_DC").join("Galerie_ Live")
+        );
+        assert_eq!(
+            release_folder("かめりあ", "Odyssée", true),
+            Path::new("kameria").join("Odyssee")
+        );
+    }
+
+    #[test]
+    pub fn test_check_layout() {
+        let files = [
+            ("p1", "/music/p1-flac.zip"),
+            ("p2", "/music/p2-flac.zip"),
+            ("p3", "/music/P1-FLAC.zip"),
+        ]
+        .map(|(release_id, path)| (release_id.to_owned(), PathBuf::from(path)));
+        let layout = check_layout(files, |path| path == Path::new("/music/p2-flac.zip"));
+
+        let problems: Vec<_> = layout.iter().map(|file| file.problem.as_ref()).collect();
+        assert_eq!(
+            problems,
+            [
+                None,
+                Some(&LayoutProblem::Exists),
+                Some(&LayoutProblem::CollidesWith("p1".to_owned()))
+            ]
+        );
+    }
+}
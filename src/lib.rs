@@ -0,0 +1,58 @@
+#![deny(clippy::all)]
+#![deny(clippy::pedantic)]
+#![deny(clippy::nursery)]
+#![deny(clippy::cargo)]
+// #![deny(missing_docs)]
+#![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::module_name_repetitions)]
+#![allow(clippy::items_after_statements)]
+#![cfg_attr(test, allow(clippy::manual_string_new, clippy::unreadable_literal))]
+#![allow(clippy::missing_errors_doc)]
+#![allow(clippy::missing_panics_doc)]
+#![allow(clippy::must_use_candidate)]
+
+pub mod api;
+pub mod archive;
+pub mod backpressure;
+pub mod bands;
+pub mod browser;
+pub mod cache;
+pub mod cas;
+pub mod changes;
+pub mod checksums;
+pub mod cli;
+pub mod config;
+pub mod confirm;
+pub mod controls;
+pub mod cookies;
+pub mod download;
+pub mod error;
+pub mod estimate;
+pub mod filter;
+pub mod friday;
+pub mod journal;
+pub mod layout;
+pub mod listener;
+pub mod loudness;
+pub mod markers;
+pub mod middlewares;
+pub mod models;
+pub mod normalize;
+pub mod notify;
+pub mod pinning;
+pub mod planner;
+pub mod postprocess;
+pub mod progress;
+pub mod report;
+pub mod schedule;
+pub mod scratch;
+pub mod session;
+pub mod snapshot;
+pub mod staging;
+pub mod stream;
+pub mod tagging;
+pub mod template;
+pub mod timestamp;
+#[cfg(feature = "self-update")]
+pub mod update;
+pub mod volumes;
@@ -0,0 +1,249 @@
+use std::{io, time::Duration};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::cookies::{self, RawCookie};
+
+// Where a companion browser extension posts fresh cookies, in any format cookie files are read in
+pub const COOKIES_PATH: &str = "/cookies";
+pub const DEFAULT_PORT: u16 = 8765;
+
+// A cookie export is a few KB, anything much larger isn't one
+const MAX_BODY_BYTES: usize = 1_000_000;
+const MAX_HEADER_LINES: usize = 100;
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+
+// A browser sends its whole request at once, so a connection still sending after this is dropped
+// rather than holding up the next one
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Reply {
+    // the browser checking whether it may post cookies from another origin
+    Preflight,
+    Accepted(Vec<RawCookie>),
+    Rejected(u16, String),
+}
+
+// Only as much of HTTP/1.1 as posting cookies from a browser needs
+pub async fn read_request(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Request> {
+    let mut line = String::new();
+    read_line(reader, &mut line).await?;
+    let mut request_line = line.split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Err(invalid_request("malformed request line"));
+    };
+    let (method, path) = (method.to_owned(), path.to_owned());
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    for _ in 0..=MAX_HEADER_LINES {
+        line.clear();
+        if read_line(reader, &mut line).await? == 0 {
+            return Err(invalid_request("connection closed within the headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            if content_length > MAX_BODY_BYTES {
+                return Err(invalid_request("body too large for a cookie export"));
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await?;
+            return Ok(Request {
+                method,
+                path,
+                authorization,
+                body,
+            });
+        }
+
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid_request("malformed content length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.trim().to_owned());
+        }
+    }
+    Err(invalid_request("too many headers"))
+}
+
+// A line of at most MAX_LINE_BYTES, so a client can't have it grow without end
+async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    line: &mut String,
+) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE_BYTES).read_line(line).await?;
+    if read as u64 == MAX_LINE_BYTES && !line.ends_with('\n') {
+        return Err(invalid_request("line too long"));
+    }
+    Ok(read)
+}
+
+fn invalid_request(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+// Any website could post to a port on localhost, so the token printed when listening is required
+// to replace the session
+pub fn respond_to(request: &Request, token: &str, now: i64) -> Reply {
+    if request.path != COOKIES_PATH {
+        return Reply::Rejected(404, format!("Cookies are posted to {COOKIES_PATH}"));
+    }
+    match request.method.as_str() {
+        "OPTIONS" => return Reply::Preflight,
+        "POST" => {}
+        _ => return Reply::Rejected(405, "Cookies are sent with POST".to_owned()),
+    }
+    if request.authorization.as_deref() != Some(&format!("Bearer {token}")) {
+        return Reply::Rejected(401, "Missing or wrong token".to_owned());
+    }
+
+    let Ok(cookie_data) = std::str::from_utf8(&request.body) else {
+        return Reply::Rejected(400, "Cookies aren't valid UTF-8".to_owned());
+    };
+    let raw_cookies = match cookies::parse_raw_cookies(cookie_data) {
+        Ok(raw_cookies) => raw_cookies,
+        Err(e) => return Reply::Rejected(400, e.to_string()),
+    };
+    if cookies::find_identity_cookie(raw_cookies.clone(), now).is_none() {
+        return Reply::Rejected(
+            400,
+            format!(
+                "No unexpired `{}` cookie among the cookies",
+                cookies::IDENTITY_COOKIE_NAME
+            ),
+        );
+    }
+    Reply::Accepted(cookies::merge_raw_cookies(raw_cookies))
+}
+
+const fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+// Every response allows other origins, as the cookies are posted from a page or an extension
+pub async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    status: u16,
+    body: &str,
+) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: POST\r\n\
+         Access-Control-Allow-Headers: Authorization, Content-Type\r\n\
+         Connection: close\r\n\r\n{body}",
+        reason_phrase(status),
+        body.len()
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const COOKIES: &str = r#"[{"name": "identity", "value": "abc", "domain": ".bandcamp.com", "path": "/", "expirationDate": 1919434332}]"#;
+
+    fn cookie_request(method: &str, path: &str, authorization: Option<&str>) -> Request {
+        Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            authorization: authorization.map(str::to_owned),
+            body: COOKIES.as_bytes().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_read_request() {
+        let raw_request = format!(
+            "POST /cookies HTTP/1.1\r\nHost: 127.0.0.1:8765\r\nauthorization: Bearer t0ken\r\nContent-Length: {}\r\n\r\n{COOKIES}",
+            COOKIES.len()
+        );
+
+        let request = read_request(&mut raw_request.as_bytes()).await.unwrap();
+        assert_eq!(
+            request,
+            cookie_request("POST", "/cookies", Some("Bearer t0ken"))
+        );
+    }
+
+    #[test_case("GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n" ; "cut off headers")]
+    #[test_case("\r\n\r\n" ; "no request line")]
+    #[test_case("POST /cookies HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n" ; "large body")]
+    #[test_case("POST /cookies HTTP/1.1\r\nContent-Length: 10\r\n\r\n[]" ; "short body")]
+    #[test_case(&format!("POST /cookies HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(10_000)) ; "long line")]
+    #[tokio::test]
+    pub async fn test_read_request_invalid(raw_request: &str) {
+        assert!(read_request(&mut raw_request.as_bytes()).await.is_err());
+    }
+
+    #[test]
+    pub fn test_respond_to() {
+        let now = 1_600_000_000;
+
+        let reply = respond_to(
+            &cookie_request("POST", "/cookies", Some("Bearer t0ken")),
+            "t0ken",
+            now,
+        );
+        assert!(matches!(reply, Reply::Accepted(cookies) if cookies.len() == 1));
+        assert!(matches!(
+            respond_to(&cookie_request("OPTIONS", "/cookies", None), "t0ken", now),
+            Reply::Preflight
+        ));
+    }
+
+    #[test_case("POST", "/", Some("Bearer t0ken"), 404)]
+    #[test_case("GET", "/cookies", Some("Bearer t0ken"), 405)]
+    #[test_case("POST", "/cookies", None, 401)]
+    #[test_case("POST", "/cookies", Some("Bearer other"), 401)]
+    pub fn test_respond_to_rejected(
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        status: u16,
+    ) {
+        let reply = respond_to(
+            &cookie_request(method, path, authorization),
+            "t0ken",
+            1_600_000_000,
+        );
+        assert!(matches!(reply, Reply::Rejected(s, _) if s == status));
+    }
+
+    #[test]
+    pub fn test_respond_to_expired_identity() {
+        let reply = respond_to(
+            &cookie_request("POST", "/cookies", Some("Bearer t0ken")),
+            "t0ken",
+            2_000_000_000,
+        );
+        assert!(matches!(reply, Reply::Rejected(400, _)));
+    }
+}
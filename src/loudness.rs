@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use crate::{archive, error::LoudnessError};
+
+// ReplayGain 2.0's reference level
+const REFERENCE_LUFS: f64 = -18.0;
+
+// Only these get tagged, as ffmpeg writes tags for both without re-encoding
+const TAGGED_EXTENSIONS: &[&str] = &["flac", "mp3"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Loudness {
+    // integrated loudness, in LUFS
+    pub integrated: f64,
+    // true peak, in dBFS
+    pub peak: f64,
+}
+
+impl Loudness {
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LUFS - self.integrated
+    }
+
+    pub fn linear_peak(&self) -> f64 {
+        10f64.powf(self.peak / 20.0)
+    }
+}
+
+// The album as a whole, approximated from its tracks rather than measured again: the power mean of
+// their loudness, and the highest of their peaks
+pub fn album_loudness(tracks: &[Loudness]) -> Option<Loudness> {
+    let mut power = 0.0;
+    let mut count = 0.0;
+    let mut peak = f64::NEG_INFINITY;
+    for track in tracks {
+        power += 10f64.powf(track.integrated / 10.0);
+        count += 1.0;
+        peak = peak.max(track.peak);
+    }
+
+    (count > 0.0).then(|| Loudness {
+        integrated: 10.0 * (power / count).log10(),
+        peak,
+    })
+}
+
+// Reads the summary ffmpeg's ebur128 filter prints at the end, ignoring the per-frame lines
+// before it
+fn parse_summary(output: &str) -> Option<Loudness> {
+    let summary = &output[output.rfind("Summary:")?..];
+    let value = |label: &str| {
+        summary
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(label))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+    };
+
+    Some(Loudness {
+        integrated: value("I:")?,
+        peak: value("Peak:")?,
+    })
+}
+
+// The tracks to tag that were added to `folder` since `since`, by the folder they're in, each
+// folder taken as an album. Hidden folders, such as the state and staging folders, are left alone.
+pub fn find_new_tracks(
+    folder: &Path,
+    since: SystemTime,
+) -> io::Result<BTreeMap<PathBuf, Vec<PathBuf>>> {
+    let mut albums: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for path in archive::visible_files(folder)? {
+        if !is_tagged_file(&path) {
+            continue;
+        }
+
+        // extractors usually keep the modification time from the archive, so the creation time is
+        // what tells a new track apart, where the platform has one
+        let metadata = fs::metadata(&path)?;
+        let added = metadata.created().or_else(|_| metadata.modified())?;
+        if added >= since {
+            let album = path.parent().unwrap_or(folder).to_owned();
+            albums.entry(album).or_default().push(path);
+        }
+    }
+
+    for tracks in albums.values_mut() {
+        tracks.sort();
+    }
+    Ok(albums)
+}
+
+fn is_tagged_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            TAGGED_EXTENSIONS
+                .iter()
+                .any(|tagged| tagged.eq_ignore_ascii_case(extension))
+        })
+}
+
+fn replaygain_tags(track: Loudness, album: Loudness) -> [(&'static str, String); 4] {
+    [
+        ("REPLAYGAIN_TRACK_GAIN", format!("{:.2} dB", track.gain())),
+        (
+            "REPLAYGAIN_TRACK_PEAK",
+            format!("{:.6}", track.linear_peak()),
+        ),
+        ("REPLAYGAIN_ALBUM_GAIN", format!("{:.2} dB", album.gain())),
+        (
+            "REPLAYGAIN_ALBUM_PEAK",
+            format!("{:.6}", album.linear_peak()),
+        ),
+    ]
+}
+
+// Measures tracks with ffmpeg's EBU R128 filter, and writes the result as ReplayGain tags
+pub struct LoudnessScanner {
+    ffmpeg: PathBuf,
+}
+
+impl LoudnessScanner {
+    pub const fn new(ffmpeg: PathBuf) -> Self {
+        Self { ffmpeg }
+    }
+
+    pub fn measure(&self, track: &Path) -> Result<Loudness, LoudnessError> {
+        let output = Command::new(&self.ffmpeg)
+            .args(["-hide_banner", "-nostats", "-i"])
+            .arg(track)
+            .args([
+                "-map",
+                "0:a:0",
+                "-filter:a",
+                "ebur128=peak=true",
+                "-f",
+                "null",
+                "-",
+            ])
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(LoudnessError::Failed(output.status.to_string()));
+        }
+
+        parse_summary(&String::from_utf8_lossy(&output.stderr)).ok_or(LoudnessError::NoSummary)
+    }
+
+    // Rewrites the track next to itself without re-encoding it, then moves it over the original
+    pub fn tag(
+        &self,
+        track: &Path,
+        loudness: Loudness,
+        album: Loudness,
+    ) -> Result<(), LoudnessError> {
+        let file_name = track.file_name().unwrap_or_default().to_string_lossy();
+        // keeping the extension, which ffmpeg picks the container by
+        let tagged_path = track.with_file_name(format!(".replaygain-{file_name}"));
+
+        let mut command = Command::new(&self.ffmpeg);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+            .arg(track)
+            .args(["-map", "0", "-map_metadata", "0", "-c", "copy"]);
+        for (tag, value) in replaygain_tags(loudness, album) {
+            command.arg("-metadata").arg(format!("{tag}={value}"));
+        }
+        let status = command.arg(&tagged_path).stdin(Stdio::null()).status()?;
+        if !status.success() {
+            // ffmpeg may leave a partial file behind
+            let _ = fs::remove_file(&tagged_path);
+            return Err(LoudnessError::Failed(status.to_string()));
+        }
+
+        fs::rename(&tagged_path, track)?;
+        Ok(())
+    }
+
+    // Measures every track before tagging any, as the album gain depends on all of them
+    pub fn tag_album(&self, tracks: &[PathBuf]) -> Result<(), LoudnessError> {
+        let loudness = tracks
+            .iter()
+            .map(|track| self.measure(track))
+            .collect::<Result<Vec<_>, _>>()?;
+        let Some(album) = album_loudness(&loudness) else {
+            return Ok(());
+        };
+
+        for (track, loudness) in tracks.iter().zip(loudness) {
+            self.tag(track, loudness, album)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const EBUR128_OUTPUT: &str = "\
+[Parsed_ebur128_0 @ 0x5581] t: 0.1   TARGET:-23 LUFS    M:-120.7 S:-120.7     I: -70.0 LUFS
+[Parsed_ebur128_0 @ 0x5581] Summary:
+
+  Integrated loudness:
+    I:         -12.4 LUFS
+    Threshold: -22.7 LUFS
+
+  Loudness range:
+    LRA:         4.9 LU
+    Threshold: -32.6 LUFS
+    LRA low:   -15.8 LUFS
+    LRA high:  -10.9 LUFS
+
+  True peak:
+    Peak:        0.6 dBFS
+";
+
+    #[test]
+    pub fn test_parse_summary() {
+        assert_eq!(
+            parse_summary(EBUR128_OUTPUT),
+            Some(Loudness {
+                integrated: -12.4,
+                peak: 0.6,
+            })
+        );
+        assert_eq!(parse_summary("Output #0, null, to 'pipe:':"), None);
+    }
+
+    #[test]
+    pub fn test_replaygain_tags() {
+        let track = Loudness {
+            integrated: -12.4,
+            peak: 0.0,
+        };
+        let quiet = Loudness {
+            integrated: -22.4,
+            peak: -6.0,
+        };
+        let album = album_loudness(&[track, quiet]).unwrap();
+        assert!((album.integrated - -15.0).abs() < 0.1);
+        assert!(album.peak.abs() < f64::EPSILON);
+        assert_eq!(album_loudness(&[]), None);
+
+        assert_eq!(
+            replaygain_tags(track, album),
+            [
+                ("REPLAYGAIN_TRACK_GAIN", "-5.60 dB".to_owned()),
+                ("REPLAYGAIN_TRACK_PEAK", "1.000000".to_owned()),
+                ("REPLAYGAIN_ALBUM_GAIN", "-3.00 dB".to_owned()),
+                ("REPLAYGAIN_ALBUM_PEAK", "1.000000".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_find_new_tracks() {
+        let root = tempfile::tempdir().unwrap();
+        let album = root.path().join("Anomalie - Galerie");
+        fs::create_dir_all(&album).unwrap();
+        fs::create_dir_all(root.path().join(".bandcamp-dl")).unwrap();
+        let before = SystemTime::now() - Duration::from_mins(1);
+        fs::write(album.join("02 - Galerie.mp3"), b"galerie").unwrap();
+        fs::write(album.join("01 - Intro.flac"), b"intro").unwrap();
+        fs::write(album.join("cover.jpg"), b"cover").unwrap();
+        fs::write(
+            root.path().join(".bandcamp-dl").join("01 - Intro.flac"),
+            b"intro",
+        )
+        .unwrap();
+
+        let albums = find_new_tracks(root.path(), before).unwrap();
+        assert_eq!(
+            albums.into_iter().collect::<Vec<_>>(),
+            [(
+                album.clone(),
+                vec![
+                    album.join("01 - Intro.flac"),
+                    album.join("02 - Galerie.mp3")
+                ]
+            )]
+        );
+        let later = SystemTime::now() + Duration::from_mins(1);
+        assert!(find_new_tracks(root.path(), later).unwrap().is_empty());
+    }
+}
@@ -2,21 +2,17 @@
 #![deny(clippy::pedantic)]
 #![deny(clippy::nursery)]
 #![deny(clippy::cargo)]
-// #![deny(missing_docs)]
 #![allow(clippy::multiple_crate_versions)]
-#![allow(clippy::module_name_repetitions)]
-#![allow(clippy::items_after_statements)]
 
+use bandcamp_dl::{cli, timestamp};
 use clap::Parser;
 
-mod api;
-mod cache;
-mod cli;
-mod cookies;
-mod error;
-mod middlewares;
+fn main() -> anyhow::Result<()> {
+    // while there's still a single thread, see `init_local_offset`
+    timestamp::init_local_offset();
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    cli::run_program(cli::Cli::try_parse()?).await
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(async { cli::run_program(cli::Cli::try_parse()?).await })
 }
@@ -0,0 +1,271 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    cache,
+    models::{self, Versioned},
+    schedule, timestamp,
+};
+
+pub const MARKERS_FILE_NAME: &str = "probe-markers.json";
+
+// Why probing a release's download page came back empty handed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MarkerKind {
+    // The page lists no digital download, e.g. for merch only purchases
+    NoDigitalItem,
+    // The page itself is gone (404 or 410), which labels sometimes undo later
+    Unavailable,
+}
+
+impl MarkerKind {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::NoDigitalItem => "no-digital-item",
+            Self::Unavailable => "unavailable",
+        }
+    }
+}
+
+// How long each kind of marker holds before the release is probed again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxAges {
+    pub no_digital_item: Duration,
+    pub unavailable: Duration,
+}
+
+impl Default for MaxAges {
+    fn default() -> Self {
+        Self {
+            no_digital_item: Duration::from_hours(7 * 24),
+            unavailable: Duration::from_hours(30 * 24),
+        }
+    }
+}
+
+impl MaxAges {
+    pub const fn get(&self, kind: MarkerKind) -> Duration {
+        match kind {
+            MarkerKind::NoDigitalItem => self.no_digital_item,
+            MarkerKind::Unavailable => self.unavailable,
+        }
+    }
+
+    pub const fn set(&mut self, kind: MarkerKind, max_age: Duration) {
+        match kind {
+            MarkerKind::NoDigitalItem => self.no_digital_item = max_age,
+            MarkerKind::Unavailable => self.unavailable = max_age,
+        }
+    }
+}
+
+// e.g. "unavailable=14d", or "no-digital-item=0" to probe those releases on every sync
+pub fn parse_max_age(max_age: &str) -> Result<(MarkerKind, Duration), String> {
+    let (kind, duration) = max_age
+        .split_once('=')
+        .ok_or_else(|| format!("expected MARKER=DURATION, got \"{max_age}\""))?;
+    let kind = match kind.trim() {
+        "no-digital-item" => MarkerKind::NoDigitalItem,
+        "unavailable" => MarkerKind::Unavailable,
+        kind => {
+            return Err(format!(
+                "unknown marker \"{kind}\", expected no-digital-item or unavailable"
+            ))
+        }
+    };
+    Ok((kind, schedule::parse_duration(duration)?))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Marker {
+    pub kind: MarkerKind,
+    // unix timestamp, in seconds, recorded as RFC 3339
+    #[serde(with = "timestamp::rfc3339")]
+    pub marked_at: i64,
+}
+
+// Releases whose download page recently had nothing to download, so each sync doesn't request
+// them all over again. Keyed by the normalized release id, like the download cache.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeMarkers {
+    markers: HashMap<String, Marker>,
+}
+
+impl ProbeMarkers {
+    pub fn mark(&mut self, release_id: &str, kind: MarkerKind, now: OffsetDateTime) {
+        self.markers.insert(
+            cache::normalize_release_id(release_id),
+            Marker {
+                kind,
+                marked_at: now.unix_timestamp(),
+            },
+        );
+    }
+
+    pub fn clear(&mut self, release_id: &str) {
+        self.markers
+            .remove(&cache::normalize_release_id(release_id));
+    }
+
+    pub fn get(&self, release_id: &str) -> Option<&Marker> {
+        self.markers.get(&cache::normalize_release_id(release_id))
+    }
+
+    // Whether the release was marked recently enough to skip probing it this time
+    pub fn is_fresh(&self, release_id: &str, max_ages: &MaxAges, now: OffsetDateTime) -> bool {
+        self.get(release_id)
+            .is_some_and(|marker| is_fresh(marker, max_ages, now))
+    }
+
+    // Drops the markers that ran out, so the file doesn't keep every release that ever failed,
+    // returning the releases they were for
+    pub fn prune(&mut self, max_ages: &MaxAges, now: OffsetDateTime) -> Vec<String> {
+        let mut pruned = Vec::new();
+        self.markers.retain(|release_id, marker| {
+            let fresh = is_fresh(marker, max_ages, now);
+            if !fresh {
+                pruned.push(release_id.clone());
+            }
+            fresh
+        });
+        pruned.sort();
+        pruned
+    }
+
+    // Carries over what changed from `before` to `after`, such as in a sync, leaving alone the
+    // markers other syncs wrote meanwhile
+    pub fn apply_changes(&mut self, before: &Self, after: &Self) {
+        for release_id in before.markers.keys() {
+            if !after.markers.contains_key(release_id) {
+                self.markers.remove(release_id);
+            }
+        }
+        for (release_id, marker) in &after.markers {
+            if before.markers.get(release_id) != Some(marker) {
+                self.markers.insert(release_id.clone(), *marker);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.markers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markers.is_empty()
+    }
+}
+
+fn is_fresh(marker: &Marker, max_ages: &MaxAges, now: OffsetDateTime) -> bool {
+    let max_age = i64::try_from(max_ages.get(marker.kind).as_secs()).unwrap_or(i64::MAX);
+    now.unix_timestamp().saturating_sub(marker.marked_at) < max_age
+}
+
+// Version 0 had the same fields, only without the version. Up to version 1, `marked_at` was
+// recorded as a unix timestamp, which is still read as is.
+impl Versioned for ProbeMarkers {
+    const SCHEMA_VERSION: u32 = 2;
+}
+
+pub fn read_markers(markers_data: &str) -> Result<ProbeMarkers, serde_json::Error> {
+    models::read_versioned(markers_data)
+}
+
+pub fn serialize_markers(markers: &ProbeMarkers) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(&models::versioned_value(markers)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn at(unix_timestamp: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(unix_timestamp).unwrap()
+    }
+
+    #[test_case("unavailable=14d", MarkerKind::Unavailable, 14 * DAY; "unavailable")]
+    #[test_case("no-digital-item=12h", MarkerKind::NoDigitalItem, DAY / 2; "no digital item")]
+    #[test_case("no-digital-item=0", MarkerKind::NoDigitalItem, 0; "disabled")]
+    pub fn test_parse_max_age(max_age: &str, kind: MarkerKind, seconds: i64) {
+        assert_eq!(
+            parse_max_age(max_age),
+            Ok((kind, Duration::from_secs(seconds.unsigned_abs())))
+        );
+    }
+
+    #[test_case("unavailable"; "no duration")]
+    #[test_case("gone=1d"; "unknown marker")]
+    #[test_case("unavailable=soon"; "invalid duration")]
+    pub fn test_parse_max_age_invalid(max_age: &str) {
+        assert!(parse_max_age(max_age).is_err());
+    }
+
+    #[test]
+    pub fn test_markers_expire() {
+        let max_ages = MaxAges::default();
+        let mut markers = ProbeMarkers::default();
+        markers.mark("199396767", MarkerKind::NoDigitalItem, at(0));
+        markers.mark("r181302019", MarkerKind::Unavailable, at(0));
+
+        assert!(markers.is_fresh("p199396767", &max_ages, at(6 * DAY)));
+        assert!(!markers.is_fresh("p199396767", &max_ages, at(7 * DAY)));
+        assert!(markers.is_fresh("r181302019", &max_ages, at(7 * DAY)));
+        assert!(!markers.is_fresh("p1", &max_ages, at(0)));
+
+        assert_eq!(markers.prune(&max_ages, at(7 * DAY)), ["p199396767"]);
+        assert_eq!(markers.len(), 1);
+        assert!(markers.get("r181302019").is_some());
+
+        markers.clear("r181302019");
+        assert!(markers.is_empty());
+    }
+
+    #[test]
+    pub fn test_apply_changes() {
+        let mut before = ProbeMarkers::default();
+        before.mark("p1", MarkerKind::NoDigitalItem, at(0));
+        before.mark("p2", MarkerKind::NoDigitalItem, at(0));
+        let mut after = before.clone();
+        after.clear("p1");
+        after.mark("p3", MarkerKind::Unavailable, at(DAY));
+
+        // what another sync wrote in the meantime
+        let mut current = before.clone();
+        current.mark("p2", MarkerKind::Unavailable, at(DAY));
+        current.mark("p4", MarkerKind::NoDigitalItem, at(DAY));
+
+        current.apply_changes(&before, &after);
+        assert!(current.get("p1").is_none());
+        assert_eq!(current.get("p2").unwrap().kind, MarkerKind::Unavailable);
+        assert!(current.get("p3").is_some());
+        assert!(current.get("p4").is_some());
+        assert_eq!(current.len(), 3);
+    }
+
+    #[test]
+    pub fn test_zero_max_age_never_fresh() {
+        let mut max_ages = MaxAges::default();
+        max_ages.set(MarkerKind::Unavailable, Duration::ZERO);
+        let mut markers = ProbeMarkers::default();
+        markers.mark("p1", MarkerKind::Unavailable, at(DAY));
+
+        assert!(!markers.is_fresh("p1", &max_ages, at(DAY)));
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let mut markers = ProbeMarkers::default();
+        markers.mark("p1", MarkerKind::Unavailable, at(DAY));
+
+        let serialized = serialize_markers(&markers).unwrap();
+        assert!(serialized.contains("\"unavailable\""));
+        assert_eq!(read_markers(&serialized).unwrap(), markers);
+    }
+}
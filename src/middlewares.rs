@@ -2,10 +2,18 @@ use anyhow::anyhow;
 use http::{Extensions, HeaderMap, StatusCode};
 use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next, Result};
-use std::sync::{Arc, Mutex};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
 use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
+use crate::{api::endpoints, models::Versioned};
+
 #[derive(Debug, Copy, Clone)]
 pub struct Rate {
     num: u64,
@@ -18,31 +26,56 @@ impl Rate {
         assert!(per.as_nanos() > 0);
         Self { num, per }
     }
-}
 
-#[derive(Debug)]
-struct State {
-    until: Instant,
-    rem: u64,
+    fn per_nanos(&self) -> u64 {
+        u64::try_from(self.per.as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    fn emission_interval_nanos(&self) -> u64 {
+        (self.per_nanos() / self.num).max(1)
+    }
 }
 
+// A GCRA token bucket: every request reserves the next free emission slot with a single atomic
+// compare-and-swap, so waiting requests are spread out one interval apart instead of all waking
+// up together when a window resets. Up to `num` requests may still burst through at once.
 #[derive(Debug, Clone)]
 pub struct RateLimitMiddleware {
     rate: Rate,
-    state: Arc<Mutex<State>>,
+    start: Instant,
+    theoretical_arrival: Arc<AtomicU64>,
 }
 
 impl RateLimitMiddleware {
     pub fn new(num: u64, per: Duration) -> Self {
-        let rate = Rate::new(num, per);
-        let state = State {
-            until: Instant::now(),
-            rem: rate.num,
-        };
-
         Self {
-            rate,
-            state: Arc::new(Mutex::new(state)),
+            rate: Rate::new(num, per),
+            start: Instant::now(),
+            theoretical_arrival: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // Reserves a slot for a request made `now_nanos` after `start`, returning how long the
+    // request has to wait before it may be sent
+    fn reserve(&self, now_nanos: u64) -> Duration {
+        let interval = self.rate.emission_interval_nanos();
+        let burst_tolerance = self.rate.per_nanos().saturating_sub(interval);
+
+        let mut current = self.theoretical_arrival.load(Ordering::Acquire);
+        loop {
+            let arrival = current.max(now_nanos);
+            match self.theoretical_arrival.compare_exchange_weak(
+                current,
+                arrival.saturating_add(interval),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    let allowed_at = arrival.saturating_sub(burst_tolerance);
+                    return Duration::from_nanos(allowed_at.saturating_sub(now_nanos));
+                }
+                Err(actual) => current = actual,
+            }
         }
     }
 }
@@ -55,40 +88,28 @@ impl Middleware for RateLimitMiddleware {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> Result<Response> {
-        let now = Instant::now();
-        let should_sleep = {
-            let mut state = self.state.lock().unwrap();
-
-            if now >= state.until {
-                state.until = now + self.rate.per;
-                state.rem = self.rate.num;
-            }
+        let now_nanos = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        let wait = self.reserve(now_nanos);
 
-            if state.rem > 0 {
-                state.rem -= 1;
-                None
-            } else {
-                Some(state.until - now)
-            }
-        };
-
-        if let Some(sleep_duration) = should_sleep {
-            sleep(sleep_duration).await;
+        if !wait.is_zero() {
+            sleep(wait).await;
         }
 
         next.run(req, extensions).await
     }
 }
 
+// Shared by every request going through the client, so a single 429 pauses all of them until the
+// server's retry-after has elapsed, instead of just the request that hit it
 pub struct RetryMiddleware {
-    is_waiting: Arc<Mutex<bool>>,
+    cooldown_until: Arc<Mutex<Option<Instant>>>,
     max_retries: u32,
 }
 
 impl RetryMiddleware {
     pub fn new(max_retries: u32) -> Self {
         Self {
-            is_waiting: Arc::new(Mutex::new(false)),
+            cooldown_until: Arc::new(Mutex::new(None)),
             max_retries,
         }
     }
@@ -100,7 +121,26 @@ impl RetryMiddleware {
             .and_then(|v| v.parse::<u64>().ok())
             .map(Duration::from_secs)
     }
+
+    fn fallback_backoff(attempt: u32) -> Duration {
+        Duration::from_secs(1 << attempt.min(6))
+    }
+
+    fn remaining_cooldown(&self, now: Instant) -> Option<Duration> {
+        self.cooldown_until
+            .lock()
+            .unwrap()
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn extend_cooldown(&self, now: Instant, duration: Duration) {
+        let mut cooldown_until = self.cooldown_until.lock().unwrap();
+        let until = now + duration;
+        *cooldown_until = Some(cooldown_until.map_or(until, |current| current.max(until)));
+    }
 }
+
 #[async_trait::async_trait]
 impl Middleware for RetryMiddleware {
     async fn handle(
@@ -109,10 +149,9 @@ impl Middleware for RetryMiddleware {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> Result<Response> {
-        for _ in 0..self.max_retries {
-            if *self.is_waiting.lock().unwrap() {
-                sleep(Duration::from_millis(100)).await;
-                continue;
+        for attempt in 0..self.max_retries {
+            while let Some(remaining) = self.remaining_cooldown(Instant::now()) {
+                sleep(remaining).await;
             }
 
             let response = next
@@ -121,11 +160,9 @@ impl Middleware for RetryMiddleware {
                 .await?;
 
             if response.status() == StatusCode::TOO_MANY_REQUESTS {
-                if let Some(retry_after) = Self::get_retry_after(response.headers()) {
-                    *self.is_waiting.lock().unwrap() = true;
-                    sleep(retry_after).await;
-                    *self.is_waiting.lock().unwrap() = false;
-                }
+                let retry_after = Self::get_retry_after(response.headers())
+                    .unwrap_or_else(|| Self::fallback_backoff(attempt));
+                self.extend_cooldown(Instant::now(), retry_after);
                 continue;
             }
 
@@ -137,3 +174,202 @@ impl Middleware for RetryMiddleware {
         )))
     }
 }
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct EndpointStats {
+    pub requests: u64,
+    // requests that failed outright or were answered with a 4xx/5xx status
+    pub errors: u64,
+    pub total_latency_ms: u64,
+    pub max_latency_ms: u64,
+    pub statuses: BTreeMap<u16, u64>,
+}
+
+// The --stats-json file. Version 0 was the bare map of endpoints.
+#[derive(Serialize, JsonSchema)]
+pub struct StatsExport {
+    pub endpoints: BTreeMap<&'static str, EndpointStats>,
+}
+
+impl Versioned for StatsExport {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+impl EndpointStats {
+    fn record(&mut self, latency: Duration, status: Option<StatusCode>) {
+        let latency_ms = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        self.requests += 1;
+        self.total_latency_ms = self.total_latency_ms.saturating_add(latency_ms);
+        self.max_latency_ms = self.max_latency_ms.max(latency_ms);
+        match status {
+            Some(status) => {
+                *self.statuses.entry(status.as_u16()).or_default() += 1;
+                if status.is_client_error() || status.is_server_error() {
+                    self.errors += 1;
+                }
+            }
+            None => self.errors += 1,
+        }
+    }
+
+    pub fn mean_latency_ms(&self) -> u64 {
+        self.total_latency_ms
+            .checked_div(self.requests)
+            .unwrap_or_default()
+    }
+}
+
+// Shared with whoever reports on the run, while the middleware fills it in
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    endpoints: Mutex<BTreeMap<&'static str, EndpointStats>>,
+}
+
+impl RequestMetrics {
+    // `status` is `None` for requests that got no response at all
+    pub fn record(&self, endpoint: &'static str, latency: Duration, status: Option<StatusCode>) {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .entry(endpoint)
+            .or_default()
+            .record(latency, status);
+    }
+
+    pub fn endpoints(&self) -> BTreeMap<&'static str, EndpointStats> {
+        self.endpoints.lock().unwrap().clone()
+    }
+}
+
+// Sits below the rate limiter and retries, so every request actually sent is counted once and
+// its latency doesn't include time spent waiting for a slot
+pub struct MetricsMiddleware {
+    metrics: Arc<RequestMetrics>,
+}
+
+impl MetricsMiddleware {
+    pub const fn new(metrics: Arc<RequestMetrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for MetricsMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let endpoint = endpoints::endpoint_class(req.url());
+        let started = Instant::now();
+        let result = next.run(req, extensions).await;
+
+        self.metrics.record(
+            endpoint,
+            started.elapsed(),
+            result.as_ref().ok().map(Response::status),
+        );
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    #[test]
+    pub fn test_rate_limit_allows_initial_burst() {
+        let limiter = RateLimitMiddleware::new(10, Duration::from_secs(10));
+
+        for _ in 0..10 {
+            assert_eq!(limiter.reserve(0), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    pub fn test_rate_limit_spreads_waiters() {
+        let limiter = RateLimitMiddleware::new(10, Duration::from_secs(10));
+        for _ in 0..10 {
+            limiter.reserve(0);
+        }
+
+        assert_eq!(limiter.reserve(0), Duration::from_secs(1));
+        assert_eq!(limiter.reserve(0), Duration::from_secs(2));
+        assert_eq!(limiter.reserve(0), Duration::from_secs(3));
+    }
+
+    #[test]
+    pub fn test_rate_limit_replenishes_over_time() {
+        let limiter = RateLimitMiddleware::new(2, Duration::from_secs(2));
+        assert_eq!(limiter.reserve(0), Duration::ZERO);
+        assert_eq!(limiter.reserve(0), Duration::ZERO);
+        assert_eq!(limiter.reserve(0), Duration::from_secs(1));
+
+        // long idle period, the bucket is full again but doesn't grow past its size
+        assert_eq!(limiter.reserve(60 * SECOND), Duration::ZERO);
+        assert_eq!(limiter.reserve(60 * SECOND), Duration::ZERO);
+        assert_eq!(limiter.reserve(60 * SECOND), Duration::from_secs(1));
+    }
+
+    #[test]
+    pub fn test_retry_cooldown_only_extends() {
+        let retry = RetryMiddleware::new(5);
+        let now = Instant::now();
+        assert_eq!(retry.remaining_cooldown(now), None);
+
+        retry.extend_cooldown(now, Duration::from_secs(30));
+        retry.extend_cooldown(now, Duration::from_secs(5));
+        assert_eq!(retry.remaining_cooldown(now), Some(Duration::from_secs(30)));
+
+        assert_eq!(
+            retry.remaining_cooldown(now + Duration::from_secs(30)),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_request_metrics() {
+        let metrics = RequestMetrics::default();
+        metrics.record(
+            "fancollection",
+            Duration::from_millis(100),
+            Some(StatusCode::OK),
+        );
+        metrics.record(
+            "fancollection",
+            Duration::from_millis(300),
+            Some(StatusCode::TOO_MANY_REQUESTS),
+        );
+        metrics.record("stat_download", Duration::from_secs(2), None);
+
+        let endpoints = metrics.endpoints();
+        let fancollection = &endpoints["fancollection"];
+        assert_eq!(fancollection.requests, 2);
+        assert_eq!(fancollection.errors, 1);
+        assert_eq!(fancollection.mean_latency_ms(), 200);
+        assert_eq!(fancollection.max_latency_ms, 300);
+        assert_eq!(fancollection.statuses, BTreeMap::from([(200, 1), (429, 1)]));
+
+        assert_eq!(endpoints["stat_download"].errors, 1);
+        assert!(endpoints["stat_download"].statuses.is_empty());
+        assert_eq!(EndpointStats::default().mean_latency_ms(), 0);
+    }
+
+    #[test]
+    pub fn test_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(RetryMiddleware::get_retry_after(&headers), None);
+
+        headers.insert("retry-after", "12".parse().unwrap());
+        assert_eq!(
+            RetryMiddleware::get_retry_after(&headers),
+            Some(Duration::from_secs(12))
+        );
+
+        headers.insert("retry-after", "soon".parse().unwrap());
+        assert_eq!(RetryMiddleware::get_retry_after(&headers), None);
+    }
+}
@@ -0,0 +1,132 @@
+use schemars::{schema_for, JsonSchema, Schema};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+// Every JSON state file and export carries the version of its schema in this field. Files written
+// before versions were introduced don't have it, and are taken as version 0.
+pub const SCHEMA_VERSION_FIELD: &str = "schema_version";
+
+pub trait Versioned {
+    const SCHEMA_VERSION: u32;
+
+    // Rewrites data of `from_version` into the shape of the version right after it
+    fn upgrade(_from_version: u32, value: Value) -> Value {
+        value
+    }
+}
+
+pub fn schema_version(value: &Value) -> u32 {
+    value
+        .get(SCHEMA_VERSION_FIELD)
+        .and_then(Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(0)
+}
+
+// Older files are upgraded one version at a time. Files from a newer release are read as well as
+// they can be, ignoring the fields this one doesn't know, so going back to an older binary
+// doesn't lose the state. Those that can't be say so, rather than what they're missing.
+pub fn read_versioned<T: Versioned + DeserializeOwned>(data: &str) -> Result<T, serde_json::Error> {
+    let mut value: Value = serde_json::from_str(data)?;
+    let version = schema_version(&value);
+    for from_version in version..T::SCHEMA_VERSION {
+        value = T::upgrade(from_version, value);
+    }
+    if let Value::Object(fields) = &mut value {
+        fields.remove(SCHEMA_VERSION_FIELD);
+    }
+    serde_json::from_value(value).map_err(|e| {
+        if version <= T::SCHEMA_VERSION {
+            return e;
+        }
+        serde::de::Error::custom(format!(
+            "written by a newer version of bandcamp-dl (schema version {version}, this one reads up to {}), {e}",
+            T::SCHEMA_VERSION
+        ))
+    })
+}
+
+pub fn versioned_value<T: Versioned + Serialize>(state: &T) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(state)?;
+    if let Value::Object(fields) = &mut value {
+        fields.insert(SCHEMA_VERSION_FIELD.to_owned(), T::SCHEMA_VERSION.into());
+    }
+    Ok(value)
+}
+
+// The schema of `T` as written by `versioned_value`, which adds its version
+pub fn versioned_schema<T: Versioned + JsonSchema>() -> Schema {
+    let mut schema = schema_for!(T);
+    let version_schema = serde_json::json!({ "const": T::SCHEMA_VERSION });
+    if let Some(Value::Object(properties)) = schema.get_mut("properties") {
+        properties.insert(SCHEMA_VERSION_FIELD.to_owned(), version_schema);
+    }
+    if let Some(Value::Array(required)) = schema.get_mut("required") {
+        required.insert(0, SCHEMA_VERSION_FIELD.into());
+    }
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct State {
+        names: Vec<String>,
+        count: u32,
+    }
+
+    impl Versioned for State {
+        const SCHEMA_VERSION: u32 = 2;
+
+        // version 0 was a bare list of names, version 1 had no count
+        fn upgrade(from_version: u32, value: Value) -> Value {
+            match from_version {
+                0 => serde_json::json!({ "names": value }),
+                _ => value,
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_read_unversioned() {
+        let state: State = read_versioned(r#"["a", "b"]"#).unwrap();
+        assert_eq!(state.names, ["a", "b"]);
+        assert_eq!(state.count, 0);
+    }
+
+    #[test]
+    pub fn test_read_newer_version() {
+        let state: State =
+            read_versioned(r#"{"schema_version": 3, "names": ["a"], "count": 1, "extra": true}"#)
+                .unwrap();
+        assert_eq!(state.names, ["a"]);
+        assert_eq!(state.count, 1);
+    }
+
+    #[test]
+    pub fn test_read_unreadable_newer_version() {
+        let error = read_versioned::<State>(r#"{"schema_version": 3, "names": "a"}"#).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "written by a newer version of bandcamp-dl (schema version 3, this one reads up to 2)"
+        ));
+
+        let error = read_versioned::<State>(r#"{"schema_version": 2, "names": "a"}"#).unwrap_err();
+        assert!(!error.to_string().contains("newer version"));
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let state = State {
+            names: vec!["a".to_owned()],
+            count: 1,
+        };
+
+        let value = versioned_value(&state).unwrap();
+        assert_eq!(schema_version(&value), 2);
+        assert_eq!(read_versioned::<State>(&value.to_string()).unwrap(), state);
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    archive,
+    template::{NameTemplate, TemplateFields},
+};
+
+// A custom field set on every track, from --tag-template, e.g. "COMMENT=bandcamp {sale_id}". The
+// field is named as in Vorbis comments, which lofty maps to each format's own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagTemplate {
+    field: String,
+    template: NameTemplate,
+}
+
+impl FromStr for TagTemplate {
+    type Err = String;
+
+    fn from_str(tag_template: &str) -> Result<Self, Self::Err> {
+        let Some((field, template)) = tag_template.split_once('=') else {
+            return Err(format!(
+                "expected FIELD=TEMPLATE, e.g. \"COMMENT={{sale_id}}\", got \"{tag_template}\""
+            ));
+        };
+        let field = field.trim();
+        if field.is_empty() || field.contains(char::is_whitespace) {
+            return Err(format!("invalid field name \"{field}\""));
+        }
+
+        Ok(Self {
+            field: field.to_uppercase(),
+            template: template.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for TagTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.field, self.template)
+    }
+}
+
+impl TagTemplate {
+    pub fn render(&self, fields: &TemplateFields) -> (String, String) {
+        (self.field.clone(), self.template.render(fields))
+    }
+}
+
+// What every track of a release is tagged with
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReleaseTags {
+    pub album_artist: String,
+    pub album: String,
+    pub year: Option<i32>,
+    // by field name, from --tag-template
+    pub custom: Vec<(String, String)>,
+}
+
+// Where a track stands in its release
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackPosition {
+    pub track: u32,
+    pub track_total: u32,
+    // only for releases unpacked into one folder per disc
+    pub disc: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NormalizeReport {
+    pub normalized: usize,
+    // audio files whose tags couldn't be read or written, with why
+    pub failed: Vec<(PathBuf, String)>,
+    // --tag-template fields left out of a file, as its kind of tag has no place for them
+    pub skipped_fields: Vec<(PathBuf, String)>,
+}
+
+// Rewrites the tags of an unpacked release the same way whatever label released it, as Bandcamp
+// leaves them as uploaded. Only available when built with the "normalize-tags" feature.
+pub struct TagNormalizer {
+    enabled: bool,
+}
+
+impl TagNormalizer {
+    pub const fn new(enabled: bool) -> Self {
+        Self {
+            enabled: enabled && cfg!(feature = "normalize-tags"),
+        }
+    }
+
+    pub const fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Numbers the tracks among `entries`, the files of the release's archive by their path within
+    // `folder`, so files put there otherwise don't count. Audio files whose tags can't be read
+    // don't either.
+    pub fn normalize_release(
+        &self,
+        folder: &Path,
+        entries: &[PathBuf],
+        tags: &ReleaseTags,
+    ) -> NormalizeReport {
+        let mut report = NormalizeReport::default();
+        if !self.enabled {
+            return report;
+        }
+
+        let mut tracks = Vec::new();
+        for path in entries
+            .iter()
+            .filter(|entry| archive::is_audio_file(entry))
+            .map(|entry| folder.join(entry))
+        {
+            match read_tags(&path) {
+                Ok(()) => tracks.push(path),
+                Err(e) => report.failed.push((path, e)),
+            }
+        }
+
+        for (path, position) in track_positions(tracks) {
+            match normalize_file(&path, tags, position) {
+                Ok(skipped_fields) => {
+                    report.normalized += 1;
+                    report
+                        .skipped_fields
+                        .extend(skipped_fields.into_iter().map(|e| (path.clone(), e)));
+                }
+                Err(e) => report.failed.push((path, e)),
+            }
+        }
+        report
+    }
+}
+
+// Tracks are numbered in the order of their file names, which Bandcamp starts with the track
+// number, and each folder holding tracks is a disc, in the order of the folders' names
+#[allow(clippy::cast_possible_truncation)]
+pub fn track_positions(files: Vec<PathBuf>) -> Vec<(PathBuf, TrackPosition)> {
+    let mut discs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let folder = file.parent().unwrap_or_else(|| Path::new("")).to_owned();
+        discs.entry(folder).or_default().push(file);
+    }
+
+    let disc_total = discs.len() as u32;
+    discs
+        .into_values()
+        .zip(1..)
+        .flat_map(|(mut tracks, disc)| {
+            tracks.sort();
+            let track_total = tracks.len() as u32;
+            tracks.into_iter().zip(1..).map(move |(file, track)| {
+                let position = TrackPosition {
+                    track,
+                    track_total,
+                    disc: (disc_total > 1).then_some((disc, disc_total)),
+                };
+                (file, position)
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "normalize-tags")]
+fn read_tags(path: &Path) -> Result<(), String> {
+    lofty::read_from_path(path)
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+// Returns why each --tag-template field that couldn't be written was left out
+#[cfg(feature = "normalize-tags")]
+fn normalize_file(
+    path: &Path,
+    tags: &ReleaseTags,
+    position: TrackPosition,
+) -> Result<Vec<String>, String> {
+    use lofty::{
+        config::WriteOptions,
+        file::{AudioFile, TaggedFileExt},
+        tag::{Accessor, ItemKey, Tag, TagType},
+    };
+
+    use crate::tagging;
+
+    let mut tagged_file = lofty::read_from_path(path).map_err(|e| e.to_string())?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or("the file has no tag to normalize")?;
+
+    tag.insert_text(ItemKey::AlbumArtist, tags.album_artist.clone());
+    tag.set_album(tags.album.clone());
+    if let Some(year) = tags.year.and_then(|year| u32::try_from(year).ok()) {
+        tag.set_year(year);
+    }
+    tag.set_track(position.track);
+    tag.set_track_total(position.track_total);
+    if let Some((disc, disc_total)) = position.disc {
+        tag.set_disk(disc);
+        tag.set_disk_total(disc_total);
+    }
+    let mut skipped_fields = Vec::new();
+    for (field, value) in &tags.custom {
+        let key = match ItemKey::from_key(TagType::VorbisComments, field) {
+            ItemKey::Unknown(_) => ItemKey::from_key(tag.tag_type(), field),
+            key => key,
+        };
+        if !tag.insert_text(key, value.clone()) {
+            skipped_fields.push(format!(
+                "{field} can't be written in a {:?} tag",
+                tag.tag_type()
+            ));
+        }
+    }
+
+    tagging::replace_tagged(path, |copy| {
+        tagged_file
+            .save_to_path(copy, WriteOptions::default())
+            .map_err(|e| e.to_string())
+    })?;
+    Ok(skipped_fields)
+}
+
+#[cfg(not(feature = "normalize-tags"))]
+fn read_tags(_: &Path) -> Result<(), String> {
+    Err("this build can't read tags".to_owned())
+}
+
+#[cfg(not(feature = "normalize-tags"))]
+fn normalize_file(_: &Path, _: &ReleaseTags, _: TrackPosition) -> Result<Vec<String>, String> {
+    Err("this build can't write tags".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    pub fn test_parse_tag_template() {
+        let tag_template: TagTemplate = "comment=bandcamp {sale_id}".parse().unwrap();
+        assert_eq!(tag_template.to_string(), "COMMENT=bandcamp {sale_id}");
+
+        let fields = TemplateFields {
+            sale_id: "p199396767",
+            ..TemplateFields::default()
+        };
+        assert_eq!(
+            tag_template.render(&fields),
+            ("COMMENT".to_owned(), "bandcamp p199396767".to_owned())
+        );
+    }
+
+    #[test_case("COMMENT", "expected FIELD=TEMPLATE")]
+    #[test_case("=x", "invalid field name")]
+    #[test_case("MY FIELD=x", "invalid field name" ; "field with a space")]
+    #[test_case("GROUPING={genre}", "unknown field")]
+    pub fn test_invalid_tag_template(tag_template: &str, error: &str) {
+        let e = tag_template.parse::<TagTemplate>().unwrap_err();
+        assert!(e.contains(error), "{e}");
+    }
+
+    #[test]
+    pub fn test_track_positions() {
+        let positions = track_positions(vec![
+            PathBuf::from("Galerie/02 Odyssée.flac"),
+            PathBuf::from("Galerie/01 Galerie.flac"),
+        ]);
+        assert_eq!(
+            positions,
+            [
+                (
+                    PathBuf::from("Galerie/01 Galerie.flac"),
+                    TrackPosition {
+                        track: 1,
+                        track_total: 2,
+                        disc: None
+                    }
+                ),
+                (
+                    PathBuf::from("Galerie/02 Odyssée.flac"),
+                    TrackPosition {
+                        track: 2,
+                        track_total: 2,
+                        disc: None
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_track_positions_by_disc() {
+        let positions = track_positions(vec![
+            PathBuf::from("Galerie/CD 2/01 Velours.flac"),
+            PathBuf::from("Galerie/CD 1/02 Odyssée.flac"),
+            PathBuf::from("Galerie/CD 1/01 Galerie.flac"),
+        ]);
+        let summary: Vec<_> = positions
+            .iter()
+            .map(|(path, position)| {
+                (
+                    path.file_name().unwrap().to_string_lossy().into_owned(),
+                    position.track,
+                    position.track_total,
+                    position.disc,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("01 Galerie.flac".to_owned(), 1, 2, Some((1, 2))),
+                ("02 Odyssée.flac".to_owned(), 2, 2, Some((1, 2))),
+                ("01 Velours.flac".to_owned(), 1, 1, Some((2, 2))),
+            ]
+        );
+    }
+
+    #[cfg(feature = "normalize-tags")]
+    #[test]
+    pub fn test_normalize_release() {
+        use lofty::{
+            file::TaggedFileExt,
+            tag::{Accessor, ItemKey},
+        };
+
+        // a FLAC file of no frames, with only its stream info and some padding
+        let mut flac = b"fLaC\x00\x00\x00\x22\x10\x00\x10\x00".to_vec();
+        flac.extend([0; 6]);
+        flac.extend([0x0a, 0xc4, 0x42, 0xf0, 0, 0, 0, 0]);
+        flac.extend([0; 16]);
+        flac.extend([0x81, 0, 0, 8]);
+        flac.extend([0; 8]);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("01 Galerie.flac"), &flac).unwrap();
+        std::fs::write(dir.path().join("02 Odyssée.flac"), &flac).unwrap();
+        std::fs::write(dir.path().join("03 Velours.flac"), b"not audio").unwrap();
+        // put there by hand, rather than coming from the archive
+        std::fs::write(dir.path().join("04 Bonus.flac"), &flac).unwrap();
+
+        let tags = ReleaseTags {
+            album_artist: "Anomalie".to_owned(),
+            album: "Galerie".to_owned(),
+            year: Some(2021),
+            custom: vec![
+                ("LABEL".to_owned(), "Nomad".to_owned()),
+                ("NOT A FIELD".to_owned(), "x".to_owned()),
+            ],
+        };
+        let entries = [
+            PathBuf::from("01 Galerie.flac"),
+            PathBuf::from("02 Odyssée.flac"),
+            PathBuf::from("03 Velours.flac"),
+            PathBuf::from("cover.jpg"),
+        ];
+        let report = TagNormalizer::new(true).normalize_release(dir.path(), &entries, &tags);
+        assert_eq!(report.normalized, 2);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.skipped_fields.len(), 2);
+
+        let tagged_file = lofty::read_from_path(dir.path().join("02 Odyssée.flac")).unwrap();
+        let tag = tagged_file.primary_tag().unwrap();
+        assert_eq!(tag.get_string(&ItemKey::AlbumArtist), Some("Anomalie"));
+        assert_eq!(tag.album().as_deref(), Some("Galerie"));
+        assert_eq!(tag.year(), Some(2021));
+        assert_eq!((tag.track(), tag.track_total()), (Some(2), Some(2)));
+        assert_eq!(tag.get_string(&ItemKey::Label), Some("Nomad"));
+
+        let untouched = lofty::read_from_path(dir.path().join("04 Bonus.flac")).unwrap();
+        assert!(untouched.primary_tag().is_none());
+    }
+}
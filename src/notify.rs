@@ -0,0 +1,95 @@
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::{friday::FridayOutlook, timestamp};
+
+// Native desktop notifications, so a sync left running in the background doesn't break silently.
+// Only available when built with the "notifications" feature.
+pub struct Notifier {
+    enabled: bool,
+}
+
+impl Notifier {
+    pub fn new(enabled: bool) -> Self {
+        if enabled && !cfg!(feature = "notifications") {
+            eprintln!("This build has no desktop notification support, ignoring --notify");
+        }
+
+        Self {
+            enabled: enabled && cfg!(feature = "notifications"),
+        }
+    }
+
+    #[cfg_attr(not(feature = "notifications"), allow(clippy::missing_const_for_fn))]
+    pub fn notify(&self, summary: &str, body: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        #[cfg(feature = "notifications")]
+        if let Err(e) = notify_rust::Notification::new()
+            .appname("bandcamp-dl")
+            .summary(summary)
+            .body(body)
+            .show()
+        {
+            eprintln!("Failed showing a desktop notification: {e}");
+        }
+
+        #[cfg(not(feature = "notifications"))]
+        let _ = (summary, body);
+    }
+}
+
+// The outcome of one sync in watch mode, as appended to the --notify-json file, one per line
+#[derive(Debug, Serialize)]
+pub struct SyncEvent {
+    // unix timestamp, in seconds, written as RFC 3339 in UTC
+    #[serde(with = "timestamp::rfc3339")]
+    pub finished_at: i64,
+    pub downloaded: Option<usize>,
+    pub error: Option<String>,
+    pub bandcamp_friday: FridayOutlook,
+}
+
+impl SyncEvent {
+    pub fn append_to(&self, path: &Path) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::{Date, Month};
+
+    #[test]
+    pub fn test_sync_event_json() {
+        let event = SyncEvent {
+            finished_at: 1_612_137_600,
+            downloaded: Some(2),
+            error: None,
+            bandcamp_friday: FridayOutlook::from_today(
+                Date::from_calendar_date(2021, Month::February, 1).unwrap(),
+                &[],
+            ),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "finished_at": "2021-02-01T00:00:00Z",
+                "downloaded": 2,
+                "error": null,
+                "bandcamp_friday": { "date": "2021-02-05", "days_until": 4, "near": true }
+            })
+        );
+    }
+}
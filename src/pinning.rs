@@ -0,0 +1,277 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+    SignatureScheme,
+};
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+use crate::error::TlsPinningError;
+
+const PIN_PREFIX: &str = "sha256//";
+
+const SEQUENCE_TAG: u8 = 0x30;
+const EXPLICIT_VERSION_TAG: u8 = 0xa0;
+
+// SHA-256 of a certificate's DER encoded SubjectPublicKeyInfo, written the same way as curl's
+// `--pinnedpubkey` ("sha256//<base64>") so existing pins can be reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsPin([u8; 32]);
+
+impl TlsPin {
+    pub fn from_certificate(certificate_der: &[u8]) -> Option<Self> {
+        subject_public_key_info(certificate_der).map(|spki| Self(Sha256::digest(spki).into()))
+    }
+}
+
+impl FromStr for TlsPin {
+    type Err = String;
+
+    fn from_str(pin: &str) -> Result<Self, Self::Err> {
+        let encoded = pin
+            .trim()
+            .strip_prefix(PIN_PREFIX)
+            .ok_or_else(|| format!("TLS pin \"{pin}\" should start with \"{PIN_PREFIX}\""))?;
+        let digest = BASE64
+            .decode(encoded)
+            .map_err(|e| format!("TLS pin \"{pin}\" isn't valid base64: {e}"))?;
+
+        digest
+            .try_into()
+            .map(Self)
+            .map_err(|_| format!("TLS pin \"{pin}\" isn't a SHA-256 digest"))
+    }
+}
+
+impl fmt::Display for TlsPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{PIN_PREFIX}{}", BASE64.encode(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for TlsPin {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// Checks the pins while the TLS handshake is still going, once the certificate chain was verified
+// the usual way, so nothing is sent to a server that doesn't match them
+#[derive(Debug)]
+pub struct PinnedCertVerifier {
+    pins: Vec<TlsPin>,
+    webpki: Arc<WebPkiServerVerifier>,
+}
+
+impl PinnedCertVerifier {
+    pub fn new(pins: Vec<TlsPin>, provider: Arc<CryptoProvider>) -> Result<Self, rustls::Error> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        Ok(Self { pins, webpki })
+    }
+
+    fn verify_pin(&self, host: &str, certificate_der: &[u8]) -> Result<(), TlsPinningError> {
+        let found = TlsPin::from_certificate(certificate_der)
+            .ok_or_else(|| TlsPinningError::InvalidCertificate(host.to_owned()))?;
+
+        if self.pins.contains(&found) {
+            Ok(())
+        } else {
+            Err(TlsPinningError::PinMismatch {
+                host: host.to_owned(),
+                found,
+            })
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        self.verify_pin(&server_name.to_str(), end_entity)
+            .map_err(|e| CertificateError::Other(OtherError(Arc::new(e))))?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+// For a reqwest client that only talks to servers matching one of `pins`
+pub fn pinned_tls_config(pins: Vec<TlsPin>) -> Result<ClientConfig, rustls::Error> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let verifier = PinnedCertVerifier::new(pins, Arc::clone(&provider))?;
+
+    Ok(ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+struct DerElement<'a> {
+    tag: u8,
+    encoded: &'a [u8],
+    contents: &'a [u8],
+    rest: &'a [u8],
+}
+
+// Splits off the DER element at the start of `input`, keeping whatever follows it in `rest`
+fn read_element(input: &[u8]) -> Option<DerElement<'_>> {
+    let (&tag, rest) = input.split_first()?;
+    let (&length_byte, rest) = rest.split_first()?;
+
+    let (length, rest) = if length_byte < 0x80 {
+        (usize::from(length_byte), rest)
+    } else {
+        let length_size = usize::from(length_byte & 0x7f);
+        if length_size == 0 || length_size > size_of::<usize>() {
+            return None;
+        }
+        let (length_bytes, rest) = rest.split_at_checked(length_size)?;
+        let length = length_bytes
+            .iter()
+            .fold(0, |length, byte| (length << 8) | usize::from(*byte));
+        (length, rest)
+    };
+
+    let header_size = input.len() - rest.len();
+    let (contents, rest) = rest.split_at_checked(length)?;
+    Some(DerElement {
+        tag,
+        encoded: &input[..header_size + length],
+        contents,
+        rest,
+    })
+}
+
+fn subject_public_key_info(certificate_der: &[u8]) -> Option<&[u8]> {
+    let certificate = read_element(certificate_der).filter(|e| e.tag == SEQUENCE_TAG)?;
+    let mut tbs_certificate = read_element(certificate.contents)
+        .filter(|e| e.tag == SEQUENCE_TAG)?
+        .contents;
+
+    // skip the optional version, then the serial number, signature algorithm, issuer, validity
+    // and subject that precede the public key
+    if tbs_certificate.first() == Some(&EXPLICIT_VERSION_TAG) {
+        tbs_certificate = read_element(tbs_certificate)?.rest;
+    }
+    for _ in 0..5 {
+        tbs_certificate = read_element(tbs_certificate)?.rest;
+    }
+
+    read_element(tbs_certificate)
+        .filter(|e| e.tag == SEQUENCE_TAG)
+        .map(|e| e.encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    const EXAMPLE_CERTIFICATE: &[u8] = include_bytes!("data/fake/example-certificate.der");
+    const EXAMPLE_PIN: &str = "sha256//QB1t9KHqaWsK1OGJCyV/ip261DFvz9zb23ITp8YlRjw=";
+
+    #[test]
+    pub fn test_pin_from_certificate() {
+        let pin = TlsPin::from_certificate(EXAMPLE_CERTIFICATE).unwrap();
+
+        assert_eq!(pin, EXAMPLE_PIN.parse().unwrap());
+        assert_eq!(pin.to_string(), EXAMPLE_PIN);
+    }
+
+    #[test]
+    pub fn test_pin_from_truncated_certificate() {
+        let truncated = &EXAMPLE_CERTIFICATE[..EXAMPLE_CERTIFICATE.len() / 2];
+
+        assert_eq!(TlsPin::from_certificate(truncated), None);
+        assert_eq!(TlsPin::from_certificate(&[]), None);
+    }
+
+    #[test]
+    pub fn test_verify_pin() {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let pinned =
+            PinnedCertVerifier::new(vec![EXAMPLE_PIN.parse().unwrap()], Arc::clone(&provider))
+                .unwrap();
+        let other = PinnedCertVerifier::new(
+            vec!["sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+                .parse()
+                .unwrap()],
+            provider,
+        )
+        .unwrap();
+
+        assert!(pinned
+            .verify_pin("bandcamp.com", EXAMPLE_CERTIFICATE)
+            .is_ok());
+        assert_matches!(
+            other.verify_pin("bandcamp.com", EXAMPLE_CERTIFICATE),
+            Err(TlsPinningError::PinMismatch { host, .. }) if host == "bandcamp.com"
+        );
+        assert_matches!(
+            pinned.verify_pin("bandcamp.com", &EXAMPLE_CERTIFICATE[..10]),
+            Err(TlsPinningError::InvalidCertificate(_))
+        );
+    }
+
+    #[test]
+    pub fn test_pinned_tls_config() {
+        assert!(pinned_tls_config(vec![EXAMPLE_PIN.parse().unwrap()]).is_ok());
+    }
+
+    #[test_case("QB1t9KHqaWsK1OGJCyV/ip261DFvz9zb23ITp8YlRjw="; "missing prefix")]
+    #[test_case("sha256//not base64!"; "invalid base64")]
+    #[test_case("sha256//QB1t9KHqaWsK1OGJ"; "wrong digest length")]
+    pub fn test_parse_invalid_pin(pin: &str) {
+        assert!(pin.parse::<TlsPin>().is_err());
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    api::data::{DigitalItem, DownloadData, DownloadFormat},
+    error::OfflineQueueError,
+    filter::Pattern,
+    models::{self, Versioned},
+    timestamp,
+};
+
+#[derive(Clone)]
+pub struct PlannedDownload {
+    pub sale_id: String,
+    pub item: DigitalItem,
+    pub format: DownloadFormat,
+}
+
+impl PlannedDownload {
+    pub fn has_format(&self, format: DownloadFormat) -> bool {
+        self.item
+            .downloads
+            .as_ref()
+            .is_some_and(|downloads| downloads.contains_key(&format))
+    }
+
+    pub fn expected_bytes(&self) -> Option<u64> {
+        self.item
+            .downloads
+            .as_ref()?
+            .get(&self.format)
+            .and_then(DownloadData::size_bytes)
+    }
+}
+
+// A release a dry run would download, as written to the --plan-json file
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct PlanEntry {
+    pub sale_id: String,
+    pub title: String,
+    pub artist: String,
+    pub format: &'static str,
+    // the path it would be saved under, within one of the download folders
+    pub file_name: String,
+    pub expected_bytes: Option<u64>,
+}
+
+impl PlanEntry {
+    pub fn new(planned: &PlannedDownload, file_name: &str) -> Self {
+        Self {
+            sale_id: planned.sale_id.clone(),
+            title: planned.item.title.clone(),
+            artist: planned.item.artist.clone(),
+            format: planned.format.name(),
+            file_name: file_name.to_owned(),
+            expected_bytes: planned.expected_bytes(),
+        }
+    }
+}
+
+// The --plan-json file. Version 0 was the bare list of releases.
+#[derive(Serialize, JsonSchema)]
+pub struct Plan {
+    pub releases: Vec<PlanEntry>,
+}
+
+impl Versioned for Plan {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+// A release queued by --offline-queue, with the signed download link it was resolved to
+#[derive(Serialize, Deserialize)]
+pub struct QueuedDownload {
+    pub item: DigitalItem,
+    pub format: DownloadFormat,
+    pub url: String,
+}
+
+// Resolved on one run and downloaded by `execute-plan` on a later one, possibly once the links
+// expired, which are then resolved again
+#[derive(Serialize, Deserialize)]
+pub struct OfflineQueue {
+    // when the links were resolved, as a unix timestamp, recorded as RFC 3339
+    #[serde(with = "timestamp::rfc3339")]
+    pub resolved_at: i64,
+    // by sale id
+    pub downloads: BTreeMap<String, QueuedDownload>,
+}
+
+impl Versioned for OfflineQueue {
+    const SCHEMA_VERSION: u32 = 3;
+
+    // Versions 1 and 2 kept their number in a field of their own, and listed the downloads with
+    // their sale ids. Version 1 recorded `resolved_at` as a unix timestamp, which is still read.
+    fn upgrade(from_version: u32, mut value: Value) -> Value {
+        if from_version != 0 {
+            return value;
+        }
+        if let Value::Object(fields) = &mut value {
+            fields.remove("version");
+            if let Some(Value::Array(downloads)) = fields.remove("downloads") {
+                let downloads: serde_json::Map<_, _> = downloads
+                    .into_iter()
+                    .filter_map(|download| {
+                        let sale_id = download.get("sale_id")?.as_str()?.to_owned();
+                        Some((sale_id, download))
+                    })
+                    .collect();
+                fields.insert("downloads".to_owned(), downloads.into());
+            }
+        }
+        value
+    }
+}
+
+impl OfflineQueue {
+    pub const fn new(resolved_at: i64, downloads: BTreeMap<String, QueuedDownload>) -> Self {
+        Self {
+            resolved_at,
+            downloads,
+        }
+    }
+
+    pub fn read(queue_data: &str) -> Result<Self, OfflineQueueError> {
+        Ok(models::read_versioned(queue_data)?)
+    }
+
+    pub fn serialize(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&models::versioned_value(self)?)
+    }
+
+    pub fn get(&self, sale_id: &str) -> Option<&QueuedDownload> {
+        self.downloads.get(sale_id)
+    }
+
+    // The queued items, to be planned like newly found ones
+    pub fn items(&self) -> HashMap<String, DigitalItem> {
+        self.downloads
+            .iter()
+            .map(|(sale_id, queued)| (sale_id.clone(), queued.item.clone()))
+            .collect()
+    }
+}
+
+pub trait SelectionPolicy: Send + Sync {
+    fn apply(&self, plan: Vec<PlannedDownload>) -> Vec<PlannedDownload>;
+}
+
+pub struct DownloadPlanner {
+    format: DownloadFormat,
+    policies: Vec<Box<dyn SelectionPolicy>>,
+}
+
+impl DownloadPlanner {
+    pub fn new(format: DownloadFormat) -> Self {
+        Self {
+            format,
+            policies: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with<P: SelectionPolicy + 'static>(mut self, policy: P) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    // Policies are applied in insertion order, on top of a plan sorted by sale id
+    pub fn plan(&self, items: HashMap<String, DigitalItem>) -> Vec<PlannedDownload> {
+        let mut plan: Vec<_> = items
+            .into_iter()
+            .map(|(sale_id, item)| PlannedDownload {
+                sale_id,
+                item,
+                format: self.format,
+            })
+            .collect();
+        plan.sort_by(|a, b| a.sale_id.cmp(&b.sale_id));
+
+        self.policies
+            .iter()
+            .fold(plan, |plan, policy| policy.apply(plan))
+    }
+}
+
+// What the artist and title filters look at, both in the collection items a sync narrows down
+// before fetching their download pages and in the downloads it plans from them
+pub trait Named {
+    fn artist(&self) -> &str;
+    fn title(&self) -> &str;
+}
+
+impl Named for PlannedDownload {
+    fn artist(&self) -> &str {
+        &self.item.artist
+    }
+
+    fn title(&self) -> &str {
+        &self.item.title
+    }
+}
+
+pub type BoxedPredicate<T = PlannedDownload> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+
+pub struct Filter<F> {
+    predicate: F,
+}
+
+impl<F> Filter<F> {
+    pub const fn new(predicate: F) -> Self {
+        Self { predicate }
+    }
+
+    pub fn matches<T>(&self, item: &T) -> bool
+    where
+        F: Fn(&T) -> bool,
+    {
+        (self.predicate)(item)
+    }
+}
+
+impl<T: Named> Filter<BoxedPredicate<T>> {
+    pub fn artist(pattern: Pattern) -> Self {
+        Self::new(Box::new(move |item: &T| pattern.is_match(item.artist())))
+    }
+
+    pub fn title(pattern: Pattern) -> Self {
+        Self::new(Box::new(move |item: &T| pattern.is_match(item.title())))
+    }
+}
+
+impl Filter<BoxedPredicate> {
+    pub fn item_type(item_type: &str) -> Self {
+        let item_type = item_type.to_owned();
+        Self::new(Box::new(move |download| {
+            download.item.item_type == item_type
+        }))
+    }
+}
+
+impl<F: Fn(&PlannedDownload) -> bool + Send + Sync> SelectionPolicy for Filter<F> {
+    fn apply(&self, plan: Vec<PlannedDownload>) -> Vec<PlannedDownload> {
+        plan.into_iter().filter(|d| self.matches(d)).collect()
+    }
+}
+
+pub struct SortBy<F> {
+    compare: F,
+}
+
+impl<F: Fn(&PlannedDownload, &PlannedDownload) -> Ordering + Send + Sync> SortBy<F> {
+    pub const fn new(compare: F) -> Self {
+        Self { compare }
+    }
+}
+
+impl<F: Fn(&PlannedDownload, &PlannedDownload) -> Ordering + Send + Sync> SelectionPolicy
+    for SortBy<F>
+{
+    fn apply(&self, mut plan: Vec<PlannedDownload>) -> Vec<PlannedDownload> {
+        plan.sort_by(|a, b| (self.compare)(a, b));
+        plan
+    }
+}
+
+pub struct Quota {
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl Quota {
+    pub const fn new(offset: usize, limit: Option<usize>) -> Self {
+        Self { offset, limit }
+    }
+}
+
+impl SelectionPolicy for Quota {
+    fn apply(&self, plan: Vec<PlannedDownload>) -> Vec<PlannedDownload> {
+        plan.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+pub struct FormatFallback {
+    fallbacks: Vec<DownloadFormat>,
+}
+
+impl FormatFallback {
+    pub const fn new(fallbacks: Vec<DownloadFormat>) -> Self {
+        Self { fallbacks }
+    }
+}
+
+impl SelectionPolicy for FormatFallback {
+    fn apply(&self, plan: Vec<PlannedDownload>) -> Vec<PlannedDownload> {
+        plan.into_iter()
+            .map(|mut download| {
+                if !download.has_format(download.format) {
+                    if let Some(format) = self.fallbacks.iter().find(|f| download.has_format(**f)) {
+                        download.format = *format;
+                    }
+                }
+                download
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digital_item(artist: &str, title: &str, formats: &[DownloadFormat]) -> DigitalItem {
+        DigitalItem {
+            downloads: Some(
+                formats
+                    .iter()
+                    .map(|format| {
+                        (
+                            *format,
+                            DownloadData {
+                                size_mb: None,
+                                description: String::new(),
+                                encoding_name: format.to_string(),
+                                url: format!("https://bandcamp.com/download?format={format}"),
+                            },
+                        )
+                    })
+                    .collect(),
+            ),
+            package_release_date: None,
+            title: title.to_owned(),
+            artist: artist.to_owned(),
+            download_type: "a".to_owned(),
+            download_type_str: "album".to_owned(),
+            item_type: "album".to_owned(),
+            art_id: 0,
+            page_url: None,
+        }
+    }
+
+    fn sample_items() -> HashMap<String, DigitalItem> {
+        HashMap::from([
+            (
+                "p3".to_owned(),
+                digital_item("Anomalie", "Galerie", &[DownloadFormat::Flac]),
+            ),
+            (
+                "p1".to_owned(),
+                digital_item("Apparat", "Silizium", &[DownloadFormat::Mp3_320]),
+            ),
+            (
+                "p2".to_owned(),
+                digital_item("Anomalie", "Odyssée", &[DownloadFormat::Flac]),
+            ),
+        ])
+    }
+
+    fn sale_ids(plan: &[PlannedDownload]) -> Vec<&str> {
+        plan.iter().map(|d| d.sale_id.as_str()).collect()
+    }
+
+    #[test]
+    pub fn test_plan_without_policies_is_sorted() {
+        let plan = DownloadPlanner::new(DownloadFormat::Flac).plan(sample_items());
+
+        assert_eq!(sale_ids(&plan), ["p1", "p2", "p3"]);
+        assert!(plan.iter().all(|d| d.format == DownloadFormat::Flac));
+    }
+
+    #[test]
+    pub fn test_plan_filter_artist() {
+        let plan = DownloadPlanner::new(DownloadFormat::Flac)
+            .with(Filter::<BoxedPredicate>::artist(
+                Pattern::parse("glob:anomalie").unwrap(),
+            ))
+            .plan(sample_items());
+
+        assert_eq!(sale_ids(&plan), ["p2", "p3"]);
+
+        let plan = DownloadPlanner::new(DownloadFormat::Flac)
+            .with(Filter::<BoxedPredicate>::title(
+                Pattern::parse("glob:s*").unwrap(),
+            ))
+            .plan(sample_items());
+
+        assert_eq!(sale_ids(&plan), ["p1"]);
+    }
+
+    #[test]
+    pub fn test_plan_custom_filter_and_order() {
+        let plan = DownloadPlanner::new(DownloadFormat::Flac)
+            .with(Filter::new(|d: &PlannedDownload| d.item.title != "Galerie"))
+            .with(SortBy::new(|a: &PlannedDownload, b: &PlannedDownload| {
+                b.sale_id.cmp(&a.sale_id)
+            }))
+            .plan(sample_items());
+
+        assert_eq!(sale_ids(&plan), ["p2", "p1"]);
+    }
+
+    #[test]
+    pub fn test_plan_quota() {
+        let plan = DownloadPlanner::new(DownloadFormat::Flac)
+            .with(Quota::new(1, Some(1)))
+            .plan(sample_items());
+
+        assert_eq!(sale_ids(&plan), ["p2"]);
+    }
+
+    #[test]
+    pub fn test_plan_format_fallback() {
+        let plan = DownloadPlanner::new(DownloadFormat::Flac)
+            .with(FormatFallback::new(vec![
+                DownloadFormat::Alac,
+                DownloadFormat::Mp3_320,
+            ]))
+            .plan(sample_items());
+
+        assert_eq!(plan[0].sale_id, "p1");
+        assert_eq!(plan[0].format, DownloadFormat::Mp3_320);
+        assert_eq!(plan[1].format, DownloadFormat::Flac);
+        assert_eq!(plan[2].format, DownloadFormat::Flac);
+    }
+
+    #[test]
+    pub fn test_offline_queue_round_trip() {
+        let item = digital_item("Anomalie", "Galerie", &[DownloadFormat::Flac]);
+        let queue = OfflineQueue::new(
+            1_700_000_000,
+            BTreeMap::from([
+                (
+                    "p3".to_owned(),
+                    QueuedDownload {
+                        item: item.clone(),
+                        format: DownloadFormat::Flac,
+                        url: "https://p4.bcbits.com/download/album/p3?token=1700000000_abc"
+                            .to_owned(),
+                    },
+                ),
+                (
+                    "p1".to_owned(),
+                    QueuedDownload {
+                        item,
+                        format: DownloadFormat::Mp3_320,
+                        url: "https://p4.bcbits.com/download/album/p1?token=1700000000_def"
+                            .to_owned(),
+                    },
+                ),
+            ]),
+        );
+
+        let queue = OfflineQueue::read(&queue.serialize().unwrap()).unwrap();
+        assert_eq!(queue.resolved_at, 1_700_000_000);
+        assert_eq!(queue.get("p1").unwrap().format, DownloadFormat::Mp3_320);
+        assert!(queue.get("p2").is_none());
+        assert_eq!(queue.items().len(), 2);
+    }
+
+    #[test]
+    pub fn test_read_listed_offline_queue() {
+        let item = serde_json::to_value(digital_item("Anomalie", "Galerie", &[])).unwrap();
+        let queue = OfflineQueue::read(
+            &serde_json::json!({
+                "version": 1,
+                "resolved_at": 1_700_000_000,
+                "downloads": [
+                    { "sale_id": "p1", "item": item, "format": "flac", "url": "https://p4.bcbits.com/download/album/p1" },
+                ],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(queue.resolved_at, 1_700_000_000);
+        assert_eq!(queue.get("p1").unwrap().format, DownloadFormat::Flac);
+
+        assert!(OfflineQueue::read(
+            r#"{"schema_version": 4, "resolved_at": 0, "downloads": [1, 2]}"#
+        )
+        .is_err());
+    }
+}
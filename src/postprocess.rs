@@ -0,0 +1,156 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::Serialize;
+
+use crate::{
+    api::data::{DigitalItem, DownloadFormat},
+    error::PostProcessError,
+};
+
+// Bumped whenever a field changes meaning or goes away. New fields may be added without a bump,
+// so post-processors should ignore the ones they don't know.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Written as a single line of JSON to the post-processor's stdin, which is closed afterwards
+#[derive(Debug, Serialize)]
+pub struct CompletedRelease<'a> {
+    pub version: u32,
+    pub release_id: &'a str,
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub year: Option<i32>,
+    pub format: &'static str,
+    pub page_url: Option<&'a str>,
+    pub path: &'a Path,
+}
+
+impl<'a> CompletedRelease<'a> {
+    pub fn new(
+        release_id: &'a str,
+        item: &'a DigitalItem,
+        format: DownloadFormat,
+        path: &'a Path,
+    ) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            release_id,
+            title: &item.title,
+            artist: &item.artist,
+            year: item.release_year(),
+            format: format.name(),
+            page_url: item.page_url.as_deref(),
+            path,
+        }
+    }
+}
+
+// An external program run once per completed release, in the folder the release was saved to.
+// It shares our stdout and stderr, and fails by exiting with a non-zero status.
+pub struct PostProcessor {
+    program: PathBuf,
+}
+
+impl PostProcessor {
+    pub const fn new(program: PathBuf) -> Self {
+        Self { program }
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    pub fn run(&self, release: &CompletedRelease) -> Result<(), PostProcessError> {
+        let mut input = serde_json::to_vec(release)?;
+        input.push(b'\n');
+
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .current_dir(release.path.parent().unwrap_or_else(|| Path::new(".")))
+            .spawn()?;
+        // a program that doesn't read its input may close stdin early, which isn't an error
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(e) = stdin.write_all(&input) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(PostProcessError::Failed(status.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn digital_item() -> DigitalItem {
+        serde_json::from_value(serde_json::json!({
+            "downloads": null,
+            "package_release_date": "01 Apr 2024 00:00:00 GMT",
+            "title": "Galerie",
+            "artist": "Anomalie",
+            "download_type": "a",
+            "download_type_str": "album",
+            "item_type": "album",
+            "art_id": 1,
+            "page_url": "https://anomalie.bandcamp.com/album/galerie"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    pub fn test_completed_release_json() {
+        let item = digital_item();
+        let release = CompletedRelease::new(
+            "p199396767",
+            &item,
+            DownloadFormat::Flac,
+            Path::new("/music/p199396767-(flac).zip"),
+        );
+
+        assert_eq!(
+            serde_json::to_value(&release).unwrap(),
+            serde_json::json!({
+                "version": 1,
+                "release_id": "p199396767",
+                "title": "Galerie",
+                "artist": "Anomalie",
+                "year": 2024,
+                "format": "flac",
+                "page_url": "https://anomalie.bandcamp.com/album/galerie",
+                "path": "/music/p199396767-(flac).zip"
+            })
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_run() {
+        let item = digital_item();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("p199396767-(flac).zip");
+        let release = CompletedRelease::new("p199396767", &item, DownloadFormat::Flac, &path);
+
+        assert!(PostProcessor::new("cat".into()).run(&release).is_ok());
+        assert!(PostProcessor::new("true".into()).run(&release).is_ok());
+        assert_matches!(
+            PostProcessor::new("false".into()).run(&release),
+            Err(PostProcessError::Failed(_))
+        );
+        assert_matches!(
+            PostProcessor::new("/does/not/exist".into()).run(&release),
+            Err(PostProcessError::IoError(_))
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::{controls, download::Download, staging};
+
+// Told about the progress of each stage of a sync, so front ends can show it however they like.
+// Called from several tasks at once, and for downloads started in parallel, so implementations
+// only get `&self`. Every method does nothing by default.
+pub trait ProgressReporter: Send + Sync {
+    // A page of the collection was listed, with this many items on it
+    fn collection_page_fetched(&self, _items: usize) {}
+
+    // The download link of a release was found
+    fn item_resolved(&self, _release_id: &str) {}
+
+    // Some bytes of a release were written to disk
+    fn bytes_downloaded(&self, _release_id: &str, _bytes: u64) {}
+
+    // A release was fully downloaded
+    fn item_downloaded(&self, _release_id: &str) {}
+
+    // A downloaded release was unpacked into this many files
+    fn item_extracted(&self, _release_id: &str, _files: usize) {}
+}
+
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgressTotals {
+    pub pages: u64,
+    pub items_listed: u64,
+    pub items_resolved: u64,
+    pub bytes_downloaded: u64,
+    pub items_downloaded: u64,
+    pub items_extracted: u64,
+}
+
+// Running totals, for front ends that would rather poll than be called
+#[derive(Default)]
+pub struct ProgressCounters {
+    pages: AtomicU64,
+    items_listed: AtomicU64,
+    items_resolved: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    items_downloaded: AtomicU64,
+    items_extracted: AtomicU64,
+}
+
+impl ProgressCounters {
+    pub fn totals(&self) -> ProgressTotals {
+        ProgressTotals {
+            pages: self.pages.load(Ordering::Relaxed),
+            items_listed: self.items_listed.load(Ordering::Relaxed),
+            items_resolved: self.items_resolved.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            items_downloaded: self.items_downloaded.load(Ordering::Relaxed),
+            items_extracted: self.items_extracted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ProgressReporter for ProgressCounters {
+    fn collection_page_fetched(&self, items: usize) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.items_listed.fetch_add(items as u64, Ordering::Relaxed);
+    }
+
+    fn item_resolved(&self, _release_id: &str) {
+        self.items_resolved.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn bytes_downloaded(&self, _release_id: &str, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn item_downloaded(&self, _release_id: &str) {
+        self.items_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn item_extracted(&self, _release_id: &str, _files: usize) {
+        self.items_extracted.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// indicatif templates, whose placeholders only look like format arguments
+#[allow(clippy::literal_string_with_formatting_args)]
+const OVERALL_TEMPLATE: &str = "{pos}/{len} releases [{bar:30}] {msg}, {eta} left";
+#[allow(clippy::literal_string_with_formatting_args)]
+const SIZED_FILE_TEMPLATE: &str =
+    "{msg} [{bar:30}] {bytes}/{total_bytes} at {binary_bytes_per_sec}, {eta} left";
+const UNSIZED_FILE_TEMPLATE: &str = "{msg} {bytes} at {binary_bytes_per_sec}";
+
+// A bar for each download in flight above one for the whole run, on stderr when it's a terminal.
+// The downloaders report to it as they write, and it passes everything on to `reporter`.
+pub struct DownloadProgress {
+    bars: MultiProgress,
+    overall: ProgressBar,
+    started: Instant,
+    bytes: AtomicU64,
+    // of the downloads being followed, by release id
+    file_bars: Mutex<HashMap<String, ProgressBar>>,
+    reporter: Arc<dyn ProgressReporter>,
+}
+
+impl DownloadProgress {
+    pub fn new(releases: usize) -> Self {
+        let bars = MultiProgress::new();
+        let overall = bars.add(
+            ProgressBar::new(releases as u64).with_style(
+                ProgressStyle::with_template(OVERALL_TEMPLATE)
+                    .unwrap()
+                    .progress_chars("=> "),
+            ),
+        );
+        Self {
+            bars,
+            overall,
+            started: Instant::now(),
+            bytes: AtomicU64::new(0),
+            file_bars: Mutex::default(),
+            reporter: Arc::new(NoProgress),
+        }
+    }
+
+    #[must_use]
+    pub fn with_reporter(mut self, reporter: Arc<dyn ProgressReporter>) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    // For printing without the bars getting in the way
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.bars.suspend(|| controls::with_line_output(f))
+    }
+
+    // Shows a bar for each of the downloads, with its expected size, until they're done
+    pub async fn follow<F: Future>(
+        &self,
+        downloads: &[(Download, Option<u64>)],
+        future: F,
+    ) -> F::Output {
+        for (download, expected_bytes) in downloads {
+            let bar = self
+                .bars
+                .insert_before(&self.overall, file_bar(download, *expected_bytes));
+            self.file_bars().insert(download.release_id.clone(), bar);
+        }
+
+        let output = future.await;
+
+        for (download, _) in downloads {
+            let bar = self.file_bars().remove(&download.release_id);
+            if let Some(bar) = bar {
+                bar.finish_and_clear();
+            }
+        }
+        output
+    }
+
+    fn file_bars(&self) -> MutexGuard<'_, HashMap<String, ProgressBar>> {
+        self.file_bars
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl ProgressReporter for DownloadProgress {
+    fn collection_page_fetched(&self, items: usize) {
+        self.reporter.collection_page_fetched(items);
+    }
+
+    fn item_resolved(&self, release_id: &str) {
+        self.reporter.item_resolved(release_id);
+    }
+
+    fn bytes_downloaded(&self, release_id: &str, bytes: u64) {
+        let bar = self.file_bars().get(release_id).cloned();
+        if let Some(bar) = bar {
+            bar.inc(bytes);
+        }
+        let total = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.overall
+            .set_message(transfer_summary(total, self.started.elapsed()));
+        self.reporter.bytes_downloaded(release_id, bytes);
+    }
+
+    fn item_downloaded(&self, release_id: &str) {
+        self.overall.inc(1);
+        self.reporter.item_downloaded(release_id);
+    }
+
+    fn item_extracted(&self, release_id: &str, files: usize) {
+        self.reporter.item_extracted(release_id, files);
+    }
+}
+
+impl Drop for DownloadProgress {
+    fn drop(&mut self) {
+        self.overall.finish_and_clear();
+    }
+}
+
+fn file_bar(download: &Download, expected_bytes: Option<u64>) -> ProgressBar {
+    let bar = expected_bytes.map_or_else(ProgressBar::no_length, ProgressBar::new);
+    let template = if expected_bytes.is_some() {
+        SIZED_FILE_TEMPLATE
+    } else {
+        UNSIZED_FILE_TEMPLATE
+    };
+    bar.with_style(
+        ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars("=> "),
+    )
+    .with_message(staging::completed_file_name(&download.filename).to_owned())
+}
+
+fn transfer_summary(bytes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    let per_second = if seconds > 0.0 {
+        (bytes as f64 / seconds) as u64
+    } else {
+        0
+    };
+    format!("{} at {}/s", HumanBytes(bytes), HumanBytes(per_second))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_transfer_summary() {
+        assert_eq!(
+            transfer_summary(300 * 1024 * 1024, Duration::from_secs(10)),
+            "300.00 MiB at 30.00 MiB/s"
+        );
+        assert_eq!(transfer_summary(0, Duration::ZERO), "0 B at 0 B/s");
+    }
+
+    #[tokio::test]
+    pub async fn test_download_progress() {
+        let counters = Arc::new(ProgressCounters::default());
+        let progress = DownloadProgress::new(1).with_reporter(counters.clone());
+        let download = Download::new(
+            "p1".to_owned(),
+            "https://bandcamp.com/download".parse().unwrap(),
+            "p1-flac.zip.part".to_owned(),
+        );
+
+        let files = [(download, Some(7))];
+        progress
+            .follow(&files, async {
+                progress.bytes_downloaded("p1", 4);
+                assert_eq!(progress.file_bars()["p1"].position(), 4);
+                progress.bytes_downloaded("p1", 3);
+            })
+            .await;
+        assert!(progress.file_bars().is_empty());
+
+        progress.item_downloaded("p1");
+        assert_eq!(
+            counters.totals(),
+            ProgressTotals {
+                bytes_downloaded: 7,
+                items_downloaded: 1,
+                ..ProgressTotals::default()
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_counters_from_several_threads() {
+        let counters = Arc::new(ProgressCounters::default());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let counters = Arc::clone(&counters);
+                std::thread::spawn(move || {
+                    counters.collection_page_fetched(10);
+                    counters.item_resolved("p1");
+                    counters.bytes_downloaded("p1", 1_000);
+                    counters.bytes_downloaded("p1", 500);
+                    counters.item_downloaded("p1");
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(
+            counters.totals(),
+            ProgressTotals {
+                pages: 4,
+                items_listed: 40,
+                items_resolved: 4,
+                bytes_downloaded: 6_000,
+                items_downloaded: 4,
+                items_extracted: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt::Write;
+
+use htmlize::{escape_attribute, escape_text};
+use time::OffsetDateTime;
+
+use crate::{
+    api::{
+        data::{DigitalItem, DownloadFormat},
+        endpoints,
+    },
+    timestamp,
+};
+
+pub const HTML_REPORT_FILE_NAME: &str = "bandcamp-dl-report.html";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportStatus {
+    Downloaded,
+    // downloaded, but with fewer tracks than the release has
+    Incomplete,
+    // failed, or left for a later run by the time budget
+    NotDownloaded,
+}
+
+pub struct ReportEntry {
+    pub sale_id: String,
+    pub title: String,
+    pub artist: String,
+    pub art_id: i64,
+    pub page_url: Option<String>,
+    pub format: DownloadFormat,
+    pub status: ReportStatus,
+}
+
+// What a sync run tried to fetch, and how that went
+#[derive(Default)]
+pub struct SyncReport {
+    entries: Vec<ReportEntry>,
+}
+
+impl SyncReport {
+    pub fn add(&mut self, sale_id: &str, digital_item: &DigitalItem, format: DownloadFormat) {
+        self.entries.push(ReportEntry {
+            sale_id: sale_id.to_owned(),
+            title: digital_item.title.clone(),
+            artist: digital_item.artist.clone(),
+            art_id: digital_item.art_id,
+            page_url: digital_item.page_url.clone(),
+            format,
+            status: ReportStatus::NotDownloaded,
+        });
+    }
+
+    pub fn mark_downloaded(&mut self, sale_id: &str) {
+        for entry in self.entries.iter_mut().filter(|e| e.sale_id == sale_id) {
+            entry.status = ReportStatus::Downloaded;
+        }
+    }
+
+    pub fn mark_incomplete(&mut self, sale_id: &str) {
+        for entry in self.entries.iter_mut().filter(|e| e.sale_id == sale_id) {
+            entry.status = ReportStatus::Incomplete;
+        }
+    }
+
+    pub fn entries(&self) -> &[ReportEntry] {
+        &self.entries
+    }
+
+    fn count(&self, status: ReportStatus) -> usize {
+        self.entries.iter().filter(|e| e.status == status).count()
+    }
+
+    // A single self contained page, readable on a phone
+    pub fn render_html(&self, generated_at: OffsetDateTime) -> String {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| (&a.artist, &a.title).cmp(&(&b.artist, &b.title)));
+
+        let mut items = String::new();
+        for entry in entries {
+            let (status_class, status_label) = match entry.status {
+                ReportStatus::Downloaded => ("downloaded", "Downloaded"),
+                ReportStatus::Incomplete => ("missing", "Incomplete"),
+                ReportStatus::NotDownloaded => ("missing", "Not downloaded"),
+            };
+            let art = if entry.art_id > 0 {
+                format!(
+                    r#"<img src="{}" alt="" width="80" height="80" loading="lazy">"#,
+                    art_thumbnail_url(entry.art_id)
+                )
+            } else {
+                r#"<div class="no-art"></div>"#.to_owned()
+            };
+            let title = escape_text(&entry.title);
+            let title = entry.page_url.as_deref().map_or_else(
+                || title.to_string(),
+                |page_url| format!(r#"<a href="{}">{title}</a>"#, escape_attribute(page_url)),
+            );
+
+            let _ = writeln!(
+                items,
+                r#"<li class="{status_class}">{art}<div class="info"><div class="title">{title}</div><div class="artist">{}</div><div class="format">{}</div></div><span class="status">{status_label}</span></li>"#,
+                escape_text(&entry.artist),
+                escape_text(entry.format.to_string()),
+            );
+        }
+
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>bandcamp-dl sync report</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 0 auto; max-width: 48rem; padding: 1rem; }}
+ul {{ list-style: none; padding: 0; }}
+li {{ display: flex; align-items: center; gap: 0.75rem; padding: 0.5rem 0; border-bottom: 1px solid #ddd; }}
+img, .no-art {{ width: 80px; height: 80px; flex-shrink: 0; border-radius: 4px; background: #eee; }}
+.info {{ flex-grow: 1; min-width: 0; }}
+.title {{ font-weight: bold; }}
+.artist, .format, .summary {{ color: #666; }}
+.status {{ font-size: 0.85rem; white-space: nowrap; }}
+.downloaded .status {{ color: #2e7d32; }}
+.missing .status {{ color: #c62828; }}
+</style>
+</head>
+<body>
+<h1>Sync report</h1>
+<p class="summary">{} downloaded, {}{} not downloaded &middot; <time datetime="{}">{}</time></p>
+<ul>
+{items}</ul>
+</body>
+</html>
+"#,
+            self.count(ReportStatus::Downloaded),
+            match self.count(ReportStatus::Incomplete) {
+                0 => String::new(),
+                incomplete => format!("{incomplete} incomplete, "),
+            },
+            self.count(ReportStatus::NotDownloaded),
+            timestamp::to_rfc3339(generated_at),
+            timestamp::format_local(generated_at),
+        )
+    }
+}
+
+fn art_thumbnail_url(art_id: i64) -> String {
+    endpoints::art_url(art_id, 7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digital_item(title: &str, artist: &str, art_id: i64, page_url: Option<&str>) -> DigitalItem {
+        DigitalItem {
+            downloads: None,
+            package_release_date: None,
+            title: title.to_owned(),
+            artist: artist.to_owned(),
+            download_type: "a".to_owned(),
+            download_type_str: "album".to_owned(),
+            item_type: "album".to_owned(),
+            art_id,
+            page_url: page_url.map(str::to_owned),
+        }
+    }
+
+    fn sample_report() -> SyncReport {
+        let mut report = SyncReport::default();
+        report.add(
+            "p2",
+            &digital_item(
+                "Galerie",
+                "Anomalie",
+                1_234_567,
+                Some("https://anomalie.bandcamp.com/album/galerie"),
+            ),
+            DownloadFormat::Flac,
+        );
+        report.add(
+            "p1",
+            &digital_item("<Silizium> & more", "Apparat", 0, None),
+            DownloadFormat::Mp3_320,
+        );
+        report.mark_downloaded("p2");
+        report
+    }
+
+    #[test]
+    pub fn test_mark_downloaded() {
+        let report = sample_report();
+
+        let statuses: Vec<_> = report
+            .entries()
+            .iter()
+            .map(|e| (e.sale_id.as_str(), e.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("p2", ReportStatus::Downloaded),
+                ("p1", ReportStatus::NotDownloaded)
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_render_html() {
+        let html = sample_report()
+            .render_html(OffsetDateTime::from_unix_timestamp(1_609_459_200).unwrap());
+
+        assert!(html.contains(
+            r#"1 downloaded, 1 not downloaded &middot; <time datetime="2021-01-01T00:00:00Z">2021-01-01 00:00 UTC</time>"#
+        ));
+        assert!(html.contains(r#"<img src="https://f4.bcbits.com/img/a0001234567_7.jpg""#));
+        assert!(
+            html.contains(r#"<a href="https://anomalie.bandcamp.com/album/galerie">Galerie</a>"#)
+        );
+        assert!(html.contains("&lt;Silizium&gt; &amp; more"));
+        assert!(html.contains(r#"<div class="no-art"></div>"#));
+
+        // sorted by artist
+        assert!(html.find("Anomalie").unwrap() < html.find("Apparat").unwrap());
+    }
+
+    #[test]
+    pub fn test_render_html_incomplete() {
+        let mut report = sample_report();
+        report.mark_incomplete("p1");
+        let html = report.render_html(OffsetDateTime::from_unix_timestamp(1_609_459_200).unwrap());
+
+        assert!(html.contains("1 downloaded, 1 incomplete, 0 not downloaded"));
+        assert!(html.contains(r#"<span class="status">Incomplete</span>"#));
+    }
+}
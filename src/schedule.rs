@@ -0,0 +1,222 @@
+use std::{
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+use time::OffsetDateTime;
+
+pub mod cron;
+
+use cron::CronSchedule;
+
+// Accepts plain seconds ("90") or unit suffixed parts ("1h30m", "45s")
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    if let Ok(seconds) = duration.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut number = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("missing number before '{c}' in \"{duration}\""))?;
+        let unit_seconds = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(format!("unknown duration unit '{c}' in \"{duration}\"")),
+        };
+        total += Duration::from_secs(value * unit_seconds);
+        number.clear();
+    }
+
+    if !number.is_empty() || total.is_zero() {
+        return Err(format!("invalid duration \"{duration}\""));
+    }
+    Ok(total)
+}
+
+// The inverse of `parse_duration`, e.g. "1h30m"
+pub fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    if seconds == 0 {
+        return "0s".to_owned();
+    }
+
+    let mut formatted = String::new();
+    for (unit, unit_seconds) in [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60), ('s', 1)] {
+        if seconds >= unit_seconds {
+            let _ = write!(formatted, "{}{unit}", seconds / unit_seconds);
+            seconds %= unit_seconds;
+        }
+    }
+    formatted
+}
+
+// When watch mode syncs next: a fixed time after the previous sync, or at a cron schedule's next
+// match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchSchedule {
+    Every(Duration),
+    Cron(CronSchedule),
+}
+
+impl WatchSchedule {
+    // `None` for a cron schedule that never matches again
+    pub fn delay_after(&self, now: OffsetDateTime) -> Option<Duration> {
+        match self {
+            Self::Every(interval) => Some(*interval),
+            Self::Cron(schedule) => schedule
+                .next_after(now)
+                .map(|next| (next - now).try_into().unwrap_or_default()),
+        }
+    }
+}
+
+// Up to `max_jitter` on top of `delay`, so that many installs on the same schedule don't all hit
+// Bandcamp at the same second
+pub fn with_jitter(delay: Duration, max_jitter: Duration) -> Duration {
+    delay + Duration::from_secs(fastrand::u64(0..=max_jitter.as_secs()))
+}
+
+// Started along with the sync, so the time spent finding releases counts against it too. The
+// throughput is only measured over the downloads themselves.
+pub struct TimeBudget {
+    deadline: Instant,
+    bytes_downloaded: u64,
+    download_time: Duration,
+}
+
+impl TimeBudget {
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + budget,
+            bytes_downloaded: 0,
+            download_time: Duration::ZERO,
+        }
+    }
+
+    pub fn record_downloaded(&mut self, bytes: u64, elapsed: Duration) {
+        self.bytes_downloaded += bytes;
+        self.download_time += elapsed;
+    }
+
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.deadline.saturating_duration_since(now)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn bytes_per_second(&self) -> Option<f64> {
+        let elapsed = self.download_time.as_secs_f64();
+        (self.bytes_downloaded > 0 && elapsed > 0.0).then(|| self.bytes_downloaded as f64 / elapsed)
+    }
+
+    // Whether a download of about `expected_bytes` is expected to finish before the deadline.
+    // Until some throughput has been observed, anything may start while time remains.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn can_start(&self, now: Instant, expected_bytes: Option<u64>) -> bool {
+        let remaining = self.remaining(now);
+        if remaining.is_zero() {
+            return false;
+        }
+
+        match (expected_bytes, self.bytes_per_second()) {
+            (Some(expected_bytes), Some(bytes_per_second)) => {
+                expected_bytes as f64 / bytes_per_second <= remaining.as_secs_f64()
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("90", 90)]
+    #[test_case("45s", 45)]
+    #[test_case("30m", 30 * 60)]
+    #[test_case("1h30m", 90 * 60)]
+    #[test_case("1d2h", 26 * 60 * 60)]
+    pub fn test_parse_duration(duration: &str, seconds: u64) {
+        assert_eq!(parse_duration(duration), Ok(Duration::from_secs(seconds)));
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("h"; "unit without number")]
+    #[test_case("1h30"; "trailing number")]
+    #[test_case("10w"; "unknown unit")]
+    pub fn test_parse_duration_invalid(duration: &str) {
+        assert!(parse_duration(duration).is_err());
+    }
+
+    #[test_case(0, "0s")]
+    #[test_case(45, "45s")]
+    #[test_case(90 * 60, "1h30m")]
+    #[test_case(26 * 60 * 60 + 5, "1d2h5s")]
+    pub fn test_format_duration(seconds: u64, expected: &str) {
+        assert_eq!(format_duration(Duration::from_secs(seconds)), expected);
+        if seconds > 0 {
+            assert_eq!(parse_duration(expected), Ok(Duration::from_secs(seconds)));
+        }
+    }
+
+    #[test]
+    pub fn test_watch_schedule_delay() {
+        let now = OffsetDateTime::from_unix_timestamp(1_609_459_200).unwrap() // 2021-01-01 00:00
+            + Duration::from_secs(30);
+
+        assert_eq!(
+            WatchSchedule::Every(Duration::from_hours(6)).delay_after(now),
+            Some(Duration::from_hours(6))
+        );
+        assert_eq!(
+            WatchSchedule::Cron("0 3 * * *".parse().unwrap()).delay_after(now),
+            Some(Duration::from_hours(3) - Duration::from_secs(30))
+        );
+        assert_eq!(
+            WatchSchedule::Cron("0 0 31 2 *".parse().unwrap()).delay_after(now),
+            None
+        );
+    }
+
+    #[test]
+    pub fn test_with_jitter() {
+        let delay = Duration::from_mins(1);
+        assert_eq!(with_jitter(delay, Duration::ZERO), delay);
+
+        let jittered = with_jitter(delay, Duration::from_mins(10));
+        assert!(jittered >= delay && jittered <= delay + Duration::from_mins(10));
+    }
+
+    #[test]
+    pub fn test_time_budget_without_throughput() {
+        let budget = TimeBudget::new(Duration::from_mins(1));
+        let now = Instant::now();
+
+        assert!(budget.can_start(now, Some(u64::MAX)));
+        assert!(budget.can_start(now, None));
+        assert!(!budget.can_start(now + Duration::from_mins(1), None));
+    }
+
+    #[test]
+    pub fn test_time_budget_skips_large_items_near_deadline() {
+        let mut budget = TimeBudget::new(Duration::from_mins(1));
+        budget.record_downloaded(25 * 1_000_000, Duration::from_secs(25));
+
+        // 1 MB/s over 25 seconds of downloading, with about 10 seconds left
+        let now = Instant::now() + Duration::from_secs(50);
+        assert!(budget.can_start(now, Some(5 * 1_000_000)));
+        assert!(!budget.can_start(now, Some(2_000 * 1_000_000)));
+        assert!(budget.can_start(now, None));
+    }
+}
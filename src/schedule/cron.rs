@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use time::{Date, Duration, Month, OffsetDateTime, Time, UtcOffset};
+
+// How far ahead a schedule is searched before it's taken to never match (e.g. "0 0 31 2 *")
+const MAX_SEARCH_YEARS: i32 = 5;
+
+// The allowed values of one field, as a bit set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Field {
+    values: u64,
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(field: &str, min: u8, max: u8) -> Result<Self, String> {
+        let mut values = 0;
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u8>()
+                        .ok()
+                        .filter(|&step| step > 0)
+                        .ok_or_else(|| format!("invalid step \"{step}\""))?,
+                ),
+                None => (item, 1),
+            };
+
+            let parse_value = |value: &str| {
+                value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|value| (min..=max).contains(value))
+                    .ok_or_else(|| format!("\"{value}\" isn't between {min} and {max}"))
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (parse_value(start)?, parse_value(end)?)
+            } else {
+                let start = parse_value(range)?;
+                // "5/15" runs from 5 to the end, like most crons do
+                (start, if item.contains('/') { max } else { start })
+            };
+            if start > end {
+                return Err(format!("range \"{range}\" is backwards"));
+            }
+
+            for value in (start..=end).step_by(usize::from(step)) {
+                values |= 1 << value;
+            }
+        }
+
+        Ok(Self {
+            values,
+            restricted: field != "*",
+        })
+    }
+
+    fn parse_named(name: &str, field: &str, min: u8, max: u8) -> Result<Self, String> {
+        Self::parse(field, min, max).map_err(|e| format!("invalid {name} field: {e}"))
+    }
+
+    const fn contains(self, value: u8) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+// A standard five field cron expression (minute, hour, day of month, month, day of week),
+// evaluated in UTC
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday) in \"{expression}\""
+            ));
+        };
+
+        let mut weekdays = Field::parse_named("weekday", weekdays, 0, 7)?;
+        // both 0 and 7 are sunday
+        if weekdays.contains(7) {
+            weekdays.values = (weekdays.values | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: Field::parse_named("minute", minutes, 0, 59)?,
+            hours: Field::parse_named("hour", hours, 0, 23)?,
+            days: Field::parse_named("day", days, 1, 31)?,
+            months: Field::parse_named("month", months, 1, 12)?,
+            weekdays,
+        })
+    }
+}
+
+impl CronSchedule {
+    // When both days of the month and of the week are given, either may match, as in cron
+    const fn matches_date(&self, date: Date) -> bool {
+        let day = self.days.contains(date.day());
+        let weekday = self
+            .weekdays
+            .contains(date.weekday().number_days_from_sunday());
+
+        match (self.days.restricted, self.weekdays.restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    // The first matching minute strictly after `after`
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let after = after.to_offset(UtcOffset::UTC);
+        let mut next = after.replace_time(Time::from_hms(after.hour(), after.minute(), 0).ok()?)
+            + Duration::minutes(1);
+        let last_year = after.year() + MAX_SEARCH_YEARS;
+
+        while next.year() <= last_year {
+            if !self.months.contains(u8::from(next.month())) {
+                let (year, month) = match next.month() {
+                    Month::December => (next.year() + 1, Month::January),
+                    month => (next.year(), month.next()),
+                };
+                next = next.replace_date(Date::from_calendar_date(year, month, 1).ok()?);
+                next = next.replace_time(Time::MIDNIGHT);
+            } else if !self.matches_date(next.date()) {
+                next = next.replace_time(Time::MIDNIGHT) + Duration::days(1);
+            } else if !self.hours.contains(next.hour()) {
+                next =
+                    next.replace_time(Time::from_hms(next.hour(), 0, 0).ok()?) + Duration::hours(1);
+            } else if !self.minutes.contains(next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    fn at(year: i32, month: u8, day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, Month::try_from(month).unwrap(), day)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test_case("0 3 * * *", at(2021, 1, 1, 12, 34), at(2021, 1, 2, 3, 0); "daily")]
+    #[test_case("0 3 * * *", at(2021, 1, 1, 2, 59) + Duration::seconds(59), at(2021, 1, 1, 3, 0); "later today")]
+    #[test_case("0 3 * * *", at(2021, 1, 1, 3, 0), at(2021, 1, 2, 3, 0); "strictly after")]
+    #[test_case("*/15 * * * *", at(2021, 1, 1, 12, 34), at(2021, 1, 1, 12, 45); "step")]
+    #[test_case("30 1-5/2 * * *", at(2021, 1, 1, 3, 31), at(2021, 1, 1, 5, 30); "range with step")]
+    #[test_case("0 0 1 */3 *", at(2021, 2, 10, 0, 0), at(2021, 4, 1, 0, 0); "quarterly")]
+    #[test_case("0 4 * * 0", at(2021, 1, 1, 0, 0), at(2021, 1, 3, 4, 0); "sunday")]
+    #[test_case("0 4 * * 7", at(2021, 1, 1, 0, 0), at(2021, 1, 3, 4, 0); "sunday as 7")]
+    #[test_case("0 0 13 * 5", at(2021, 1, 2, 0, 0), at(2021, 1, 8, 0, 0); "day or weekday")]
+    #[test_case("0 0 29 2 *", at(2021, 1, 1, 0, 0), at(2024, 2, 29, 0, 0); "leap day")]
+    #[test_case("59 23 31 12 *", at(2021, 12, 31, 23, 59), at(2022, 12, 31, 23, 59); "year end")]
+    pub fn test_next_after(expression: &str, after: OffsetDateTime, expected: OffsetDateTime) {
+        let schedule: CronSchedule = expression.parse().unwrap();
+        assert_eq!(schedule.next_after(after), Some(expected));
+    }
+
+    #[test]
+    pub fn test_never_matches() {
+        let schedule: CronSchedule = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(schedule.next_after(at(2021, 1, 1, 0, 0)), None);
+    }
+
+    #[test_case(""; "empty")]
+    #[test_case("0 3 * *"; "too few fields")]
+    #[test_case("0 3 * * * *"; "too many fields")]
+    #[test_case("60 * * * *"; "minute out of range")]
+    #[test_case("0 * 0 * *"; "day out of range")]
+    #[test_case("*/0 * * * *"; "zero step")]
+    #[test_case("0 5-1 * * *"; "backwards range")]
+    #[test_case("0 3 * jan *"; "names")]
+    pub fn test_invalid(expression: &str) {
+        assert!(expression.parse::<CronSchedule>().is_err());
+    }
+}
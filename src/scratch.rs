@@ -0,0 +1,263 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    archive::UPDATE_SCRATCH_DIR_NAME,
+    backpressure, download, layout,
+    models::{self, Versioned},
+    timestamp,
+};
+
+pub const SCRATCH_FILE_NAME: &str = "scratch.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScratchKind {
+    // A folder downloads are written to as ".part" files until they complete
+    Staging,
+    // A file of signed download links written by --offline-queue, which expire
+    OfflineQueue,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScratchEntry {
+    pub kind: ScratchKind,
+    pub path: PathBuf,
+    // unix timestamp, in seconds, recorded as RFC 3339
+    #[serde(with = "timestamp::rfc3339")]
+    pub used_at: i64,
+}
+
+// The temporary data syncs of a library left outside of its download folder, so `clean` finds it
+// again wherever --tmp-dir or --staging-dir put it
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScratchRegistry {
+    entries: Vec<ScratchEntry>,
+}
+
+impl ScratchRegistry {
+    pub fn record(&mut self, kind: ScratchKind, path: &Path, now: OffsetDateTime) {
+        let used_at = now.unix_timestamp();
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.kind == kind && entry.path == path)
+        {
+            Some(entry) => entry.used_at = used_at,
+            None => self.entries.push(ScratchEntry {
+                kind,
+                path: path.to_owned(),
+                used_at,
+            }),
+        }
+    }
+
+    pub fn forget(&mut self, kind: ScratchKind, path: &Path) {
+        self.entries
+            .retain(|entry| entry.kind != kind || entry.path != path);
+    }
+
+    pub fn entries(&self) -> &[ScratchEntry] {
+        &self.entries
+    }
+}
+
+impl Versioned for ScratchRegistry {
+    const SCHEMA_VERSION: u32 = 1;
+}
+
+pub fn read_registry(registry_data: &str) -> Result<ScratchRegistry, serde_json::Error> {
+    models::read_versioned(registry_data)
+}
+
+pub fn serialize_registry(registry: &ScratchRegistry) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(&models::versioned_value(registry)?)
+}
+
+// Reads, changes and writes the registry of `state_dir` under a lock, so runs sharing the state
+// folder don't drop each other's entries. A broken registry starts over, as it only helps
+// cleaning up.
+pub fn update_registry<T>(
+    state_dir: &Path,
+    update: impl FnOnce(&mut ScratchRegistry) -> T,
+) -> io::Result<T> {
+    std::fs::create_dir_all(state_dir)?;
+    let registry_path = state_dir.join(SCRATCH_FILE_NAME);
+    let lock = File::create(state_dir.join(format!("{SCRATCH_FILE_NAME}.lock")))?;
+    lock.lock()?;
+
+    let mut registry = std::fs::read_to_string(&registry_path)
+        .ok()
+        .and_then(|registry_data| read_registry(&registry_data).ok())
+        .unwrap_or_default();
+    let result = update(&mut registry);
+    std::fs::write(&registry_path, serialize_registry(&registry)?)?;
+    Ok(result)
+}
+
+// The partial downloads under `folder` (including the segments of chunked downloads), write
+// probes and the scratch folders of cut short extractions, untouched since `cutoff`. Only names
+// this tool gives are matched, and the staging folders within `folder` are left to be looked at on
+// their own.
+pub fn stale_artifacts(
+    folder: &Path,
+    staging_dirs: &[PathBuf],
+    cutoff: SystemTime,
+) -> io::Result<Vec<PathBuf>> {
+    let mut stale = Vec::new();
+    let mut folders = vec![folder.to_owned()];
+    while let Some(folder) = folders.pop() {
+        for (path, is_dir) in stale_entries(&folder, cutoff, &mut stale)? {
+            if is_dir && !staging_dirs.contains(&path) {
+                folders.push(path);
+            }
+        }
+    }
+    stale.sort();
+    Ok(stale)
+}
+
+// Only the artifacts right in a staging folder, which --staging-dir may have put in a folder
+// shared with other things
+pub fn stale_staged_artifacts(folder: &Path, cutoff: SystemTime) -> io::Result<Vec<PathBuf>> {
+    let mut stale = Vec::new();
+    stale_entries(folder, cutoff, &mut stale)?;
+    stale.sort();
+    Ok(stale)
+}
+
+// Adds the stale artifacts of `folder` to `stale`, returning the rest of its entries, each along
+// with whether it's a folder. Entries removed while looking are skipped.
+fn stale_entries(
+    folder: &Path,
+    cutoff: SystemTime,
+    stale: &mut Vec<PathBuf>,
+) -> io::Result<Vec<(PathBuf, bool)>> {
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut others = Vec::new();
+    for entry in entries {
+        let (path, metadata) = match entry.and_then(|entry| Ok((entry.path(), entry.metadata()?))) {
+            Ok(entry) => entry,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let is_stale = metadata.modified()? < cutoff;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let is_artifact = if metadata.is_dir() {
+            file_name == UPDATE_SCRATCH_DIR_NAME
+        } else {
+            metadata.is_file() && is_partial_file_name(&file_name)
+        };
+
+        if !is_artifact {
+            others.push((path, metadata.is_dir()));
+        } else if is_stale {
+            stale.push(path);
+        }
+    }
+    Ok(others)
+}
+
+// A release downloading, a segment of one, or a write probe
+fn is_partial_file_name(file_name: &str) -> bool {
+    if file_name == backpressure::PROBE_FILE_NAME {
+        return true;
+    }
+    download::segment_source(file_name)
+        .or_else(|| file_name.strip_suffix(".part"))
+        .is_some_and(layout::is_release_file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(unix_timestamp: i64) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(unix_timestamp).unwrap()
+    }
+
+    #[test]
+    pub fn test_record_and_forget() {
+        let mut registry = ScratchRegistry::default();
+        registry.record(ScratchKind::Staging, Path::new("/tmp/a"), at(1));
+        registry.record(ScratchKind::OfflineQueue, Path::new("/tmp/a"), at(2));
+        registry.record(ScratchKind::Staging, Path::new("/tmp/a"), at(3));
+
+        assert_eq!(registry.entries().len(), 2);
+        assert_eq!(registry.entries()[0].used_at, 3);
+
+        registry.forget(ScratchKind::Staging, Path::new("/tmp/a"));
+        assert_eq!(registry.entries().len(), 1);
+        assert_eq!(registry.entries()[0].kind, ScratchKind::OfflineQueue);
+    }
+
+    #[test]
+    pub fn test_update_registry() {
+        let dir = tempfile::tempdir().unwrap();
+
+        update_registry(dir.path(), |registry| {
+            registry.record(ScratchKind::Staging, Path::new("/tmp/a"), at(1));
+        })
+        .unwrap();
+        let entries = update_registry(dir.path(), |registry| registry.entries().to_vec()).unwrap();
+        assert_eq!(entries.len(), 1);
+
+        let serialized = std::fs::read_to_string(dir.path().join(SCRATCH_FILE_NAME)).unwrap();
+        assert!(serialized.contains("\"staging\""));
+        assert!(serialized.contains("\"schema_version\": 1"));
+
+        std::fs::write(dir.path().join(SCRATCH_FILE_NAME), "not json").unwrap();
+        let entries = update_registry(dir.path(), |registry| registry.entries().len()).unwrap();
+        assert_eq!(entries, 0);
+    }
+
+    #[test]
+    pub fn test_stale_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging_dir = dir.path().join(".bandcamp-dl").join("staging");
+        std::fs::create_dir_all(dir.path().join("Anomalie").join(UPDATE_SCRATCH_DIR_NAME)).unwrap();
+        std::fs::create_dir_all(&staging_dir).unwrap();
+        std::fs::write(dir.path().join("p1-(flac).zip.part"), b"PK").unwrap();
+        std::fs::write(dir.path().join("p1-(flac).zip.0-9-of-20.part"), b"PK").unwrap();
+        std::fs::write(dir.path().join("p2-(flac).zip"), b"PK").unwrap();
+        std::fs::write(dir.path().join(backpressure::PROBE_FILE_NAME), b"").unwrap();
+        std::fs::write(dir.path().join("Anomalie").join("01.flac.part"), b"fLaC").unwrap();
+        std::fs::write(staging_dir.join("p3-(flac).zip.part"), b"PK").unwrap();
+
+        let later = SystemTime::now() + Duration::from_mins(1);
+        assert_eq!(
+            stale_artifacts(dir.path(), std::slice::from_ref(&staging_dir), later).unwrap(),
+            [
+                dir.path().join(backpressure::PROBE_FILE_NAME),
+                dir.path().join("Anomalie").join(UPDATE_SCRATCH_DIR_NAME),
+                dir.path().join("p1-(flac).zip.0-9-of-20.part"),
+                dir.path().join("p1-(flac).zip.part"),
+            ]
+        );
+        assert_eq!(
+            stale_staged_artifacts(&staging_dir, later).unwrap(),
+            [staging_dir.join("p3-(flac).zip.part")]
+        );
+        assert!(stale_staged_artifacts(&dir.path().join("missing"), later)
+            .unwrap()
+            .is_empty());
+        let earlier = SystemTime::now() - Duration::from_mins(1);
+        assert!(stale_artifacts(dir.path(), &[], earlier)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{cookies::RawCookie, error::SessionStoreError};
+
+// The identity cookie is all Bandcamp needs to recognize a session, so it's the only one kept in
+// the OS keyring. Only available when built with the "keyring" feature.
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "bandcamp-dl";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = crate::cookies::IDENTITY_COOKIE_NAME;
+
+// Stored as JSON, so its expiration and domain come back along with the value
+pub fn save_identity(cookie: &RawCookie) -> Result<(), SessionStoreError> {
+    write_keyring(&serde_json::to_string(cookie)?)
+}
+
+pub fn load_identity() -> Result<RawCookie, SessionStoreError> {
+    Ok(serde_json::from_str(&read_keyring()?)?)
+}
+
+// A cookie export with only the identity cookie, in the JSON format cookie files are read in
+pub fn identity_cookie_data(cookie: &RawCookie) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&[cookie])
+}
+
+#[cfg(feature = "keyring")]
+fn write_keyring(secret: &str) -> Result<(), SessionStoreError> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?.set_password(secret)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "keyring"))]
+#[allow(clippy::missing_const_for_fn)]
+fn write_keyring(_: &str) -> Result<(), SessionStoreError> {
+    Err(SessionStoreError::Unsupported)
+}
+
+#[cfg(feature = "keyring")]
+fn read_keyring() -> Result<String, SessionStoreError> {
+    match keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?.get_password() {
+        Ok(secret) => Ok(secret),
+        Err(keyring::Error::NoEntry) => Err(SessionStoreError::NotSaved),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+#[allow(clippy::missing_const_for_fn)]
+fn read_keyring() -> Result<String, SessionStoreError> {
+    Err(SessionStoreError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookies;
+
+    #[test]
+    pub fn test_identity_cookie_data() {
+        let mut cookie = RawCookie::new("identity".to_owned(), "abc".to_owned());
+        cookie.host = Some(".bandcamp.com".to_owned());
+        cookie.expires = Some("1919434332".to_owned());
+
+        let cookie_data = identity_cookie_data(&cookie).unwrap();
+        let cookie_store = cookies::read_json_file(&cookie_data, "https://bandcamp.com").unwrap();
+        let names: Vec<_> = cookie_store.iter_any().map(|c| c.name()).collect();
+        assert_eq!(names, ["identity"]);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    api::{data, Collection, SaleIdUrlMap},
+    cache::{self, DownloadCache},
+    models::{self, Versioned},
+    timestamp,
+};
+
+pub const SNAPSHOT_FILE_NAME: &str = "collection-snapshot.json";
+
+// The collection as last seen online, so it can still be looked at while Bandcamp is unreachable
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CollectionSnapshot {
+    // unix timestamp, in seconds, recorded as RFC 3339
+    #[serde(with = "timestamp::rfc3339")]
+    pub taken_at: i64,
+    pub fan_id: i64,
+    pub username: String,
+    pub download_urls: SaleIdUrlMap,
+    pub items: Vec<data::CollectionItem>,
+    // unknown in older snapshots, and when hidden items weren't listed
+    pub hidden: Option<BTreeSet<String>>,
+}
+
+pub struct SnapshotRelease<'a> {
+    pub sale_id: &'a str,
+    pub download_url: &'a str,
+    pub item: Option<&'a data::CollectionItem>,
+}
+
+impl CollectionSnapshot {
+    pub fn new(
+        summary: &data::ParsedFanCollectionSummary,
+        collection: Collection,
+        taken_at: OffsetDateTime,
+    ) -> Self {
+        Self {
+            taken_at: taken_at.unix_timestamp(),
+            fan_id: summary.fan_id,
+            username: summary.collection_summary.username.clone(),
+            download_urls: collection.download_urls,
+            items: collection.items,
+            hidden: collection.hidden,
+        }
+    }
+
+    pub fn taken_at(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::from_unix_timestamp(self.taken_at).ok()
+    }
+
+    // Releases listed by both snapshots that were hidden or unhidden since the previous one, with
+    // whether they're hidden now. Only told when both of them listed hidden items.
+    pub fn scope_changes<'a>(&'a self, previous: &Self) -> Vec<(&'a str, bool)> {
+        let (Some(hidden), Some(previously_hidden)) = (&self.hidden, &previous.hidden) else {
+            return Vec::new();
+        };
+
+        let mut changes: Vec<_> = self
+            .download_urls
+            .keys()
+            .filter(|sale_id| previous.download_urls.contains_key(*sale_id))
+            .filter_map(|sale_id| {
+                let is_hidden = hidden.contains(sale_id);
+                (is_hidden != previously_hidden.contains(sale_id))
+                    .then_some((sale_id.as_str(), is_hidden))
+            })
+            .collect();
+        changes.sort_unstable();
+        changes
+    }
+
+    // Releases with a download that aren't in the download cache yet, by artist and title
+    pub fn pending_releases(&self, download_cache: &DownloadCache) -> Vec<SnapshotRelease<'_>> {
+        let mut pending: Vec<_> = self
+            .download_urls
+            .iter()
+            .filter(|(sale_id, _)| {
+                !download_cache.contains_key(&cache::normalize_release_id(sale_id))
+            })
+            .map(|(sale_id, download_url)| SnapshotRelease {
+                sale_id,
+                download_url,
+                item: self
+                    .items
+                    .iter()
+                    .find(|item| item.sale_key().as_deref() == Some(sale_id.as_str())),
+            })
+            .collect();
+
+        pending.sort_by(|a, b| {
+            let key = |release: &SnapshotRelease| {
+                release
+                    .item
+                    .map(|item| (item.band_name.clone(), item.item_title.clone()))
+            };
+            (key(a), a.sale_id).cmp(&(key(b), b.sale_id))
+        });
+        pending
+    }
+}
+
+// Version 0 had the same fields, only without the version. Up to version 1, `taken_at` was
+// recorded as a unix timestamp, which is still read as is.
+impl Versioned for CollectionSnapshot {
+    const SCHEMA_VERSION: u32 = 2;
+}
+
+pub fn read_snapshot(snapshot_data: &str) -> Result<CollectionSnapshot, serde_json::Error> {
+    models::read_versioned(snapshot_data)
+}
+
+pub fn serialize_snapshot(snapshot: &CollectionSnapshot) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&models::versioned_value(snapshot)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection_item(
+        sale_item_id: i64,
+        band_name: &str,
+        item_title: &str,
+    ) -> data::CollectionItem {
+        serde_json::from_value(serde_json::json!({
+            "sale_item_id": sale_item_id,
+            "sale_item_type": "p",
+            "band_name": band_name,
+            "item_title": item_title,
+        }))
+        .unwrap()
+    }
+
+    fn snapshot() -> CollectionSnapshot {
+        CollectionSnapshot {
+            taken_at: 1_609_459_200,
+            fan_id: 1234,
+            username: "fan".to_owned(),
+            download_urls: [
+                ("p1", "https://bandcamp.com/download?id=1"),
+                ("p2", "https://bandcamp.com/download?id=2"),
+                ("p3", "https://bandcamp.com/download?id=3"),
+                ("p4", "https://bandcamp.com/download?id=4"),
+            ]
+            .into_iter()
+            .map(|(sale_id, url)| (sale_id.to_owned(), url.to_owned()))
+            .collect(),
+            items: vec![
+                collection_item(1, "Apparat", "Silizium"),
+                collection_item(2, "Anomalie", "Galerie"),
+                collection_item(3, "Camellia", "Toxic Violet Cubes"),
+                // streaming only, without a download
+                collection_item(5, "Anomalie", "Odyssée"),
+            ],
+            hidden: Some(BTreeSet::from(["p2".to_owned()])),
+        }
+    }
+
+    #[test]
+    pub fn test_pending_releases() {
+        let download_cache =
+            cache::read_download_cache("3| \"Toxic Violet Cubes\" (2021) by Camellia").unwrap();
+        let snapshot = snapshot();
+
+        let pending: Vec<_> = snapshot
+            .pending_releases(&download_cache)
+            .iter()
+            .map(|release| {
+                (
+                    release.sale_id,
+                    release.item.map(|item| item.item_title.as_str()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            pending,
+            [
+                ("p4", None),
+                ("p2", Some("Galerie")),
+                ("p1", Some("Silizium"))
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_scope_changes() {
+        let previous = snapshot();
+        let mut current = snapshot();
+        current.hidden = Some(BTreeSet::from(["p1".to_owned(), "p5".to_owned()]));
+        current.download_urls.insert(
+            "p5".to_owned(),
+            "https://bandcamp.com/download?id=5".to_owned(),
+        );
+
+        // p5 is new rather than hidden since
+        assert_eq!(
+            current.scope_changes(&previous),
+            [("p1", true), ("p2", false)]
+        );
+
+        let older = CollectionSnapshot {
+            hidden: None,
+            ..snapshot()
+        };
+        assert!(current.scope_changes(&older).is_empty());
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let snapshot = read_snapshot(&serialize_snapshot(&snapshot()).unwrap()).unwrap();
+
+        assert_eq!(snapshot.username, "fan");
+        assert_eq!(snapshot.download_urls.len(), 4);
+        assert_eq!(snapshot.items.len(), 4);
+        assert_eq!(
+            snapshot.taken_at(),
+            OffsetDateTime::from_unix_timestamp(1_609_459_200).ok()
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    fmt::Write,
+    io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use sha2::{Digest, Sha256};
+
+static TEMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// Set once at startup from --tmp-dir, for everything that would go to the system temp folder
+pub fn set_temp_dir(dir: PathBuf) {
+    let _ = TEMP_DIR.set(dir);
+}
+
+pub fn temp_dir() -> PathBuf {
+    TEMP_DIR.get().cloned().unwrap_or_else(std::env::temp_dir)
+}
+
+// Each download folder stages in its own folder of the temp folder, so syncs of different
+// libraries running at once never write to the same partial file
+pub fn temp_staging_dir(destination: &Path) -> PathBuf {
+    let destination = std::path::absolute(destination).unwrap_or_else(|_| destination.to_owned());
+    let digest = Sha256::digest(destination.as_os_str().as_encoded_bytes());
+    let key = digest[..6].iter().fold(String::new(), |mut key, byte| {
+        let _ = write!(key, "{byte:02x}");
+        key
+    });
+    temp_dir().join("bandcamp-dl").join(key)
+}
+
+// Where downloads are staged on the destination itself
+pub fn local_staging_dir(destination: &Path) -> PathBuf {
+    destination.join(".bandcamp-dl").join("staging")
+}
+
+// A download is written under this name until it's complete and verified, so one cut short never
+// carries the name of a finished file, e.g. "p1-flac.zip.part"
+pub fn partial_file_name(filename: &str) -> String {
+    format!("{filename}.part")
+}
+
+// The name a partial file gets once complete
+pub fn completed_file_name(partial_file_name: &str) -> &str {
+    partial_file_name
+        .strip_suffix(".part")
+        .unwrap_or(partial_file_name)
+}
+
+// Gives a partial file its final name, in the folder it was downloaded to
+pub fn complete_in_place(folder: &Path, partial_file_name: &str) -> io::Result<PathBuf> {
+    let final_path = folder.join(completed_file_name(partial_file_name));
+    std::fs::rename(folder.join(partial_file_name), &final_path)?;
+    Ok(final_path)
+}
+
+// Written aside first, so an interrupted write never leaves a truncated file behind. Named like a
+// partial download, so `clean` finds what's left of one.
+pub fn write_atomically(path: &Path, data: impl AsRef<[u8]>) -> io::Result<()> {
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".part");
+    std::fs::write(&partial_path, data)?;
+    std::fs::rename(partial_path, path)
+}
+
+// Where downloads are written until they're complete, so the download folder never holds a
+// partial file. Staging on the destination's filesystem keeps the final move a cheap rename.
+pub struct StagingArea {
+    dir: PathBuf,
+}
+
+impl StagingArea {
+    // Prefers the system temp folder when it shares the destination's filesystem, then a folder
+    // on the destination itself, then staging in place for mounts where neither can be used. A
+    // temp folder set with --tmp-dir is used wherever it is, as a scratch disk.
+    pub fn for_destination(destination: &Path, override_dir: Option<&Path>) -> Self {
+        if let Some(override_dir) = override_dir {
+            return Self::create_or_in_place(override_dir.to_owned(), destination);
+        }
+        if TEMP_DIR.get().is_some() {
+            return Self::create_or_in_place(temp_staging_dir(destination), destination);
+        }
+
+        let system_temp = temp_staging_dir(destination);
+        if same_filesystem(&std::env::temp_dir(), destination) == Some(true)
+            && std::fs::create_dir_all(&system_temp).is_ok()
+        {
+            return Self { dir: system_temp };
+        }
+
+        Self::create_or_in_place(local_staging_dir(destination), destination)
+    }
+
+    fn create_or_in_place(dir: PathBuf, destination: &Path) -> Self {
+        match std::fs::create_dir_all(&dir) {
+            Ok(()) => Self { dir },
+            Err(e) => {
+                eprintln!(
+                    "Failed creating the staging folder {}, downloading in place instead: {e}",
+                    dir.display()
+                );
+                Self {
+                    dir: destination.to_owned(),
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    // Where `filename` is written until it's committed
+    pub fn partial_path(&self, filename: &str) -> PathBuf {
+        self.dir.join(partial_file_name(filename))
+    }
+
+    // Moves a completed file to `final_path`, creating its folders, and copying it when the rename
+    // can't be done (e.g. across filesystems)
+    pub fn commit(&self, filename: &str, final_path: &Path) -> io::Result<()> {
+        let staged_path = self.partial_path(filename);
+        let destination = final_path.parent().unwrap_or_else(|| Path::new(""));
+        std::fs::create_dir_all(destination)?;
+        match std::fs::rename(&staged_path, final_path) {
+            Ok(()) => return Ok(()),
+            Err(e) if self.dir == destination => return Err(e),
+            Err(_) => {}
+        }
+
+        // copied next to the destination first, so an interrupted copy isn't taken as done
+        let copied_path = destination.join(partial_file_name(filename));
+        std::fs::copy(&staged_path, &copied_path)?;
+        std::fs::rename(copied_path, final_path)?;
+        std::fs::remove_file(&staged_path)?;
+        Ok(())
+    }
+}
+
+// `None` when it can't be told, such as on platforms without device ids
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let a = std::fs::metadata(a).ok()?;
+    let b = std::fs::metadata(b).ok()?;
+    Some(a.dev() == b.dev())
+}
+
+#[cfg(not(unix))]
+pub fn same_filesystem(_: &Path, _: &Path) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_override_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = StagingArea::for_destination(dir.path(), Some(&dir.path().join("staging")));
+
+        assert_eq!(staging.path(), dir.path().join("staging"));
+        assert!(staging.path().is_dir());
+    }
+
+    #[test]
+    pub fn test_temp_staging_dir() {
+        let library = temp_staging_dir(Path::new("/music/library"));
+        assert!(library.starts_with(temp_dir().join("bandcamp-dl")));
+        assert_eq!(library, temp_staging_dir(Path::new("/music/library")));
+        assert_ne!(library, temp_staging_dir(Path::new("/music/other")));
+    }
+
+    #[test]
+    pub fn test_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("music");
+        std::fs::create_dir_all(&destination).unwrap();
+        let staging = StagingArea::for_destination(&destination, Some(&dir.path().join("staging")));
+
+        std::fs::write(staging.partial_path("p1-flac.zip"), b"PK").unwrap();
+        let final_path = destination.join("Anomalie").join("2021 - Galerie.zip");
+        staging.commit("p1-flac.zip", &final_path).unwrap();
+
+        assert_eq!(std::fs::read(&final_path).unwrap(), b"PK");
+        assert!(!staging.partial_path("p1-flac.zip").exists());
+    }
+
+    #[test]
+    pub fn test_commit_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = StagingArea {
+            dir: dir.path().to_owned(),
+        };
+
+        std::fs::write(dir.path().join("p1-flac.zip.part"), b"PK").unwrap();
+        assert!(!dir.path().join("p1-flac.zip").exists());
+        staging
+            .commit("p1-flac.zip", &dir.path().join("p1-flac.zip"))
+            .unwrap();
+        assert!(dir.path().join("p1-flac.zip").exists());
+        assert!(!dir.path().join("p1-flac.zip.part").exists());
+    }
+
+    #[test]
+    pub fn test_complete_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let partial = partial_file_name("01 - Odyssée.mp3");
+        std::fs::write(dir.path().join(&partial), b"ID3").unwrap();
+
+        assert_eq!(completed_file_name(&partial), "01 - Odyssée.mp3");
+        assert_eq!(
+            complete_in_place(dir.path(), &partial).unwrap(),
+            dir.path().join("01 - Odyssée.mp3")
+        );
+        assert!(!dir.path().join(partial).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn test_same_filesystem() {
+        let temp_dir = std::env::temp_dir();
+        assert_eq!(same_filesystem(&temp_dir, &temp_dir), Some(true));
+        assert_eq!(
+            same_filesystem(&temp_dir, Path::new("/does/not/exist")),
+            None
+        );
+    }
+}
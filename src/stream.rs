@@ -0,0 +1,124 @@
+use crate::{
+    api::data::TralbumData,
+    layout::sanitize_path_component,
+    template::{NameTemplate, TemplateFields},
+};
+
+// Kept apart from the regular downloads, as stream captures are lossy MP3-128 copies
+pub const STREAM_FOLDER_NAME: &str = "lossy-stream";
+
+pub struct StreamTrack {
+    pub url: String,
+    pub filename: String,
+}
+
+// e.g. "Anomalie - Galerie" with the default template
+pub fn album_folder_name(
+    tralbum: &TralbumData,
+    template: &NameTemplate,
+    ascii_filenames: bool,
+) -> String {
+    sanitize_path_component(
+        &template.render(&TemplateFields {
+            artist: &tralbum.artist,
+            title: &tralbum.current.title,
+            ..TemplateFields::default()
+        }),
+        ascii_filenames,
+    )
+}
+
+// Tracks without a stream are left out
+pub fn stream_tracks(tralbum: &TralbumData, ascii_filenames: bool) -> Vec<StreamTrack> {
+    tralbum
+        .trackinfo
+        .iter()
+        .filter_map(|track| {
+            let url = track.mp3_128_url()?;
+            let filename = track.track_num.map_or_else(
+                || format!("{}.mp3", track.title),
+                |track_num| format!("{track_num:02} - {}.mp3", track.title),
+            );
+
+            Some(StreamTrack {
+                url,
+                filename: sanitize_path_component(&filename, ascii_filenames),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tralbum() -> TralbumData {
+        serde_json::from_str(
+            r#"{
+                "artist": "Anomalie",
+                "current": {"title": "Galerie: Live?"},
+                "trackinfo": [
+                    {"title": "Odyssée", "track_num": 1, "file": {"mp3-128": "https://t4.bcbits.com/stream/1"}},
+                    {"title": "Not streamable", "track_num": 2, "file": null},
+                    {"title": "AC/DC", "track_num": 3, "file": {"mp3-128": "//t4.bcbits.com/stream/3"}}
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    pub fn test_album_folder_name() {
+        assert_eq!(
+            album_folder_name(&tralbum(), &NameTemplate::default(), false),
+            "Anomalie - Galerie_ Live_"
+        );
+        assert_eq!(
+            album_folder_name(&tralbum(), &"{title|slug}".parse().unwrap(), false),
+            "galerie-live"
+        );
+    }
+
+    #[test]
+    pub fn test_stream_tracks() {
+        let tracks = stream_tracks(&tralbum(), false);
+
+        let tracks: Vec<_> = tracks
+            .iter()
+            .map(|t| (t.filename.as_str(), t.url.as_str()))
+            .collect();
+        assert_eq!(
+            tracks,
+            [
+                ("01 - Odyssée.mp3", "https://t4.bcbits.com/stream/1"),
+                ("03 - AC_DC.mp3", "https://t4.bcbits.com/stream/3"),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_single_track_filename() {
+        let tralbum: TralbumData = serde_json::from_str(
+            r#"{"artist": "Apparat", "current": {"title": "Silizium"},
+                "trackinfo": [{"title": "Silizium", "track_num": null, "file": {"mp3-128": "https://t4.bcbits.com/stream/9"}}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(stream_tracks(&tralbum, false)[0].filename, "Silizium.mp3");
+    }
+
+    #[test]
+    pub fn test_ascii_filenames() {
+        let tracks = stream_tracks(&tralbum(), true);
+        assert_eq!(tracks[0].filename, "01 - Odyssee.mp3");
+
+        let tralbum: TralbumData = serde_json::from_str(
+            r#"{"artist": "かめりあ", "current": {"title": "Ωmega Rhapsody"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            album_folder_name(&tralbum, &NameTemplate::default(), true),
+            "kameria - Omega Rhapsody"
+        );
+    }
+}
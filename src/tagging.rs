@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use crate::archive;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EmbedReport {
+    pub embedded: usize,
+    // audio files whose tags couldn't be read or written, with why
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+// Embeds cover art into the audio files of an unpacked release, as their front cover, since the
+// art in Bandcamp's files is often missing or scaled down. Only available when built with the
+// "embed-art" feature.
+pub struct ArtEmbedder {
+    enabled: bool,
+}
+
+impl ArtEmbedder {
+    pub const fn new(enabled: bool) -> Self {
+        Self {
+            enabled: enabled && cfg!(feature = "embed-art"),
+        }
+    }
+
+    pub const fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Every audio file under `folder` gets `art` in place of the front cover it had
+    pub fn embed_tree(&self, folder: &Path, art: &[u8]) -> std::io::Result<EmbedReport> {
+        let mut report = EmbedReport::default();
+        if !self.enabled {
+            return Ok(report);
+        }
+
+        for path in archive::audio_files(folder)? {
+            match embed_file(&path, art) {
+                Ok(()) => report.embedded += 1,
+                Err(e) => report.failed.push((path, e)),
+            }
+        }
+        Ok(report)
+    }
+}
+
+// Tags are written to a copy of the file, which then replaces it, so a track hardlinked elsewhere,
+// as --dedupe-tracks does, doesn't change there too
+#[cfg(any(feature = "embed-art", feature = "normalize-tags"))]
+pub fn replace_tagged(
+    path: &Path,
+    write_tags: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let copy = path.with_file_name(crate::staging::partial_file_name(&file_name));
+    let result = std::fs::copy(path, &copy)
+        .map_err(|e| e.to_string())
+        .and_then(|_| write_tags(&copy))
+        .and_then(|()| std::fs::rename(&copy, path).map_err(|e| e.to_string()));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&copy);
+    }
+    result
+}
+
+#[cfg(feature = "embed-art")]
+fn embed_file(path: &Path, art: &[u8]) -> Result<(), String> {
+    use lofty::{
+        config::WriteOptions,
+        file::{AudioFile, TaggedFileExt},
+        picture::{Picture, PictureType},
+        tag::Tag,
+    };
+
+    let mut picture = Picture::from_reader(&mut &art[..]).map_err(|e| e.to_string())?;
+    picture.set_pic_type(PictureType::CoverFront);
+
+    let mut tagged_file = lofty::read_from_path(path).map_err(|e| e.to_string())?;
+    if tagged_file.primary_tag().is_none() {
+        tagged_file.insert_tag(Tag::new(tagged_file.primary_tag_type()));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or("the file has no tag to embed into")?;
+    tag.remove_picture_type(PictureType::CoverFront);
+    tag.push_picture(picture);
+    replace_tagged(path, |copy| {
+        tagged_file
+            .save_to_path(copy, WriteOptions::default())
+            .map_err(|e| e.to_string())
+    })
+}
+
+#[cfg(not(feature = "embed-art"))]
+fn embed_file(_: &Path, _: &[u8]) -> Result<(), String> {
+    Err("this build can't write tags".to_owned())
+}
+
+#[cfg(all(test, feature = "embed-art"))]
+mod tests {
+    use super::*;
+    use lofty::{file::TaggedFileExt, picture::PictureType};
+
+    // A FLAC file of no frames, with only its stream info and some padding
+    fn empty_flac() -> Vec<u8> {
+        let mut flac = b"fLaC".to_vec();
+        // a STREAMINFO block of 34 bytes
+        flac.extend([0x00, 0, 0, 34]);
+        flac.extend([0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        // 44.1 kHz, 2 channels, 16 bits per sample, no samples
+        flac.extend([0x0a, 0xc4, 0x42, 0xf0, 0, 0, 0, 0]);
+        flac.extend([0; 16]);
+        // the last block, of PADDING
+        flac.extend([0x81, 0, 0, 8]);
+        flac.extend([0; 8]);
+        flac
+    }
+
+    #[test]
+    pub fn test_embed_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("CD 1")).unwrap();
+        std::fs::write(
+            dir.path().join("CD 1").join("01 Odyssée.flac"),
+            empty_flac(),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("02 Galerie.flac"), b"not audio").unwrap();
+        std::fs::write(dir.path().join("cover.png"), b"not audio either").unwrap();
+        let art = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0";
+
+        let report = ArtEmbedder::new(true).embed_tree(dir.path(), art).unwrap();
+        assert_eq!(report.embedded, 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, dir.path().join("02 Galerie.flac"));
+
+        let tagged_file =
+            lofty::read_from_path(dir.path().join("CD 1").join("01 Odyssée.flac")).unwrap();
+        let pictures = tagged_file.primary_tag().unwrap().pictures();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].pic_type(), PictureType::CoverFront);
+    }
+
+    #[test]
+    pub fn test_replace_tagged_breaks_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = dir.path().join("01 Odyssée.flac");
+        let linked = dir.path().join("linked.flac");
+        std::fs::write(&track, empty_flac()).unwrap();
+        std::fs::hard_link(&track, &linked).unwrap();
+
+        replace_tagged(&track, |copy| {
+            std::fs::write(copy, b"tagged").map_err(|e| e.to_string())
+        })
+        .unwrap();
+        assert_eq!(std::fs::read(&track).unwrap(), b"tagged");
+        assert_eq!(std::fs::read(&linked).unwrap(), empty_flac());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        assert!(replace_tagged(&track, |_| Err("no".to_owned())).is_err());
+        assert_eq!(std::fs::read(&track).unwrap(), b"tagged");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fmt, str::FromStr};
+
+use time::Date;
+
+pub const DEFAULT_FOLDER_TEMPLATE: &str = "{artist} - {title}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Artist,
+    Title,
+    Year,
+    ReleaseDate,
+    ItemType,
+    SaleId,
+}
+
+// What the fields of a template are filled with. Those a name doesn't know, such as the year of a
+// stream capture, are left empty.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TemplateFields<'a> {
+    pub artist: &'a str,
+    pub title: &'a str,
+    pub year: Option<i32>,
+    pub release_date: Option<Date>,
+    // "album" or "track"
+    pub item_type: &'a str,
+    pub sale_id: &'a str,
+}
+
+impl TemplateFields<'_> {
+    fn value(&self, field: Field) -> String {
+        match field {
+            Field::Artist => self.artist.to_owned(),
+            Field::Title => self.title.to_owned(),
+            Field::Year => self.year.map(|year| year.to_string()).unwrap_or_default(),
+            // e.g. "2021-04-01"
+            Field::ReleaseDate => self
+                .release_date
+                .map(|date| {
+                    format!(
+                        "{:04}-{:02}-{:02}",
+                        date.year(),
+                        u8::from(date.month()),
+                        date.day()
+                    )
+                })
+                .unwrap_or_default(),
+            Field::ItemType => self.item_type.to_owned(),
+            Field::SaleId => self.sale_id.to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Modifier {
+    Lower,
+    Upper,
+    // lowercase ASCII words joined by dashes, e.g. "Galerie: Live?" to "galerie-live"
+    Slug,
+    // at most this many characters
+    Truncate(usize),
+}
+
+impl Modifier {
+    fn parse(modifier: &str) -> Result<Self, String> {
+        let modifier = modifier.trim();
+        if let Some(length) = modifier
+            .strip_prefix("truncate(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            return match length.trim().parse() {
+                Ok(length) if length > 0 => Ok(Self::Truncate(length)),
+                _ => Err(format!("invalid length in \"{modifier}\"")),
+            };
+        }
+
+        match modifier {
+            "lower" => Ok(Self::Lower),
+            "upper" => Ok(Self::Upper),
+            "slug" => Ok(Self::Slug),
+            _ => Err(format!(
+                "unknown modifier \"{modifier}\", expected lower, upper, slug or truncate(N)"
+            )),
+        }
+    }
+
+    fn apply(self, value: &str) -> String {
+        match self {
+            Self::Lower => value.to_lowercase(),
+            Self::Upper => value.to_uppercase(),
+            Self::Slug => slugify(value),
+            Self::Truncate(length) => value
+                .chars()
+                .take(length)
+                .collect::<String>()
+                .trim_end()
+                .to_owned(),
+        }
+    }
+}
+
+fn slugify(value: &str) -> String {
+    deunicode::deunicode(value)
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field, Vec<Modifier>),
+}
+
+// A name made of literal text and `{field}` placeholders, each optionally followed by modifiers
+// applied left to right, such as "{artist|lower} - {title|slug|truncate(60)}". The fields are
+// artist, title, year (or release_year), release_date, item_type and sale_id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let literal = &rest[..start];
+            if literal.contains('}') {
+                return Err(format!("unmatched \"}}\" in \"{template}\""));
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Literal(literal.to_owned()));
+            }
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("unclosed \"{{\" in \"{template}\""));
+            };
+            parts.push(parse_placeholder(&rest[start + 1..start + end])?);
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unmatched \"}}\" in \"{template}\""));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        Ok(Self {
+            template: template.to_owned(),
+            parts,
+        })
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, String> {
+    let mut pieces = placeholder.split('|');
+    let field = match pieces.next().unwrap_or_default().trim() {
+        "artist" => Field::Artist,
+        "title" => Field::Title,
+        "year" | "release_year" => Field::Year,
+        "release_date" => Field::ReleaseDate,
+        "item_type" => Field::ItemType,
+        "sale_id" => Field::SaleId,
+        field => {
+            return Err(format!(
+                "unknown field \"{field}\", expected artist, title, year, release_year, release_date, item_type or sale_id"
+            ))
+        }
+    };
+    let modifiers = pieces.map(Modifier::parse).collect::<Result<_, _>>()?;
+    Ok(Part::Field(field, modifiers))
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        DEFAULT_FOLDER_TEMPLATE
+            .parse()
+            .expect("the default template is valid")
+    }
+}
+
+impl NameTemplate {
+    pub fn render(&self, fields: &TemplateFields) -> String {
+        self.render_with(fields, |value| value)
+    }
+
+    // With each field's value passed through `escape` once its modifiers are applied, e.g. so the
+    // "/" of an artist isn't taken for one of the template's folders
+    pub fn render_with(
+        &self,
+        fields: &TemplateFields,
+        escape: impl Fn(String) -> String,
+    ) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Field(field, modifiers) => escape(
+                    modifiers
+                        .iter()
+                        .fold(fields.value(*field), |value, modifier| {
+                            modifier.apply(&value)
+                        }),
+                ),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("{artist} - {title}", "Anomalie - Galerie: Live Édition")]
+    #[test_case("{artist|lower}_{title|slug}", "anomalie_galerie-live-edition")]
+    #[test_case("{artist|upper} ({title|truncate(7)})", "ANOMALIE (Galerie)")]
+    #[test_case("{title|slug|truncate(12)}", "galerie-live")]
+    #[test_case("{ title | lower }", "galerie: live édition")]
+    #[test_case("{year}/{item_type}-{sale_id}", "2021/album-p199396767")]
+    #[test_case(
+        "{release_year}/{release_date} {title|slug}",
+        "2021/2021-04-01 galerie-live-edition"
+    )]
+    pub fn test_render(template: &str, expected: &str) {
+        let template: NameTemplate = template.parse().unwrap();
+        let fields = TemplateFields {
+            artist: "Anomalie",
+            title: "Galerie: Live Édition",
+            year: Some(2021),
+            release_date: Some(Date::from_calendar_date(2021, time::Month::April, 1).unwrap()),
+            item_type: "album",
+            sale_id: "p199396767",
+        };
+        assert_eq!(template.render(&fields), expected);
+    }
+
+    #[test]
+    pub fn test_render_with() {
+        let template: NameTemplate = "{artist}/{title|upper}".parse().unwrap();
+        let fields = TemplateFields {
+            artist: "AC/DC",
+            title: "Back in Black",
+            ..TemplateFields::default()
+        };
+        assert_eq!(
+            template.render_with(&fields, |value| value.replace('/', "_")),
+            "AC_DC/BACK IN BLACK"
+        );
+        assert_eq!(
+            "{year}{release_date}"
+                .parse::<NameTemplate>()
+                .unwrap()
+                .render(&fields),
+            ""
+        );
+    }
+
+    #[test_case("{artist", "unclosed")]
+    #[test_case("artist}", "unmatched")]
+    #[test_case("} {artist}", "unmatched" ; "unmatched before a field")]
+    #[test_case("{genre}", "unknown field")]
+    #[test_case("{title|reverse}", "unknown modifier")]
+    #[test_case("{title|truncate(0)}", "invalid length")]
+    #[test_case("{title|truncate(a)}", "invalid length")]
+    pub fn test_invalid_template(template: &str, error: &str) {
+        let e = template.parse::<NameTemplate>().unwrap_err();
+        assert!(e.contains(error), "{e}");
+    }
+
+    #[test]
+    pub fn test_default_template() {
+        assert_eq!(NameTemplate::default().to_string(), DEFAULT_FOLDER_TEMPLATE);
+    }
+}
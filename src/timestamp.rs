@@ -0,0 +1,135 @@
+use std::sync::OnceLock;
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+
+static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+// Looks up the terminal's time zone, which can only be done soundly while the process has a single
+// thread, so before the runtime starts. Times are shown in UTC when it wasn't or couldn't be.
+pub fn init_local_offset() {
+    if let Ok(offset) = UtcOffset::current_local_offset() {
+        let _ = LOCAL_OFFSET.set(offset);
+    }
+}
+
+fn local_offset() -> UtcOffset {
+    LOCAL_OFFSET.get().copied().unwrap_or(UtcOffset::UTC)
+}
+
+// As timestamps are recorded, e.g. "2021-01-01T00:00:00Z"
+pub fn to_rfc3339(timestamp: OffsetDateTime) -> String {
+    timestamp
+        .to_offset(UtcOffset::UTC)
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| timestamp.unix_timestamp().to_string())
+}
+
+pub fn unix_to_rfc3339(unix_timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(unix_timestamp)
+        .map_or_else(|_| unix_timestamp.to_string(), to_rfc3339)
+}
+
+pub fn parse_rfc3339(timestamp: &str) -> Option<OffsetDateTime> {
+    OffsetDateTime::parse(timestamp, &Rfc3339).ok()
+}
+
+// As times are shown in the terminal, e.g. "2021-01-01 01:00 +01:00", or "2021-01-01 00:00 UTC".
+// The offset is the terminal's current one, which is shown, so times across a daylight saving
+// change are still exact.
+pub fn format_local(timestamp: OffsetDateTime) -> String {
+    let offset = local_offset();
+    let local = timestamp.to_offset(offset);
+    let zone = if offset.is_utc() {
+        "UTC".to_owned()
+    } else {
+        format!(
+            "{}{:02}:{:02}",
+            if offset.is_negative() { '-' } else { '+' },
+            offset.whole_hours().unsigned_abs(),
+            offset.minutes_past_hour().unsigned_abs()
+        )
+    };
+    format!(
+        "{} {:02}:{:02} {zone}",
+        local.date(),
+        local.hour(),
+        local.minute()
+    )
+}
+
+// For unix timestamp fields, written as RFC 3339 and read in either form, so files recorded
+// before timestamps were RFC 3339 still load
+pub mod rfc3339 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Timestamp {
+        Unix(i64),
+        Rfc3339(String),
+    }
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub fn serialize<S: Serializer>(
+        unix_timestamp: &i64,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::unix_to_rfc3339(*unix_timestamp))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        match Timestamp::deserialize(deserializer)? {
+            Timestamp::Unix(unix_timestamp) => Ok(unix_timestamp),
+            Timestamp::Rfc3339(timestamp) => super::parse_rfc3339(&timestamp)
+                .map(time::OffsetDateTime::unix_timestamp)
+                .ok_or_else(|| D::Error::custom(format!("invalid timestamp \"{timestamp}\""))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Event {
+        #[serde(with = "rfc3339")]
+        at: i64,
+    }
+
+    #[test]
+    pub fn test_rfc3339() {
+        assert_eq!(unix_to_rfc3339(1_609_459_200), "2021-01-01T00:00:00Z");
+        assert_eq!(
+            parse_rfc3339("2021-01-01T01:00:00+01:00").map(OffsetDateTime::unix_timestamp),
+            Some(1_609_459_200)
+        );
+        assert_eq!(parse_rfc3339("2021-01-01 00:00"), None);
+    }
+
+    #[test]
+    pub fn test_serde() {
+        let event = Event { at: 1_609_459_200 };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"at":"2021-01-01T00:00:00Z"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Event>(r#"{"at":"2021-01-01T00:00:00Z"}"#).unwrap(),
+            event
+        );
+        assert_eq!(
+            serde_json::from_str::<Event>(r#"{"at":1609459200}"#).unwrap(),
+            event
+        );
+        assert!(serde_json::from_str::<Event>(r#"{"at":"yesterday"}"#).is_err());
+    }
+
+    #[test]
+    pub fn test_format_local() {
+        // tests run threaded, so the local offset is never looked up
+        let timestamp = OffsetDateTime::from_unix_timestamp(1_609_459_200).unwrap();
+        assert_eq!(format_local(timestamp), "2021-01-01 00:00 UTC");
+    }
+}
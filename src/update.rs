@@ -0,0 +1,279 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::SelfUpdateError;
+
+pub const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/cN3rd/bandcamp-dl/releases/latest";
+
+// Lists the SHA-256 of every binary of a release, as `sha256sum` prints them, after a comment
+// line naming the release, e.g. "# bandcamp-dl v1.2.3"
+pub const CHECKSUMS_ASSET_NAME: &str = "SHA256SUMS";
+// The Ed25519 signature of the checksums file, base64 encoded
+pub const SIGNATURE_ASSET_NAME: &str = "SHA256SUMS.sig";
+
+// The base64 encoded Ed25519 key release checksums are signed with, given when building the
+// release binaries. Builds without one refuse to update, as they couldn't tell a forged release.
+const SIGNING_KEY: Option<&str> = option_env!("BANDCAMP_DL_UPDATE_KEY");
+
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    pub fn asset(&self, name: &str) -> Result<&ReleaseAsset, SelfUpdateError> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| SelfUpdateError::MissingAsset(name.to_owned()))
+    }
+
+    // Only a higher version is an update, a tag that isn't a version never is
+    pub fn is_newer_than(&self, current_version: &str) -> bool {
+        match (
+            parse_version(&self.tag_name),
+            parse_version(current_version),
+        ) {
+            (Some(release), Some(current)) => release > current,
+            _ => false,
+        }
+    }
+}
+
+// "v1.2.3" and "1.2.3" -> (1, 2, 3), ignoring any pre-release or build suffix
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(str::parse);
+    let version = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+    parts.next().is_none().then_some(version)
+}
+
+// The binary for the platform we run on, e.g. "bandcamp-dl-x86_64-linux" or
+// "bandcamp-dl-x86_64-windows.exe"
+pub fn binary_asset_name() -> String {
+    format!(
+        "bandcamp-dl-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+// The checksum of `name` in a `sha256sum` listing, whose names may be marked as binary by a '*'
+fn find_checksum<'a>(checksums: &'a str, name: &str) -> Option<&'a str> {
+    checksums.lines().find_map(|line| {
+        let (checksum, file_name) = line.split_once(char::is_whitespace)?;
+        let file_name = file_name.trim_start();
+        (file_name.strip_prefix('*').unwrap_or(file_name) == name).then_some(checksum)
+    })
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+pub fn verify_checksum(checksums: &str, name: &str, binary: &[u8]) -> Result<(), SelfUpdateError> {
+    let expected = find_checksum(checksums, name)
+        .ok_or_else(|| SelfUpdateError::MissingChecksum(name.to_owned()))?;
+    if expected.eq_ignore_ascii_case(&sha256_hex(binary)) {
+        Ok(())
+    } else {
+        Err(SelfUpdateError::ChecksumMismatch(name.to_owned()))
+    }
+}
+
+// The release the checksums were signed for, so those of an older release can't be passed off as
+// a newer one's
+fn manifest_version(checksums: &str) -> Option<&str> {
+    checksums
+        .lines()
+        .next()?
+        .strip_prefix("# bandcamp-dl ")
+        .map(str::trim)
+}
+
+pub fn verify_manifest(
+    checksums: &str,
+    signature: &str,
+    tag_name: &str,
+) -> Result<(), SelfUpdateError> {
+    let key = SIGNING_KEY.ok_or(SelfUpdateError::NoSigningKey)?;
+    verify_manifest_with(key, checksums, signature, tag_name)
+}
+
+fn verify_manifest_with(
+    key: &str,
+    checksums: &str,
+    signature: &str,
+    tag_name: &str,
+) -> Result<(), SelfUpdateError> {
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    let key = BASE64_STANDARD
+        .decode(key.trim())
+        .map_err(|_| SelfUpdateError::BadSignature)?;
+    let signature = BASE64_STANDARD
+        .decode(signature.trim())
+        .map_err(|_| SelfUpdateError::BadSignature)?;
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(checksums.as_bytes(), &signature)
+        .map_err(|_| SelfUpdateError::BadSignature)?;
+
+    match manifest_version(checksums) {
+        Some(version) if version == tag_name => Ok(()),
+        version => Err(SelfUpdateError::VersionMismatch(
+            version.unwrap_or("no release").to_owned(),
+        )),
+    }
+}
+
+// Written next to the running binary and renamed over it, so an interrupted update leaves the old
+// one working. The old binary is moved aside first, as Windows doesn't let a running one be
+// replaced but does let it be renamed.
+pub fn replace_executable(executable: &Path, binary: &[u8]) -> Result<(), SelfUpdateError> {
+    let new_path = sibling_path(executable, "new");
+    std::fs::write(&new_path, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&new_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    let old_path = sibling_path(executable, "old");
+    std::fs::rename(executable, &old_path)?;
+    if let Err(e) = std::fs::rename(&new_path, executable) {
+        std::fs::rename(&old_path, executable)?;
+        return Err(e.into());
+    }
+    // still in use on Windows, where it's left for the next update to replace
+    let _ = std::fs::remove_file(&old_path);
+    Ok(())
+}
+
+fn sibling_path(executable: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = executable.file_name().unwrap_or_default().to_owned();
+    file_name.push(format!(".{suffix}"));
+    executable.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use test_case::test_case;
+
+    #[test_case("v1.2.3", Some((1, 2, 3)))]
+    #[test_case("0.1.0", Some((0, 1, 0)))]
+    #[test_case("v2.0.0-rc.1", Some((2, 0, 0)))]
+    #[test_case("v1.2", None)]
+    #[test_case("nightly", None)]
+    pub fn test_parse_version(version: &str, expected: Option<(u64, u64, u64)>) {
+        assert_eq!(parse_version(version), expected);
+    }
+
+    #[test]
+    pub fn test_is_newer_than() {
+        let release = |tag_name: &str| Release {
+            tag_name: tag_name.to_owned(),
+            assets: Vec::new(),
+        };
+
+        assert!(release("v0.2.0").is_newer_than("0.1.9"));
+        assert!(release("v0.1.10").is_newer_than("0.1.9"));
+        assert!(!release("v0.1.0").is_newer_than("0.1.0"));
+        assert!(!release("nightly").is_newer_than("0.1.0"));
+    }
+
+    #[test]
+    pub fn test_verify_checksum() {
+        let checksums = format!(
+            "# bandcamp-dl v0.2.0\n{}  bandcamp-dl-x86_64-linux\n{} *bandcamp-dl-x86_64-windows.exe\n",
+            sha256_hex(b"linux"),
+            sha256_hex(b"windows")
+        );
+
+        assert!(verify_checksum(&checksums, "bandcamp-dl-x86_64-linux", b"linux").is_ok());
+        assert!(verify_checksum(&checksums, "bandcamp-dl-x86_64-windows.exe", b"windows").is_ok());
+        assert_matches!(
+            verify_checksum(&checksums, "bandcamp-dl-x86_64-linux", b"tampered"),
+            Err(SelfUpdateError::ChecksumMismatch(_))
+        );
+        assert_matches!(
+            verify_checksum(&checksums, "bandcamp-dl-aarch64-macos", b"macos"),
+            Err(SelfUpdateError::MissingChecksum(_))
+        );
+    }
+
+    #[test]
+    pub fn test_replace_executable() {
+        let folder = tempfile::tempdir().unwrap();
+        let executable = folder.path().join("bandcamp-dl");
+        std::fs::write(&executable, b"old").unwrap();
+
+        replace_executable(&executable, b"new").unwrap();
+        assert_eq!(std::fs::read(&executable).unwrap(), b"new");
+        assert!(!sibling_path(&executable, "new").exists());
+    }
+
+    #[test]
+    pub fn test_verify_manifest() {
+        use base64::{prelude::BASE64_STANDARD, Engine};
+        use ring::{
+            rand::SystemRandom,
+            signature::{Ed25519KeyPair, KeyPair},
+        };
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let key = BASE64_STANDARD.encode(key_pair.public_key());
+        let sign = |checksums: &str| BASE64_STANDARD.encode(key_pair.sign(checksums.as_bytes()));
+        let checksums = "# bandcamp-dl v0.2.0\nabc  bandcamp-dl-x86_64-linux\n";
+
+        assert!(verify_manifest_with(&key, checksums, &sign(checksums), "v0.2.0").is_ok());
+        assert_matches!(
+            verify_manifest_with(
+                &key,
+                "# bandcamp-dl v0.2.0\ndef  bandcamp-dl-x86_64-linux\n",
+                &sign(checksums),
+                "v0.2.0"
+            ),
+            Err(SelfUpdateError::BadSignature)
+        );
+
+        // an older release's signed checksums, served as the latest one
+        let old_checksums = "# bandcamp-dl v0.1.0\nabc  bandcamp-dl-x86_64-linux\n";
+        assert_matches!(
+            verify_manifest_with(&key, old_checksums, &sign(old_checksums), "v0.2.0"),
+            Err(SelfUpdateError::VersionMismatch(version)) if version == "v0.1.0"
+        );
+        let unnamed = "abc  bandcamp-dl-x86_64-linux\n";
+        assert_matches!(
+            verify_manifest_with(&key, unnamed, &sign(unnamed), "v0.2.0"),
+            Err(SelfUpdateError::VersionMismatch(_))
+        );
+    }
+}
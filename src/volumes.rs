@@ -0,0 +1,176 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::models::{self, Versioned};
+
+// Which of several download folders each release landed in, kept beside the download cache so
+// the cache itself stays readable by bandcamp-collection-downloader
+pub const VOLUMES_FILE_NAME: &str = "volumes.json";
+
+pub type VolumeMap = BTreeMap<String, PathBuf>;
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct VolumeFile {
+    volumes: VolumeMap,
+}
+
+impl Versioned for VolumeFile {
+    const SCHEMA_VERSION: u32 = 1;
+
+    // Version 0 was the bare map, with no room for a version
+    fn upgrade(from_version: u32, value: Value) -> Value {
+        match from_version {
+            0 => serde_json::json!({ "volumes": value }),
+            _ => value,
+        }
+    }
+}
+
+pub fn read_volume_map(volume_data: &str) -> Result<VolumeMap, serde_json::Error> {
+    models::read_versioned::<VolumeFile>(volume_data).map(|file| file.volumes)
+}
+
+pub fn serialize_volume_map(volumes: &VolumeMap) -> Result<String, serde_json::Error> {
+    let file = VolumeFile {
+        volumes: volumes.clone(),
+    };
+    serde_json::to_string_pretty(&models::versioned_value(&file)?)
+}
+
+pub fn available_space(folder: &Path) -> Option<u64> {
+    fs4::available_space(folder).ok()
+}
+
+struct Target {
+    folder: PathBuf,
+    free_bytes: u64,
+}
+
+// Spreads releases over several download folders by free space, optionally keeping each artist's
+// releases together
+pub struct VolumeRouter {
+    targets: Vec<Target>,
+    pin_artists: bool,
+    pinned_artists: HashMap<String, usize>,
+}
+
+impl VolumeRouter {
+    // Folders whose free space can't be read are treated as full, and only used as a last resort
+    pub fn new(folders: Vec<(PathBuf, Option<u64>)>, pin_artists: bool) -> Self {
+        Self {
+            targets: folders
+                .into_iter()
+                .map(|(folder, free_bytes)| Target {
+                    folder,
+                    free_bytes: free_bytes.unwrap_or_default(),
+                })
+                .collect(),
+            pin_artists,
+            pinned_artists: HashMap::new(),
+        }
+    }
+
+    pub fn pin_artist(&mut self, artist: &str, folder: &Path) {
+        if !self.pin_artists {
+            return;
+        }
+        if let Some(index) = self.targets.iter().position(|t| t.folder == folder) {
+            self.pinned_artists.insert(artist.to_owned(), index);
+        }
+    }
+
+    // Picks the folder of the artist's earlier releases while it has room (when pinning), and the
+    // folder with the most free space otherwise. The release's size is taken off the picked folder.
+    pub fn route(&mut self, artist: &str, size: u64) -> &Path {
+        let index = self
+            .pinned_artists
+            .get(artist)
+            .copied()
+            .filter(|&index| self.targets[index].free_bytes >= size)
+            .or_else(|| {
+                self.targets
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(index, target)| (target.free_bytes, std::cmp::Reverse(*index)))
+                    .map(|(index, _)| index)
+            })
+            .expect("VolumeRouter needs at least one download folder");
+
+        if self.pin_artists {
+            self.pinned_artists.insert(artist.to_owned(), index);
+        }
+        let target = &mut self.targets[index];
+        target.free_bytes = target.free_bytes.saturating_sub(size);
+        &target.folder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(pin_artists: bool) -> VolumeRouter {
+        VolumeRouter::new(
+            vec![
+                (PathBuf::from("/mnt/a"), Some(1_000)),
+                (PathBuf::from("/mnt/b"), Some(1_500)),
+                (PathBuf::from("/mnt/c"), None),
+            ],
+            pin_artists,
+        )
+    }
+
+    #[test]
+    pub fn test_read_unversioned_volume_map() {
+        let volumes = read_volume_map(r#"{"p1": "/mnt/a", "p2": "/mnt/b"}"#).unwrap();
+        assert_eq!(volumes.get("p2"), Some(&PathBuf::from("/mnt/b")));
+
+        let serialized = serialize_volume_map(&volumes).unwrap();
+        assert!(serialized.contains("\"schema_version\": 1"));
+        assert_eq!(read_volume_map(&serialized).unwrap(), volumes);
+    }
+
+    #[test]
+    pub fn test_route_by_free_space() {
+        let mut router = router(false);
+        router.pin_artist("Camellia", Path::new("/mnt/a"));
+
+        assert_eq!(router.route("Anomalie", 800), Path::new("/mnt/b"));
+        assert_eq!(router.route("Apparat", 300), Path::new("/mnt/a"));
+        assert_eq!(router.route("Anomalie", 100), Path::new("/mnt/a"));
+        assert_eq!(router.route("Camellia", 100), Path::new("/mnt/b"));
+    }
+
+    #[test]
+    pub fn test_route_keeps_artists_together() {
+        let mut router = router(true);
+        router.pin_artist("Anomalie", Path::new("/mnt/a"));
+
+        assert_eq!(router.route("Anomalie", 900), Path::new("/mnt/a"));
+        // no room left next to the earlier releases
+        assert_eq!(router.route("Anomalie", 900), Path::new("/mnt/b"));
+        assert_eq!(router.route("Anomalie", 100), Path::new("/mnt/b"));
+    }
+
+    #[test]
+    pub fn test_pin_unknown_folder() {
+        let mut router = router(true);
+        router.pin_artist("Anomalie", Path::new("/mnt/elsewhere"));
+
+        assert_eq!(router.route("Anomalie", 100), Path::new("/mnt/b"));
+    }
+
+    #[test]
+    pub fn test_volume_map_round_trip() {
+        let volumes = VolumeMap::from([("p199396767".to_owned(), PathBuf::from("/mnt/a"))]);
+        let read = read_volume_map(&serialize_volume_map(&volumes).unwrap()).unwrap();
+
+        assert_eq!(read, volumes);
+    }
+}